@@ -9,11 +9,18 @@ M.defaults = {
   debounce_ms = 30,
   toggle_key = "<C-j>",
   live_conversion = true,
+  live_engine = "auto", -- Conversion engine for live preview ("dict" skips Zenzai, "zenzai", "auto")
+  live_zenzai_profile = nil, -- Zenzai model profile for live preview (nil = the session's)
+  health_check_ms = 10000, -- Ping interval; a server that misses a ping or dies is restarted (0 disables)
+  halfwidth_katakana = false, -- Add the half-width katakana form (ｱｽﾞｷ) as a candidate
+  fullwidth_alphanumeric = true, -- Add the full-width form of readings with ASCII letters or digits (ａｂｃ)
+  hidden_candidate_kinds = {}, -- Kinds left out ("emoji", "symbol"); vim.b.azuki_hidden_candidate_kinds per buffer
   highlight = {
     pending = "AzukiPending",
     selected = "AzukiSelected",
     segment = "AzukiSegment",
     current_segment = "AzukiCurrentSegment",
+    annotation = "AzukiAnnotation",
   },
   learning = true,
   learning_file = vim.fn.stdpath("data") .. "/azuki/learning.json",
@@ -23,6 +30,19 @@ M.defaults = {
     model_path = nil, -- Path to zenz GGUF model (auto-detect if nil)
     inference_limit = 10, -- Max inference iterations (higher = better accuracy, slower)
     contextual = false, -- Enable context-aware conversion
+    beam_width = 3, -- Conversion hypotheses kept by beam search (1 = greedy)
+    idle_unload_secs = 0, -- Unload the model after this many idle seconds (0 = never)
+    lora_path = nil, -- LoRA adapter (GGUF) applied on top of the model
+    lora_scale = 1.0, -- Strength of the LoRA adapter
+    n_threads = nil, -- Inference threads (nil = half the CPU cores)
+    n_batch = 512, -- Prompt tokens decoded per llama.cpp batch
+    n_ctx = nil, -- Context size in tokens (nil = sized to each prompt)
+    use_mmap = true, -- Map the model file into memory instead of reading it
+    use_mlock = false, -- Lock the model in RAM so it is never paged out
+    temperature = 0, -- Sample beam tokens at this temperature (0 = deterministic)
+    seed = nil, -- Sampling seed, for reproducible conversions (nil = from the clock)
+    prompt_format = nil, -- "v2" or "v3" (nil = detect from the model metadata)
+    -- profiles = { small = { model_path = "...", beam_width = 1 } }, -- Other models, by name
   },
 }
 
@@ -12,6 +12,9 @@ local ui = require("azuki.ui")
 --- Debounce timer
 local debounce_timer = nil
 
+--- seq of the conversion awaiting its response, if any
+local in_flight_seq = nil
+
 --- Callbacks for mode changes (set by input.lua)
 M.on_disable = nil
 
@@ -38,11 +41,19 @@ local function update_display()
       state.data.preedit_start_col,
       state.data.segments,
       state.data.current_segment,
-      state.data.romaji_buffer
+      state.data.romaji_buffer,
+      state.get_selected_annotation()
     )
   elseif state.has_selection() then
     local display_text = state.data.candidates[state.data.selected_index] .. state.data.romaji_buffer
-    ui.show_candidate(bufnr, state.data.preedit_start_row, state.data.preedit_start_col, display_text, true)
+    ui.show_candidate(
+      bufnr,
+      state.data.preedit_start_row,
+      state.data.preedit_start_col,
+      display_text,
+      true,
+      state.get_selected_annotation()
+    )
   else
     local display_text = state.data.hiragana .. state.data.romaji_buffer
     ui.show_preedit(bufnr, state.data.preedit_start_row, state.data.preedit_start_col, display_text)
@@ -55,28 +66,48 @@ local function request_conversion()
     return
   end
 
+  -- The previous conversion is stale once the reading changed
+  if in_flight_seq then
+    server.cancel(in_flight_seq)
+  end
+
   local current_seq = server.get_seq() + 1
   state.data.last_seq = current_seq
-
-  server.convert(state.data.hiragana, { live = true }, function(response)
+  in_flight_seq = current_seq
+
+  local opts = {
+    live = true,
+    engine = config.get("live_engine"),
+    zenzai_profile = config.get("live_zenzai_profile"),
+  }
+  -- Show Zenzai's partial output until the candidates arrive
+  opts.on_progress = function(progress)
+    local bufnr = state.data.bufnr
+    if progress.seq ~= state.data.last_seq or not bufnr then
+      return
+    end
+    local text = progress.text .. state.data.romaji_buffer
+    ui.show_candidate(bufnr, state.data.preedit_start_row, state.data.preedit_start_col, text, false)
+  end
+  server.convert(state.data.hiragana, opts, function(response)
+    if response.seq == in_flight_seq then
+      in_flight_seq = nil
+    end
     if response.seq ~= state.data.last_seq then
       return
     end
 
     if response.type == "convert_result" then
       if response.segments and #response.segments > 0 then
-        state.data.segments = response.segments
-        state.data.current_segment = 1
-        for _, seg in ipairs(state.data.segments) do
-          seg.selected_index = 1
-        end
+        state.set_segments(response.segments)
+        -- active_segment is 0-indexed and only present when a cursor was sent
+        state.data.current_segment = (response.active_segment or 0) + 1
       else
         state.data.segments = {}
         state.data.current_segment = 1
       end
 
-      state.data.candidates = response.candidates or {}
-      state.data.selected_index = #state.data.candidates > 0 and 1 or 0
+      state.set_candidates(response.candidates)
 
       update_display()
     end
@@ -336,9 +367,9 @@ function M.shrink_segment()
     "shrink",
     function(response)
       if response.type == "adjust_segment_result" then
-        state.data.segments = response.segments
-        for _, s in ipairs(state.data.segments) do
-          s.selected_index = 1
+        state.set_segments(response.segments)
+        if response.candidates then
+          state.set_candidates(response.candidates)
         end
         update_display()
       end
@@ -368,9 +399,9 @@ function M.extend_segment()
     "extend",
     function(response)
       if response.type == "adjust_segment_result" then
-        state.data.segments = response.segments
-        for _, s in ipairs(state.data.segments) do
-          s.selected_index = 1
+        state.set_segments(response.segments)
+        if response.candidates then
+          state.set_candidates(response.candidates)
         end
         update_display()
       end
@@ -15,6 +15,7 @@ local function setup_highlights()
   vim.api.nvim_set_hl(0, hl.selected, { reverse = true, default = true })
   vim.api.nvim_set_hl(0, hl.segment, { underline = true, default = true })
   vim.api.nvim_set_hl(0, hl.current_segment, { reverse = true, bold = true, default = true })
+  vim.api.nvim_set_hl(0, hl.annotation, { link = "Comment", default = true })
 end
 
 --- Setup user commands
@@ -35,6 +36,60 @@ local function setup_commands()
     M.toggle()
   end, { desc = "Toggle Japanese input mode" })
 
+  vim.api.nvim_create_user_command("AzukiMode", function(cmd)
+    M.set_mode(cmd.args)
+  end, {
+    desc = "Set how readings are converted",
+    nargs = 1,
+    complete = function()
+      return { "hiragana", "katakana", "halfwidth_katakana", "abbrev" }
+    end,
+  })
+
+  vim.api.nvim_create_user_command("AzukiAddWord", function(cmd)
+    M.add_word(cmd.fargs[1], cmd.fargs[2])
+  end, { desc = "Register a word in the user dictionary", nargs = "+" })
+
+  vim.api.nvim_create_user_command("AzukiRemoveWord", function(cmd)
+    M.remove_word(cmd.fargs[1], cmd.fargs[2])
+  end, { desc = "Remove a word from the user dictionary", nargs = "+" })
+
+  vim.api.nvim_create_user_command("AzukiUndoCommit", function()
+    M.undo_commit()
+  end, { desc = "Forget the last commit in the learning data" })
+
+  vim.api.nvim_create_user_command("AzukiUnloadModel", function()
+    M.unload_model()
+  end, { desc = "Unload the Zenzai model until the next conversion needs it" })
+
+  vim.api.nvim_create_user_command("AzukiZenzaiStatus", function()
+    M.zenzai_status()
+  end, { desc = "Show the Zenzai model state and why it isn't converting" })
+
+  vim.api.nvim_create_user_command("AzukiStats", function()
+    M.stats()
+  end, { desc = "Show typing statistics" })
+
+  vim.api.nvim_create_user_command("AzukiMetrics", function()
+    M.metrics()
+  end, { desc = "Show server latencies and memory use" })
+
+  vim.api.nvim_create_user_command("AzukiSessions", function()
+    M.sessions()
+  end, { desc = "List the sessions of the server" })
+
+  vim.api.nvim_create_user_command("AzukiReloadDictionaries", function()
+    M.reload_dictionaries()
+  end, { desc = "Reload dictionaries from disk" })
+
+  vim.api.nvim_create_user_command("AzukiDictInfo", function()
+    M.dict_info()
+  end, { desc = "Show the loaded dictionaries and load failures" })
+
+  vim.api.nvim_create_user_command("AzukiDictSet", function(cmd)
+    M.dict_set(cmd.args ~= "" and cmd.args or nil)
+  end, { desc = "Switch this session's dictionary set, or show the sets", nargs = "?" })
+
   vim.api.nvim_create_user_command("AzukiTest", function(cmd)
     M.test_convert(cmd.args)
   end, { desc = "Test conversion", nargs = "?" })
@@ -101,10 +156,371 @@ end
 --- Show status
 function M.status()
   local server_status = server.is_active() and "running" or "stopped"
+  if server.is_active() and server.dictionary_loading then
+    server_status = server_status .. " (loading dictionaries)"
+  end
   local input_status = input.is_enabled() and "enabled" or "disabled"
   vim.notify(string.format("[azuki] Server: %s, Input: %s", server_status, input_status), vim.log.levels.INFO)
 end
 
+--- Notify the result of a user dictionary request
+--- @param action string
+--- @param reading string
+--- @param candidate string
+local function notify_entry_result(action, reading, candidate)
+  return function(response)
+    if response.type == "error" then
+      vim.notify("[azuki] Error: " .. response.error, vim.log.levels.ERROR)
+    elseif response.success then
+      vim.notify(string.format("[azuki] %s: %s → %s", action, reading, candidate), vim.log.levels.INFO)
+    else
+      vim.notify(string.format("[azuki] Not registered: %s → %s", reading, candidate), vim.log.levels.WARN)
+    end
+  end
+end
+
+--- Register a word in the user dictionary
+--- @param reading string|nil Hiragana reading
+--- @param candidate string|nil Word to register
+function M.add_word(reading, candidate)
+  if not reading or not candidate then
+    vim.notify("[azuki] Usage: :AzukiAddWord {reading} {word}", vim.log.levels.WARN)
+    return
+  end
+  server.add_entry(reading, candidate, notify_entry_result("Registered", reading, candidate))
+end
+
+--- Remove a word from the user dictionary
+--- @param reading string|nil Hiragana reading
+--- @param candidate string|nil Registered word
+function M.remove_word(reading, candidate)
+  if not reading or not candidate then
+    vim.notify("[azuki] Usage: :AzukiRemoveWord {reading} {word}", vim.log.levels.WARN)
+    return
+  end
+  server.remove_entry(reading, candidate, notify_entry_result("Removed", reading, candidate))
+end
+
+--- Set how readings are converted in this session
+--- @param mode string "hiragana", "katakana", "halfwidth_katakana" or "abbrev"
+function M.set_mode(mode)
+  if not server.is_active() then
+    vim.notify("[azuki] Server not running. Use :AzukiStart first.", vim.log.levels.WARN)
+    return
+  end
+
+  server.set_mode(mode, function(response)
+    if response.type == "error" then
+      vim.notify("[azuki] Error: " .. response.error, vim.log.levels.ERROR)
+    elseif response.type == "mode_result" then
+      vim.notify("[azuki] Mode: " .. response.mode, vim.log.levels.INFO)
+    end
+  end)
+end
+
+--- Free the memory of the Zenzai model; it is loaded again when needed
+function M.unload_model()
+  if not server.is_active() then
+    vim.notify("[azuki] Server not running. Use :AzukiStart first.", vim.log.levels.WARN)
+    return
+  end
+
+  server.unload_model(function(response)
+    if response.type ~= "unload_model_result" then
+      return
+    end
+    if response.unloaded then
+      vim.notify("[azuki] Zenzai model unloaded", vim.log.levels.INFO)
+    else
+      vim.notify("[azuki] No Zenzai model loaded", vim.log.levels.INFO)
+    end
+  end)
+end
+
+--- Show the Zenzai model, worker and cache state, and its latest error
+function M.zenzai_status()
+  if not server.is_active() then
+    vim.notify("[azuki] Server not running. Use :AzukiStart first.", vim.log.levels.WARN)
+    return
+  end
+
+  server.zenzai_status(function(response)
+    if response.type ~= "zenzai_status_result" then
+      return
+    end
+    local lines = {
+      string.format("[azuki] Zenzai: %s", response.enabled and "enabled" or "disabled"),
+    }
+    if response.model_path then
+      table.insert(lines, "  Model: " .. response.model_path)
+    end
+    if response.worker_pid then
+      table.insert(lines, string.format("  Worker process: %d", response.worker_pid))
+    end
+    local status = response.models
+    if status then
+      local profiles = vim.tbl_keys(status.models)
+      table.sort(profiles)
+      for _, profile in ipairs(profiles) do
+        local model = status.models[profile]
+        table.insert(
+          lines,
+          string.format(
+            "  %s: %s (%s, %d GPU layers, %d contexts)",
+            profile,
+            model.path,
+            model.prompt_format,
+            model.gpu_layers,
+            model.contexts
+          )
+        )
+      end
+      if status.n_ctx and status.n_ctx ~= vim.NIL then
+        table.insert(lines, string.format("  Context size: %d tokens", status.n_ctx))
+      end
+      local cache = status.prompt_cache
+      if cache.hit_rate ~= vim.NIL then
+        table.insert(lines, string.format("  Prompt cache: %d%% of tokens reused", cache.hit_rate * 100))
+      end
+    end
+    if response.last_error then
+      table.insert(lines, "  Last error: " .. response.last_error)
+    end
+    vim.notify(table.concat(lines, "\n"), vim.log.levels.INFO)
+  end)
+end
+
+--- Forget the last commit so it doesn't affect candidate order
+function M.undo_commit()
+  if not server.is_active() then
+    vim.notify("[azuki] Server not running. Use :AzukiStart first.", vim.log.levels.WARN)
+    return
+  end
+
+  server.undo_commit(function(response)
+    if response.type ~= "undo_commit_result" then
+      return
+    end
+    if response.undone then
+      vim.notify(
+        string.format("[azuki] Forgot %s → %s", response.reading, response.candidate),
+        vim.log.levels.INFO
+      )
+    else
+      vim.notify("[azuki] Nothing to undo", vim.log.levels.INFO)
+    end
+  end)
+end
+
+--- Show typing statistics for this session
+function M.stats()
+  if not server.is_active() then
+    vim.notify("[azuki] Server not running. Use :AzukiStart first.", vim.log.levels.WARN)
+    return
+  end
+
+  server.session_stats(function(response)
+    if response.type ~= "session_stats_result" then
+      return
+    end
+    local stats = response.stats
+    local first = stats.candidate_index_counts[1] or 0
+    local rate = stats.commits > 0 and (first * 100 / stats.commits) or 0
+    vim.notify(
+      string.format(
+        "[azuki] Conversions: %d, Commits: %d, First candidate: %d%%",
+        stats.conversions,
+        stats.commits,
+        rate
+      ),
+      vim.log.levels.INFO
+    )
+  end)
+end
+
+--- Show request latencies, cache hit rates and memory use of the server
+function M.metrics()
+  if not server.is_active() then
+    vim.notify("[azuki] Server not running. Use :AzukiStart first.", vim.log.levels.WARN)
+    return
+  end
+
+  server.get_stats(function(response)
+    if response.type ~= "stats_result" then
+      return
+    end
+    local lines = { string.format("[azuki] Uptime: %ds", response.uptime_ms / 1000) }
+    local kinds = vim.tbl_keys(response.requests)
+    table.sort(kinds)
+    for _, kind in ipairs(kinds) do
+      local stats = response.requests[kind]
+      table.insert(
+        lines,
+        string.format(
+          "  %s: %d (%d errors), p50 %.1fms, p90 %.1fms, p99 %.1fms",
+          kind,
+          stats.count,
+          stats.errors,
+          stats.p50_ms,
+          stats.p90_ms,
+          stats.p99_ms
+        )
+      )
+    end
+    local cache = response.remote_cache
+    if cache and cache.hit_rate ~= vim.NIL then
+      table.insert(lines, string.format("  Remote dictionary cache: %d%% hits", cache.hit_rate * 100))
+    end
+    local memory = response.memory
+    table.insert(
+      lines,
+      string.format(
+        "  Dictionary: %.1fMB in memory, %.1fMB mapped",
+        memory.dictionary_bytes / 1048576,
+        memory.mapped_bytes / 1048576
+      )
+    )
+    vim.notify(table.concat(lines, "\n"), vim.log.levels.INFO)
+  end)
+end
+
+--- List the server's sessions with their idle time and pending reading
+function M.sessions()
+  if not server.is_active() then
+    vim.notify("[azuki] Server not running. Use :AzukiStart first.", vim.log.levels.WARN)
+    return
+  end
+
+  server.list_sessions(function(response)
+    if response.type ~= "list_sessions_result" then
+      return
+    end
+    local lines = { string.format("[azuki] Sessions: %d", #response.sessions) }
+    for _, session in ipairs(response.sessions) do
+      local own = session.session_id == server.session_id and " (this)" or ""
+      table.insert(
+        lines,
+        string.format(
+          "  %s%s: age %ds, idle %ds, %.1fKB%s",
+          session.session_id,
+          own,
+          session.age_ms / 1000,
+          session.idle_ms / 1000,
+          session.memory_bytes / 1024,
+          session.reading and (", reading " .. session.reading) or ""
+        )
+      )
+    end
+    vim.notify(table.concat(lines, "\n"), vim.log.levels.INFO)
+  end)
+end
+
+--- One line per dictionary file of a load report, then the user dictionary
+--- @param response table reload_dictionaries_result or dict_info_result
+--- @return string
+local function dictionary_lines(response)
+  local lines = {}
+  for _, dictionary in ipairs(response.dictionaries) do
+    if dictionary.error then
+      table.insert(lines, string.format("%s: %s", dictionary.path, dictionary.error))
+    else
+      table.insert(
+        lines,
+        string.format(
+          "%s: %d entries (%s, %dms)",
+          dictionary.path,
+          dictionary.entries,
+          dictionary.encoding,
+          dictionary.elapsed_ms
+        )
+      )
+    end
+  end
+  table.insert(lines, string.format("User dictionary: %d words", response.user_entries))
+  return table.concat(lines, "\n")
+end
+
+--- Reload dictionaries from disk (e.g. after downloading a new SKK-JISYO)
+function M.reload_dictionaries()
+  if not server.is_active() then
+    vim.notify("[azuki] Server not running. Use :AzukiStart first.", vim.log.levels.WARN)
+    return
+  end
+
+  server.reload_dictionaries(function(response)
+    if response.type == "error" then
+      vim.notify("[azuki] Error: " .. response.error, vim.log.levels.ERROR)
+      return
+    end
+    if response.type ~= "reload_dictionaries_result" then
+      return
+    end
+    vim.notify("[azuki] Reloaded dictionaries\n" .. dictionary_lines(response), vim.log.levels.INFO)
+  end)
+end
+
+--- Show the dictionaries loaded, their entry counts, encodings and failures
+function M.dict_info()
+  if not server.is_active() then
+    vim.notify("[azuki] Server not running. Use :AzukiStart first.", vim.log.levels.WARN)
+    return
+  end
+
+  server.dict_info(function(response)
+    if response.type ~= "dict_info_result" then
+      return
+    end
+    local title = response.loading and "[azuki] Dictionaries (still loading)" or "[azuki] Dictionaries"
+    if response.dictionary_set then
+      title = title .. " of set " .. response.dictionary_set
+    end
+    vim.notify(title .. "\n" .. dictionary_lines(response), vim.log.levels.INFO)
+  end)
+end
+
+--- Switch the dictionary set this session converts with
+--- @param name string|nil Set name ("default" for the top-level dictionaries); nil shows the sets
+function M.dict_set(name)
+  if not server.is_active() then
+    vim.notify("[azuki] Server not running. Use :AzukiStart first.", vim.log.levels.WARN)
+    return
+  end
+
+  if not name then
+    server.dict_info(function(response)
+      if response.type ~= "dict_info_result" then
+        return
+      end
+      local sets = vim.list_extend({ "default" }, response.dictionary_sets or {})
+      vim.notify(
+        string.format(
+          "[azuki] Dictionary set: %s (available: %s)",
+          response.dictionary_set or "default",
+          table.concat(sets, ", ")
+        ),
+        vim.log.levels.INFO
+      )
+    end)
+    return
+  end
+
+  server.session_state({ dictionary_set = name }, function(response)
+    if response.type == "error" then
+      vim.notify("[azuki] Error: " .. response.error, vim.log.levels.ERROR)
+      return
+    end
+    if response.type ~= "session_state_result" then
+      return
+    end
+    local message = "[azuki] Dictionary set: " .. (response.dictionary_set or "default")
+    if response.dictionary_set_loading then
+      -- dictionary_set_ready follows once it is loaded
+      message = message .. " (loading)"
+    end
+    vim.notify(message, vim.log.levels.INFO)
+  end)
+end
+
 --- Test conversion (for verification)
 --- @param reading string|nil Test input (hiragana)
 function M.test_convert(reading)
@@ -118,7 +534,11 @@ function M.test_convert(reading)
 
   server.convert(reading, { live = true }, function(response)
     if response.type == "convert_result" then
-      local candidates = table.concat(response.candidates, ", ")
+      local texts = {}
+      for _, candidate in ipairs(response.candidates) do
+        table.insert(texts, candidate.text)
+      end
+      local candidates = table.concat(texts, ", ")
       vim.notify("[azuki] Candidates: " .. candidates, vim.log.levels.INFO)
     elseif response.type == "error" then
       vim.notify("[azuki] Error: " .. response.error, vim.log.levels.ERROR)
@@ -19,7 +19,7 @@ M.control_keys = {
 }
 
 --- Special character keys that should be handled as input
-M.special_keys = { "-", "'" }
+M.special_keys = { "-", "'", ">" }
 
 --- Setup key mappings for a buffer
 --- @param bufnr number Buffer number
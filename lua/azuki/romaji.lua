@@ -205,6 +205,8 @@ M.table = {
 
   -- Special
   ["-"] = "ー",
+  -- Prefix/suffix marker, passed through to the reading
+  [">"] = ">",
 }
 
 --- Consonants that can form sokuon (small tsu) when doubled
@@ -11,9 +11,14 @@ M.stderr = nil
 M.seq = 0
 M.session_id = nil
 M.callbacks = {}
+M.progress_callbacks = {} -- Handlers of convert_progress notifications, by seq
 M.read_buffer = ""
+M.chunks = {} -- Parts of responses split into chunks, by seq
 M.is_running = false
 M.stop_callback = nil -- Callback to invoke after server exit
+M.dictionary_loading = false -- Dictionaries still loading in the background
+M.stopping = false -- Exit was requested with stop()
+M.health_timer = nil
 
 --- Configuration
 local config = {
@@ -96,6 +101,118 @@ function M.send(msg, callback)
   M.stdin:write(frame)
 end
 
+--- Stop pinging the server
+local function stop_health_check()
+  if M.health_timer then
+    M.health_timer:stop()
+    M.health_timer:close()
+    M.health_timer = nil
+  end
+end
+
+--- Ping the server periodically, killing it if a ping goes unanswered for a whole interval
+--- The exit handler then starts a new server.
+local function start_health_check()
+  local interval = require("azuki.config").get("health_check_ms")
+  if not interval or interval <= 0 then
+    return
+  end
+
+  local awaiting = false
+  M.health_timer = vim.uv.new_timer()
+  M.health_timer:start(
+    interval,
+    interval,
+    vim.schedule_wrap(function()
+      if not M.is_running then
+        return
+      end
+      if awaiting then
+        vim.notify("[azuki] Server not responding, restarting", vim.log.levels.WARN)
+        stop_health_check()
+        M.handle:kill("sigkill")
+        return
+      end
+      awaiting = true
+      M.ping(function()
+        awaiting = false
+      end)
+    end)
+  )
+end
+
+--- Handle one decoded message from the server
+--- @param response table
+local function handle_message(response)
+  -- Handle init response specially to store session_id
+  if response.type == "init_result" and response.session_id then
+    M.session_id = response.session_id
+  end
+
+  -- Server-initiated: dictionaries loaded in the background are in use
+  if response.type == "dictionary_ready" then
+    M.dictionary_loading = false
+    vim.notify(
+      string.format("[azuki] Dictionaries loaded (%d entries, %dms)", response.entries, response.elapsed_ms),
+      vim.log.levels.DEBUG
+    )
+  end
+
+  -- Server events, sent because init asked for notifications
+  if response.type == "resource_warning" then
+    vim.schedule(function()
+      vim.notify("[azuki] " .. response.message, vim.log.levels.WARN)
+    end)
+  elseif response.type == "dictionary_reloaded" then
+    vim.notify(string.format("[azuki] Dictionaries reloaded (%d entries)", response.entries), vim.log.levels.DEBUG)
+  elseif response.type == "dictionary_set_ready" then
+    vim.schedule(function()
+      vim.notify(
+        string.format("[azuki] Dictionary set %s loaded (%d entries)", response.name, response.entries),
+        vim.log.levels.INFO
+      )
+    end)
+  end
+
+  -- Partial Zenzai output; the response for the same seq comes later
+  local seq = response.seq
+  if response.type == "convert_progress" then
+    local on_progress = M.progress_callbacks[seq]
+    if on_progress then
+      vim.schedule(function()
+        on_progress(response)
+      end)
+    end
+    return
+  end
+  if seq then
+    M.progress_callbacks[seq] = nil
+  end
+
+  -- Call registered callback
+  if seq and M.callbacks[seq] then
+    vim.schedule(function()
+      M.callbacks[seq](response)
+      M.callbacks[seq] = nil
+    end)
+  end
+end
+
+--- Collect a part of a response too large for one frame
+--- @param chunk table
+--- @return string|nil json The whole response once every part has arrived
+local function collect_chunk(chunk)
+  local parts = M.chunks[chunk.seq] or { received = 0 }
+  M.chunks[chunk.seq] = parts
+  parts[chunk.index + 1] = chunk.data
+  parts.received = parts.received + 1
+  if parts.received < chunk.count then
+    return nil
+  end
+  M.chunks[chunk.seq] = nil
+  return table.concat(parts)
+end
+
 --- Process received data from server
 --- @param data string
 local function process_data(data)
@@ -120,21 +237,18 @@ local function process_data(data)
 
     -- Parse and handle the response
     local ok, response = pcall(vim.fn.json_decode, json_str)
-    if ok and response then
-      -- Handle init response specially to store session_id
-      if response.type == "init_result" and response.session_id then
-        M.session_id = response.session_id
-      end
-
-      -- Call registered callback
-      local seq = response.seq
-      if seq and M.callbacks[seq] then
-        vim.schedule(function()
-          M.callbacks[seq](response)
-          M.callbacks[seq] = nil
-        end)
+    -- Parts of a large response are decoded once all have arrived
+    if ok and response and response.type == "chunk" then
+      json_str = collect_chunk(response)
+      if json_str then
+        ok, response = pcall(vim.fn.json_decode, json_str)
+      else
+        response = nil
       end
-    else
+    end
+    if ok and response then
+      handle_message(response)
+    elseif not ok then
       vim.notify("[azuki] Failed to parse server response: " .. json_str, vim.log.levels.WARN)
     end
   end
@@ -174,6 +288,10 @@ function M.start(opts, callback)
     stdio = { stdin, stdout, stderr },
   }, function(code, signal)
     vim.schedule(function()
+      stop_health_check()
+      -- A server that died on its own is started again when health checks are on
+      local health_check_ms = require("azuki.config").get("health_check_ms")
+      local restart = not M.stopping and health_check_ms and health_check_ms > 0
       M.is_running = false
       M.handle = nil
       M.stdin = nil
@@ -191,6 +309,10 @@ function M.start(opts, callback)
         M.stop_callback = nil
         cb()
       end
+
+      if restart then
+        M.start()
+      end
     end)
   end)
 
@@ -210,9 +332,13 @@ function M.start(opts, callback)
   M.stdout = stdout
   M.stderr = stderr
   M.is_running = true
+  M.stopping = false
   M.read_buffer = ""
+  M.chunks = {}
   M.seq = 0
   M.callbacks = {}
+  M.progress_callbacks = {}
+  M.dictionary_loading = true
 
   -- Read stdout
   stdout:read_start(function(err, data)
@@ -241,7 +367,7 @@ function M.start(opts, callback)
   end)
 
   -- Send init message with zenzai config if enabled
-  local init_msg = { type = "init" }
+  local init_msg = { type = "init", notifications = true }
 
   -- Include zenzai configuration if available
   local azuki_config = require("azuki.config")
@@ -255,13 +381,34 @@ function M.start(opts, callback)
     init_msg.zenzai = zenzai_config
   end
 
+  -- Pass through server-side settings that are set in the user config
+  for _, key in ipairs({ "sessions", "punctuation", "fallback", "providers", "conversion", "remote_dictionary", "romaji" }) do
+    local value = azuki_config.get(key)
+    if value ~= nil then
+      init_msg[key] = value
+    end
+  end
+  -- Settings for this editor's session only
+  init_msg.settings = azuki_config.get("session_settings")
+
   M.send(init_msg, function(response)
     if response.type == "init_result" then
+      -- dictionary_ready may already have arrived
+      M.dictionary_loading = M.dictionary_loading and response.dictionary_loading == true
       local info_parts = { "[azuki] Server initialized (v" .. response.version .. ")" }
       if response.zenzai_enabled then
         table.insert(info_parts, " with Zenzai")
       end
       vim.notify(table.concat(info_parts), vim.log.levels.INFO)
+      for _, dictionary in ipairs(response.dictionaries or {}) do
+        if dictionary.error then
+          vim.notify(
+            string.format("[azuki] Failed to load dictionary %s: %s", dictionary.path, dictionary.error),
+            vim.log.levels.WARN
+          )
+        end
+      end
+      start_health_check()
       if callback then
         callback(true)
       end
@@ -286,6 +433,7 @@ function M.stop(callback)
 
   -- Store callback to be invoked after exit cleanup
   M.stop_callback = callback
+  M.stopping = true
 
   M.send({ type = "shutdown" }, function()
     -- Callback will be invoked by exit handler after cleanup
@@ -294,7 +442,7 @@ end
 
 --- Send a convert request
 --- @param reading string Hiragana string to convert
---- @param opts table|nil Options (cursor, live, etc.)
+--- @param opts table|nil Options (cursor, live, engine, zenzai_profile, on_progress called with partial Zenzai output, etc.)
 --- @param callback function Called with response
 function M.convert(reading, opts, callback)
   if not M.session_id then
@@ -312,8 +460,16 @@ function M.convert(reading, opts, callback)
     cursor = opts.cursor,
     options = {
       live = opts.live or false,
+      engine = opts.engine,
+      zenzai_profile = opts.zenzai_profile,
+      stream = opts.on_progress ~= nil,
+      halfwidth_katakana = require("azuki.config").get("halfwidth_katakana") or false,
+      fullwidth_alphanumeric = require("azuki.config").get("fullwidth_alphanumeric") ~= false,
     },
   }, callback)
+  if opts.on_progress then
+    M.progress_callbacks[M.seq] = opts.on_progress
+  end
 end
 
 --- Send a commit request
@@ -336,6 +492,39 @@ function M.commit(reading, candidate, callback)
   }, callback)
 end
 
+--- Drop the loaded Zenzai model to free memory
+--- @param callback function|nil Called with response
+function M.unload_model(callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({ type = "unload_model" }, callback)
+end
+
+--- Ask for the Zenzai model, worker and cache state
+--- @param callback function Called with response
+function M.zenzai_status(callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({ type = "zenzai_status" }, callback)
+end
+
+--- Take back what the latest commit taught the learning data
+--- @param callback function|nil Called with response
+function M.undo_commit(callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({ type = "undo_commit" }, callback)
+end
+
 --- Send an adjust_segment request
 --- @param reading string Full hiragana reading
 --- @param segments table[] Current segment information
@@ -360,6 +549,273 @@ function M.adjust_segment(reading, segments, segment_index, direction, callback)
   }, callback)
 end
 
+--- Request fresh candidates for one segment
+--- @param reading string Full reading
+--- @param segments table[] Current segment information
+--- @param segment_index number Segment index (0-indexed for server)
+--- @param callback function Called with response
+function M.convert_segment(reading, segments, segment_index, callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    if callback then
+      callback({ type = "error", error = "Server not initialized" })
+    end
+    return
+  end
+
+  M.send({
+    type = "convert_segment",
+    reading = reading,
+    segments = segments,
+    segment_index = segment_index,
+  }, callback)
+end
+
+--- Convert several readings in one request
+--- @param readings string[] Hiragana readings
+--- @param contexts string[]|nil Text before each reading (nil for the session's context)
+--- @param callback function Called with response
+function M.convert_batch(readings, contexts, callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    if callback then
+      callback({ type = "error", error = "Server not initialized" })
+    end
+    return
+  end
+
+  M.send({
+    type = "convert_batch",
+    readings = readings,
+    contexts = contexts,
+  }, callback)
+end
+
+--- Register a word in the user dictionary
+--- @param reading string Hiragana reading
+--- @param candidate string Word to register
+--- @param callback function|nil Called with response
+function M.add_entry(reading, candidate, callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({
+    type = "add_entry",
+    reading = reading,
+    candidate = candidate,
+  }, callback)
+end
+
+--- Remove a word from the user dictionary
+--- @param reading string Hiragana reading
+--- @param candidate string Registered word
+--- @param callback function|nil Called with response
+function M.remove_entry(reading, candidate, callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({
+    type = "remove_entry",
+    reading = reading,
+    candidate = candidate,
+  }, callback)
+end
+
+--- Load the dictionaries and the user dictionary again
+--- @param callback function Called with response
+function M.reload_dictionaries(callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({ type = "reload_dictionaries" }, callback)
+end
+
+--- Ask which dictionaries are loaded, with entry counts and failures
+--- @param callback function Called with response
+function M.dict_info(callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({ type = "dict_info" }, callback)
+end
+
+--- Request readings starting with a partial reading, with their top candidates
+--- @param prefix string Partial hiragana reading
+--- @param callback function Called with response
+function M.complete(prefix, callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({
+    type = "complete",
+    prefix = prefix,
+  }, callback)
+end
+
+--- Convert romaji to hiragana with the server's table
+--- @param romaji string Romaji input
+--- @param callback function Called with response (kana and pending romaji)
+function M.to_kana(romaji, callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({
+    type = "to_kana",
+    romaji = romaji,
+  }, callback)
+end
+
+--- Cancel a request that hasn't been answered yet
+--- The cancelled request is still answered, with an error if it was stopped in time.
+--- @param target_seq number seq of the request to cancel
+function M.cancel(target_seq)
+  if not M.is_running then
+    return
+  end
+
+  M.send({ type = "cancel", target_seq = target_seq })
+end
+
+--- Check that the server is alive and see how busy it is
+--- The ping goes unanswered only when every worker of the server is stuck.
+--- @param callback function Called with response (uptime, dictionary status, queue depth)
+function M.ping(callback)
+  if not M.is_running then
+    return
+  end
+
+  M.send({ type = "ping" }, callback)
+end
+
+--- Send several requests in one round trip, handled in order
+--- @param requests table[] Requests without seq or session_id (e.g. { type = "commit", ... })
+--- @param callback function Called with the batch_result response
+function M.batch(requests, callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  -- Sub-requests share the batch's seq
+  local seq = M.seq + 1
+  for _, request in ipairs(requests) do
+    request.seq = seq
+    request.session_id = request.session_id or M.session_id
+  end
+
+  M.send({ type = "batch", requests = requests }, callback)
+end
+
+--- Change how this session's readings are converted
+--- @param mode string "hiragana", "katakana", "halfwidth_katakana" or "abbrev"
+--- @param callback function|nil Called with response
+function M.set_mode(mode, callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({ type = "set_mode", mode = mode }, callback)
+end
+
+--- Read this session's input mode, and change its context and chosen candidates
+--- @param changes table|nil Fields to change: context, select, zenzai_profile, dictionary_set
+--- @param callback function Called with response
+function M.session_state(changes, callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  local request = vim.tbl_extend("force", changes or {}, { type = "session_state" })
+  M.send(request, callback)
+end
+
+--- Request typing statistics for this session
+--- @param callback function Called with response
+function M.session_stats(callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({ type = "session_stats" }, callback)
+end
+
+--- Request the live sessions of the server
+--- @param callback function Called with response
+function M.list_sessions(callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({ type = "list_sessions" }, callback)
+end
+
+--- Request per-request-type latencies, cache hit rates and memory use
+--- @param callback function Called with response
+function M.get_stats(callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({ type = "get_stats" }, callback)
+end
+
+--- Request the settings in effect for this session
+--- @param callback function Called with response (global, session and effective settings)
+function M.get_config(callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({ type = "get_config" }, callback)
+end
+
+--- Change server settings without restarting it
+--- @param settings table Settings to change (candidate_limit, halfwidth_katakana, zenzai, learning)
+--- @param scope string|nil "session" (default) or "global"
+--- @param callback function|nil Called with response
+function M.set_config(settings, scope, callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({
+    type = "set_config",
+    scope = scope or "session",
+    settings = settings,
+  }, callback)
+end
+
+--- Change the server's log level, or report it when level is nil
+--- @param level string|nil "off", "error", "warn", "info" or "debug"
+--- @param callback function|nil Called with response (level and log file)
+function M.set_log_level(level, callback)
+  if not M.session_id then
+    vim.notify("[azuki] Server not initialized yet", vim.log.levels.WARN)
+    return
+  end
+
+  M.send({ type = "set_log_level", level = level }, callback)
+end
+
 --- Check if server is running
 --- @return boolean
 function M.is_active()
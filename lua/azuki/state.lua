@@ -3,12 +3,15 @@
 
 local M = {}
 
+local config = require("azuki.config")
+
 --- Default state values
 local defaults = {
   enabled = false,
   romaji_buffer = "",
   hiragana = "",
   candidates = {},
+  candidate_annotations = {},
   selected_index = 0,
   segments = {},
   current_segment = 1,
@@ -31,6 +34,7 @@ function M.reset_conversion()
   M.data.romaji_buffer = ""
   M.data.hiragana = ""
   M.data.candidates = {}
+  M.data.candidate_annotations = {}
   M.data.selected_index = 0
   M.data.segments = {}
   M.data.current_segment = 1
@@ -40,6 +44,7 @@ end
 --- Reset candidates and segments only (keep romaji and hiragana)
 function M.clear_candidates()
   M.data.candidates = {}
+  M.data.candidate_annotations = {}
   M.data.selected_index = 0
   M.data.segments = {}
   M.data.current_segment = 1
@@ -53,6 +58,66 @@ function M.set_preedit_anchor(row, col)
   M.data.preedit_start_col = col
 end
 
+--- Candidate kinds ("emoji", "symbol") hidden in the current buffer
+--- @return table<string, boolean>
+local function hidden_kinds()
+  local kinds = config.get("hidden_candidate_kinds") or {}
+  local bufnr = M.data.bufnr
+  if bufnr and vim.api.nvim_buf_is_valid(bufnr) and vim.b[bufnr].azuki_hidden_candidate_kinds ~= nil then
+    kinds = vim.b[bufnr].azuki_hidden_candidate_kinds
+  end
+  local hidden = {}
+  for _, kind in ipairs(kinds) do
+    hidden[kind] = true
+  end
+  return hidden
+end
+
+--- Store whole-phrase candidates from a server response
+--- @param candidates table[]|nil Candidate objects ({ text, annotation, source, kind, score })
+function M.set_candidates(candidates)
+  local hidden = hidden_kinds()
+  M.data.candidates = {}
+  M.data.candidate_annotations = {}
+  for _, candidate in ipairs(candidates or {}) do
+    if not (candidate.kind and hidden[candidate.kind]) then
+      table.insert(M.data.candidates, candidate.text)
+      M.data.candidate_annotations[#M.data.candidates] = candidate.annotation
+    end
+  end
+  M.data.selected_index = #M.data.candidates > 0 and 1 or 0
+end
+
+--- Store segments from a server response, selecting their first candidates
+--- @param segments table[] Segments ({ reading, start, length, candidates, annotations, kinds })
+function M.set_segments(segments)
+  local hidden = hidden_kinds()
+  for _, seg in ipairs(segments) do
+    if seg.kinds and next(hidden) then
+      local shown = vim.tbl_filter(function(text)
+        return not hidden[seg.kinds[text]]
+      end, seg.candidates)
+      seg.candidates = #shown > 0 and shown or { seg.reading }
+    end
+    seg.selected_index = 1
+  end
+  M.data.segments = segments
+end
+
+--- Get the annotation of the current selection, if any
+--- @return string|nil
+function M.get_selected_annotation()
+  if #M.data.segments > 0 then
+    local seg = M.data.segments[M.data.current_segment]
+    if not seg or not seg.annotations then
+      return nil
+    end
+    local text = seg.candidates[seg.selected_index or 1]
+    return text and seg.annotations[text]
+  end
+  return M.data.candidate_annotations[M.data.selected_index]
+end
+
 --- Get combined text from segments
 --- @return string Combined text from all segments
 function M.get_segments_text()
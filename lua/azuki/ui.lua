@@ -55,13 +55,23 @@ function M.show_preedit(bufnr, row, col, text)
   })
 end
 
+--- Append a candidate annotation to virtual text chunks
+--- @param virt_text table[] Virtual text chunks
+--- @param annotation string|nil Annotation from the server
+local function append_annotation(virt_text, annotation)
+  if annotation and annotation ~= "" then
+    table.insert(virt_text, { " [" .. annotation .. "]", config.get("highlight").annotation })
+  end
+end
+
 --- Show conversion candidate (replaces preedit)
 --- @param bufnr number Buffer number
 --- @param row number Row number (0-indexed)
 --- @param col number Column number (0-indexed)
 --- @param candidate string The candidate text
 --- @param is_selected boolean Whether this candidate is selected
-function M.show_candidate(bufnr, row, col, candidate, is_selected)
+--- @param annotation string|nil Annotation shown after the candidate
+function M.show_candidate(bufnr, row, col, candidate, is_selected, annotation)
   M.clear(bufnr)
 
   if candidate == "" then
@@ -74,8 +84,11 @@ function M.show_candidate(bufnr, row, col, candidate, is_selected)
   -- Clamp col to valid range
   col = clamp_col(bufnr, row, col)
 
+  local virt_text = { { candidate, hl_group } }
+  append_annotation(virt_text, annotation)
+
   M.current_mark_id = vim.api.nvim_buf_set_extmark(bufnr, M.ns_id, row, col, {
-    virt_text = { { candidate, hl_group } },
+    virt_text = virt_text,
     virt_text_pos = "inline",
     right_gravity = true,
   })
@@ -88,7 +101,8 @@ end
 --- @param segments table[] Array of segment objects from server
 --- @param current_segment number Current segment index (1-indexed)
 --- @param pending_romaji string Pending romaji input
-function M.show_segments(bufnr, row, col, segments, current_segment, pending_romaji)
+--- @param annotation string|nil Annotation of the current segment's candidate
+function M.show_segments(bufnr, row, col, segments, current_segment, pending_romaji, annotation)
   M.clear(bufnr)
 
   if #segments == 0 then
@@ -118,6 +132,7 @@ function M.show_segments(bufnr, row, col, segments, current_segment, pending_rom
   if pending_romaji and pending_romaji ~= "" then
     table.insert(virt_text, { pending_romaji, hl.pending })
   end
+  append_annotation(virt_text, annotation)
 
   -- Clamp col to valid range
   col = clamp_col(bufnr, row, col)
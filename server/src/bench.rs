@@ -0,0 +1,175 @@
+//! Zenzai benchmark (`azuki-server bench-zenzai`)
+//!
+//! Loads the model of each configured profile, converts a fixed set of
+//! readings and reports throughput, latency percentiles and memory use, to
+//! compare quantizations and GPU settings.
+
+use crate::config::ServerConfig;
+use crate::error::Result;
+#[cfg(feature = "zenzai")]
+use crate::metrics::{millis, percentile};
+#[cfg(feature = "zenzai")]
+use std::time::{Duration, Instant};
+
+/// Readings converted by the benchmark, from single words to long phrases
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+const READINGS: &[&str] = &[
+    "きょう",
+    "へんかん",
+    "にほんご",
+    "かんじ",
+    "きしゃのきしゃがきしゃできしゃした",
+    "わたしはがくせいです",
+    "あしたはあめがふるでしょう",
+    "かいぎのしりょうをおくります",
+    "このしょうひんはにんきがあります",
+    "でんしゃがおくれています",
+    "よろしくおねがいします",
+    "きのうともだちとえいがをみにいきました",
+    "しぜんげんごしょりのけんきゅう",
+    "せいせいもでるのせいのうひょうか",
+    "いんたーねっとにせつぞくできません",
+    "こんしゅうまつはやまにのぼるよていです",
+    "このもんだいはかんたんにはかいけつできない",
+    "かれはむかしからえがうまかった",
+    "しんかんせんのしていせきをよやくした",
+    "ぷろぐらむのばぐをしゅうせいする",
+];
+
+/// Resident memory of this process, in KiB
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+struct Memory {
+    /// Current resident set size
+    rss_kib: u64,
+    /// Peak resident set size
+    peak_kib: u64,
+}
+
+impl Memory {
+    /// Read from `/proc/self/status`, None where it isn't available
+    #[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+    fn current() -> Option<Self> {
+        Self::parse(&std::fs::read_to_string("/proc/self/status").ok()?)
+    }
+
+    #[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+    fn parse(status: &str) -> Option<Self> {
+        let field = |name: &str| {
+            status.lines().find_map(|line| {
+                let value = line.strip_prefix(name)?.strip_prefix(':')?;
+                value.trim().strip_suffix("kB")?.trim().parse().ok()
+            })
+        };
+        Some(Memory {
+            rss_kib: field("VmRSS")?,
+            peak_kib: field("VmHWM")?,
+        })
+    }
+}
+
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+fn mib(kib: u64) -> f64 {
+    kib as f64 / 1024.0
+}
+
+/// Benchmark every Zenzai profile of the configuration
+#[cfg(feature = "zenzai")]
+pub fn run(config: ServerConfig) -> Result<()> {
+    use crate::cancel::CancelToken;
+    use crate::zenzai::{ZenzaiBackend, DEFAULT_PROFILE};
+
+    let zenzai = config.zenzai.unwrap_or_default();
+    let mut profiles = vec![DEFAULT_PROFILE.to_string()];
+    profiles.extend(zenzai.profiles.keys().cloned());
+    let mut backend = ZenzaiBackend::new(zenzai);
+    let cancel = CancelToken::default();
+
+    for profile in profiles {
+        let before = Memory::current();
+        let started = Instant::now();
+        backend.load(&profile)?;
+        let load_time = started.elapsed();
+        let loaded = Memory::current();
+        println!(
+            "profile {}: loaded in {:.0}ms{}",
+            profile,
+            millis(load_time),
+            match (before, loaded) {
+                (Some(before), Some(loaded)) => format!(
+                    ", rss +{:.1} MiB",
+                    mib(loaded.rss_kib.saturating_sub(before.rss_kib))
+                ),
+                _ => String::new(),
+            }
+        );
+
+        let mut latencies: Vec<Duration> = Vec::with_capacity(READINGS.len());
+        let mut tokens = 0;
+        for reading in READINGS {
+            let started = Instant::now();
+            let outputs =
+                backend.convert("bench", Some(&profile), reading, None, &cancel, &mut |_| {})?;
+            latencies.push(started.elapsed());
+            tokens += backend.last_output_tokens();
+            log_debug!(
+                "[bench] {} -> {:?}",
+                reading,
+                outputs.first().map(|c| &c.text)
+            );
+        }
+        let total: Duration = latencies.iter().sum();
+        latencies.sort_unstable();
+
+        println!(
+            "  {} conversions, {:.1} tokens/s",
+            latencies.len(),
+            tokens as f64 / total.as_secs_f64()
+        );
+        println!(
+            "  latency p50 {:.0}ms, p90 {:.0}ms, p99 {:.0}ms, max {:.0}ms",
+            percentile(&latencies, 50),
+            percentile(&latencies, 90),
+            percentile(&latencies, 99),
+            latencies.last().map_or(0.0, |d| millis(*d))
+        );
+        if let Some(memory) = Memory::current() {
+            println!(
+                "  rss {:.1} MiB, peak {:.1} MiB",
+                mib(memory.rss_kib),
+                mib(memory.peak_kib)
+            );
+        }
+        // Free the model before loading the next one
+        backend.unload();
+    }
+    Ok(())
+}
+
+/// Benchmark every Zenzai profile of the configuration
+#[cfg(not(feature = "zenzai"))]
+pub fn run(config: ServerConfig) -> Result<()> {
+    let _ = config;
+    Err(crate::error::AzukiError::Config(
+        "azuki-server was built without the zenzai feature".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_memory() {
+        let status = "Name:\tazuki-server\nVmHWM:\t  204800 kB\nVmRSS:\t  102400 kB\n";
+        assert_eq!(
+            Memory::parse(status),
+            Some(Memory {
+                rss_kib: 102400,
+                peak_kib: 204800,
+            })
+        );
+        assert_eq!(mib(102400), 100.0);
+        assert!(Memory::parse("Name:\tazuki-server\n").is_none());
+    }
+}
@@ -0,0 +1,209 @@
+//! Candidate blocklist ("never show me this homophone")
+//!
+//! Stored in a human-editable file using the SKK entry syntax, one reading
+//! per line: `reading /candidate1/candidate2/`. Lines starting with `;` are
+//! comments. The same text is used for export and import, so the file can
+//! be synced across machines alongside the user dictionary.
+
+use crate::error::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Header written at the top of the blocklist file
+const HEADER: &str = ";; azuki candidate blocklist\n;; reading /candidate1/candidate2/\n";
+
+/// Set of (reading, candidate) pairs that must never be offered
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    entries: BTreeMap<String, BTreeSet<String>>,
+    /// File the blocklist is persisted to, if any
+    path: Option<PathBuf>,
+}
+
+impl Blocklist {
+    /// Create an empty in-memory blocklist
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the blocklist from `path`, persisting later changes there
+    ///
+    /// A missing file yields an empty blocklist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut blocklist = Self {
+            entries: BTreeMap::new(),
+            path: Some(path.to_path_buf()),
+        };
+        if path.exists() {
+            let content = fs::read_to_string(path)?;
+            blocklist.merge_text(&content);
+        }
+        Ok(blocklist)
+    }
+
+    /// Block a candidate for a reading. Returns false if already blocked.
+    pub fn block(&mut self, reading: &str, candidate: &str) -> bool {
+        self.entries
+            .entry(reading.to_string())
+            .or_default()
+            .insert(candidate.to_string())
+    }
+
+    /// Unblock a candidate for a reading. Returns false if it wasn't blocked.
+    pub fn unblock(&mut self, reading: &str, candidate: &str) -> bool {
+        let Some(candidates) = self.entries.get_mut(reading) else {
+            return false;
+        };
+        let removed = candidates.remove(candidate);
+        if candidates.is_empty() {
+            self.entries.remove(reading);
+        }
+        removed
+    }
+
+    /// Check whether a candidate is blocked for a reading
+    pub fn is_blocked(&self, reading: &str, candidate: &str) -> bool {
+        self.entries
+            .get(reading)
+            .is_some_and(|candidates| candidates.contains(candidate))
+    }
+
+    /// Remove blocked candidates for a reading
+    ///
+    /// The reading itself is never removed, so the list can't become empty.
+    pub fn filter(&self, reading: &str, candidates: &mut Vec<String>) {
+        if self.entries.is_empty() {
+            return;
+        }
+        candidates.retain(|c| c == reading || !self.is_blocked(reading, c));
+        if candidates.is_empty() {
+            candidates.push(reading.to_string());
+        }
+    }
+
+    /// Total number of blocked (reading, candidate) pairs
+    pub fn len(&self) -> usize {
+        self.entries.values().map(BTreeSet::len).sum()
+    }
+
+    /// Check whether nothing is blocked
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Serialize to the file format
+    pub fn to_text(&self) -> String {
+        let mut text = String::from(HEADER);
+        for (reading, candidates) in &self.entries {
+            text.push_str(reading);
+            text.push_str(" /");
+            for candidate in candidates {
+                text.push_str(candidate);
+                text.push('/');
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Add all entries from text in the file format
+    ///
+    /// Returns the number of newly blocked pairs.
+    pub fn merge_text(&mut self, text: &str) -> usize {
+        let mut added = 0;
+        for line in text.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let Some((reading, rest)) = line.split_once(' ') else {
+                continue;
+            };
+            for candidate in rest.split('/').map(str::trim).filter(|c| !c.is_empty()) {
+                if self.block(reading, candidate) {
+                    added += 1;
+                }
+            }
+        }
+        added
+    }
+
+    /// Drop all entries
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Write the blocklist to its file, if it has one
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.to_text())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_and_filter() {
+        let mut blocklist = Blocklist::new();
+        assert!(blocklist.block("きょう", "京"));
+        assert!(!blocklist.block("きょう", "京"));
+
+        let mut candidates = vec!["今日".to_string(), "京".to_string(), "きょう".to_string()];
+        blocklist.filter("きょう", &mut candidates);
+        assert_eq!(candidates, vec!["今日", "きょう"]);
+
+        assert!(blocklist.unblock("きょう", "京"));
+        assert!(!blocklist.unblock("きょう", "京"));
+        assert!(blocklist.is_empty());
+    }
+
+    #[test]
+    fn test_filter_keeps_reading() {
+        let mut blocklist = Blocklist::new();
+        blocklist.block("あずき", "小豆");
+        blocklist.block("あずき", "あずき");
+
+        let mut candidates = vec!["小豆".to_string()];
+        blocklist.filter("あずき", &mut candidates);
+        assert_eq!(candidates, vec!["あずき"]);
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut blocklist = Blocklist::new();
+        blocklist.block("きょう", "京");
+        blocklist.block("きょう", "教");
+        blocklist.block("かんじ", "幹事");
+
+        let text = blocklist.to_text();
+        let mut imported = Blocklist::new();
+        assert_eq!(imported.merge_text(&text), 3);
+        assert!(imported.is_blocked("きょう", "教"));
+        assert!(imported.is_blocked("かんじ", "幹事"));
+        assert_eq!(imported.to_text(), text);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("azuki-blocklist-{}", std::process::id()));
+        let mut blocklist = Blocklist::load(&path).unwrap();
+        assert!(blocklist.is_empty());
+        blocklist.block("きょう", "京");
+        blocklist.save().unwrap();
+
+        let loaded = Blocklist::load(&path).unwrap();
+        assert!(loaded.is_blocked("きょう", "京"));
+        assert_eq!(loaded.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
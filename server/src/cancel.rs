@@ -0,0 +1,48 @@
+//! Cancellation of in-flight requests
+//!
+//! The connection's reader thread cancels a request's token when a
+//! `cancel` request names it; long-running work checks the token between
+//! steps and gives up with [`AzukiError::Cancelled`].
+
+use crate::error::{AzukiError, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Shared flag set when a request is cancelled
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Ask the work holding this token to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the token was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fail with `Cancelled` once the token was cancelled
+    pub fn check(&self) -> Result<()> {
+        match self.is_cancelled() {
+            true => Err(AzukiError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_is_shared_between_clones() {
+        let token = CancelToken::default();
+        let clone = token.clone();
+        assert!(token.check().is_ok());
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(token.check().unwrap_err().code(), "cancelled");
+    }
+}
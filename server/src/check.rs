@@ -0,0 +1,410 @@
+//! Dictionary linter (`azuki-server check-dict`)
+//!
+//! The server skips lines it can't parse and lets later entries replace
+//! earlier ones without a word, so a word missing from conversion is hard
+//! to trace back to its dictionary line. This reads a dictionary the way
+//! the server does and reports every line that won't load as written.
+
+use crate::config::source_files;
+use crate::dictionary::{
+    decode_content, is_indexed, is_okuri_ari_key, parse_annotated_entry, parse_okuri_ari_entry,
+    read_file,
+};
+use crate::error::{AzukiError, DictionaryError, Result};
+use crate::mapped_dict::MappedDictionary;
+use crate::{json_dict, mozc_dict};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// How much a problem matters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The line is not loaded
+    Error,
+    /// The line is loaded, but likely not as intended
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+/// Problem found in a dictionary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Line number from 1, None for the whole file
+    pub line: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Problem {
+    fn error(line: usize, message: String) -> Self {
+        Self {
+            line: Some(line),
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(line: usize, message: String) -> Self {
+        Self {
+            line: Some(line),
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+/// Outcome of checking one dictionary file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// `skk`, `json`, `mozc` or `indexed`
+    pub format: &'static str,
+    /// Detected encoding
+    pub encoding: &'static str,
+    /// Entries that load
+    pub entries: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    fn count(&self, severity: Severity) -> usize {
+        self.problems
+            .iter()
+            .filter(|p| p.severity == severity)
+            .count()
+    }
+}
+
+/// Check dictionary files, printing their problems and a summary of each
+///
+/// Directories stand for the files in them, as in the configuration.
+/// Returns the number of errors.
+pub fn run(paths: &[PathBuf]) -> Result<usize> {
+    let mut errors = 0;
+    for input in paths {
+        let files = source_files(input).map_err(|source| DictionaryError::Io {
+            path: input.clone(),
+            source,
+        })?;
+        for path in files {
+            let report = check(&path)?;
+            for problem in &report.problems {
+                match problem.line {
+                    Some(line) => println!(
+                        "{}:{}: {}: {}",
+                        path.display(),
+                        line,
+                        problem.severity,
+                        problem.message
+                    ),
+                    None => println!(
+                        "{}: {}: {}",
+                        path.display(),
+                        problem.severity,
+                        problem.message
+                    ),
+                }
+            }
+            println!(
+                "{}: {} entries ({}, {}), {} errors, {} warnings",
+                path.display(),
+                report.entries,
+                report.format,
+                report.encoding,
+                report.count(Severity::Error),
+                report.count(Severity::Warning)
+            );
+            errors += report.count(Severity::Error);
+        }
+    }
+    Ok(errors)
+}
+
+/// Check one dictionary file
+///
+/// Fails only if the file can't be read; everything wrong with its
+/// content is a problem in the report.
+pub fn check(path: &Path) -> Result<Report> {
+    if is_indexed(path) {
+        let (entries, problems) = match MappedDictionary::open(path) {
+            Ok(mapped) => (
+                mapped.len() + mapped.okuri_ari_entries().count(),
+                Vec::new(),
+            ),
+            Err(e) => (
+                0,
+                vec![Problem {
+                    line: None,
+                    severity: Severity::Error,
+                    message: e.to_string(),
+                }],
+            ),
+        };
+        return Ok(Report {
+            format: "indexed",
+            encoding: "UTF-8",
+            entries,
+            problems,
+        });
+    }
+    let bytes = read_file(path).map_err(|source| DictionaryError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let (content, encoding) = decode_content(&bytes);
+
+    let mut problems = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.contains('\u{FFFD}') {
+            problems.push(Problem::warning(
+                i + 1,
+                format!(
+                    "bytes invalid in {} were replaced with U+FFFD; pin the encoding if it was misdetected",
+                    encoding
+                ),
+            ));
+        }
+    }
+    let (format, entries) = if let Some(format) = json_dict::detect(path, &content) {
+        match json_dict::parse(path, format, &content) {
+            Ok(entries) => ("json", entries.len()),
+            Err(AzukiError::Dictionary(DictionaryError::Parse { line, message, .. })) => {
+                problems.push(Problem::error(line, message));
+                ("json", 0)
+            }
+            Err(e) => return Err(e),
+        }
+    } else if mozc_dict::detect(path, &content) {
+        ("mozc", check_mozc(&content, &mut problems))
+    } else {
+        ("skk", check_skk(&content, &mut problems))
+    };
+    problems.sort_by_key(|p| p.line);
+    Ok(Report {
+        format,
+        encoding,
+        entries,
+        problems,
+    })
+}
+
+/// Check the lines of a Mozc dictionary, returning how many load
+fn check_mozc(content: &str, problems: &mut Vec<Problem>) -> usize {
+    let mut entries = 0;
+    for (i, line) in content.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match mozc_dict::parse_line(line) {
+            Some(_) => entries += 1,
+            None => problems.push(Problem::error(
+                i + 1,
+                "expected reading, left id, right id, cost and surface separated by tabs"
+                    .to_string(),
+            )),
+        }
+    }
+    entries
+}
+
+/// Check the lines of an SKK dictionary, returning how many load
+fn check_skk(content: &str, problems: &mut Vec<Problem>) -> usize {
+    let mut in_okuri_nasi = false;
+    let mut marked = false;
+    // First line of each reading, per section
+    let mut seen: [HashMap<&str, usize>; 2] = Default::default();
+    let mut entries = 0;
+
+    for (i, line) in content.lines().enumerate() {
+        let number = i + 1;
+        if line.starts_with(";; okuri-ari") {
+            (in_okuri_nasi, marked) = (false, true);
+            continue;
+        }
+        if line.starts_with(";; okuri-nasi") {
+            (in_okuri_nasi, marked) = (true, true);
+            continue;
+        }
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let Some((reading, rest)) = line.split_once(' ') else {
+            problems.push(Problem::error(
+                number,
+                "no space between the reading and its candidates".to_string(),
+            ));
+            continue;
+        };
+        if reading.is_empty() {
+            problems.push(Problem::error(number, "empty reading".to_string()));
+            continue;
+        }
+        let candidates = if in_okuri_nasi {
+            parse_annotated_entry(line).map(|(_, candidates)| candidates)
+        } else {
+            parse_okuri_ari_entry(line).map(|(_, candidates, _)| candidates)
+        };
+        let Some(candidates) = candidates else {
+            problems.push(Problem::error(
+                number,
+                format!("no candidates for {}", reading),
+            ));
+            continue;
+        };
+        entries += 1;
+
+        if !rest.starts_with('/') || !rest.trim_end().ends_with('/') {
+            problems.push(Problem::warning(
+                number,
+                "candidates should be enclosed in slashes (/候補/)".to_string(),
+            ));
+        }
+        let mut texts: Vec<&str> = Vec::new();
+        for (text, _) in &candidates {
+            if texts.contains(&text.as_str()) {
+                problems.push(Problem::warning(
+                    number,
+                    format!("candidate {} is listed twice", text),
+                ));
+            } else {
+                texts.push(text);
+            }
+        }
+        if let Some(first) = seen[in_okuri_nasi as usize].insert(reading, number) {
+            problems.push(Problem::warning(
+                number,
+                format!(
+                    "{} repeats the entry on line {}, which this one replaces",
+                    reading, first
+                ),
+            ));
+        }
+        if !in_okuri_nasi && !is_okuri_ari_key(reading) {
+            let hint = match marked {
+                true => "",
+                false => "; okuri-nasi entries must follow a \";; okuri-nasi entries.\" line",
+            };
+            problems.push(Problem::warning(
+                number,
+                format!(
+                    "okuri-ari reading {} does not end in an okuri consonant like かk{}",
+                    reading, hint
+                ),
+            ));
+        } else if in_okuri_nasi && is_okuri_ari_key(reading) {
+            problems.push(Problem::warning(
+                number,
+                format!(
+                    "{} ends in an okuri consonant but is in the okuri-nasi section",
+                    reading
+                ),
+            ));
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn problems(content: &str) -> Vec<(usize, Severity)> {
+        let mut problems = Vec::new();
+        check_skk(content, &mut problems);
+        problems
+            .into_iter()
+            .map(|p| (p.line.unwrap(), p.severity))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_dictionary_has_no_problems() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/test-dict.utf8");
+        let report = check(&path).unwrap();
+        assert_eq!(report.problems, vec![]);
+        assert_eq!((report.format, report.encoding), ("skk", "UTF-8"));
+        assert_eq!(report.entries, 15);
+    }
+
+    #[test]
+    fn test_reports_malformed_lines() {
+        let content = "\
+;; okuri-nasi entries.
+きょう/今日/
+ /空/
+へんかん //
+かんじ 漢字/
+";
+        assert_eq!(
+            problems(content),
+            vec![
+                (2, Severity::Error),
+                (3, Severity::Error),
+                (4, Severity::Error),
+                (5, Severity::Warning),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_duplicates() {
+        let content = ";; okuri-nasi entries.\nきょう /今日/京/今日/\nきょう /教/\n";
+        let mut found = Vec::new();
+        check_skk(content, &mut found);
+        let messages: Vec<_> = found.iter().map(|p| p.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "candidate 今日 is listed twice",
+                "きょう repeats the entry on line 2, which this one replaces"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_misplaced_okuri_sections() {
+        // Without section markers every entry is read as okuri-ari
+        let unmarked = "きょう /今日/\nかk /書/\n";
+        let mut found = Vec::new();
+        check_skk(unmarked, &mut found);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.contains(";; okuri-nasi entries."));
+
+        let content = ";; okuri-ari entries.\nかk /書/\n;; okuri-nasi entries.\nいt /行/\n";
+        assert_eq!(problems(content), vec![(4, Severity::Warning)]);
+    }
+
+    #[test]
+    fn test_reports_json_and_encoding_problems() {
+        let dir = std::env::temp_dir().join(format!("azuki-check-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let json = dir.join("words.jsonl");
+        fs::write(
+            &json,
+            "{\"reading\": \"きょう\", \"candidates\": [\"今日\"]}\n{\"reading\"}\n",
+        )
+        .unwrap();
+        let report = check(&json).unwrap();
+        assert_eq!(report.format, "json");
+        assert_eq!(report.problems[0].line, Some(2));
+        assert_eq!(report.problems[0].severity, Severity::Error);
+
+        // Neither UTF-8, EUC-JP nor Shift_JIS
+        let broken = dir.join("broken");
+        fs::write(&broken, b";; okuri-nasi entries.\n\xa4\xad /\xff\xfe/\n").unwrap();
+        let report = check(&broken).unwrap();
+        assert_eq!(report.problems[0].line, Some(2));
+        assert!(report.problems[0].message.contains("U+FFFD"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
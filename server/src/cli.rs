@@ -0,0 +1,326 @@
+//! Command line parsing for the server binary
+
+use crate::log::LogLevel;
+use clap::{Parser, Subcommand};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Address to accept clients on instead of stdio
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listen {
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// `HOST:PORT`
+    Tcp(String),
+}
+
+impl Listen {
+    /// Parse `tcp:HOST:PORT` or `unix:PATH`
+    pub fn parse(address: &str) -> Result<Self, String> {
+        match address.split_once(':') {
+            Some(("tcp", addr)) if addr.contains(':') => Ok(Listen::Tcp(addr.to_string())),
+            #[cfg(unix)]
+            Some(("unix", path)) if !path.is_empty() => Ok(Listen::Unix(path.into())),
+            _ => Err(format!(
+                "invalid listen address: {} (expected tcp:HOST:PORT or unix:PATH)",
+                address
+            )),
+        }
+    }
+
+    /// Format accepted by [`Listen::parse`]
+    pub fn to_arg(&self) -> String {
+        match self {
+            #[cfg(unix)]
+            Listen::Unix(path) => format!("unix:{}", path.display()),
+            Listen::Tcp(addr) => format!("tcp:{}", addr),
+        }
+    }
+}
+
+/// Subcommand the invocation asks for; without one the server runs
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    /// Manage the background daemon
+    #[cfg(unix)]
+    Daemon {
+        #[arg(value_enum)]
+        action: DaemonAction,
+    },
+    /// Measure the speed and memory use of the configured Zenzai models
+    BenchZenzai,
+    /// Merge dictionaries, first with priority, into one file in the
+    /// indexed format
+    CompileDict {
+        #[arg(value_name = "DICTIONARY", required = true)]
+        inputs: Vec<PathBuf>,
+        /// Indexed dictionary to write
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// Report problems in dictionary files
+    CheckDict {
+        #[arg(value_name = "DICTIONARY", required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Run Zenzai inference for a server over stdio (started by the server)
+    #[command(hide = true)]
+    ZenzaiWorker,
+}
+
+/// `daemon` subcommands
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DaemonAction {
+    Start,
+    Stop,
+    Status,
+}
+
+/// Parsed command line
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+#[command(name = "azuki-server", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// SKK dictionary to load (repeatable, first has priority)
+    #[arg(long = "dictionary", value_name = "PATH")]
+    pub dictionaries: Vec<PathBuf>,
+    /// JSON configuration file
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// off, error, warn, info (default) or debug
+    #[arg(long, value_name = "LEVEL", value_parser = parse_log_level)]
+    pub log_level: Option<LogLevel>,
+    /// Serve on a Unix socket instead of stdio
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH", conflicts_with = "listen")]
+    socket: Option<PathBuf>,
+    /// Serve on tcp:HOST:PORT or unix:PATH instead of stdio
+    #[arg(long, value_name = "ADDRESS", value_parser = Listen::parse)]
+    pub listen: Option<Listen>,
+    /// Answer skkserv dictionary requests on HOST:PORT (default port 1178)
+    #[arg(long, value_name = "HOST:PORT")]
+    pub skkserv: Option<String>,
+    /// Frame messages as JSON lines instead of length prefixes
+    #[arg(long)]
+    pub ndjson: bool,
+    /// Speak JSON-RPC 2.0, one message per line
+    #[arg(long)]
+    pub jsonrpc: bool,
+    /// Largest message (default 4 MiB); larger responses are sent in chunks
+    #[arg(long, value_name = "BYTES")]
+    pub max_message_size: Option<u32>,
+    /// Start the background daemon (same as `daemon start`)
+    #[cfg(unix)]
+    #[arg(long)]
+    daemon: bool,
+}
+
+impl Cli {
+    /// Parse arguments, including the program name
+    ///
+    /// `--socket PATH` is folded into `listen`, and `--daemon` into the
+    /// command.
+    pub fn parse_args<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let cli = Self::try_parse_from(args)?;
+        #[cfg(unix)]
+        let cli = cli.fold_unix_options()?;
+        Ok(cli)
+    }
+
+    #[cfg(unix)]
+    fn fold_unix_options(mut self) -> Result<Self, clap::Error> {
+        if let Some(path) = self.socket.take() {
+            self.listen = Some(Listen::Unix(path));
+        }
+        if self.daemon {
+            if self.command.is_some() {
+                return Err(clap::Error::raw(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "--daemon cannot be used with a subcommand\n",
+                )
+                .with_cmd(&<Self as clap::CommandFactory>::command()));
+            }
+            self.command = Some(Command::Daemon {
+                action: DaemonAction::Start,
+            });
+        }
+        Ok(self)
+    }
+
+    /// Options to pass on to a server started on the user's behalf
+    #[cfg(unix)]
+    pub fn server_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for dictionary in &self.dictionaries {
+            args.push("--dictionary".to_string());
+            args.push(dictionary.display().to_string());
+        }
+        if let Some(config) = &self.config {
+            args.push("--config".to_string());
+            args.push(config.display().to_string());
+        }
+        if let Some(level) = self.log_level {
+            args.push("--log-level".to_string());
+            args.push(level.name().to_string());
+        }
+        if let Some(size) = self.max_message_size {
+            args.push("--max-message-size".to_string());
+            args.push(size.to_string());
+        }
+        args
+    }
+}
+
+fn parse_log_level(name: &str) -> Result<LogLevel, String> {
+    LogLevel::parse(name).ok_or_else(|| format!("unknown log level: {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::parse_args(std::iter::once(&"azuki-server").chain(args))
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_parse_options() {
+        let cli = parse(&[
+            "--dictionary",
+            "a.dic",
+            "--dictionary=b.dic",
+            "--log-level",
+            "debug",
+            "--socket",
+            "/tmp/azuki.sock",
+        ])
+        .unwrap();
+        assert_eq!(cli.command, None);
+        assert_eq!(
+            cli.dictionaries,
+            vec![PathBuf::from("a.dic"), PathBuf::from("b.dic")]
+        );
+        assert_eq!(cli.log_level, Some(LogLevel::Debug));
+        assert_eq!(
+            cli.listen,
+            Some(Listen::Unix(PathBuf::from("/tmp/azuki.sock")))
+        );
+        assert_eq!(
+            cli.server_args(),
+            vec![
+                "--dictionary",
+                "a.dic",
+                "--dictionary",
+                "b.dic",
+                "--log-level",
+                "debug"
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_parse_listen() {
+        let cli = parse(&["--listen", "tcp:127.0.0.1:7878"]).unwrap();
+        assert_eq!(cli.listen, Some(Listen::Tcp("127.0.0.1:7878".to_string())));
+        let cli = parse(&["--listen=unix:/tmp/azuki.sock"]).unwrap();
+        assert_eq!(
+            cli.listen,
+            Some(Listen::Unix(PathBuf::from("/tmp/azuki.sock")))
+        );
+        assert_eq!(cli.listen.unwrap().to_arg(), "unix:/tmp/azuki.sock");
+        assert!(parse(&["--listen", "tcp:7878"]).is_err());
+        assert!(parse(&["--listen", "udp:127.0.0.1:7878"]).is_err());
+
+        let cli = parse(&["--skkserv", "127.0.0.1:1178"]).unwrap();
+        assert_eq!(cli.skkserv.as_deref(), Some("127.0.0.1:1178"));
+        assert!(!cli.ndjson);
+        assert!(parse(&["--ndjson"]).unwrap().ndjson);
+        assert!(parse(&["--jsonrpc"]).unwrap().jsonrpc);
+        let cli = parse(&["--max-message-size=65536"]).unwrap();
+        assert_eq!(cli.max_message_size, Some(65536));
+        assert_eq!(cli.server_args(), ["--max-message-size", "65536"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_parse_commands() {
+        assert_eq!(parse(&[]).unwrap().command, None);
+        let daemon = |action| Some(Command::Daemon { action });
+        let cli = parse(&["--config", "azuki.json", "daemon", "start"]).unwrap();
+        assert_eq!(cli.command, daemon(DaemonAction::Start));
+        assert_eq!(cli.config, Some(PathBuf::from("azuki.json")));
+        assert_eq!(
+            parse(&["daemon", "status"]).unwrap().command,
+            daemon(DaemonAction::Status)
+        );
+        assert_eq!(
+            parse(&["--daemon"]).unwrap().command,
+            daemon(DaemonAction::Start)
+        );
+        let cli = parse(&["--config", "azuki.json", "bench-zenzai"]).unwrap();
+        assert_eq!(cli.command, Some(Command::BenchZenzai));
+        let cli = parse(&["--log-level", "debug", "zenzai-worker"]).unwrap();
+        assert_eq!(cli.command, Some(Command::ZenzaiWorker));
+        let cli = parse(&["compile-dict", "SKK-JISYO.L", "user.json", "-o", "dict.azk"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::CompileDict {
+                inputs: vec![PathBuf::from("SKK-JISYO.L"), PathBuf::from("user.json")],
+                output: PathBuf::from("dict.azk"),
+            })
+        );
+        let cli = parse(&["check-dict", "user.json"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::CheckDict {
+                paths: vec![PathBuf::from("user.json")]
+            })
+        );
+    }
+
+    #[test]
+    fn test_help_and_version_exit_through_clap() {
+        use clap::error::ErrorKind;
+        assert_eq!(
+            parse(&["-V"]).unwrap_err().kind(),
+            ErrorKind::DisplayVersion
+        );
+        let help = parse(&["--help"]).unwrap_err();
+        assert_eq!(help.kind(), ErrorKind::DisplayHelp);
+        let help = help.to_string();
+        for item in [
+            "compile-dict",
+            "check-dict",
+            "--dictionary",
+            "--max-message-size",
+        ] {
+            assert!(help.contains(item), "{}", item);
+        }
+        assert!(!help.contains("zenzai-worker"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(&["--dictionary"]).is_err());
+        assert!(parse(&["--log-level", "loud"]).is_err());
+        assert!(parse(&["--max-message-size", "4MB"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["daemon"]).is_err());
+        assert!(parse(&["compile-dict", "SKK-JISYO.L"]).is_err());
+        assert!(parse(&["compile-dict", "--output", "dict.azk"]).is_err());
+        assert!(parse(&["-o", "dict.azk"]).is_err());
+        assert!(parse(&["check-dict"]).is_err());
+        assert!(parse(&["check-dict", "SKK-JISYO.L", "-o", "dict.azk"]).is_err());
+        assert!(parse(&["daemon", "restart"]).is_err());
+        assert!(parse(&["--daemon", "check-dict", "user.json"]).is_err());
+        assert!(parse(&["--socket", "/tmp/a.sock", "--listen", "tcp:127.0.0.1:1"]).is_err());
+    }
+}
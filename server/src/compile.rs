@@ -0,0 +1,138 @@
+//! Dictionary compiler (`azuki-server compile-dict`)
+//!
+//! Parsing SKK-JISYO.L takes seconds at every startup. Compiling it once
+//! into the indexed format (see `mapped_dict`) lets the server map the
+//! file and look words up in place, so startup no longer depends on the
+//! dictionary's size.
+
+use crate::config::source_files;
+use crate::dictionary::Dictionary;
+use crate::error::{AzukiError, DictionaryError, Result};
+use crate::mapped_dict::write_dictionary;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Merge the input dictionaries, first with priority, and write them to
+/// `output` in the indexed format
+///
+/// Directories stand for the files in them, as in the configuration. The
+/// output is written next to its final path and renamed over it, so a
+/// server that has the old file mapped keeps reading it intact.
+pub fn run(inputs: &[PathBuf], output: &Path) -> Result<()> {
+    let started = Instant::now();
+    let mut merged: Option<Dictionary> = None;
+    for input in inputs {
+        let paths = source_files(input).map_err(|source| DictionaryError::Io {
+            path: input.clone(),
+            source,
+        })?;
+        for path in paths {
+            let dictionary = Dictionary::load(&path)?;
+            match &mut merged {
+                Some(merged) => merged.merge(dictionary),
+                None => merged = Some(dictionary),
+            }
+        }
+    }
+    let dictionary = merged.unwrap_or_default();
+
+    let temp = temp_path(output);
+    if let Err(e) = write_file(&dictionary, &temp).and_then(|()| fs::rename(&temp, output)) {
+        let _ = fs::remove_file(&temp);
+        return Err(DictionaryError::Io {
+            path: output.to_path_buf(),
+            source: e,
+        }
+        .into());
+    }
+
+    let size = fs::metadata(output).map_or(0, |metadata| metadata.len());
+    println!(
+        "Compiled {} okuri-nasi and {} okuri-ari entries into {} ({} bytes, {} ms)",
+        dictionary.len(),
+        dictionary.okuri_ari_entries().count(),
+        output.display(),
+        size,
+        started.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+/// Write the indexed dictionary to `path` and flush it to disk
+fn write_file(dictionary: &Dictionary, path: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_dictionary(dictionary, &mut writer).map_err(|e| match e {
+        AzukiError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    })?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+}
+
+/// Temporary file in the output's directory, so the rename stays on one
+/// file system
+fn temp_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.tmp", std::process::id()));
+    output.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapped_dict::MappedDictionary;
+    use crate::reverse_index::ReverseIndex;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    #[test]
+    fn test_compile_merges_inputs_in_priority_order() {
+        let dir = std::env::temp_dir().join(format!("azuki-compile-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.json");
+        fs::write(&user, r#"{"きょう": ["強"], "あずき": ["小豆"]}"#).unwrap();
+        let output = dir.join("dict.azk");
+
+        run(&[user, fixture("test-dict.utf8")], &output).unwrap();
+        let mapped = MappedDictionary::open(&output).unwrap();
+        assert_eq!(
+            mapped.lookup("きょう").unwrap(),
+            vec!["強", "今日", "京", "教"]
+        );
+        assert_eq!(mapped.lookup("あずき").unwrap(), vec!["小豆"]);
+        assert_eq!(
+            mapped.lookup_okuri_ari("かk", Some("け")).unwrap(),
+            vec!["欠"]
+        );
+        assert!(!temp_path(&output).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compile_keeps_reverse_index() {
+        let output = std::env::temp_dir().join(format!("azuki-reverse-{}.azk", std::process::id()));
+        run(&[fixture("test-dict.utf8")], &output).unwrap();
+
+        let mapped = MappedDictionary::open(&output).unwrap();
+        let index = mapped.reverse_index().unwrap();
+        assert_eq!(
+            *index,
+            ReverseIndex::build(&Dictionary::load(fixture("test-dict.utf8")).unwrap())
+        );
+        assert_eq!(index.lookup("今日"), ["きょう"]);
+        assert_eq!(index.lookup("欠"), ["か"]);
+        fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn test_compile_fails_on_unreadable_input() {
+        let output = std::env::temp_dir().join(format!("azuki-missing-{}.azk", std::process::id()));
+        assert!(run(&[fixture("no-such-dictionary")], &output).is_err());
+        assert!(!output.exists());
+    }
+}
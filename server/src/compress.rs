@@ -0,0 +1,119 @@
+//! Response compression
+//!
+//! A client lists the encodings it can decode in `init`; responses at least
+//! as large as the threshold are then sent inside a `compressed` frame,
+//! base64 encoded. zstd and deflate in the zlib format (RFC 1950/1951) are
+//! offered, both at fast levels: the repetitive JSON of candidate lists
+//! shrinks well without spending much time per response.
+
+use flate2::write::ZlibEncoder;
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// Default size in bytes from which responses are compressed
+pub const DEFAULT_THRESHOLD: usize = 8 * 1024;
+
+/// zstd level; low levels are already several times smaller than JSON
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compression applied to response payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Zstd,
+    Deflate,
+}
+
+impl Encoding {
+    /// First of the offered encodings this server implements
+    pub fn negotiate(offered: &[String]) -> Option<Self> {
+        offered.iter().find_map(|name| match name.as_str() {
+            "zstd" => Some(Encoding::Zstd),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        })
+    }
+
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL),
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Standard base64 with padding
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let bytes = [
+            group[0],
+            *group.get(1).unwrap_or(&0),
+            *group.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= group.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_round_trip() {
+        let json = r#"{"text":"日本語","source":"dictionary","score":1.0},"#.repeat(500);
+        let inputs: [&[u8]; 4] = [b"", b"a", b"abcabcabcabcabcabc", json.as_bytes()];
+        for input in inputs {
+            let mut inflated = Vec::new();
+            ZlibDecoder::new(&Encoding::Deflate.compress(input).unwrap()[..])
+                .read_to_end(&mut inflated)
+                .unwrap();
+            assert_eq!(inflated, input);
+
+            let zstd = Encoding::Zstd.compress(input).unwrap();
+            assert_eq!(zstd::decode_all(&zstd[..]).unwrap(), input);
+        }
+        for encoding in [Encoding::Zstd, Encoding::Deflate] {
+            let compressed = encoding.compress(json.as_bytes()).unwrap();
+            assert!(compressed.len() < json.len() / 20, "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let offered = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            Encoding::negotiate(&offered(&["zstd", "deflate"])),
+            Some(Encoding::Zstd)
+        );
+        assert_eq!(
+            Encoding::negotiate(&offered(&["br", "deflate", "zstd"])),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(Encoding::negotiate(&offered(&["br"])), None);
+    }
+}
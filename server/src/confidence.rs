@@ -0,0 +1,83 @@
+//! Confidence estimate for the top conversion candidate
+//!
+//! The dictionary converter has no lattice scores, so confidence is
+//! estimated per segment from how ambiguous its reading is and how often
+//! the top candidate was chosen before. The combined confidence is the
+//! product over segments: every segment has to be right for the phrase to
+//! be right. Clients can auto-commit above a threshold in live mode.
+
+use crate::learning::LearningStore;
+use crate::message::SegmentInfo;
+
+/// Confidence (0.0 - 1.0) that a segment's first candidate is the right one
+pub fn segment_confidence(segment: &SegmentInfo, learning: &LearningStore) -> f64 {
+    if segment.fixed {
+        return 1.0;
+    }
+    // The reading itself doesn't count as an alternative conversion
+    let alternatives = segment
+        .candidates
+        .iter()
+        .filter(|c| **c != segment.reading)
+        .count();
+    let Some(top) = segment.candidates.first() else {
+        return 0.0;
+    };
+    if alternatives <= 1 {
+        return 1.0;
+    }
+    learning
+        .selection_share(&segment.reading, top, alternatives)
+        .unwrap_or(1.0 / alternatives as f64)
+}
+
+/// Confidence that the combined top candidate is right
+pub fn combined_confidence(segments: &[SegmentInfo], learning: &LearningStore) -> f64 {
+    segments
+        .iter()
+        .map(|seg| segment_confidence(seg, learning))
+        .product()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(reading: &str, candidates: &[&str]) -> SegmentInfo {
+        SegmentInfo {
+            reading: reading.to_string(),
+            start: 0,
+            length: reading.chars().count(),
+            candidates: candidates.iter().map(|s| s.to_string()).collect(),
+            fixed: false,
+            annotations: Default::default(),
+            kinds: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_unambiguous_segments_are_certain() {
+        let learning = LearningStore::new();
+        let segments = [
+            segment("にほんご", &["日本語", "にほんご"]),
+            segment("を", &["を"]),
+        ];
+        assert_eq!(combined_confidence(&segments, &learning), 1.0);
+    }
+
+    #[test]
+    fn test_ambiguity_and_learning() {
+        let mut learning = LearningStore::new();
+        let kyou = segment("きょう", &["今日", "京", "教", "きょう"]);
+        assert!((segment_confidence(&kyou, &learning) - 1.0 / 3.0).abs() < 1e-9);
+
+        for _ in 0..7 {
+            learning.record("きょう", "今日");
+        }
+        // (7 + 1) / (7 + 3)
+        assert!((segment_confidence(&kyou, &learning) - 0.8).abs() < 1e-9);
+
+        let segments = [kyou, segment("にほんご", &["日本語", "にほんご"])];
+        assert!((combined_confidence(&segments, &learning) - 0.8).abs() < 1e-9);
+    }
+}
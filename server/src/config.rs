@@ -1,7 +1,50 @@
 //! Configuration and dictionary loading
 
-use crate::dictionary::Dictionary;
-use std::path::PathBuf;
+use crate::blocklist::Blocklist;
+use crate::converter::ConversionConfig;
+use crate::dictionary::{Dictionary, DictionaryEncoding};
+use crate::error::{AzukiError, Result};
+use crate::fallback::FallbackPolicy;
+use crate::learning::LearningStore;
+use crate::log::LogLevel;
+use crate::provider::ProviderConfig;
+use crate::punctuation::PunctuationConfig;
+use crate::remote_dict::RemoteDictionaryConfig;
+use crate::romaji::RomajiConfig;
+use crate::session::SessionConfig;
+use crate::user_dict::UserDictionary;
+use crate::watch::WatchConfig;
+use crate::zenzai::ZenzaiConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Directory for persistent user data (`$XDG_DATA_HOME/azuki`)
+pub fn data_dir() -> Option<PathBuf> {
+    if let Ok(data_home) = std::env::var("XDG_DATA_HOME") {
+        if !data_home.is_empty() {
+            return Some(PathBuf::from(data_home).join("azuki"));
+        }
+    }
+    std::env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(".local/share/azuki"))
+}
+
+/// Directory for persistent state (`$XDG_STATE_HOME/azuki`)
+pub fn state_dir() -> Option<PathBuf> {
+    if let Ok(state_home) = std::env::var("XDG_STATE_HOME") {
+        if !state_home.is_empty() {
+            return Some(PathBuf::from(state_home).join("azuki"));
+        }
+    }
+    std::env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(".local/state/azuki"))
+}
 
 /// Default dictionary paths to search
 pub fn default_dictionary_paths() -> Vec<PathBuf> {
@@ -18,43 +61,368 @@ pub fn default_dictionary_paths() -> Vec<PathBuf> {
         paths.push(PathBuf::from(&home).join(".azuki/dict/SKK-JISYO.L"));
     }
 
-    // System paths
-    paths.push(PathBuf::from("/usr/share/skk/SKK-JISYO.L"));
-    paths.push(PathBuf::from("/usr/local/share/skk/SKK-JISYO.L"));
+    // System paths, as installed or compressed
+    for dir in ["/usr/share/skk", "/usr/local/share/skk"] {
+        paths.push(PathBuf::from(dir).join("SKK-JISYO.L"));
+        paths.push(PathBuf::from(dir).join("SKK-JISYO.L.gz"));
+    }
 
     paths
 }
 
-/// Find and load dictionary from default paths
-pub fn load_dictionary() -> Option<Dictionary> {
-    // Check environment variable first
-    if let Ok(dict_path) = std::env::var("AZUKI_DICTIONARY") {
-        match Dictionary::load(&dict_path) {
-            Ok(dict) => {
-                eprintln!("Loaded dictionary from AZUKI_DICTIONARY: {}", dict_path);
-                return Some(dict);
+/// Load the candidate blocklist from the data directory
+pub fn load_blocklist() -> Blocklist {
+    let Some(path) = data_dir().map(|dir| dir.join("blocklist")) else {
+        return Blocklist::new();
+    };
+    match Blocklist::load(&path) {
+        Ok(blocklist) => {
+            if !blocklist.is_empty() {
+                log_info!(
+                    "Loaded {} blocked candidates from {}",
+                    blocklist.len(),
+                    path.display()
+                );
+            }
+            blocklist
+        }
+        Err(e) => {
+            log_error!("Failed to load blocklist from {}: {}", path.display(), e);
+            Blocklist::new()
+        }
+    }
+}
+
+/// File the user dictionary is kept in
+pub fn user_dictionary_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("user-dict"))
+}
+
+/// Load the user dictionary from the data directory
+pub fn load_user_dictionary() -> UserDictionary {
+    let Some(path) = user_dictionary_path() else {
+        return UserDictionary::new();
+    };
+    match UserDictionary::load(&path) {
+        Ok(dict) => {
+            if !dict.is_empty() {
+                log_info!(
+                    "Loaded {} user dictionary words from {}",
+                    dict.len(),
+                    path.display()
+                );
             }
+            dict
+        }
+        Err(e) => {
+            log_error!(
+                "Failed to load user dictionary from {}: {}",
+                path.display(),
+                e
+            );
+            UserDictionary::new()
+        }
+    }
+}
+
+/// Load candidate learning data from the state directory
+pub fn load_learning() -> LearningStore {
+    let Some(path) = state_dir().map(|dir| dir.join("learning.json")) else {
+        return LearningStore::new();
+    };
+    match LearningStore::load(&path) {
+        Ok(store) => {
+            if !store.is_empty() {
+                log_info!(
+                    "Loaded learning data for {} readings from {}",
+                    store.len(),
+                    path.display()
+                );
+            }
+            store
+        }
+        Err(e) => {
+            log_error!(
+                "Failed to load learning data from {}: {}",
+                path.display(),
+                e
+            );
+            LearningStore::new()
+        }
+    }
+}
+
+/// Name of the dictionary set made of the top-level `dictionaries`
+pub const DEFAULT_SET: &str = "default";
+
+/// Startup configuration read from `--config <file>` (JSON)
+///
+/// The sections mirror those of the `init` request, which override them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Dictionaries to load instead of the default search, first has priority
+    #[serde(default)]
+    pub dictionaries: Vec<DictionarySource>,
+    /// Other dictionaries sessions can switch to by name; `default` is
+    /// `dictionaries`
+    #[serde(default)]
+    pub dictionary_sets: BTreeMap<String, Vec<DictionarySource>>,
+    #[serde(default)]
+    pub sessions: Option<SessionConfig>,
+    #[serde(default)]
+    pub punctuation: Option<PunctuationConfig>,
+    #[serde(default)]
+    pub fallback: Option<FallbackPolicy>,
+    #[serde(default)]
+    pub providers: Option<ProviderConfig>,
+    #[serde(default)]
+    pub conversion: Option<ConversionConfig>,
+    #[serde(default)]
+    pub remote_dictionary: Option<RemoteDictionaryConfig>,
+    #[serde(default)]
+    pub romaji: Option<RomajiConfig>,
+    /// Zenzai model, also the one `bench-zenzai` measures
+    #[serde(default)]
+    pub zenzai: Option<ZenzaiConfig>,
+    /// Reloading dictionaries when their files change
+    #[serde(default)]
+    pub watch: Option<WatchConfig>,
+    /// Log level, unless `--log-level` is given
+    #[serde(default)]
+    pub log_level: Option<LogLevel>,
+    /// Largest message in bytes, unless `--max-message-size` is given
+    #[serde(default)]
+    pub max_message_size: Option<u32>,
+    /// Size in bytes from which responses are compressed, for clients that
+    /// ask for compression
+    #[serde(default)]
+    pub compression_threshold: Option<usize>,
+}
+
+impl ServerConfig {
+    /// Read a configuration file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        let config: Self = serde_json::from_str(&content)
+            .map_err(|e| AzukiError::Config(format!("{}: {}", path.display(), e)))?;
+        if config.dictionary_sets.contains_key(DEFAULT_SET) {
+            return Err(AzukiError::Config(format!(
+                "{}: dictionary set \"{}\" is `dictionaries`",
+                path.display(),
+                DEFAULT_SET
+            )));
+        }
+        Ok(config)
+    }
+}
+
+/// A configured dictionary file, or a directory of them
+///
+/// Written as a path, or as `{"path": ..., "encoding": "shift_jis"}` to
+/// pin the encoding of a file auto-detection gets wrong.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "SourceEntry")]
+pub struct DictionarySource {
+    pub path: PathBuf,
+    pub encoding: Option<DictionaryEncoding>,
+}
+
+/// `dictionaries` entry as written in the configuration file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SourceEntry {
+    Path(PathBuf),
+    Pinned {
+        path: PathBuf,
+        encoding: Option<DictionaryEncoding>,
+    },
+}
+
+impl From<SourceEntry> for DictionarySource {
+    fn from(entry: SourceEntry) -> Self {
+        match entry {
+            SourceEntry::Path(path) => path.into(),
+            SourceEntry::Pinned { path, encoding } => Self { path, encoding },
+        }
+    }
+}
+
+impl From<PathBuf> for DictionarySource {
+    fn from(path: PathBuf) -> Self {
+        Self {
+            path,
+            encoding: None,
+        }
+    }
+}
+
+/// Outcome of loading one dictionary file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DictionaryLoad {
+    pub path: PathBuf,
+    /// Okuri-nasi and okuri-ari entries read; 0 if loading failed
+    pub entries: usize,
+    /// Encoding detected, if the file could be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// Time spent reading and parsing the file
+    pub elapsed_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Load dictionaries, or search the default locations if none are given
+///
+/// Later dictionaries add candidates after those of earlier ones.
+/// Dictionaries that fail to load are skipped; every file tried is
+/// reported with its outcome.
+pub fn load_dictionaries_reporting(
+    sources: &[DictionarySource],
+) -> (Option<Dictionary>, Vec<DictionaryLoad>) {
+    let mut report = Vec::new();
+    if sources.is_empty() {
+        let dictionary = search_dictionary(&mut report);
+        return (dictionary, report);
+    }
+    let mut merged: Option<Dictionary> = None;
+    for source in sources {
+        let paths = match source_files(&source.path) {
+            Ok(paths) => paths,
             Err(e) => {
-                eprintln!("Failed to load dictionary from {}: {}", dict_path, e);
+                log_error!(
+                    "Failed to list dictionaries in {}: {}",
+                    source.path.display(),
+                    e
+                );
+                report.push(DictionaryLoad {
+                    path: source.path.clone(),
+                    entries: 0,
+                    encoding: None,
+                    elapsed_ms: 0,
+                    error: Some(e.to_string()),
+                });
+                continue;
+            }
+        };
+        for path in paths {
+            if let Some(dict) = load_reporting(&path, source.encoding, &mut report) {
+                match &mut merged {
+                    Some(merged) => merged.merge(dict),
+                    None => merged = Some(dict),
+                }
             }
         }
     }
+    if merged.is_none() {
+        log_warn!("No dictionary could be loaded. Running without dictionary.");
+    }
+    (merged, report)
+}
+
+/// Dictionary files of a configured path
+///
+/// A directory stands for the files in it, in name order so the first has
+/// priority; hidden files, backups ending in `~` and subdirectories are
+/// skipped. Any other path is a file, which may not exist.
+pub fn source_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || name.ends_with('~') || !entry.path().is_file() {
+            continue;
+        }
+        files.push(entry.path());
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Load one dictionary file, adding the outcome to `report`
+fn load_reporting(
+    path: &Path,
+    encoding: Option<DictionaryEncoding>,
+    report: &mut Vec<DictionaryLoad>,
+) -> Option<Dictionary> {
+    let started = Instant::now();
+    let loaded = Dictionary::load_with(path, encoding);
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    match loaded {
+        Ok(dict) => {
+            report.push(DictionaryLoad {
+                path: path.to_path_buf(),
+                entries: dict.len() + dict.okuri_ari_entries().count(),
+                encoding: dict.encoding().map(str::to_string),
+                elapsed_ms,
+                error: None,
+            });
+            Some(dict)
+        }
+        Err(e) => {
+            log_error!("Failed to load dictionary from {}: {}", path.display(), e);
+            report.push(DictionaryLoad {
+                path: path.to_path_buf(),
+                entries: 0,
+                encoding: None,
+                elapsed_ms,
+                error: Some(e.to_string()),
+            });
+            None
+        }
+    }
+}
+
+/// Files `load_dictionaries_reporting` may read for the given paths
+///
+/// With no paths this is every location of the default search, so a
+/// dictionary installed later is picked up too. Directories are listed
+/// along with their files, as adding or removing a file changes them.
+pub fn dictionary_sources(sources: &[DictionarySource]) -> Vec<PathBuf> {
+    if !sources.is_empty() {
+        let mut paths = Vec::new();
+        for source in sources {
+            if source.path.is_dir() {
+                paths.push(source.path.clone());
+            }
+            paths.extend(source_files(&source.path).unwrap_or_default());
+        }
+        return paths;
+    }
+    let mut sources: Vec<PathBuf> = std::env::var("AZUKI_DICTIONARY")
+        .ok()
+        .map(PathBuf::from)
+        .into_iter()
+        .collect();
+    sources.extend(default_dictionary_paths());
+    sources
+}
+
+/// Find and load dictionary from default paths
+fn search_dictionary(report: &mut Vec<DictionaryLoad>) -> Option<Dictionary> {
+    // Check environment variable first
+    if let Ok(dict_path) = std::env::var("AZUKI_DICTIONARY") {
+        if let Some(dict) = load_reporting(Path::new(&dict_path), None, report) {
+            log_info!("Loaded dictionary from AZUKI_DICTIONARY: {}", dict_path);
+            return Some(dict);
+        }
+    }
 
     // Search default paths
     for path in default_dictionary_paths() {
         if path.exists() {
-            match Dictionary::load(&path) {
-                Ok(dict) => {
-                    eprintln!("Loaded dictionary from: {}", path.display());
-                    return Some(dict);
-                }
-                Err(e) => {
-                    eprintln!("Failed to load dictionary from {}: {}", path.display(), e);
-                }
+            if let Some(dict) = load_reporting(&path, None, report) {
+                log_info!("Loaded dictionary from: {}", path.display());
+                return Some(dict);
             }
         }
     }
 
-    eprintln!("No dictionary found. Running without dictionary (hiragana pass-through mode).");
+    log_warn!("No dictionary found. Running without dictionary (hiragana pass-through mode).");
     None
 }
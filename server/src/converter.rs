@@ -1,12 +1,85 @@
 //! Kana-kanji conversion logic
 
 use crate::dictionary::Dictionary;
-use serde::Serialize;
+use crate::error::{AzukiError, Result};
+use crate::kana;
+use crate::lattice::{self, NodeClass};
+use crate::numeric;
+use crate::provider::{DynamicCandidate, ProviderConfig, ProviderRegistry};
+use crate::punctuation::PunctuationConfig;
+use crate::remote_dict::RemoteDictionary;
+use crate::tokenizer::{ScriptTokenizer, Token, TokenKind, Tokenizer};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Soft limit on the number of characters converted in a single request
+///
+/// Longer readings (e.g. pasted text) are converted in chunks of at most
+/// this many characters, so one request never builds an unbounded result.
+pub const READING_SOFT_LIMIT: usize = 64;
+
+/// Most kana taken on each side of a number when matching numeric entries
+const MAX_NUMERIC_AFFIX: usize = 4;
+
+/// Segmentations of one run with their extra cost over the best one
+type Alternatives = Vec<(u32, Vec<Segment>)>;
+
+/// Characters after which a chunk boundary is preferred
+const CHUNK_BREAK_CHARS: &[char] = &[
+    '。', '、', '．', '，', '！', '？', '!', '?', ' ', '\u{3000}', '\n',
+];
+
+/// Check that a reading can be converted
+///
+/// Empty readings and control characters (other than newline and tab,
+/// which occur in pasted text) are rejected. Whitespace-only readings are
+/// valid and pass through unconverted.
+pub fn validate_reading(reading: &str) -> Result<()> {
+    if reading.is_empty() {
+        return Err(AzukiError::Protocol("Empty reading".to_string()));
+    }
+    if let Some(c) = reading
+        .chars()
+        .find(|c| c.is_control() && !matches!(c, '\n' | '\t'))
+    {
+        return Err(AzukiError::Protocol(format!(
+            "Reading contains control character U+{:04X}",
+            c as u32
+        )));
+    }
+    Ok(())
+}
+
+/// Markers of SKK prefix ("ちょう>") and suffix (">てき") entries
+const AFFIX_MARKERS: [char; 2] = ['>', '<'];
+
+/// Whether a reading asks for a prefix or suffix entry
+///
+/// The marker opens or closes the reading, as SKK clients send it; a
+/// marker alone is an ordinary character.
+pub fn is_affix_reading(reading: &str) -> bool {
+    reading.chars().nth(1).is_some()
+        && (reading.starts_with(AFFIX_MARKERS) || reading.ends_with(AFFIX_MARKERS))
+}
+
+/// Reading as dictionaries spell it, with katakana in hiragana
+/// ("トウキョウ" -> "とうきょう", as when re-converting katakana text)
+fn dictionary_reading(reading: &str) -> Cow<'_, str> {
+    if reading.chars().any(|c| matches!(c, 'ァ'..='ヶ' | 'ｦ'..='ﾟ')) {
+        Cow::Owned(kana::katakana_to_hiragana(reading))
+    } else {
+        Cow::Borrowed(reading)
+    }
+}
 
 /// Segment information for UI display
 #[derive(Debug, Clone, Serialize)]
 pub struct Segment {
-    /// Reading (hiragana) for this segment
+    /// Reading of this segment as sent (hiragana, or katakana on re-conversion)
     pub reading: String,
     /// Start position in the original reading (character index)
     pub start: usize,
@@ -14,6 +87,8 @@ pub struct Segment {
     pub length: usize,
     /// Conversion candidates for this segment
     pub candidates: Vec<String>,
+    /// Latin or numeric run that passes through unconverted
+    pub fixed: bool,
 }
 
 /// Conversion result with segment information
@@ -23,6 +98,47 @@ pub struct ConversionResult {
     pub combined_candidates: Vec<String>,
     /// Individual segment information
     pub segments: Vec<Segment>,
+    /// Ranking score of each combined candidate but the reading
+    pub scores: HashMap<String, f64>,
+}
+
+/// Conversion configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct ConversionConfig {
+    /// Maximum number of whole-phrase candidates, not counting the reading
+    #[serde(default = "default_n_best")]
+    pub n_best: usize,
+    /// Look readings up by their fuzzy form when they have no entry as
+    /// typed, so "とーきょー" and "つずく" find "とうきょう" and "つづく"
+    #[serde(default)]
+    pub fuzzy_readings: bool,
+}
+
+fn default_n_best() -> usize {
+    5
+}
+
+impl Default for ConversionConfig {
+    fn default() -> Self {
+        Self {
+            n_best: default_n_best(),
+            fuzzy_readings: false,
+        }
+    }
+}
+
+/// Cost of picking a segment's next candidate instead of its first
+///
+/// In the same units as lattice path costs, so candidate alternatives and
+/// alternative segmentations can be ranked against each other.
+const CANDIDATE_RANK_COST: u32 = 15;
+
+/// Score (0.0 - 1.0] of a phrase from its ranking cost
+///
+/// The best phrase scores 1.0; each candidate rank picked further down a
+/// segment's list (or the equivalent segmentation cost) lowers it.
+pub fn phrase_score(cost: u32) -> f64 {
+    1.0 / (1.0 + f64::from(cost) / f64::from(CANDIDATE_RANK_COST))
 }
 
 /// Direction for segment boundary adjustment
@@ -34,103 +150,778 @@ pub enum AdjustDirection {
     Extend,
 }
 
+/// Kinds of candidates generated from the reading itself
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GeneratedForms {
+    /// "あずき" -> "ｱｽﾞｷ"
+    pub halfwidth_katakana: bool,
+    /// "abc" -> "ａｂｃ", only for readings with ASCII letters or digits
+    pub fullwidth_alphanumeric: bool,
+}
+
 /// Kana-kanji converter
+///
+/// The dictionary is shared read-mostly through an `Arc`, so cloning a
+/// converter (per session, per connection or per worker) is cheap. Updates
+/// such as user word registration copy the dictionary on write, leaving
+/// other holders with their current snapshot.
+#[derive(Clone)]
 pub struct Converter {
-    dictionary: Option<Dictionary>,
+    dictionary: Option<Arc<Dictionary>>,
+    /// skkserv queried for readings in addition to the dictionary
+    remote: Option<Arc<RemoteDictionary>>,
+    punctuation: PunctuationConfig,
+    conversion: ConversionConfig,
+    providers: ProviderRegistry,
+    tokenizer: Arc<dyn Tokenizer>,
 }
 
 impl Converter {
     /// Create a new converter with optional dictionary
     pub fn new(dictionary: Option<Dictionary>) -> Self {
-        Self { dictionary }
+        Self::with_shared(dictionary.map(Arc::new))
     }
 
-    /// Segment reading into convertible parts with position information
-    pub fn segment_with_info(&self, reading: &str) -> Vec<Segment> {
-        let dict = match &self.dictionary {
-            Some(d) => d,
-            None => {
-                // No dictionary, return entire reading as one segment
-                return vec![Segment {
-                    reading: reading.to_string(),
-                    start: 0,
-                    length: reading.chars().count(),
-                    candidates: vec![reading.to_string()],
-                }];
+    /// Create a converter over an already shared dictionary
+    pub fn with_shared(dictionary: Option<Arc<Dictionary>>) -> Self {
+        Self {
+            dictionary,
+            remote: None,
+            punctuation: PunctuationConfig::default(),
+            conversion: ConversionConfig::default(),
+            providers: ProviderRegistry::new(),
+            tokenizer: Arc::new(ScriptTokenizer),
+        }
+    }
+
+    /// Set the punctuation policy applied to combined candidates
+    pub fn set_punctuation(&mut self, punctuation: PunctuationConfig) {
+        self.punctuation = punctuation;
+    }
+
+    /// Punctuation policy applied to combined candidates
+    pub fn punctuation(&self) -> &PunctuationConfig {
+        &self.punctuation
+    }
+
+    /// Set the conversion configuration
+    pub fn set_conversion(&mut self, conversion: ConversionConfig) {
+        self.conversion = conversion;
+        self.index_fuzzy_readings();
+    }
+
+    /// Replace the tokenizer that separates fixed runs from convertible text
+    #[allow(dead_code)]
+    pub fn set_tokenizer(&mut self, tokenizer: Arc<dyn Tokenizer>) {
+        self.tokenizer = tokenizer;
+    }
+
+    /// Set which dynamic candidate providers are enabled globally
+    pub fn set_provider_config(&mut self, config: ProviderConfig) {
+        self.providers.set_config(config);
+    }
+
+    /// Registry of dynamic candidate providers
+    pub fn providers(&self) -> &ProviderRegistry {
+        &self.providers
+    }
+
+    /// Candidates from enabled dynamic providers, given per-session overrides
+    pub fn dynamic_candidates(
+        &self,
+        reading: &str,
+        overrides: &BTreeMap<String, bool>,
+    ) -> Vec<DynamicCandidate> {
+        self.providers
+            .candidates(reading, overrides, SystemTime::now())
+    }
+
+    /// Candidates generated from a reading rather than looked up
+    ///
+    /// Forms identical to the reading are left out.
+    pub fn generated_candidates(reading: &str, forms: GeneratedForms) -> Vec<String> {
+        let mut generated = Vec::new();
+        if forms.fullwidth_alphanumeric && reading.chars().any(|c| c.is_ascii_alphanumeric()) {
+            generated.push(kana::halfwidth_to_fullwidth(reading));
+        }
+        if forms.halfwidth_katakana {
+            generated.push(kana::to_halfwidth_katakana(reading));
+        }
+        generated.retain(|text| text != reading);
+        generated
+    }
+
+    /// Shared handle to the dictionary, for handing to other converters
+    pub fn shared_dictionary(&self) -> Option<Arc<Dictionary>> {
+        self.dictionary.clone()
+    }
+
+    /// Replace the dictionary
+    pub fn set_dictionary(&mut self, dictionary: Option<Arc<Dictionary>>) {
+        self.dictionary = dictionary;
+        self.index_fuzzy_readings();
+    }
+
+    /// Index the dictionary's fuzzy readings now if they are used, rather
+    /// than in the first conversion
+    fn index_fuzzy_readings(&self) {
+        if let Some(dict) = self
+            .dictionary
+            .as_ref()
+            .filter(|_| self.conversion.fuzzy_readings)
+        {
+            dict.fuzzy_index();
+        }
+    }
+
+    /// Exchange the dictionary with another one
+    pub fn swap_dictionary(&mut self, dictionary: &mut Option<Arc<Dictionary>>) {
+        std::mem::swap(&mut self.dictionary, dictionary);
+    }
+
+    /// Replace the remote dictionary
+    pub fn set_remote_dictionary(&mut self, remote: Option<Arc<RemoteDictionary>>) {
+        self.remote = remote;
+    }
+
+    /// Remote dictionary, if configured
+    pub fn remote_dictionary(&self) -> Option<&RemoteDictionary> {
+        self.remote.as_deref()
+    }
+
+    /// Candidates from the dictionary followed by new ones from the remote dictionary
+    ///
+    /// Readings with numbers fall back to numeric entries ("3がつ" -> "#がつ"),
+    /// and inflected readings add okuri-ari candidates ("いって" -> "行って").
+    /// Okuri-ari candidates whose okurigana the dictionary lists come
+    /// before okuri-nasi words that merely share the reading ("かく" ->
+    /// "書く" before "角"); guessed inflections come after them. With fuzzy
+    /// readings on, a reading without an entry takes those of its other
+    /// spellings ("とーきょー" -> "東京").
+    ///
+    /// Katakana readings are looked up in hiragana, and the katakana
+    /// itself follows their candidates ("トウキョウ" -> "東京",
+    /// "トウキョウ").
+    pub fn lookup_combined(&self, reading: &str) -> Option<Vec<String>> {
+        let key = dictionary_reading(reading);
+        let mut candidates = self.lookup_hiragana(&key)?;
+        if key != reading && !candidates.iter().any(|c| c == reading) {
+            candidates.push(reading.to_string());
+        }
+        Some(candidates)
+    }
+
+    /// `lookup_combined` for a reading in hiragana
+    fn lookup_hiragana(&self, reading: &str) -> Option<Vec<String>> {
+        let dictionary = self.dictionary.as_ref();
+        let exact: Option<Vec<String>> = dictionary
+            .and_then(|dict| dict.lookup(reading))
+            .map(|candidates| candidates.into_iter().map(String::from).collect())
+            .or_else(|| self.lookup_numeric(reading))
+            .or_else(|| self.lookup_fuzzy(reading));
+        let inflections = dictionary
+            .map(|dict| dict.lookup_inflections(reading))
+            .unwrap_or_default();
+        let mut local: Option<Vec<String>> = None;
+        let ranked = [Some(inflections.verified), exact, Some(inflections.guessed)];
+        for candidates in ranked.into_iter().flatten().filter(|c| !c.is_empty()) {
+            let local = local.get_or_insert_with(Vec::new);
+            for candidate in candidates {
+                if !local.contains(&candidate) {
+                    local.push(candidate);
+                }
             }
+        }
+        let Some(remote) = self.remote.as_ref().and_then(|r| r.lookup(reading)) else {
+            return local;
         };
+        let mut candidates = local.unwrap_or_default();
+        for candidate in remote {
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+        Some(candidates)
+    }
 
-        let chars: Vec<char> = reading.chars().collect();
-        let mut segments = Vec::new();
-        let mut pos = 0;
+    /// Numeric entry candidates for a reading with numbers
+    fn lookup_numeric(&self, reading: &str) -> Option<Vec<String>> {
+        let (key, numbers) = numeric::split_numbers(reading)?;
+        self.dictionary.as_ref()?.lookup_numeric(&key, &numbers)
+    }
 
-        while pos < chars.len() {
-            let mut best_match: Option<(usize, String)> = None;
+    /// Candidates of the readings spelled like `reading`, when fuzzy
+    /// readings are on
+    fn lookup_fuzzy(&self, reading: &str) -> Option<Vec<String>> {
+        if !self.conversion.fuzzy_readings {
+            return None;
+        }
+        let dict = self.dictionary.as_ref()?;
+        let mut result: Vec<String> = Vec::new();
+        for spelled in dict.fuzzy_readings(reading) {
+            for candidate in dict.lookup(&spelled).unwrap_or_default() {
+                if !result.iter().any(|c| c == candidate) {
+                    result.push(candidate.to_string());
+                }
+            }
+        }
+        (!result.is_empty()).then_some(result)
+    }
 
-            // Try longest match first
-            for end in (pos + 1..=chars.len()).rev() {
-                let substr: String = chars[pos..end].iter().collect();
-                if dict.lookup(&substr).is_some() {
-                    best_match = Some((end - pos, substr));
-                    break;
+    /// Candidates of SKK abbrev entries for an ASCII word ("file" -> "ファイル")
+    ///
+    /// Case is ignored: the word is looked up as typed, then in lower and
+    /// upper case.
+    pub fn lookup_abbrev(&self, word: &str) -> Option<Vec<String>> {
+        if !word.chars().any(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let mut variants = vec![word.to_string()];
+        for variant in [word.to_ascii_lowercase(), word.to_ascii_uppercase()] {
+            if !variants.contains(&variant) {
+                variants.push(variant);
+            }
+        }
+        let mut result: Option<Vec<String>> = None;
+        for candidates in variants.iter().filter_map(|v| self.lookup_combined(v)) {
+            let result = result.get_or_insert_with(Vec::new);
+            for candidate in candidates {
+                if !result.contains(&candidate) {
+                    result.push(candidate);
                 }
             }
+        }
+        result
+    }
+
+    /// Dictionary annotation of a candidate
+    ///
+    /// Candidates of numeric entries ("3がつ" -> "三月") are annotated with
+    /// the form of their numbers unless the entry has its own.
+    pub fn annotation(&self, reading: &str, candidate: &str) -> Option<&str> {
+        let dictionary = self.dictionary.as_ref()?;
+        let reading = dictionary_reading(reading);
+        dictionary.annotation(&reading, candidate).or_else(|| {
+            let (key, numbers) = numeric::split_numbers(&reading)?;
+            dictionary.numeric_annotation(&key, &numbers, candidate)
+        })
+    }
+
+    /// Whether the dictionary or the remote dictionary has the reading
+    fn has_entry(&self, reading: &str) -> bool {
+        let reading = &*dictionary_reading(reading);
+        self.dictionary.as_ref().is_some_and(|dict| {
+            dict.contains(reading)
+                || dict.lookup_inflected(reading).is_some()
+                || (self.conversion.fuzzy_readings && !dict.fuzzy_readings(reading).is_empty())
+        }) || self
+            .remote
+            .as_ref()
+            .is_some_and(|remote| remote.lookup(reading).is_some())
+    }
+
+    /// Combined candidates with the reading itself as the last one
+    fn lookup_with_fallback(&self, reading: &str) -> Vec<String> {
+        let mut candidates = self.lookup_combined(reading).unwrap_or_default();
+        if !candidates.iter().any(|c| c == reading) {
+            candidates.push(reading.to_string());
+        }
+        candidates
+    }
+
+    /// Candidates of a particle: they stay in kana unless the user picks
+    /// another candidate
+    ///
+    /// A particle in katakana is kept in hiragana ("ハ" -> "は").
+    fn particle_candidates(&self, reading: &str) -> Vec<String> {
+        let mut candidates = vec![dictionary_reading(reading).into_owned()];
+        for candidate in self.lookup_with_fallback(reading) {
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+        candidates
+    }
+
+    /// Look up one segment of a segmented reading again
+    ///
+    /// The segment's reading is taken from `reading` at its span. A
+    /// particle following another segment keeps its reading first, as in
+    /// the initial segmentation; fixed tokens are returned unchanged.
+    pub fn convert_segment(&self, reading: &str, segments: &[Segment], index: usize) -> Segment {
+        let segment = &segments[index];
+        if segment.fixed {
+            return segment.clone();
+        }
+        let seg_reading: String = reading
+            .chars()
+            .skip(segment.start)
+            .take(segment.length)
+            .collect();
+        let candidates = if index > 0 && lattice::is_particle(&dictionary_reading(&seg_reading)) {
+            self.particle_candidates(&seg_reading)
+        } else {
+            self.lookup_with_fallback(&seg_reading)
+        };
+        Segment {
+            reading: seg_reading,
+            start: segment.start,
+            length: segment.length,
+            candidates,
+            fixed: false,
+        }
+    }
+
+    /// Register a word in this converter's dictionary (copy-on-write)
+    ///
+    /// Creates an empty dictionary if none is loaded.
+    pub fn add_entry(&mut self, reading: &str, candidate: &str) {
+        let dict = self
+            .dictionary
+            .get_or_insert_with(|| Arc::new(Dictionary::new()));
+        Arc::make_mut(dict).add_entry(reading, candidate);
+    }
 
-            match best_match {
-                Some((len, seg_reading)) => {
-                    let candidates = dict.lookup_with_fallback(&seg_reading);
-                    segments.push(Segment {
-                        reading: seg_reading,
-                        start: pos,
-                        length: len,
-                        candidates,
-                    });
-                    pos += len;
+    /// Alternative segmentations of a reading, cheapest first
+    ///
+    /// Fixed tokens (latin words, numbers) become their own segments and
+    /// are never looked up, except numbers matching a numeric entry
+    /// together with their neighbouring kana; the kana between them is
+    /// segmented along lattice paths. The first entry is the best segmentation with cost 0. Each further
+    /// entry replaces the segmentation of one convertible run with one of
+    /// its next best lattice paths, at the extra cost over the best path.
+    fn segmentations(&self, reading: &str, limit: usize) -> Vec<(u32, Vec<Segment>)> {
+        let chars: Vec<char> = reading.chars().collect();
+        if is_affix_reading(reading) {
+            return vec![(0, vec![self.affix_segment(reading, chars.len())])];
+        }
+        let tokens = self.tokenizer.tokenize(&chars);
+        let numeric = self.numeric_segments(&chars, &tokens);
+        let mut runs: Vec<(usize, Alternatives)> = numeric
+            .iter()
+            .map(|segment| (segment.start, vec![(0, vec![segment.clone()])]))
+            .collect();
+
+        for token in &tokens {
+            // Parts of the token not taken by a numeric segment
+            let end = token.start + token.length;
+            let mut pieces = Vec::new();
+            let mut pos = token.start;
+            for segment in numeric
+                .iter()
+                .filter(|s| s.start < end && s.start + s.length > token.start)
+            {
+                if segment.start > pos {
+                    pieces.push((pos, segment.start));
                 }
-                None => {
-                    // No match, take single character
-                    let ch: String = chars[pos..pos + 1].iter().collect();
-                    segments.push(Segment {
-                        reading: ch.clone(),
-                        start: pos,
-                        length: 1,
-                        candidates: vec![ch],
-                    });
-                    pos += 1;
+                pos = pos.max(segment.start + segment.length);
+            }
+            if pos < end {
+                pieces.push((pos, end));
+            }
+
+            for (start, end) in pieces {
+                let text = &chars[start..end];
+                match token.kind {
+                    TokenKind::Fixed => {
+                        let text: String = text.iter().collect();
+                        // Abbrev entries make ASCII words convertible
+                        let abbrev = self.lookup_abbrev(&text);
+                        let fixed = abbrev.is_none();
+                        let mut candidates = abbrev.unwrap_or_default();
+                        if !candidates.contains(&text) {
+                            candidates.push(text.clone());
+                        }
+                        runs.push((
+                            start,
+                            vec![(
+                                0,
+                                vec![Segment {
+                                    reading: text,
+                                    start,
+                                    length: end - start,
+                                    candidates,
+                                    fixed,
+                                }],
+                            )],
+                        ));
+                    }
+                    TokenKind::Convertible => {
+                        runs.push((start, self.segment_convertible(text, start, limit)));
+                    }
                 }
             }
         }
+        runs.sort_by_key(|(start, _)| *start);
+        let runs: Vec<Alternatives> = runs.into_iter().map(|(_, run)| run).collect();
+
+        let join = |varied: usize, alternative: &[Segment]| -> Vec<Segment> {
+            runs.iter()
+                .enumerate()
+                .flat_map(|(i, alternatives)| match i == varied {
+                    true => alternative.to_vec(),
+                    false => alternatives[0].1.clone(),
+                })
+                .collect()
+        };
+
+        let mut result = vec![(0, join(usize::MAX, &[]))];
+        for (i, alternatives) in runs.iter().enumerate() {
+            for (extra, alternative) in alternatives.iter().skip(1) {
+                result.push((*extra, join(i, alternative)));
+            }
+        }
+        result.sort_by_key(|(cost, _)| *cost);
+        result.truncate(limit.max(1));
+        result
+    }
+
+    /// The single segment of a prefix or suffix reading
+    ///
+    /// The reading without its marker comes last, so committing without
+    /// converting doesn't insert the marker.
+    fn affix_segment(&self, reading: &str, length: usize) -> Segment {
+        let mut candidates = self.lookup_combined(reading).unwrap_or_default();
+        let bare = reading.trim_matches(AFFIX_MARKERS).to_string();
+        if !candidates.contains(&bare) {
+            candidates.push(bare);
+        }
+        Segment {
+            reading: reading.to_string(),
+            start: 0,
+            length,
+            candidates,
+            fixed: false,
+        }
+    }
 
+    /// Segments for numbers with the kana around them ("3がつ")
+    ///
+    /// Each number token takes the longest kana before and after it that
+    /// forms a numeric dictionary entry ("#がつ"). Numbers without one
+    /// stay fixed tokens.
+    fn numeric_segments(&self, chars: &[char], tokens: &[Token]) -> Vec<Segment> {
+        if self.dictionary.is_none() {
+            return Vec::new();
+        }
+        let mut segments: Vec<Segment> = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            let number = &chars[token.start..token.start + token.length];
+            if token.kind != TokenKind::Fixed || !number.iter().all(|&c| numeric::is_digit(c)) {
+                continue;
+            }
+            // Kana not already taken by the previous number
+            let taken = segments.last().map_or(0, |s| s.start + s.length);
+            let before = match i.checked_sub(1).map(|p| &tokens[p]) {
+                Some(prev) if prev.kind == TokenKind::Convertible => {
+                    (token.start - prev.start.max(taken)).min(MAX_NUMERIC_AFFIX)
+                }
+                _ => 0,
+            };
+            let after = match tokens.get(i + 1) {
+                Some(next) if next.kind == TokenKind::Convertible => {
+                    next.length.min(MAX_NUMERIC_AFFIX)
+                }
+                _ => 0,
+            };
+
+            let mut best: Option<Segment> = None;
+            for pre in (0..=before).rev() {
+                for post in (0..=after).rev() {
+                    if pre + post == 0
+                        || best
+                            .as_ref()
+                            .is_some_and(|b| b.length >= pre + post + number.len())
+                    {
+                        continue;
+                    }
+                    let start = token.start - pre;
+                    let length = pre + number.len() + post;
+                    let reading: String = chars[start..start + length].iter().collect();
+                    if let Some(mut candidates) = self.lookup_numeric(&reading) {
+                        if !candidates.contains(&reading) {
+                            candidates.push(reading.clone());
+                        }
+                        best = Some(Segment {
+                            reading,
+                            start,
+                            length,
+                            candidates,
+                            fixed: false,
+                        });
+                    }
+                }
+            }
+            segments.extend(best);
+        }
         segments
     }
 
+    /// Segment a convertible run along its cheapest lattice paths
+    ///
+    /// Returns up to `limit` segmentations with their extra cost over the
+    /// best one.
+    fn segment_convertible(
+        &self,
+        chars: &[char],
+        offset: usize,
+        limit: usize,
+    ) -> Vec<(u32, Vec<Segment>)> {
+        if !self.has_dictionary() {
+            // No dictionary, return the entire run as one segment
+            let reading: String = chars.iter().collect();
+            return vec![(
+                0,
+                vec![Segment {
+                    reading: reading.clone(),
+                    start: offset,
+                    length: chars.len(),
+                    candidates: vec![reading],
+                    fixed: false,
+                }],
+            )];
+        }
+
+        // Katakana is segmented as hiragana, so its particles are found
+        let hiragana: Vec<char> = chars.iter().map(|&c| kana::hiragana_char(c)).collect();
+        let paths = lattice::best_paths(
+            &hiragana,
+            |reading| self.has_entry(reading),
+            |reading| self.dictionary.as_ref()?.cost(&dictionary_reading(reading)),
+            limit,
+        );
+        let best_cost = paths[0].cost;
+        // Alternatives that leave more characters unconverted only add
+        // half-converted phrases
+        let unknown = |path: &lattice::Path| -> usize {
+            path.nodes
+                .iter()
+                .filter(|node| node.class == NodeClass::Unknown)
+                .map(|node| node.length)
+                .sum()
+        };
+        let best_unknown = unknown(&paths[0]);
+        paths
+            .into_iter()
+            .filter(|path| unknown(path) <= best_unknown)
+            .map(|path| {
+                let segments = path
+                    .nodes
+                    .into_iter()
+                    .map(|node| {
+                        let reading: String =
+                            chars[node.start..node.start + node.length].iter().collect();
+                        let candidates = match node.class {
+                            NodeClass::Particle => self.particle_candidates(&reading),
+                            NodeClass::Content | NodeClass::Unknown => {
+                                self.lookup_with_fallback(&reading)
+                            }
+                        };
+                        Segment {
+                            reading,
+                            start: offset + node.start,
+                            length: node.length,
+                            candidates,
+                            fixed: false,
+                        }
+                    })
+                    .collect();
+                (path.cost - best_cost, segments)
+            })
+            .collect()
+    }
+
     /// Convert with segment information
     pub fn convert_with_segments(&self, reading: &str) -> ConversionResult {
+        self.convert_with_segments_using(reading, |_| {})
+    }
+
+    /// Convert with segment information, adjusting candidates on the way
+    ///
+    /// `rerank` is applied to the segments of every segmentation before
+    /// whole-phrase candidates are combined from them, so filtering and
+    /// reordering carry over to the N-best list.
+    pub fn convert_with_segments_using(
+        &self,
+        reading: &str,
+        rerank: impl Fn(&mut Segment),
+    ) -> ConversionResult {
         if reading.is_empty() {
             return ConversionResult {
                 combined_candidates: vec![],
                 segments: vec![],
+                scores: HashMap::new(),
             };
         }
 
-        let segments = self.segment_with_info(reading);
+        // Whitespace has no conversion; keep it as a single segment
+        if reading.chars().all(char::is_whitespace) {
+            let text = self.punctuation.apply(reading);
+            return ConversionResult {
+                combined_candidates: vec![text.clone()],
+                segments: vec![Segment {
+                    reading: reading.to_string(),
+                    start: 0,
+                    length: reading.chars().count(),
+                    candidates: vec![text],
+                    fixed: false,
+                }],
+                scores: HashMap::new(),
+            };
+        }
 
-        // Combine first candidates from each segment
-        let combined: String = segments
-            .iter()
-            .map(|s| s.candidates.first().unwrap_or(&s.reading).as_str())
-            .collect();
+        let mut segmentations = self.segmentations(reading, self.conversion.n_best);
+        let mut ranked = Vec::new();
+        for (extra, segments) in &mut segmentations {
+            segments.iter_mut().for_each(&rerank);
+            for (cost, text) in self.ranked_combinations(segments) {
+                ranked.push((*extra + cost, text));
+            }
+        }
+        ranked.sort_by_key(|(cost, _)| *cost);
+
+        let (combined_candidates, scores) = self.finish_combined(reading, ranked);
+        ConversionResult {
+            combined_candidates,
+            segments: segmentations.swap_remove(0).1,
+            scores,
+        }
+    }
 
-        let mut combined_candidates = vec![combined];
-        // Add original reading as fallback
-        if combined_candidates[0] != reading {
-            combined_candidates.push(reading.to_string());
+    /// Convert with a segment boundary at `cursor` (a character index)
+    ///
+    /// If the best segmentation has no boundary there, the readings before
+    /// and after the cursor are segmented separately and whole-phrase
+    /// candidates are combined from both.
+    pub fn convert_with_boundary_using(
+        &self,
+        reading: &str,
+        cursor: usize,
+        rerank: impl Fn(&mut Segment),
+    ) -> ConversionResult {
+        let result = self.convert_with_segments_using(reading, &rerank);
+        let chars: Vec<char> = reading.chars().collect();
+        if cursor == 0
+            || cursor >= chars.len()
+            || result
+                .segments
+                .iter()
+                .any(|seg| seg.start + seg.length == cursor)
+        {
+            return result;
         }
 
+        let before: String = chars[..cursor].iter().collect();
+        let after: String = chars[cursor..].iter().collect();
+        let mut segments = self.segmentations(&before, 1).swap_remove(0).1;
+        segments.extend(
+            self.segmentations(&after, 1)
+                .swap_remove(0)
+                .1
+                .into_iter()
+                .map(|mut seg| {
+                    seg.start += cursor;
+                    seg
+                }),
+        );
+        segments.iter_mut().for_each(&rerank);
+        let (combined_candidates, scores) = self.combine_candidates(reading, &segments);
         ConversionResult {
             combined_candidates,
             segments,
+            scores,
+        }
+    }
+
+    /// Build whole-phrase candidates from segments
+    ///
+    /// Combines segment candidates into up to `n_best` phrases, best first,
+    /// and adds the original reading as a fallback. The punctuation policy
+    /// is applied to all of them. Returns the phrases with their scores.
+    pub fn combine_candidates(
+        &self,
+        reading: &str,
+        segments: &[Segment],
+    ) -> (Vec<String>, HashMap<String, f64>) {
+        if segments.is_empty() {
+            return (vec![], HashMap::new());
+        }
+        let ranked = self.ranked_combinations(segments);
+        self.finish_combined(reading, ranked)
+    }
+
+    /// The `n_best` cheapest choices of one candidate per segment
+    ///
+    /// Picking a segment's k-th candidate costs k times
+    /// [`CANDIDATE_RANK_COST`]. A segment's reading is only used where it is
+    /// the first candidate; the all-kana phrase is added separately.
+    fn ranked_combinations(&self, segments: &[Segment]) -> Vec<(u32, String)> {
+        let options: Vec<Vec<&str>> = segments
+            .iter()
+            .map(|seg| {
+                let mut options = vec![seg.candidates.first().unwrap_or(&seg.reading).as_str()];
+                options.extend(
+                    seg.candidates
+                        .iter()
+                        .skip(1)
+                        .filter(|c| **c != seg.reading)
+                        .map(String::as_str),
+                );
+                options
+            })
+            .collect();
+
+        // Best-first search over candidate index vectors
+        let mut ranked = Vec::new();
+        let mut queue = BinaryHeap::from([Reverse((0u32, vec![0usize; options.len()]))]);
+        let mut seen = HashSet::new();
+        while let Some(Reverse((cost, choice))) = queue.pop() {
+            if ranked.len() >= self.conversion.n_best.max(1) {
+                break;
+            }
+            if !seen.insert(choice.clone()) {
+                continue;
+            }
+            ranked.push((
+                cost,
+                choice
+                    .iter()
+                    .zip(&options)
+                    .map(|(&i, options)| options[i])
+                    .collect(),
+            ));
+            for (segment, &i) in choice.iter().enumerate() {
+                if i + 1 < options[segment].len() {
+                    let mut next = choice.clone();
+                    next[segment] += 1;
+                    queue.push(Reverse((cost + CANDIDATE_RANK_COST, next)));
+                }
+            }
+        }
+        ranked
+    }
+
+    /// Punctuate and deduplicate ranked phrases and append the reading
+    fn finish_combined(
+        &self,
+        reading: &str,
+        ranked: Vec<(u32, String)>,
+    ) -> (Vec<String>, HashMap<String, f64>) {
+        let reading = self.punctuation.apply(reading);
+        let mut combined: Vec<String> = Vec::new();
+        let mut scores = HashMap::new();
+        for (cost, text) in ranked {
+            let text = self.punctuation.apply(&text);
+            if text != reading && !combined.contains(&text) {
+                scores.insert(text.clone(), phrase_score(cost));
+                combined.push(text);
+            }
+            if combined.len() >= self.conversion.n_best.max(1) {
+                break;
+            }
         }
+        combined.push(reading);
+        (combined, scores)
     }
 
     /// Check if segment adjustment is possible
@@ -140,6 +931,11 @@ impl Converter {
             return false;
         }
 
+        // Fixed tokens can't be split or merged into kana
+        if segments[index].fixed || segments[index + 1].fixed {
+            return false;
+        }
+
         match direction {
             AdjustDirection::Shrink => segments[index].length > 1,
             AdjustDirection::Extend => segments[index + 1].length > 1,
@@ -181,7 +977,32 @@ impl Converter {
         };
 
         // Rebuild segments with new boundaries
-        self.rebuild_segments_from_boundaries(&chars, &new_boundaries)
+        self.rebuild_segments_from_boundaries(&chars, &new_boundaries, current_segments)
+    }
+
+    /// Cut a reading into segments of the given lengths
+    ///
+    /// Segments of `current_segments` with the same span are kept; the
+    /// others are looked up again. Returns `None` if the lengths don't add
+    /// up to the reading.
+    pub fn resegment(
+        &self,
+        reading: &str,
+        lengths: &[usize],
+        current_segments: &[Segment],
+    ) -> Option<Vec<Segment>> {
+        let chars: Vec<char> = reading.chars().collect();
+        if lengths.contains(&0) || lengths.iter().sum::<usize>() != chars.len() {
+            return None;
+        }
+        let boundaries: Vec<usize> = lengths
+            .iter()
+            .scan(0, |end, length| {
+                *end += length;
+                Some(*end)
+            })
+            .collect();
+        Some(self.rebuild_segments_from_boundaries(&chars, &boundaries, current_segments))
     }
 
     /// Calculate boundaries after shrinking a segment
@@ -228,10 +1049,13 @@ impl Converter {
     }
 
     /// Rebuild segments from character boundaries
+    ///
+    /// Segments whose span didn't change (including fixed tokens) are kept.
     fn rebuild_segments_from_boundaries(
         &self,
         chars: &[char],
         boundaries: &[usize],
+        current_segments: &[Segment],
     ) -> Vec<Segment> {
         let mut segments = Vec::new();
         let mut start = 0;
@@ -241,17 +1065,24 @@ impl Converter {
                 continue;
             }
 
+            if let Some(seg) = current_segments
+                .iter()
+                .find(|s| s.start == start && s.length == end - start)
+            {
+                segments.push(seg.clone());
+                start = end;
+                continue;
+            }
+
             let seg_reading: String = chars[start..end].iter().collect();
-            let candidates = match &self.dictionary {
-                Some(dict) => dict.lookup_with_fallback(&seg_reading),
-                None => vec![seg_reading.clone()],
-            };
+            let candidates = self.lookup_with_fallback(&seg_reading);
 
             segments.push(Segment {
                 reading: seg_reading,
                 start,
                 length: end - start,
                 candidates,
+                fixed: false,
             });
 
             start = end;
@@ -260,9 +1091,30 @@ impl Converter {
         segments
     }
 
-    /// Check if dictionary is loaded
+    /// Find the end of the chunk that starts at `start`
+    ///
+    /// Returns the end index (exclusive) of the next chunk of at most `limit`
+    /// characters. When the remainder does not fit, the boundary is placed
+    /// after the last punctuation or space in the second half of the window
+    /// so that phrases are not cut in the middle where avoidable.
+    pub fn chunk_end(chars: &[char], start: usize, limit: usize) -> usize {
+        let limit = limit.max(1);
+        if chars.len() - start <= limit {
+            return chars.len();
+        }
+
+        let hard_end = start + limit;
+        let min_end = start + limit / 2;
+        (min_end..hard_end)
+            .rev()
+            .find(|&i| CHUNK_BREAK_CHARS.contains(&chars[i]))
+            .map(|i| i + 1)
+            .unwrap_or(hard_end)
+    }
+
+    /// Check if a dictionary is loaded or a remote dictionary configured
     pub fn has_dictionary(&self) -> bool {
-        self.dictionary.is_some()
+        self.dictionary.is_some() || self.remote.is_some()
     }
 }
 
@@ -277,6 +1129,88 @@ mod tests {
     use super::*;
     use std::path::PathBuf;
 
+    #[test]
+    fn test_fixed_tokens_pass_through() {
+        let dict = Dictionary::load(test_dict_path()).unwrap();
+        let converter = Converter::new(Some(dict));
+        let result = converter.convert_with_segments("きょうhubにほんご");
+        let readings: Vec<&str> = result.segments.iter().map(|s| s.reading.as_str()).collect();
+        assert_eq!(readings, vec!["きょう", "hub", "にほんご"]);
+        assert!(result.segments[1].fixed);
+        assert_eq!(result.segments[1].start, 3);
+        assert_eq!(result.segments[2].start, 6);
+        assert_eq!(result.combined_candidates[0], "今日hub日本語");
+
+        // Boundaries next to a fixed token can't move
+        let adjusted = converter.adjust_segment(
+            "きょうhubにほんご",
+            &result.segments,
+            0,
+            AdjustDirection::Extend,
+        );
+        assert_eq!(adjusted.len(), 3);
+        assert!(adjusted[1].fixed);
+    }
+
+    #[test]
+    fn test_abbrev_entries() {
+        let mut dict = Dictionary::load(test_dict_path()).unwrap();
+        dict.add_entry("file", "ファイル");
+        dict.add_entry("PDF", "ＰＤＦ");
+        let converter = Converter::new(Some(dict));
+
+        let result = converter.convert_with_segments("file");
+        assert_eq!(result.segments.len(), 1);
+        assert!(!result.segments[0].fixed);
+        assert_eq!(result.segments[0].candidates, vec!["ファイル", "file"]);
+        // Case-insensitive
+        let result = converter.convert_with_segments("File");
+        assert_eq!(result.segments[0].candidates, vec!["ファイル", "File"]);
+        let result = converter.convert_with_segments("pdf");
+        assert_eq!(result.segments[0].candidates, vec!["ＰＤＦ", "pdf"]);
+
+        let result = converter.convert_with_segments("きょうfile");
+        assert_eq!(result.combined_candidates[0], "今日ファイル");
+        // Words without an entry stay fixed
+        let result = converter.convert_with_segments("hub");
+        assert!(result.segments[0].fixed);
+    }
+
+    #[test]
+    fn test_numeric_entries() {
+        let converter = Converter::new(Some(load_test_dictionary()));
+        let result = converter.convert_with_segments("3がつ");
+        assert_eq!(result.segments.len(), 1);
+        assert_eq!(
+            result.segments[0].candidates,
+            vec!["3月", "３月", "三月", "3がつ"]
+        );
+        assert_eq!(result.combined_candidates[0], "3月");
+        assert_eq!(converter.annotation("3がつ", "三月"), Some("漢数字"));
+        assert_eq!(converter.annotation("3がつ", "３月"), Some("全角数字"));
+        assert_eq!(converter.annotation("3がつ", "3がつ"), None);
+
+        // Kana around the number that is not part of the entry is converted as usual
+        let result = converter.convert_with_segments("だい12かんじ");
+        let readings: Vec<&str> = result.segments.iter().map(|s| s.reading.as_str()).collect();
+        assert_eq!(readings, vec!["だい12", "かんじ"]);
+        assert_eq!(result.combined_candidates[0], "第１２漢字");
+
+        // Numbers without a numeric entry stay fixed
+        let result = converter.convert_with_segments("3にち");
+        assert!(result.segments[0].fixed);
+        assert_eq!(result.segments[0].reading, "3");
+    }
+
+    #[test]
+    fn test_validate_reading() {
+        assert!(validate_reading("きょう").is_ok());
+        assert!(validate_reading("いち\nに\t").is_ok());
+        assert!(validate_reading(" ").is_ok());
+        assert!(validate_reading("").is_err());
+        assert!(validate_reading("き\u{0}").is_err());
+    }
+
     fn test_dict_path() -> PathBuf {
         PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/test-dict.utf8")
     }
@@ -328,6 +1262,263 @@ mod tests {
             .any(|c| c.contains("今日")));
     }
 
+    #[test]
+    fn test_convert_lattice_segmentation() {
+        let converter = Converter::new(Some(load_test_dictionary()));
+        let result = converter.convert_with_segments("きょうはいしゃに");
+        let readings: Vec<&str> = result.segments.iter().map(|s| s.reading.as_str()).collect();
+        assert_eq!(readings, vec!["きょう", "は", "いしゃ", "に"]);
+        assert_eq!(result.combined_candidates[0], "今日は医者に");
+    }
+
+    #[test]
+    fn test_convert_segment() {
+        let converter = Converter::new(Some(load_test_dictionary()));
+        let mut segments = converter.convert_with_segments("きょうはいしゃに").segments;
+        segments[2].candidates.clear();
+
+        let segment = converter.convert_segment("きょうはいしゃに", &segments, 2);
+        assert_eq!(segment.reading, "いしゃ");
+        assert_eq!((segment.start, segment.length), (4, 3));
+        assert_eq!(segment.candidates, vec!["医者", "いしゃ"]);
+
+        // A particle after another segment stays in kana
+        let segment = converter.convert_segment("きょうはいしゃに", &segments, 3);
+        assert_eq!(segment.candidates[0], "に");
+    }
+
+    #[test]
+    fn test_convert_with_boundary_at_cursor() {
+        let converter = Converter::new(Some(load_test_dictionary()));
+        let ends = |result: &ConversionResult| -> Vec<usize> {
+            result
+                .segments
+                .iter()
+                .map(|seg| seg.start + seg.length)
+                .collect()
+        };
+
+        // A boundary already at the cursor keeps the best segmentation
+        let result = converter.convert_with_boundary_using("きょうはいしゃに", 4, |_| {});
+        assert_eq!(ends(&result), vec![3, 4, 7, 8]);
+        assert_eq!(result.combined_candidates[0], "今日は医者に");
+
+        // Inside "いしゃ" the reading is split at the cursor
+        let result = converter.convert_with_boundary_using("きょうはいしゃに", 5, |_| {});
+        assert!(ends(&result).contains(&5));
+        assert_eq!(result.segments[0].candidates[0], "今日");
+        assert!(result.combined_candidates[0].starts_with("今日"));
+        assert_eq!(
+            result.combined_candidates.last().unwrap(),
+            "きょうはいしゃに"
+        );
+    }
+
+    #[test]
+    fn test_n_best_combined_candidates() {
+        let mut converter = Converter::new(Some(load_test_dictionary()));
+        let result = converter.convert_with_segments("きょうはいしゃに");
+        assert_eq!(
+            result.combined_candidates,
+            vec![
+                "今日は医者に",
+                "京は医者に",
+                "今日歯医者に",
+                "教は医者に",
+                "今日敗者に",
+                "きょうはいしゃに"
+            ]
+        );
+
+        converter.set_conversion(ConversionConfig {
+            n_best: 2,
+            ..Default::default()
+        });
+        let result = converter.convert_with_segments("きょうはいしゃに");
+        assert_eq!(
+            result.combined_candidates,
+            vec!["今日は医者に", "京は医者に", "きょうはいしゃに"]
+        );
+        // Each step down a segment's candidates lowers the score
+        assert_eq!(result.scores["今日は医者に"], 1.0);
+        assert_eq!(
+            result.scores["京は医者に"],
+            phrase_score(CANDIDATE_RANK_COST)
+        );
+        assert!(!result.scores.contains_key("きょうはいしゃに"));
+    }
+
+    #[test]
+    fn test_chunk_end() {
+        let chars: Vec<char> = "あいうえお".chars().collect();
+        // Fits entirely
+        assert_eq!(Converter::chunk_end(&chars, 0, 10), 5);
+        // Hard cut without punctuation
+        assert_eq!(Converter::chunk_end(&chars, 0, 2), 2);
+        assert_eq!(Converter::chunk_end(&chars, 4, 2), 5);
+
+        // Prefer cutting after punctuation in the second half of the window
+        let chars: Vec<char> = "あいう、えおかきく".chars().collect();
+        assert_eq!(Converter::chunk_end(&chars, 0, 6), 4);
+        // Punctuation too early in the window is ignored
+        assert_eq!(Converter::chunk_end(&chars, 0, 8), 8);
+    }
+
+    #[test]
+    fn test_combine_candidates_after_adjust() {
+        let dict = load_test_dictionary();
+        let converter = Converter::new(Some(dict));
+
+        let result = converter.convert_with_segments("へんかんにゅうりょく");
+        assert_eq!(result.combined_candidates[0], "変換入力");
+
+        // Shrinking the first segment breaks both dictionary matches
+        let segments = converter.adjust_segment(
+            "へんかんにゅうりょく",
+            &result.segments,
+            0,
+            AdjustDirection::Shrink,
+        );
+        let (combined, scores) = converter.combine_candidates("へんかんにゅうりょく", &segments);
+        assert_eq!(combined, vec!["へんかんにゅうりょく"]);
+        assert!(scores.is_empty());
+    }
+
+    #[test]
+    fn test_shared_dictionary_copy_on_write() {
+        let converter = Converter::new(Some(load_test_dictionary()));
+        let mut session_converter = converter.clone();
+        assert!(Arc::ptr_eq(
+            &converter.shared_dictionary().unwrap(),
+            &session_converter.shared_dictionary().unwrap()
+        ));
+
+        session_converter.add_entry("あいうえお", "愛飢男");
+        let result = session_converter.convert_with_segments("あいうえお");
+        assert_eq!(result.combined_candidates[0], "愛飢男");
+
+        // The original snapshot is untouched
+        let result = converter.convert_with_segments("あいうえお");
+        assert_eq!(result.combined_candidates[0], "あいうえお");
+    }
+
+    #[test]
+    fn test_combine_candidates_punctuation_policy() {
+        use crate::punctuation::{PunctuationStyle, SpaceWidth};
+
+        let mut converter = Converter::new(Some(load_test_dictionary()));
+        converter.set_punctuation(PunctuationConfig {
+            style: PunctuationStyle::Academic,
+            space: SpaceWidth::Keep,
+        });
+        let result = converter.convert_with_segments("へんかん、にゅうりょく。");
+        assert_eq!(result.combined_candidates[0], "変換，入力．");
+        assert_eq!(result.combined_candidates[1], "へんかん，にゅうりょく．");
+    }
+
+    #[test]
+    fn test_convert_inflected_reading() {
+        let dict = load_test_dictionary();
+        let converter = Converter::new(Some(dict));
+
+        let result = converter.convert_with_segments("かえった");
+        assert_eq!(result.segments.len(), 1);
+        assert_eq!(
+            result.segments[0].candidates,
+            vec!["帰った", "返った", "かえった"]
+        );
+        let result = converter.convert_with_segments("きょういって");
+        assert_eq!(result.combined_candidates[0], "今日行って");
+    }
+
+    #[test]
+    fn test_verified_okuri_ari_candidates_rank_first() {
+        let mut dict = load_test_dictionary();
+        for (reading, word) in [("かく", "角"), ("かえる", "蛙"), ("かいて", "買い手")]
+        {
+            dict.add_entry(reading, word);
+        }
+        let converter = Converter::new(Some(dict));
+        let candidates = |reading: &str| {
+            let result = converter.convert_with_segments(reading);
+            assert_eq!(result.segments.len(), 1);
+            result.segments[0].candidates.clone()
+        };
+
+        // The okurigana block lists く for 書
+        assert_eq!(candidates("かく"), ["書く", "角", "かく"]);
+        // る is in the row of かえr
+        assert_eq!(candidates("かえる"), ["帰る", "返る", "蛙", "かえる"]);
+        // 書いて is only a euphonic guess
+        assert_eq!(
+            candidates("かいて"),
+            ["買い手", "書いて", "欠いて", "かいて"]
+        );
+        assert_eq!(candidates("いって"), ["行って", "言って", "いって"]);
+    }
+
+    #[test]
+    fn test_fuzzy_readings_find_other_spellings() {
+        let mut converter = Converter::new(Some(load_test_dictionary()));
+        assert_eq!(converter.lookup_combined("にゅーりょく"), None);
+
+        converter.set_conversion(ConversionConfig {
+            fuzzy_readings: true,
+            ..Default::default()
+        });
+        assert_eq!(converter.lookup_combined("にゅーりょく").unwrap(), ["入力"]);
+        // Spellings with an entry of their own keep it
+        assert_eq!(
+            converter.lookup_combined("きょう").unwrap(),
+            ["今日", "京", "教"]
+        );
+        let result = converter.convert_with_segments("きょーはいしゃに");
+        assert_eq!(result.combined_candidates[0], "今日は医者に");
+    }
+
+    #[test]
+    fn test_katakana_readings_are_looked_up_in_hiragana() {
+        let converter = Converter::new(Some(load_test_dictionary()));
+        assert_eq!(
+            converter.lookup_combined("キョウ").unwrap(),
+            ["今日", "京", "教", "キョウ"]
+        );
+        assert_eq!(
+            converter.lookup_combined("ｷｮｳ").unwrap(),
+            ["今日", "京", "教", "ｷｮｳ"]
+        );
+        assert_eq!(converter.annotation("カンジ", "漢字"), Some("kanji"));
+
+        let result = converter.convert_with_segments("キョウハイシャニ");
+        assert_eq!(result.combined_candidates[0], "今日は医者に");
+        assert_eq!(result.segments[0].reading, "キョウ");
+        assert_eq!(
+            result.combined_candidates.last().unwrap(),
+            "キョウハイシャニ"
+        );
+    }
+
+    #[test]
+    fn test_convert_affix_reading() {
+        let mut dict = load_test_dictionary();
+        dict.add_entry("ちょう>", "超");
+        dict.add_entry(">てき", "的");
+        let converter = Converter::new(Some(dict));
+
+        let result = converter.convert_with_segments("ちょう>");
+        assert_eq!(result.segments.len(), 1);
+        assert_eq!(result.segments[0].candidates, vec!["超", "ちょう"]);
+        let result = converter.convert_with_segments(">てき");
+        assert_eq!(result.combined_candidates[0], "的");
+        // No entry: the reading without the marker
+        let result = converter.convert_with_segments("きょう>");
+        assert_eq!(result.segments[0].candidates, vec!["きょう"]);
+
+        assert!(is_affix_reading("<てき"));
+        assert!(!is_affix_reading(">"));
+        assert!(!is_affix_reading("ちょう>かんじ"));
+    }
+
     #[test]
     fn test_convert_no_match() {
         let dict = load_test_dictionary();
@@ -336,5 +1527,24 @@ mod tests {
         // No match in dictionary
         let result = converter.convert_with_segments("あいうえお");
         assert!(result.combined_candidates.iter().any(|c| c == "あいうえお"));
+        // One pass-through segment, not one per character
+        assert_eq!(result.segments.len(), 1);
+        assert_eq!(result.segments[0].candidates, vec!["あいうえお"]);
+    }
+
+    #[test]
+    fn test_generated_candidates() {
+        let all = GeneratedForms {
+            halfwidth_katakana: true,
+            fullwidth_alphanumeric: true,
+        };
+        assert_eq!(
+            Converter::generated_candidates("git2へ", all),
+            vec!["ｇｉｔ２へ", "git2ﾍ"]
+        );
+        // Full-width forms only for readings with letters or digits
+        assert_eq!(Converter::generated_candidates("あずき", all), vec!["ｱｽﾞｷ"]);
+        assert!(Converter::generated_candidates("!?", all).is_empty());
+        assert!(Converter::generated_candidates("abc", GeneratedForms::default()).is_empty());
     }
 }
@@ -0,0 +1,306 @@
+//! Background daemon management (`azuki-server daemon start|stop|status`)
+//!
+//! The daemon is an ordinary socket-mode server (`azuki-server --socket
+//! <path>`) spawned detached from the terminal. It holds a lock for its
+//! whole life so only one runs per user, and records its pid and socket
+//! path in a pidfile under the state directory so later `stop` and
+//! `status` invocations can find it.
+
+use crate::cli::{Cli, DaemonAction, Listen};
+use crate::config::state_dir;
+use crate::error::{AzukiError, Result};
+use crate::signal::SIGTERM;
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long `start` and `stop` wait for the daemon to come up or go away
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Set in the environment of the process `start` spawns as the daemon
+const DAEMON_ENV: &str = "AZUKI_DAEMON";
+
+/// Default socket path (`$XDG_RUNTIME_DIR/azuki/azuki.sock`)
+///
+/// Falls back to the state directory when there is no runtime directory.
+pub fn socket_path() -> PathBuf {
+    if let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") {
+        if !runtime_dir.is_empty() {
+            return PathBuf::from(runtime_dir).join("azuki/azuki.sock");
+        }
+    }
+    state_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("azuki.sock")
+}
+
+fn pid_path() -> Result<PathBuf> {
+    state_dir()
+        .map(|dir| dir.join("daemon.pid"))
+        .ok_or_else(|| AzukiError::Config("Cannot determine state directory".to_string()))
+}
+
+fn lock_path() -> Result<PathBuf> {
+    Ok(pid_path()?.with_file_name("daemon.lock"))
+}
+
+/// A running daemon recorded in the pidfile
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DaemonInfo {
+    pid: u32,
+    socket: PathBuf,
+}
+
+impl DaemonInfo {
+    /// Pidfile contents: the pid, then the socket path, one per line
+    fn to_text(&self) -> String {
+        format!("{}\n{}\n", self.pid, self.socket.display())
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        let pid = lines.next()?.trim().parse().ok()?;
+        let socket = PathBuf::from(lines.next()?.trim());
+        Some(Self { pid, socket })
+    }
+}
+
+fn is_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks that the process exists
+    unsafe { libc::kill(pid, 0) == 0 }
+}
+
+/// The daemon recorded in the pidfile, if it is still running
+fn running() -> Result<Option<DaemonInfo>> {
+    let path = pid_path()?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(DaemonInfo::parse(&text).filter(|info| is_alive(info.pid)))
+}
+
+fn already_running(info: &DaemonInfo) -> AzukiError {
+    AzukiError::Config(format!(
+        "azuki-server daemon already running (pid {}, socket {})",
+        info.pid,
+        info.socket.display()
+    ))
+}
+
+/// Lock held by the daemon until it exits
+pub struct InstanceLock {
+    _file: fs::File,
+}
+
+/// Claim the single daemon instance if this process was spawned as the daemon
+///
+/// Takes the daemon lock and records this process in the pidfile. Fails if
+/// another daemon holds the lock, which also settles two `start`s racing
+/// each other.
+pub fn claim(listen: Option<&Listen>) -> Result<Option<InstanceLock>> {
+    if std::env::var_os(DAEMON_ENV).is_none() {
+        return Ok(None);
+    }
+    let Some(Listen::Unix(socket)) = listen else {
+        return Err(AzukiError::Config(
+            "The daemon only listens on Unix sockets".to_string(),
+        ));
+    };
+    let lock_path = lock_path()?;
+    if let Some(parent) = lock_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(fs::TryLockError::WouldBlock) => {
+            return Err(match running()? {
+                Some(info) => already_running(&info),
+                None => AzukiError::Config("azuki-server daemon already running".to_string()),
+            })
+        }
+        Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+    }
+
+    let info = DaemonInfo {
+        pid: std::process::id(),
+        socket: socket.clone(),
+    };
+    fs::write(pid_path()?, info.to_text())?;
+    Ok(Some(InstanceLock { _file: file }))
+}
+
+/// Remove a stale socket file, refusing if a server still listens on it
+pub fn prepare_socket(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(AzukiError::Config(format!(
+                "Socket {} is already in use",
+                path.display()
+            )));
+        }
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Run a `daemon` subcommand
+///
+/// `start` passes the server options of `cli` on to the daemon and listens
+/// on its Unix socket if one was given.
+pub fn run(action: DaemonAction, cli: &Cli) -> Result<()> {
+    match action {
+        DaemonAction::Start => start(cli),
+        DaemonAction::Stop => stop(),
+        DaemonAction::Status => status(),
+    }
+}
+
+fn start(cli: &Cli) -> Result<()> {
+    if let Some(info) = running()? {
+        return Err(already_running(&info));
+    }
+
+    let socket = match &cli.listen {
+        Some(Listen::Unix(path)) => path.clone(),
+        Some(Listen::Tcp(_)) => {
+            return Err(AzukiError::Config(
+                "The daemon only listens on Unix sockets".to_string(),
+            ))
+        }
+        None => socket_path(),
+    };
+    prepare_socket(&socket)?;
+    let pid_path = pid_path()?;
+    if let Some(parent) = pid_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let log = fs::File::create(pid_path.with_file_name("daemon.log"))?;
+
+    let mut child = Command::new(std::env::current_exe()?)
+        .args(cli.server_args())
+        .arg("--socket")
+        .arg(&socket)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log)
+        .env(DAEMON_ENV, "1")
+        // Own process group, so terminal signals don't reach the daemon
+        .process_group(0)
+        .spawn()?;
+
+    // The daemon writes the pidfile itself once it holds the lock
+    let info = DaemonInfo {
+        pid: child.id(),
+        socket,
+    };
+
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while UnixStream::connect(&info.socket).is_err() {
+        if let Some(status) = child.try_wait()? {
+            // Another start won the race
+            if let Some(other) = running()? {
+                return Err(already_running(&other));
+            }
+            return Err(AzukiError::Config(format!(
+                "Daemon exited during startup ({}), see {}",
+                status,
+                pid_path.with_file_name("daemon.log").display()
+            )));
+        }
+        if Instant::now() >= deadline {
+            return Err(AzukiError::Config(format!(
+                "Daemon did not open {} in time",
+                info.socket.display()
+            )));
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    eprintln!("azuki-server daemon started (pid {})", info.pid);
+    println!("{}", info.socket.display());
+    Ok(())
+}
+
+fn stop() -> Result<()> {
+    let Some(info) = running()? else {
+        eprintln!("azuki-server daemon is not running");
+        return Ok(());
+    };
+
+    // The pid was checked to fit in pid_t by `running`
+    if unsafe { libc::kill(info.pid as libc::pid_t, SIGTERM) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+    while is_alive(info.pid) {
+        if Instant::now() >= deadline {
+            return Err(AzukiError::Config(format!(
+                "Daemon (pid {}) did not exit",
+                info.pid
+            )));
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let _ = fs::remove_file(&info.socket);
+    fs::remove_file(pid_path()?)?;
+    eprintln!("azuki-server daemon stopped (pid {})", info.pid);
+    Ok(())
+}
+
+fn status() -> Result<()> {
+    match running()? {
+        Some(info) => {
+            println!(
+                "running (pid {}, socket {})",
+                info.pid,
+                info.socket.display()
+            );
+            Ok(())
+        }
+        None => {
+            println!("not running");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pidfile_round_trip() {
+        let info = DaemonInfo {
+            pid: 4242,
+            socket: PathBuf::from("/run/user/1000/azuki/azuki.sock"),
+        };
+        assert_eq!(DaemonInfo::parse(&info.to_text()), Some(info));
+        assert_eq!(DaemonInfo::parse("not a pid\n/tmp/sock\n"), None);
+        assert_eq!(DaemonInfo::parse("4242\n"), None);
+    }
+
+    #[test]
+    fn test_current_process_is_alive() {
+        assert!(is_alive(std::process::id()));
+        assert!(!is_alive(u32::MAX));
+    }
+}
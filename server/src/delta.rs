@@ -0,0 +1,165 @@
+//! Candidate and segment diffing for live conversion
+//!
+//! In live mode the client re-converts the whole preedit on every
+//! keystroke, and consecutive results are usually almost identical. These
+//! helpers compute what changed relative to the previous result so only
+//! the delta needs to be sent.
+
+use crate::message::{Candidate, SegmentInfo};
+use serde::Serialize;
+
+/// A candidate inserted into the list
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AddedCandidate {
+    /// Position in the new candidate list
+    pub index: usize,
+    #[serde(flatten)]
+    pub candidate: Candidate,
+}
+
+/// A segment that differs from the previous result
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SegmentChange {
+    /// Position in the new segment list
+    pub index: usize,
+    pub segment: SegmentInfo,
+}
+
+/// Changes turning one candidate list into another
+#[derive(Debug, Clone, PartialEq)]
+pub struct CandidateDelta {
+    /// Texts of candidates no longer present
+    pub removed: Vec<String>,
+    /// Candidates to insert, in ascending index order
+    pub added: Vec<AddedCandidate>,
+}
+
+/// Diff two candidate lists
+///
+/// Candidates are matched by text. The client applies the delta by
+/// dropping `removed` from its list and then inserting each of `added` at
+/// its index. That only reconstructs the new list when the candidates
+/// present in both keep their relative order, so None is returned when
+/// they were reordered and a full list must be sent.
+pub fn diff_candidates(old: &[Candidate], new: &[Candidate]) -> Option<CandidateDelta> {
+    let old_texts: Vec<&str> = old.iter().map(|c| c.text.as_str()).collect();
+    let new_texts: Vec<&str> = new.iter().map(|c| c.text.as_str()).collect();
+
+    let removed: Vec<String> = old_texts
+        .iter()
+        .filter(|c| !new_texts.contains(c))
+        .map(|c| c.to_string())
+        .collect();
+    let kept_old: Vec<&&str> = old_texts.iter().filter(|c| new_texts.contains(c)).collect();
+    let kept_new: Vec<&&str> = new_texts.iter().filter(|c| old_texts.contains(c)).collect();
+    if kept_old != kept_new {
+        return None;
+    }
+
+    let added = new
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| !old_texts.contains(&c.text.as_str()))
+        .map(|(index, candidate)| AddedCandidate {
+            index,
+            candidate: candidate.clone(),
+        })
+        .collect();
+
+    Some(CandidateDelta { removed, added })
+}
+
+/// List segments that are new or differ from the segment at the same index
+///
+/// Segments beyond the new list's length are dropped by the client based on
+/// the reported segment count.
+pub fn diff_segments(old: &[SegmentInfo], new: &[SegmentInfo]) -> Vec<SegmentChange> {
+    new.iter()
+        .enumerate()
+        .filter(|(i, seg)| old.get(*i) != Some(*seg))
+        .map(|(index, segment)| SegmentChange {
+            index,
+            segment: segment.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::CandidateSource;
+    use std::collections::BTreeMap;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn candidates(items: &[&str]) -> Vec<Candidate> {
+        items
+            .iter()
+            .map(|text| Candidate {
+                text: text.to_string(),
+                annotation: None,
+                source: CandidateSource::Dictionary,
+                kind: None,
+                score: 1.0,
+                log_prob: None,
+                confidence: None,
+            })
+            .collect()
+    }
+
+    fn segment(reading: &str, start: usize, candidates: &[&str]) -> SegmentInfo {
+        SegmentInfo {
+            reading: reading.to_string(),
+            start,
+            length: reading.chars().count(),
+            candidates: strings(candidates),
+            fixed: false,
+            annotations: BTreeMap::new(),
+            kinds: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_diff_candidates() {
+        let old = candidates(&["今日", "きょう"]);
+        let new = candidates(&["今日は", "今日", "きょう"]);
+        let delta = diff_candidates(&old, &new).unwrap();
+        assert!(delta.removed.is_empty());
+        assert_eq!(
+            delta.added,
+            vec![AddedCandidate {
+                index: 0,
+                candidate: new[0].clone()
+            }]
+        );
+
+        let delta = diff_candidates(&new, &old).unwrap();
+        assert_eq!(delta.removed, strings(&["今日は"]));
+        assert!(delta.added.is_empty());
+    }
+
+    #[test]
+    fn test_diff_candidates_reordered() {
+        let old = candidates(&["今日", "きょう"]);
+        let new = candidates(&["きょう", "今日"]);
+        assert!(diff_candidates(&old, &new).is_none());
+    }
+
+    #[test]
+    fn test_diff_segments() {
+        let old = vec![segment("きょう", 0, &["今日"]), segment("は", 3, &["は"])];
+        let new = vec![
+            segment("きょう", 0, &["今日"]),
+            segment("はい", 3, &["はい"]),
+            segment("しゃ", 5, &["しゃ"]),
+        ];
+        let changes = diff_segments(&old, &new);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].index, 1);
+        assert_eq!(changes[1].index, 2);
+
+        assert!(diff_segments(&new, &new).is_empty());
+    }
+}
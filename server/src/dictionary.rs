@@ -1,16 +1,88 @@
 //! SKK dictionary loader and lookup
 
-use encoding_rs::{EUC_JP, UTF_8};
+use crate::error::{DictionaryError, Result};
+use crate::gzip;
+use crate::json_dict;
+use crate::kana::fuzzy_reading;
+use crate::lattice;
+use crate::mapped_dict::{self, MappedDictionary};
+use crate::mozc_dict;
+use crate::numeric;
+use crate::trie::Trie;
+use encoding_rs::{Encoding, EUC_JP, ISO_2022_JP, SHIFT_JIS, UTF_8};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fs;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::mem;
 use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+/// Longest okurigana tried when looking up inflected readings
+const MAX_OKURIGANA: usize = 4;
+
+/// Text encoding of a dictionary file, for files auto-detection gets wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DictionaryEncoding {
+    #[serde(rename = "utf-8", alias = "utf8")]
+    Utf8,
+    #[serde(rename = "euc-jp", alias = "euc_jp")]
+    EucJp,
+    #[serde(rename = "shift_jis", alias = "shift-jis", alias = "sjis")]
+    ShiftJis,
+    #[serde(rename = "iso-2022-jp", alias = "iso_2022_jp")]
+    Iso2022Jp,
+}
+
+impl DictionaryEncoding {
+    fn encoding(self) -> &'static Encoding {
+        match self {
+            DictionaryEncoding::Utf8 => UTF_8,
+            DictionaryEncoding::EucJp => EUC_JP,
+            DictionaryEncoding::ShiftJis => SHIFT_JIS,
+            DictionaryEncoding::Iso2022Jp => ISO_2022_JP,
+        }
+    }
+}
+
+/// Candidates for an inflected reading
+///
+/// A candidate is verified if its entry lists the okurigana: in an
+/// okurigana block (`[く/書/]`), or, for entries without blocks, by the
+/// key's consonant being that of the okurigana itself (いt for いって).
+/// The others are guesses: euphonic forms tried for other endings, and
+/// entries whose blocks don't include the okurigana.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Inflections {
+    pub verified: Vec<String>,
+    pub guessed: Vec<String>,
+}
 
 /// SKK dictionary
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Dictionary {
     /// Okuri-nasi entries (without okurigana)
     /// Key: reading (hiragana), Value: list of candidates
     okuri_nasi: HashMap<String, Vec<String>>,
+    /// Okuri-ari entries (with okurigana)
+    /// Key: stem reading + okuri consonant (e.g. "かk"), Value: stem candidates
+    okuri_ari: HashMap<String, Vec<String>>,
+    /// Okurigana blocks of okuri-ari entries (`[く/書/]`), by key
+    okuri_blocks: HashMap<String, Vec<OkuriBlock>>,
+    /// Annotations by reading (or okuri-ari key), then candidate
+    annotations: HashMap<String, HashMap<String, String>>,
+    /// Prefix index over okuri-nasi readings, for completion
+    readings: Trie,
+    /// Indexed dictionaries below the entries above, highest priority first
+    mapped: Vec<Arc<MappedDictionary>>,
+    /// Encoding of the file this was loaded from; indexed files are UTF-8
+    encoding: Option<&'static str>,
+    /// Lattice word costs of readings imported with a cost (Mozc)
+    costs: HashMap<String, u32>,
+    /// Okuri-nasi readings by their fuzzy form (see `kana::fuzzy_reading`),
+    /// for those spelled otherwise; built when first needed
+    fuzzy: OnceLock<HashMap<String, Vec<String>>>,
 }
 
 impl Dictionary {
@@ -21,26 +93,101 @@ impl Dictionary {
 
     /// Load dictionary from file
     ///
-    /// Supports both EUC-JP and UTF-8 encoded files.
-    /// The encoding is auto-detected: UTF-8 is tried first, then EUC-JP.
+    /// Supports UTF-8, EUC-JP, Shift_JIS and ISO-2022-JP encoded files.
+    /// The encoding is auto-detected: UTF-8 is tried first, then
+    /// ISO-2022-JP if the file has its escape sequences, then EUC-JP and
+    /// Shift_JIS.
     ///
     /// SKK dictionary format:
     /// - Lines starting with `;` are comments
     /// - Entry format: `reading /candidate1/candidate2/.../`
-    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, DictionaryError> {
+    /// - Okuri-ari entries may end with okurigana blocks
+    ///   (`かk /書/欠/[く/書/]/[け/欠/]/`)
+    ///
+    /// Files in the indexed format (see `mapped_dict`) are memory-mapped
+    /// instead of parsed; JSON (see `json_dict`) and Mozc (see `mozc_dict`)
+    /// dictionaries are read as such. gzip-compressed files are
+    /// decompressed first.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::load_with(path, None)
+    }
+
+    /// Load dictionary from file, in the given encoding if one is pinned
+    pub fn load_with<P: AsRef<Path>>(
+        path: P,
+        encoding: Option<DictionaryEncoding>,
+    ) -> Result<Self> {
         let path = path.as_ref();
-        let bytes = fs::read(path).map_err(|e| DictionaryError::Io(e.to_string()))?;
+        if is_indexed(path) {
+            let mapped = MappedDictionary::open(path)?;
+            log_info!(
+                "Mapped {} okuri-nasi and {} okuri-ari entries from {}",
+                mapped.len(),
+                mapped.okuri_ari_entries().count(),
+                path.display()
+            );
+            let mut dict = Self::from_mapped(mapped);
+            dict.encoding = Some(UTF_8.name());
+            return Ok(dict);
+        }
+        let bytes = read_file(path).map_err(|source| DictionaryError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
 
-        // Try UTF-8 first, then EUC-JP
-        let (content, encoding_name) = decode_content(&bytes);
+        let (content, encoding_name) = match encoding {
+            Some(encoding) => {
+                let (content, had_errors) = decode_as(&bytes, encoding.encoding());
+                if had_errors {
+                    log_warn!(
+                        "Invalid {} in {}; replaced with U+FFFD",
+                        encoding.encoding().name(),
+                        path.display()
+                    );
+                }
+                (content, encoding.encoding().name())
+            }
+            None => decode_content(&bytes),
+        };
 
-        eprintln!(
+        log_debug!(
             "Loading dictionary from {} (detected encoding: {})",
             path.display(),
             encoding_name
         );
 
         let mut dict = Self::new();
+        dict.encoding = Some(encoding_name);
+        if let Some(format) = json_dict::detect(path, &content) {
+            for (reading, candidates) in json_dict::parse(path, format, &content)? {
+                let okuri_ari = is_okuri_ari_key(&reading);
+                dict.insert_entry(reading, candidates, okuri_ari, Vec::new());
+            }
+        } else if mozc_dict::detect(path, &content) {
+            for (reading, candidates, cost) in mozc_dict::parse(path, &content) {
+                dict.costs
+                    .insert(reading.clone(), lattice::mozc_word_cost(cost));
+                let candidates = candidates.into_iter().map(|c| (c, None)).collect();
+                dict.insert_entry(reading, candidates, false, Vec::new());
+            }
+        } else {
+            dict.parse_skk(&content);
+        }
+
+        log_info!(
+            "Loaded {} okuri-nasi and {} okuri-ari entries from {}",
+            dict.okuri_nasi.len(),
+            dict.okuri_ari.len(),
+            path.display()
+        );
+
+        Ok(dict)
+    }
+
+    /// Add the entries of an SKK dictionary file
+    ///
+    /// A reading repeated later in the file replaces the earlier entry.
+    fn parse_skk(&mut self, content: &str) {
         let mut in_okuri_nasi = false;
 
         for line in content.lines() {
@@ -64,39 +211,405 @@ impl Dictionary {
                 continue;
             }
 
-            // Only process okuri-nasi entries for now
-            if !in_okuri_nasi {
-                continue;
+            // Parse entry: "reading /candidate1;annotation/candidate2/.../"
+            let entry = if in_okuri_nasi {
+                parse_annotated_entry(line).map(|(reading, annotated)| (reading, annotated, vec![]))
+            } else {
+                parse_okuri_ari_entry(line)
+            };
+            if let Some((reading, annotated, blocks)) = entry {
+                self.insert_entry(reading, annotated, !in_okuri_nasi, blocks);
             }
+        }
+    }
 
-            // Parse entry: "reading /candidate1/candidate2/.../"
-            if let Some((reading, candidates)) = parse_entry(line) {
-                dict.okuri_nasi.insert(reading, candidates);
+    /// Add one parsed entry, replacing any entry for the same reading
+    fn insert_entry(
+        &mut self,
+        reading: String,
+        annotated: Vec<AnnotatedCandidate>,
+        okuri_ari: bool,
+        blocks: Vec<OkuriBlock>,
+    ) {
+        let mut candidates = Vec::with_capacity(annotated.len());
+        for (candidate, annotation) in annotated {
+            if let Some(annotation) = annotation {
+                self.annotations
+                    .entry(reading.clone())
+                    .or_default()
+                    .insert(candidate.clone(), annotation);
             }
+            candidates.push(candidate);
         }
+        if okuri_ari {
+            if !blocks.is_empty() {
+                self.okuri_blocks.insert(reading.clone(), blocks);
+            }
+            self.okuri_ari.insert(reading, candidates);
+        } else {
+            self.index_reading(&reading);
+            self.okuri_nasi.insert(reading, candidates);
+        }
+    }
 
-        eprintln!(
-            "Loaded {} okuri-nasi entries from {}",
-            dict.okuri_nasi.len(),
-            path.display()
-        );
-
-        Ok(dict)
+    /// Dictionary backed by an indexed dictionary file
+    pub fn from_mapped(mapped: MappedDictionary) -> Self {
+        Self {
+            mapped: vec![Arc::new(mapped)],
+            ..Self::default()
+        }
     }
 
     /// Look up candidates for a reading
-    pub fn lookup(&self, reading: &str) -> Option<&Vec<String>> {
-        self.okuri_nasi.get(reading)
+    ///
+    /// Candidates of indexed dictionaries are borrowed from their mapping.
+    pub fn lookup(&self, reading: &str) -> Option<Vec<&str>> {
+        combine(
+            self.okuri_nasi.get(reading),
+            self.mapped.iter().filter_map(|m| m.lookup(reading)),
+        )
+    }
+
+    /// Look up okuri-ari candidates by key (stem reading + okuri consonant)
+    ///
+    /// With `okurigana`, the kana right after the stem (`く` of 書く, `っ`
+    /// of 行った), an entry's block for it (`[く/書/]`) replaces the entry's
+    /// candidates; entries without such a block keep all of them.
+    pub fn lookup_okuri_ari(&self, key: &str, okurigana: Option<&str>) -> Option<Vec<&str>> {
+        combine(
+            okuri_candidates(&self.okuri_ari, &self.okuri_blocks, key, okurigana),
+            self.mapped
+                .iter()
+                .filter_map(|m| m.lookup_okuri_ari(key, okurigana)),
+        )
+    }
+
+    /// Candidates for an inflected reading from okuri-ari entries
+    ///
+    /// The reading is split into a stem and a hiragana tail of up to
+    /// `MAX_OKURIGANA` kana, longest stem first, and the stem's candidates
+    /// get the tail back: "いって" -> "いt" -> "行って". Euphonic forms also
+    /// try the endings they come from ("かいて" -> "かk", "かえった" ->
+    /// "かえr"). Verified candidates come first; see [`Inflections`].
+    pub fn lookup_inflected(&self, reading: &str) -> Option<Vec<String>> {
+        let Inflections {
+            mut verified,
+            guessed,
+        } = self.lookup_inflections(reading);
+        verified.extend(guessed);
+        (!verified.is_empty()).then_some(verified)
+    }
+
+    /// Candidates for an inflected reading, split by whether the
+    /// dictionary attests their okurigana
+    pub fn lookup_inflections(&self, reading: &str) -> Inflections {
+        let mut result = Inflections::default();
+        let splits: Vec<usize> = reading.char_indices().skip(1).map(|(i, _)| i).collect();
+        for &at in splits.iter().rev().take(MAX_OKURIGANA) {
+            let (stem, okurigana) = reading.split_at(at);
+            if !okurigana.chars().all(is_hiragana) {
+                break;
+            }
+            let first: String = okurigana.chars().take(1).collect();
+            for consonant in okuri_consonants(okurigana) {
+                let key = format!("{}{}", stem, consonant);
+                let Some(candidates) = self.lookup_okuri_ari(&key, Some(&first)) else {
+                    continue;
+                };
+                let verified = match self.lists_okurigana(&key, &first) {
+                    Some(listed) => listed,
+                    None => okurigana.chars().next().and_then(okuri_consonant) == Some(consonant),
+                };
+                for candidate in candidates {
+                    let word = format!("{}{}", candidate, okurigana);
+                    if result.verified.contains(&word) || result.guessed.contains(&word) {
+                        continue;
+                    }
+                    match verified {
+                        true => result.verified.push(word),
+                        false => result.guessed.push(word),
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Whether an okuri-ari entry has a block for an okurigana, None if it
+    /// has no blocks at all
+    fn lists_okurigana(&self, key: &str, okurigana: &str) -> Option<bool> {
+        let in_memory = self.okuri_blocks.get(key).into_iter().flatten();
+        let mut listed = None;
+        for block in in_memory.map(|(o, _)| o.as_str()) {
+            if block == okurigana {
+                return Some(true);
+            }
+            listed = Some(false);
+        }
+        for mapped in &self.mapped {
+            for (block, _) in mapped.okuri_blocks(key) {
+                if block == okurigana {
+                    return Some(true);
+                }
+                listed = Some(false);
+            }
+        }
+        listed
+    }
+
+    /// Okurigana blocks of an okuri-ari entry, with the candidates
+    /// `lookup_okuri_ari` returns for each
+    pub fn okuri_blocks(&self, key: &str) -> Vec<(&str, Vec<&str>)> {
+        let mut okuriganas: Vec<&str> = self
+            .okuri_blocks
+            .get(key)
+            .into_iter()
+            .flatten()
+            .map(|(okurigana, _)| okurigana.as_str())
+            .collect();
+        for (okurigana, _) in self.mapped.iter().flat_map(|m| m.okuri_blocks(key)) {
+            if !okuriganas.contains(&okurigana) {
+                okuriganas.push(okurigana);
+            }
+        }
+        okuriganas
+            .into_iter()
+            .filter_map(|okurigana| Some((okurigana, self.lookup_okuri_ari(key, Some(okurigana))?)))
+            .collect()
+    }
+
+    /// Check whether a reading has okuri-nasi candidates
+    pub fn contains(&self, reading: &str) -> bool {
+        self.okuri_nasi.contains_key(reading) || self.mapped.iter().any(|m| m.contains(reading))
+    }
+
+    /// Up to `limit` okuri-nasi readings starting with `prefix`
+    ///
+    /// Includes `prefix` itself if it is a reading. Shorter readings come
+    /// first.
+    pub fn readings_with_prefix(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let mut readings = self.readings.with_prefix(prefix, limit);
+        if self.mapped.is_empty() {
+            return readings;
+        }
+        for mapped in &self.mapped {
+            readings.extend(
+                mapped
+                    .readings_with_prefix(prefix)
+                    .filter(|reading| !reading.contains('#'))
+                    .map(str::to_string),
+            );
+        }
+        readings.sort_by(|a, b| a.chars().count().cmp(&b.chars().count()).then(a.cmp(b)));
+        readings.dedup();
+        readings.truncate(limit);
+        readings
+    }
+
+    /// Other okuri-nasi readings spelled like `reading` but for long vowels
+    /// or "づ"/"ぢ" ("とーきょー" -> "とうきょう")
+    ///
+    /// The first call indexes the fuzzy form of every reading, so later
+    /// ones cost a single lookup.
+    pub fn fuzzy_readings(&self, reading: &str) -> Vec<String> {
+        let folded = fuzzy_reading(reading);
+        let mut readings = Vec::new();
+        if folded != reading && self.contains(&folded) {
+            readings.push(folded.clone());
+        }
+        if let Some(spelled) = self.fuzzy_index().get(&folded) {
+            readings.extend(spelled.iter().filter(|r| *r != reading).cloned());
+        }
+        readings
+    }
+
+    /// Readings by fuzzy form, indexing them on first use
+    pub fn fuzzy_index(&self) -> &HashMap<String, Vec<String>> {
+        self.fuzzy.get_or_init(|| {
+            let mut index: HashMap<String, Vec<String>> = HashMap::new();
+            for reading in self.okuri_nasi_keys().filter(|r| !r.contains('#')) {
+                let folded = fuzzy_reading(reading);
+                if folded != reading {
+                    index.entry(folded).or_default().push(reading.to_string());
+                }
+            }
+            for readings in index.values_mut() {
+                readings.sort();
+            }
+            index
+        })
+    }
+
+    /// Add a reading to the prefix index
+    ///
+    /// Numeric entry keys ("#がつ") aren't readings anyone types.
+    fn index_reading(&mut self, reading: &str) {
+        if !reading.contains('#') {
+            self.readings.insert(reading);
+        }
+    }
+
+    /// Look up a numeric entry ("#がつ") and fill in the numbers
+    ///
+    /// Candidates whose placeholders cannot be expanded are left out;
+    /// returns `None` when none remain.
+    pub fn lookup_numeric(&self, key: &str, numbers: &[String]) -> Option<Vec<String>> {
+        let mut result: Vec<String> = Vec::new();
+        for template in self.lookup(key)? {
+            let lookup = |n: &str| Some(self.lookup(n)?.into_iter().map(String::from).collect());
+            for candidate in numeric::expand(template, numbers, lookup) {
+                if !result.contains(&candidate) {
+                    result.push(candidate);
+                }
+            }
+        }
+        (!result.is_empty()).then_some(result)
+    }
+
+    /// Annotation of a candidate expanded from a numeric entry: the entry's
+    /// own, or the form of its numbers ("三月" -> "漢数字")
+    pub fn numeric_annotation(
+        &self,
+        key: &str,
+        numbers: &[String],
+        candidate: &str,
+    ) -> Option<&str> {
+        self.lookup(key)?.into_iter().find_map(|template| {
+            let lookup = |n: &str| Some(self.lookup(n)?.into_iter().map(String::from).collect());
+            if !numeric::expand(template, numbers, lookup)
+                .iter()
+                .any(|c| c == candidate)
+            {
+                return None;
+            }
+            self.annotation(key, template)
+                .or_else(|| numeric::annotation(template))
+        })
+    }
+
+    /// Annotation of a candidate (`漢字;annotation` in the dictionary file)
+    pub fn annotation(&self, reading: &str, candidate: &str) -> Option<&str> {
+        self.annotations
+            .get(reading)
+            .and_then(|annotations| annotations.get(candidate))
+            .map(String::as_str)
+            .or_else(|| {
+                self.mapped
+                    .iter()
+                    .find_map(|m| m.annotation(reading, candidate))
+            })
+    }
+
+    /// Register a word, giving it priority over existing candidates
+    ///
+    /// The word is always stored as an okuri-nasi entry. When the candidate
+    /// ends in kana that matches the tail of the reading (e.g. "かく" ->
+    /// "書く"), the okuri-ari form ("かk" -> "書") is derived and stored as
+    /// well so inflected forms of registered verbs and adjectives convert.
+    #[allow(dead_code)]
+    pub fn add_entry(&mut self, reading: &str, candidate: &str) {
+        self.fuzzy.take();
+        insert_front(&mut self.okuri_nasi, reading, candidate);
+        self.index_reading(reading);
+        if let Some((key, stem)) = derive_okuri_ari(reading, candidate) {
+            insert_front(&mut self.okuri_ari, &key, &stem);
+            let okurigana: String = candidate[stem.len()..].chars().take(1).collect();
+            let block = self
+                .okuri_blocks
+                .get_mut(&key)
+                .and_then(|blocks| blocks.iter_mut().find(|(o, _)| *o == okurigana));
+            if let Some((_, candidates)) = block {
+                candidates.retain(|c| *c != stem);
+                candidates.insert(0, stem);
+            }
+        }
+    }
+
+    /// Add the entries of another dictionary with lower priority
+    ///
+    /// Candidates already present keep their position; new ones are
+    /// appended after them. Once this dictionary has indexed layers, the
+    /// other's in-memory entries become an indexed layer of their own so
+    /// they stay below the existing ones.
+    pub fn merge(&mut self, mut other: Dictionary) {
+        self.fuzzy.take();
+        for (reading, cost) in std::mem::take(&mut other.costs) {
+            self.costs.entry(reading).or_insert(cost);
+        }
+        let other_mapped = std::mem::take(&mut other.mapped);
+        if !self.mapped.is_empty() && !other.is_empty_in_memory() {
+            match MappedDictionary::from_dictionary(&other) {
+                Ok(layer) => {
+                    self.mapped.push(Arc::new(layer));
+                    self.mapped.extend(other_mapped);
+                    return;
+                }
+                Err(e) => log_warn!("[dictionary] Merging into memory instead: {}", e),
+            }
+        }
+        self.mapped.extend(other_mapped);
+        for reading in other.okuri_nasi.keys() {
+            self.index_reading(reading);
+        }
+        self.okuri_blocks = self.merged_okuri_blocks(&other);
+        for (table, entries) in [
+            (&mut self.okuri_nasi, other.okuri_nasi),
+            (&mut self.okuri_ari, other.okuri_ari),
+        ] {
+            for (reading, candidates) in entries {
+                let existing = table.entry(reading).or_default();
+                for candidate in candidates {
+                    if !existing.contains(&candidate) {
+                        existing.push(candidate);
+                    }
+                }
+            }
+        }
+        for (reading, annotations) in other.annotations {
+            let existing = self.annotations.entry(reading).or_default();
+            for (candidate, annotation) in annotations {
+                existing.entry(candidate).or_insert(annotation);
+            }
+        }
+    }
+
+    /// Okurigana blocks after merging `other` below this dictionary
+    ///
+    /// A side without a block for an okurigana contributes all of its
+    /// candidates, as `lookup_okuri_ari` would.
+    fn merged_okuri_blocks(&self, other: &Dictionary) -> HashMap<String, Vec<OkuriBlock>> {
+        let mut merged: HashMap<String, Vec<OkuriBlock>> = HashMap::new();
+        for (key, blocks) in self.okuri_blocks.iter().chain(&other.okuri_blocks) {
+            for (okurigana, _) in blocks {
+                let existing = merged.entry(key.clone()).or_default();
+                if existing.iter().any(|(o, _)| o == okurigana) {
+                    continue;
+                }
+                let mut candidates = Vec::new();
+                for dict in [self, other] {
+                    let own =
+                        okuri_candidates(&dict.okuri_ari, &dict.okuri_blocks, key, Some(okurigana));
+                    for candidate in own.into_iter().flatten() {
+                        if !candidates.contains(candidate) {
+                            candidates.push(candidate.clone());
+                        }
+                    }
+                }
+                existing.push((okurigana.clone(), candidates));
+            }
+        }
+        merged
     }
 
     /// Look up candidates with fallback to the reading itself
     ///
     /// Returns candidates from dictionary if found, otherwise returns the reading.
     /// Always includes the reading as the last candidate if not already present.
+    #[allow(dead_code)]
     pub fn lookup_with_fallback(&self, reading: &str) -> Vec<String> {
-        match self.okuri_nasi.get(reading) {
+        match self.lookup(reading) {
             Some(candidates) => {
-                let mut result = candidates.clone();
+                let mut result: Vec<String> = candidates.into_iter().map(String::from).collect();
                 if !result.contains(&reading.to_string()) {
                     result.push(reading.to_string());
                 }
@@ -106,35 +619,333 @@ impl Dictionary {
         }
     }
 
+    /// Iterate over okuri-nasi entries (reading, candidates)
+    pub fn entries(&self) -> impl Iterator<Item = (&str, Vec<&str>)> {
+        self.okuri_nasi_keys()
+            .map(|reading| (reading, self.lookup(reading).unwrap_or_default()))
+    }
+
+    /// Iterate over okuri-ari entries (stem reading + okuri consonant, candidates)
+    pub fn okuri_ari_entries(&self) -> impl Iterator<Item = (&str, Vec<&str>)> {
+        let mapped = self.mapped.iter().enumerate().flat_map(move |(i, m)| {
+            m.okuri_ari_entries()
+                .map(|(key, _)| key)
+                .filter(move |key| {
+                    !self.okuri_ari.contains_key(*key)
+                        && !self.mapped[..i]
+                            .iter()
+                            .any(|m| m.lookup_okuri_ari(key, None).is_some())
+                })
+        });
+        self.okuri_ari
+            .keys()
+            .map(String::as_str)
+            .chain(mapped)
+            .map(|key| (key, self.lookup_okuri_ari(key, None).unwrap_or_default()))
+    }
+
+    /// Distinct okuri-nasi readings of all layers
+    fn okuri_nasi_keys(&self) -> impl Iterator<Item = &str> {
+        let mapped = self.mapped.iter().enumerate().flat_map(move |(i, m)| {
+            m.entries()
+                .map(|(reading, _)| reading)
+                .filter(move |reading| {
+                    !self.okuri_nasi.contains_key(*reading)
+                        && !self.mapped[..i].iter().any(|m| m.contains(reading))
+                })
+        });
+        self.okuri_nasi.keys().map(String::as_str).chain(mapped)
+    }
+
+    fn is_empty_in_memory(&self) -> bool {
+        self.okuri_nasi.is_empty() && self.okuri_ari.is_empty()
+    }
+
     /// Check if dictionary is empty
     #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
-        self.okuri_nasi.is_empty()
+        self.okuri_nasi.is_empty() && self.mapped.iter().all(|m| m.len() == 0)
     }
 
     /// Get number of entries
     #[allow(dead_code)]
     pub fn len(&self) -> usize {
-        self.okuri_nasi.len()
+        if self.mapped.is_empty() {
+            return self.okuri_nasi.len();
+        }
+        self.okuri_nasi_keys().count()
+    }
+
+    /// Lattice word cost imported with a reading, if any
+    pub fn cost(&self, reading: &str) -> Option<u32> {
+        self.costs.get(reading).copied()
+    }
+
+    /// Encoding detected when the dictionary was loaded from a file
+    pub fn encoding(&self) -> Option<&'static str> {
+        self.encoding
+    }
+
+    /// Rough heap size of the entries parsed into memory
+    ///
+    /// Counts the strings of every table, and of the fuzzy reading index
+    /// once built; map and trie overhead is left out.
+    pub fn estimated_bytes(&self) -> usize {
+        fn string(s: &str) -> usize {
+            mem::size_of::<String>() + s.len()
+        }
+        fn strings(list: &[String]) -> usize {
+            list.iter().map(String::as_str).map(string).sum()
+        }
+        let entries = self
+            .okuri_nasi
+            .iter()
+            .chain(&self.okuri_ari)
+            .map(|(key, candidates)| string(key) + strings(candidates))
+            .sum::<usize>();
+        let blocks = self
+            .okuri_blocks
+            .iter()
+            .map(|(key, blocks)| {
+                string(key)
+                    + blocks
+                        .iter()
+                        .map(|(okurigana, candidates)| string(okurigana) + strings(candidates))
+                        .sum::<usize>()
+            })
+            .sum::<usize>();
+        let annotations = self
+            .annotations
+            .iter()
+            .map(|(key, notes)| {
+                string(key)
+                    + notes
+                        .iter()
+                        .map(|(c, a)| string(c) + string(a))
+                        .sum::<usize>()
+            })
+            .sum::<usize>();
+        let costs = self
+            .costs
+            .keys()
+            .map(|key| string(key) + mem::size_of::<u32>())
+            .sum::<usize>();
+        let fuzzy = self.fuzzy.get().map_or(0, |index| {
+            index
+                .iter()
+                .map(|(key, readings)| string(key) + strings(readings))
+                .sum::<usize>()
+        });
+        entries + blocks + annotations + costs + fuzzy
+    }
+
+    /// Size of the memory-mapped indexed dictionaries
+    pub fn mapped_bytes(&self) -> usize {
+        self.mapped.iter().map(|m| m.byte_len()).sum()
     }
 }
 
-/// Decode file content, trying UTF-8 first, then EUC-JP
-fn decode_content(bytes: &[u8]) -> (String, &'static str) {
-    // Try UTF-8 first
-    let (decoded, encoding, had_errors) = UTF_8.decode(bytes);
-    if !had_errors {
-        return (decoded.into_owned(), encoding.name());
+/// Candidates of the in-memory entry followed by new ones from indexed layers
+fn combine<'a>(
+    own: Option<&'a Vec<String>>,
+    layers: impl Iterator<Item = Vec<&'a str>>,
+) -> Option<Vec<&'a str>> {
+    let mut result: Option<Vec<&str>> = own.map(|c| c.iter().map(String::as_str).collect());
+    for candidates in layers {
+        let result = result.get_or_insert_with(Vec::new);
+        for candidate in candidates {
+            if !result.contains(&candidate) {
+                result.push(candidate);
+            }
+        }
+    }
+    result
+}
+
+/// Candidates of an okuri-ari entry valid with an okurigana: its block
+/// for the okurigana if there is one, otherwise the whole entry
+fn okuri_candidates<'a>(
+    entries: &'a HashMap<String, Vec<String>>,
+    blocks: &'a HashMap<String, Vec<OkuriBlock>>,
+    key: &str,
+    okurigana: Option<&str>,
+) -> Option<&'a Vec<String>> {
+    okurigana
+        .and_then(|okurigana| {
+            blocks
+                .get(key)?
+                .iter()
+                .find(|(o, _)| o == okurigana)
+                .map(|(_, candidates)| candidates)
+        })
+        .or_else(|| entries.get(key))
+}
+
+/// Whether a file starts with the indexed dictionary magic
+pub fn is_indexed(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|()| &magic == mapped_dict::MAGIC)
+}
+
+/// Read a dictionary file, decompressing it if it is gzip-compressed
+pub fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if reader.fill_buf()?.starts_with(&gzip::MAGIC) {
+        return gzip::decompress(reader);
+    }
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Insert a candidate at the front of an entry, moving it if already present
+fn insert_front(entries: &mut HashMap<String, Vec<String>>, reading: &str, candidate: &str) {
+    let candidates = entries.entry(reading.to_string()).or_default();
+    candidates.retain(|c| c != candidate);
+    candidates.insert(0, candidate.to_string());
+}
+
+/// Derive the okuri-ari form of a word from its okuri-nasi registration
+///
+/// The trailing hiragana of the candidate is treated as okurigana when the
+/// reading ends with the same kana: ("かく", "書く") -> ("かk", "書"),
+/// ("いった", "行った") -> ("いt", "行"). Returns None for all-kana
+/// candidates or when the candidate's kana tail doesn't match the reading.
+pub fn derive_okuri_ari(reading: &str, candidate: &str) -> Option<(String, String)> {
+    let tail_start = candidate
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_hiragana(*c))
+        .last()
+        .map(|(i, _)| i)?;
+    let (stem, okurigana) = candidate.split_at(tail_start);
+    if stem.is_empty() {
+        return None;
     }
 
-    // Fall back to EUC-JP
-    let (decoded, encoding, _) = EUC_JP.decode(bytes);
-    (decoded.into_owned(), encoding.name())
+    let reading_stem = reading.strip_suffix(okurigana)?;
+    if reading_stem.is_empty() {
+        return None;
+    }
+
+    let consonant = okuri_consonant(okurigana.chars().next()?)?;
+    Some((format!("{}{}", reading_stem, consonant), stem.to_string()))
+}
+
+/// Romaji key used by SKK okuri-ari entries for the first okurigana kana
+pub fn okuri_consonant(kana: char) -> Option<char> {
+    let key = match kana {
+        'あ' | 'ぁ' => 'a',
+        'い' | 'ぃ' => 'i',
+        'う' | 'ぅ' | 'ゔ' => 'u',
+        'え' | 'ぇ' => 'e',
+        'お' | 'ぉ' => 'o',
+        'か' | 'き' | 'く' | 'け' | 'こ' => 'k',
+        'が' | 'ぎ' | 'ぐ' | 'げ' | 'ご' => 'g',
+        'さ' | 'し' | 'す' | 'せ' | 'そ' => 's',
+        'ざ' | 'じ' | 'ず' | 'ぜ' | 'ぞ' => 'z',
+        'た' | 'ち' | 'つ' | 'て' | 'と' | 'っ' => 't',
+        'だ' | 'ぢ' | 'づ' | 'で' | 'ど' => 'd',
+        'な' | 'に' | 'ぬ' | 'ね' | 'の' | 'ん' => 'n',
+        'は' | 'ひ' | 'ふ' | 'へ' | 'ほ' => 'h',
+        'ば' | 'び' | 'ぶ' | 'べ' | 'ぼ' => 'b',
+        'ぱ' | 'ぴ' | 'ぷ' | 'ぺ' | 'ぽ' => 'p',
+        'ま' | 'み' | 'む' | 'め' | 'も' => 'm',
+        'や' | 'ゆ' | 'よ' | 'ゃ' | 'ゅ' | 'ょ' => 'y',
+        'ら' | 'り' | 'る' | 'れ' | 'ろ' => 'r',
+        'わ' | 'を' | 'ゎ' => 'w',
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Okuri consonants an okurigana may come from
+///
+/// い, っ and ん before て or た are euphonic forms of other endings:
+/// 書いて (かk), 帰った (かえr), 読んだ (よm).
+fn okuri_consonants(okurigana: &str) -> Vec<char> {
+    let mut chars = okurigana.chars();
+    let Some(first) = chars.next() else {
+        return Vec::new();
+    };
+    let mut consonants: Vec<char> = okuri_consonant(first).into_iter().collect();
+    if matches!(chars.next(), Some('て' | 'た' | 'で' | 'だ')) {
+        let euphonic: &[char] = match first {
+            'い' => &['k', 'g'],
+            'っ' => &['r', 'w'],
+            'ん' => &['m', 'b'],
+            _ => &[],
+        };
+        consonants.extend(euphonic);
+    }
+    consonants
+}
+
+/// Whether a reading is an okuri-ari key: hiragana then an okuri consonant
+pub fn is_okuri_ari_key(reading: &str) -> bool {
+    let mut chars = reading.chars().rev();
+    matches!(
+        (chars.next(), chars.next()),
+        (Some(consonant), Some(kana)) if consonant.is_ascii_lowercase() && is_hiragana(kana)
+    )
+}
+
+/// Check whether a character is hiragana
+fn is_hiragana(c: char) -> bool {
+    matches!(c, 'ぁ'..='ゖ')
+}
+
+/// Decode file content, trying UTF-8 first, then ISO-2022-JP, EUC-JP and
+/// Shift_JIS
+///
+/// ISO-2022-JP is 7-bit, so it is only chosen for files that switch to
+/// JIS X 0208 with an escape sequence. Content valid in none of them is
+/// decoded as EUC-JP, the usual encoding of SKK dictionaries.
+pub fn decode_content(bytes: &[u8]) -> (String, &'static str) {
+    let seven_bit = bytes.is_ascii();
+    let escaped = seven_bit && bytes.windows(3).any(|w| w == b"\x1b$B" || w == b"\x1b$@");
+    let candidates: &[&'static Encoding] = if escaped {
+        &[ISO_2022_JP]
+    } else {
+        &[UTF_8, EUC_JP, SHIFT_JIS]
+    };
+    for &encoding in candidates {
+        let (decoded, had_errors) = decode_as(bytes, encoding);
+        if !had_errors {
+            return (decoded, encoding.name());
+        }
+    }
+    (decode_as(bytes, EUC_JP).0, EUC_JP.name())
+}
+
+/// Decode file content in one encoding, replacing invalid sequences
+fn decode_as(bytes: &[u8], encoding: &'static Encoding) -> (String, bool) {
+    let (decoded, had_errors) = encoding.decode_without_bom_handling(bytes);
+    (decoded.into_owned(), had_errors)
 }
 
 /// Parse a single dictionary entry
 /// Format: "reading /candidate1/candidate2/.../"
-fn parse_entry(line: &str) -> Option<(String, Vec<String>)> {
+///
+/// Annotations are dropped; see [`parse_annotated_entry`].
+pub fn parse_entry(line: &str) -> Option<(String, Vec<String>)> {
+    parse_annotated_entry(line).map(|(reading, candidates)| {
+        let candidates = candidates
+            .into_iter()
+            .map(|(candidate, _)| candidate)
+            .collect();
+        (reading, candidates)
+    })
+}
+
+/// A candidate with its annotation, if any
+pub type AnnotatedCandidate = (String, Option<String>);
+
+/// Parse a dictionary entry, keeping each candidate's annotation
+/// Format: "reading /candidate1;annotation/candidate2/.../"
+pub fn parse_annotated_entry(line: &str) -> Option<(String, Vec<AnnotatedCandidate>)> {
     // Find the first space that separates reading from candidates
     let space_pos = line.find(' ')?;
     let reading = line[..space_pos].to_string();
@@ -147,11 +958,15 @@ fn parse_entry(line: &str) -> Option<(String, Vec<String>)> {
         if part.is_empty() {
             continue;
         }
-        // Skip entries with annotations (marked with ;)
-        // e.g., "候補;annotation" -> "候補"
-        let candidate = part.split(';').next().unwrap_or(part).to_string();
+        // e.g., "候補;annotation" -> ("候補", Some("annotation"))
+        let (candidate, annotation) = match part.split_once(';') {
+            Some((candidate, annotation)) => {
+                (candidate, Some(annotation).filter(|a| !a.is_empty()))
+            }
+            None => (part, None),
+        };
         if !candidate.is_empty() {
-            candidates.push(candidate);
+            candidates.push((evaluate_concat(candidate), annotation.map(evaluate_concat)));
         }
     }
 
@@ -162,24 +977,116 @@ fn parse_entry(line: &str) -> Option<(String, Vec<String>)> {
     Some((reading, candidates))
 }
 
-/// Dictionary error
-#[derive(Debug)]
-#[allow(dead_code)]
-pub enum DictionaryError {
-    Io(String),
-    Parse(String),
+/// Okurigana and the candidates valid with it
+pub type OkuriBlock = (String, Vec<String>);
+
+/// Parse an okuri-ari entry, separating its okurigana blocks
+/// Format: "かk /書/欠/[く/書/]/[け/欠/]/"
+///
+/// Candidates inside blocks also appear in the main list in SKK
+/// dictionaries; only the main list keeps annotations.
+pub fn parse_okuri_ari_entry(
+    line: &str,
+) -> Option<(String, Vec<AnnotatedCandidate>, Vec<OkuriBlock>)> {
+    let space_pos = line.find(' ')?;
+    let mut main = line[..=space_pos].to_string();
+    let mut blocks = Vec::new();
+    let mut block: Option<OkuriBlock> = None;
+    for part in line[space_pos + 1..].split('/') {
+        if let Some((_, candidates)) = block.as_mut() {
+            if part.trim() == "]" {
+                blocks.extend(block.take());
+                continue;
+            }
+            let candidate = part.split_once(';').map_or(part, |(c, _)| c).trim();
+            if !candidate.is_empty() {
+                candidates.push(evaluate_concat(candidate));
+            }
+        } else if let Some(okurigana) = part.trim().strip_prefix('[').filter(|o| !o.is_empty()) {
+            block = Some((okurigana.to_string(), Vec::new()));
+        } else {
+            main.push('/');
+            main.push_str(part);
+        }
+    }
+    let (reading, candidates) = parse_annotated_entry(&main)?;
+    Some((reading, candidates, blocks))
 }
 
-impl std::fmt::Display for DictionaryError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            DictionaryError::Io(e) => write!(f, "IO error: {}", e),
-            DictionaryError::Parse(e) => write!(f, "Parse error: {}", e),
+/// Evaluate a `(concat "...")` candidate into its text
+///
+/// SKK dictionaries write candidates containing `/` or `;` as
+/// `(concat "D\057C")`, with octal escapes for those characters. Other
+/// text, including other Lisp expressions, is returned as-is.
+fn evaluate_concat(text: &str) -> String {
+    text.strip_prefix("(concat")
+        .and_then(|rest| rest.strip_suffix(')'))
+        .and_then(parse_string_literals)
+        .unwrap_or_else(|| text.to_string())
+}
+
+/// Write a candidate so an SKK dictionary line reads it back unchanged
+///
+/// The inverse of [`evaluate_concat`]: text containing `/` or `;` (or
+/// anything else that would end the candidate) becomes `(concat "...")`
+/// with those characters escaped.
+pub fn encode_candidate(text: &str) -> Cow<'_, str> {
+    if !text.contains(['/', ';', '\n', '\r']) && !text.starts_with("(concat") {
+        return Cow::Borrowed(text);
+    }
+    let mut encoded = String::from("(concat \"");
+    for c in text.chars() {
+        match c {
+            '/' => encoded.push_str("\\057"),
+            ';' => encoded.push_str("\\073"),
+            '"' => encoded.push_str("\\\""),
+            '\\' => encoded.push_str("\\\\"),
+            '\n' => encoded.push_str("\\n"),
+            '\r' => encoded.push_str("\\015"),
+            c => encoded.push(c),
         }
     }
+    encoded.push_str("\")");
+    Cow::Owned(encoded)
 }
 
-impl std::error::Error for DictionaryError {}
+/// Concatenate a sequence of Lisp string literals (`"a" "b\057"`)
+fn parse_string_literals(args: &str) -> Option<String> {
+    let mut result = String::new();
+    let mut chars = args.chars().peekable();
+    loop {
+        match chars.next() {
+            None => return Some(result),
+            Some(c) if c.is_whitespace() => continue,
+            Some('"') => {}
+            Some(_) => return None,
+        }
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    d @ '0'..='7' => {
+                        let mut code = d.to_digit(8)?;
+                        for _ in 0..2 {
+                            match chars.peek().and_then(|c| c.to_digit(8)) {
+                                Some(digit) => {
+                                    code = code * 8 + digit;
+                                    chars.next();
+                                }
+                                None => break,
+                            }
+                        }
+                        result.push(char::from_u32(code)?);
+                    }
+                    'n' => result.push('\n'),
+                    't' => result.push('\t'),
+                    c => result.push(c),
+                },
+                c => result.push(c),
+            }
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -190,6 +1097,73 @@ mod tests {
         PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/test-dict.utf8")
     }
 
+    #[test]
+    fn test_merge_keeps_priority() {
+        let mut dict = Dictionary::load(test_dict_path()).unwrap();
+        let mut other = Dictionary::new();
+        other.add_entry("きょう", "強");
+        other.add_entry("きょう", "今日");
+        other.add_entry("さくら", "桜");
+        dict.merge(other);
+        assert_eq!(
+            dict.lookup("きょう").unwrap(),
+            vec!["今日", "京", "教", "強"]
+        );
+        assert_eq!(dict.lookup("さくら").unwrap(), vec!["桜"]);
+    }
+
+    #[test]
+    fn test_load_indexed_dictionary() {
+        let path = std::env::temp_dir().join(format!("azuki-indexed-{}", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        mapped_dict::write_dictionary(&Dictionary::load(test_dict_path()).unwrap(), &mut file)
+            .unwrap();
+        drop(file);
+
+        let dict = Dictionary::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(dict.lookup("きょう").unwrap(), vec!["今日", "京", "教"]);
+        assert_eq!(
+            dict.lookup_okuri_ari("かk", None).unwrap(),
+            vec!["書", "欠"]
+        );
+        assert_eq!(dict.annotation("かんじ", "漢字"), Some("kanji"));
+        assert!(dict.contains("にほんご"));
+        assert_eq!(
+            dict.len(),
+            Dictionary::load(test_dict_path()).unwrap().len()
+        );
+        assert_eq!(dict.readings_with_prefix("こ", 10), vec!["こんにちは"]);
+        assert_eq!(
+            dict.lookup_numeric("#がつ", &["3".to_string()]).unwrap(),
+            vec!["3月", "３月", "三月"]
+        );
+    }
+
+    #[test]
+    fn test_merge_with_indexed_layers_keeps_priority() {
+        let system = Dictionary::load(test_dict_path()).unwrap();
+        let mut dict = Dictionary::from_mapped(MappedDictionary::from_dictionary(&system).unwrap());
+        dict.add_entry("きょう", "強");
+        let mut other = Dictionary::new();
+        other.add_entry("きょう", "経");
+        other.add_entry("さくら", "桜");
+        dict.merge(other);
+
+        assert_eq!(
+            dict.lookup("きょう").unwrap(),
+            vec!["強", "今日", "京", "教", "経"]
+        );
+        assert_eq!(dict.lookup("さくら").unwrap(), vec!["桜"]);
+        assert_eq!(dict.len(), system.len() + 1);
+        let entries: HashMap<&str, Vec<&str>> = dict.entries().collect();
+        assert_eq!(entries["きょう"], vec!["強", "今日", "京", "教", "経"]);
+        assert_eq!(
+            dict.readings_with_prefix("さ", 10),
+            vec!["さくら".to_string()]
+        );
+    }
+
     #[test]
     fn test_parse_entry() {
         let (reading, candidates) = parse_entry("きょう /今日/京/教/").unwrap();
@@ -204,12 +1178,112 @@ mod tests {
         assert_eq!(candidates, vec!["漢字", "感じ"]);
     }
 
+    #[test]
+    fn test_parse_entry_with_concat() {
+        let (_, candidates) = parse_entry(
+            r#"でぃーしー /(concat "D\057C")/(concat "a\073" "b")/(skk-current-date)/"#,
+        )
+        .unwrap();
+        assert_eq!(candidates, vec!["D/C", "a;b", "(skk-current-date)"]);
+
+        let (_, candidates) =
+            parse_annotated_entry(r#"ゆーあーるえる /URL;(concat "http:\057\057")/"#).unwrap();
+        assert_eq!(
+            candidates,
+            vec![("URL".to_string(), Some("http://".to_string()))]
+        );
+    }
+
+    #[test]
+    fn test_encode_candidate_round_trips() {
+        assert_eq!(encode_candidate("今日"), "今日");
+        assert_eq!(encode_candidate("D/C"), r#"(concat "D\057C")"#);
+        assert_eq!(encode_candidate("a;b"), r#"(concat "a\073b")"#);
+        for text in [
+            "D/C",
+            "a;b",
+            r#"say "/" \ ok"#,
+            "(concat \"x\")",
+            "(skk-current-date)",
+        ] {
+            let line = format!("てすと /{}/", encode_candidate(text));
+            assert_eq!(parse_entry(&line).unwrap().1, vec![text], "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_parse_okuri_ari_entry() {
+        let (reading, candidates, blocks) =
+            parse_okuri_ari_entry("おおk /大;big/多/[く/多/大;big/]/[き/大/]/").unwrap();
+        assert_eq!(reading, "おおk");
+        assert_eq!(
+            candidates,
+            vec![
+                ("大".to_string(), Some("big".to_string())),
+                ("多".to_string(), None)
+            ]
+        );
+        assert_eq!(
+            blocks,
+            vec![
+                ("く".to_string(), vec!["多".to_string(), "大".to_string()]),
+                ("き".to_string(), vec!["大".to_string()])
+            ]
+        );
+
+        let (_, candidates, blocks) = parse_okuri_ari_entry("いt /行/言/").unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert!(blocks.is_empty());
+    }
+
+    #[test]
+    fn test_evaluate_concat() {
+        assert_eq!(
+            evaluate_concat(r#"(concat "\"quoted\"\\")"#),
+            r#""quoted"\"#
+        );
+        assert_eq!(evaluate_concat(r#"(concat "\0571")"#), "/1");
+        // Malformed expressions are kept as written
+        assert_eq!(evaluate_concat(r#"(concat "open)"#), r#"(concat "open)"#);
+        assert_eq!(evaluate_concat("(concat foo)"), "(concat foo)");
+    }
+
+    #[test]
+    fn test_annotations_are_kept() {
+        let (_, candidates) = parse_annotated_entry("かんじ /漢字;kanji/感じ/").unwrap();
+        assert_eq!(
+            candidates,
+            vec![
+                ("漢字".to_string(), Some("kanji".to_string())),
+                ("感じ".to_string(), None)
+            ]
+        );
+
+        let dict = Dictionary::load(test_dict_path()).unwrap();
+        assert_eq!(dict.annotation("かんじ", "漢字"), Some("kanji"));
+        assert_eq!(dict.annotation("かんじ", "感じ"), None);
+        assert_eq!(dict.lookup("かんじ").unwrap(), vec!["漢字", "感じ"]);
+    }
+
     #[test]
     fn test_parse_entry_empty() {
         assert!(parse_entry("invalid").is_none());
         assert!(parse_entry("reading //").is_none());
     }
 
+    #[test]
+    fn test_estimated_bytes() {
+        let mut dict = Dictionary::new();
+        assert_eq!(dict.estimated_bytes(), 0);
+        dict.add_entry("きょう", "今日");
+        let string = mem::size_of::<String>();
+        assert_eq!(
+            dict.estimated_bytes(),
+            2 * string + "きょう".len() + "今日".len()
+        );
+        assert_eq!(dict.mapped_bytes(), 0);
+    }
+
     #[test]
     fn test_load_dictionary_utf8() {
         let dict = Dictionary::load(test_dict_path()).unwrap();
@@ -217,15 +1291,251 @@ mod tests {
 
         // Test lookup
         let candidates = dict.lookup("きょう").unwrap();
-        assert_eq!(candidates, &vec!["今日", "京", "教"]);
+        assert_eq!(candidates, vec!["今日", "京", "教"]);
 
         let candidates = dict.lookup("あずき").unwrap();
-        assert_eq!(candidates, &vec!["小豆"]);
+        assert_eq!(candidates, vec!["小豆"]);
 
         // Non-existent entry
         assert!(dict.lookup("そんざいしない").is_none());
     }
 
+    #[test]
+    fn test_load_okuri_ari() {
+        let dict = Dictionary::load(test_dict_path()).unwrap();
+        assert_eq!(
+            dict.lookup_okuri_ari("かk", None).unwrap(),
+            vec!["書", "欠"]
+        );
+        // Okuri-ari keys don't leak into okuri-nasi lookups
+        assert!(dict.lookup("かk").is_none());
+    }
+
+    #[test]
+    fn test_lookup_okuri_ari_with_okurigana() {
+        let dict = Dictionary::load(test_dict_path()).unwrap();
+        assert_eq!(
+            dict.lookup_okuri_ari("かk", Some("く")).unwrap(),
+            vec!["書"]
+        );
+        assert_eq!(
+            dict.lookup_okuri_ari("かk", Some("け")).unwrap(),
+            vec!["欠"]
+        );
+        // No block for the okurigana: the whole entry
+        assert_eq!(
+            dict.lookup_okuri_ari("かk", Some("か")).unwrap(),
+            vec!["書", "欠"]
+        );
+        assert_eq!(
+            dict.lookup_okuri_ari("いt", Some("っ")).unwrap(),
+            vec!["行", "言"]
+        );
+        assert_eq!(
+            dict.okuri_blocks("かk"),
+            vec![("く", vec!["書"]), ("け", vec!["欠"])]
+        );
+
+        // A lower-priority entry without blocks offers all its candidates
+        let mut other = Dictionary::new();
+        other.add_entry("かく", "掻く");
+        let mut merged = dict.clone();
+        merged.merge(other);
+        assert_eq!(
+            merged.lookup_okuri_ari("かk", Some("く")).unwrap(),
+            vec!["書", "掻"]
+        );
+        let mut merged = Dictionary::from_mapped(MappedDictionary::from_dictionary(&dict).unwrap());
+        merged.add_entry("かく", "掻く");
+        assert_eq!(
+            merged.lookup_okuri_ari("かk", Some("く")).unwrap(),
+            vec!["掻", "書"]
+        );
+    }
+
+    #[test]
+    fn test_lookup_inflected() {
+        let dict = Dictionary::load(test_dict_path()).unwrap();
+        assert_eq!(
+            dict.lookup_inflected("いって").unwrap(),
+            vec!["行って", "言って"]
+        );
+        assert_eq!(
+            dict.lookup_inflected("かえった").unwrap(),
+            vec!["帰った", "返った"]
+        );
+        assert_eq!(
+            dict.lookup_inflected("かいて").unwrap(),
+            vec!["書いて", "欠いて"]
+        );
+        // The okurigana block narrows the candidates
+        assert_eq!(dict.lookup_inflected("かく").unwrap(), vec!["書く"]);
+        assert_eq!(
+            dict.lookup_inflected("かえる").unwrap(),
+            vec!["帰る", "返る"]
+        );
+        assert!(dict.lookup_inflected("きょう").is_none());
+    }
+
+    #[test]
+    fn test_lookup_inflections_verifies_okurigana() {
+        let dict = Dictionary::load(test_dict_path()).unwrap();
+        let inflections = |reading: &str| dict.lookup_inflections(reading);
+        // Listed in a block
+        assert_eq!(inflections("かく").verified, ["書く"]);
+        // The entry has blocks, but not for こ
+        assert_eq!(
+            inflections("かこう"),
+            Inflections {
+                verified: vec![],
+                guessed: vec!["書こう".to_string(), "欠こう".to_string()]
+            }
+        );
+        // No blocks: the okurigana's own row
+        assert_eq!(inflections("かえる").verified, ["帰る", "返る"]);
+        assert_eq!(inflections("いって").verified, ["行って", "言って"]);
+        // Euphonic forms are guesses
+        assert_eq!(inflections("かえった").guessed, ["帰った", "返った"]);
+        assert!(inflections("かえった").verified.is_empty());
+    }
+
+    #[test]
+    fn test_fuzzy_readings() {
+        let mut dict = Dictionary::load(test_dict_path()).unwrap();
+        assert_eq!(dict.fuzzy_readings("きょー"), ["きょう"]);
+        assert_eq!(dict.fuzzy_readings("にゅーりょく"), ["にゅうりょく"]);
+        assert!(dict.fuzzy_readings("きょう").is_empty());
+        assert!(dict.fuzzy_readings("とーきょー").is_empty());
+
+        // Words added later are indexed too, whichever way they're spelled
+        dict.add_entry("とおきょお", "遠京");
+        dict.add_entry("とーきょー", "トーキョー");
+        assert_eq!(
+            dict.fuzzy_readings("とうきょう"),
+            ["とーきょー", "とおきょお"]
+        );
+        assert_eq!(dict.fuzzy_readings("とーきょー"), ["とおきょお"]);
+    }
+
+    #[test]
+    fn test_readings_with_prefix() {
+        let mut dict = Dictionary::load(test_dict_path()).unwrap();
+        assert_eq!(dict.readings_with_prefix("こ", 10), vec!["こんにちは"]);
+        assert_eq!(dict.readings_with_prefix("きょう", 10), vec!["きょう"]);
+        // Numeric keys are not completed
+        assert!(dict.readings_with_prefix("#", 10).is_empty());
+
+        dict.add_entry("こうし", "講師");
+        let mut other = Dictionary::new();
+        other.add_entry("こ", "子");
+        dict.merge(other);
+        assert_eq!(
+            dict.readings_with_prefix("こ", 10),
+            vec!["こ", "こうし", "こんにちは"]
+        );
+    }
+
+    #[test]
+    fn test_lookup_numeric() {
+        let dict = Dictionary::load(test_dict_path()).unwrap();
+        assert_eq!(
+            dict.lookup_numeric("#がつ", &["3".to_string()]).unwrap(),
+            vec!["3月", "３月", "三月"]
+        );
+        assert_eq!(
+            dict.lookup_numeric("だい#", &["10".to_string()]).unwrap(),
+            vec!["第１０", "第十"]
+        );
+        assert!(dict.lookup_numeric("#にち", &["3".to_string()]).is_none());
+    }
+
+    #[test]
+    fn test_derive_okuri_ari() {
+        assert_eq!(
+            derive_okuri_ari("かく", "書く"),
+            Some(("かk".to_string(), "書".to_string()))
+        );
+        assert_eq!(
+            derive_okuri_ari("いった", "行った"),
+            Some(("いt".to_string(), "行".to_string()))
+        );
+        assert_eq!(
+            derive_okuri_ari("とりけす", "取り消す"),
+            Some(("とりけs".to_string(), "取り消".to_string()))
+        );
+        // All kana, no kana tail, or tail not matching the reading
+        assert_eq!(derive_okuri_ari("あずき", "あずき"), None);
+        assert_eq!(derive_okuri_ari("あずき", "小豆"), None);
+        assert_eq!(derive_okuri_ari("かく", "書け"), None);
+    }
+
+    #[test]
+    fn test_add_entry_registers_okuri_ari() {
+        let mut dict = Dictionary::load(test_dict_path()).unwrap();
+        dict.add_entry("かく", "描く");
+
+        assert_eq!(dict.lookup("かく").unwrap(), vec!["描く"]);
+        assert_eq!(
+            dict.lookup_okuri_ari("かk", None).unwrap(),
+            vec!["描", "書", "欠"]
+        );
+        assert_eq!(
+            dict.lookup_okuri_ari("かk", Some("く")).unwrap(),
+            vec!["描", "書"]
+        );
+
+        // Re-registering moves the candidate to the front without duplicating
+        dict.add_entry("かく", "書く");
+        assert_eq!(
+            dict.lookup_okuri_ari("かk", None).unwrap(),
+            vec!["書", "描", "欠"]
+        );
+    }
+
+    #[test]
+    fn test_load_gzip_dictionary() {
+        let dict = Dictionary::load(test_dict_path().with_extension("utf8.gz")).unwrap();
+        let plain = Dictionary::load(test_dict_path()).unwrap();
+        assert_eq!(dict.len(), plain.len());
+        assert_eq!(dict.lookup("きょう").unwrap(), vec!["今日", "京", "教"]);
+        assert_eq!(dict.encoding(), Some("UTF-8"));
+    }
+
+    #[test]
+    fn test_load_json_dictionary() {
+        let path = std::env::temp_dir().join(format!("azuki-words-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"あずき": [{"text": "小豆", "annotation": "豆"}], "かk": ["書"]}"#,
+        )
+        .unwrap();
+        let dict = Dictionary::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(dict.lookup("あずき"), Some(vec!["小豆"]));
+        assert_eq!(dict.annotation("あずき", "小豆"), Some("豆"));
+        assert_eq!(dict.lookup_okuri_ari("かk", None), Some(vec!["書"]));
+        assert_eq!(dict.readings_with_prefix("あず", 10), vec!["あずき"]);
+    }
+
+    #[test]
+    fn test_load_mozc_dictionary_keeps_costs() {
+        let path = std::env::temp_dir().join(format!("azuki-mozc-{}.tsv", std::process::id()));
+        std::fs::write(
+            &path,
+            "きょう\t1851\t1851\t6000\t京\nきょう\t1851\t1851\t3000\t今日\n",
+        )
+        .unwrap();
+        let dict = Dictionary::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(dict.lookup("きょう"), Some(vec!["今日", "京"]));
+        assert_eq!(dict.cost("きょう"), Some(lattice::mozc_word_cost(3000)));
+
+        let mut merged = Dictionary::load(test_dict_path()).unwrap();
+        assert_eq!(merged.cost("きょう"), None);
+        merged.merge(dict);
+        assert_eq!(merged.cost("きょう"), Some(lattice::mozc_word_cost(3000)));
+    }
+
     #[test]
     fn test_decode_content_utf8() {
         let utf8_bytes = "きょう /今日/".as_bytes();
@@ -250,6 +1560,33 @@ mod tests {
         assert_eq!(encoding, "EUC-JP");
     }
 
+    #[test]
+    fn test_decode_content_shift_jis() {
+        let (bytes, _, _) = SHIFT_JIS.encode("きょう /今日/");
+        let (decoded, encoding) = decode_content(&bytes);
+        assert_eq!(decoded, "きょう /今日/");
+        assert_eq!(encoding, "Shift_JIS");
+    }
+
+    #[test]
+    fn test_decode_content_iso_2022_jp() {
+        let (bytes, _, _) = ISO_2022_JP.encode("きょう /今日/");
+        let (decoded, encoding) = decode_content(&bytes);
+        assert_eq!(decoded, "きょう /今日/");
+        assert_eq!(encoding, "ISO-2022-JP");
+    }
+
+    #[test]
+    fn test_load_with_pinned_encoding() {
+        let path = std::env::temp_dir().join(format!("azuki-sjis-{}.dict", std::process::id()));
+        let (bytes, _, _) = SHIFT_JIS.encode(";; okuri-nasi entries.\nかい /貝/\n");
+        std::fs::write(&path, &bytes).unwrap();
+        let dict = Dictionary::load_with(&path, Some(DictionaryEncoding::ShiftJis)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(dict.lookup("かい"), Some(vec!["貝"]));
+        assert_eq!(dict.encoding(), Some("Shift_JIS"));
+    }
+
     #[test]
     fn test_lookup_with_fallback_found() {
         let dict = Dictionary::load(test_dict_path()).unwrap();
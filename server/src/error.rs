@@ -0,0 +1,198 @@
+//! Crate-wide error type
+//!
+//! Every fallible operation in the server returns [`AzukiError`]. Each
+//! variant maps to a stable protocol error code so clients can react to the
+//! kind of failure without parsing the human-readable message.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Result alias used throughout the server
+pub type Result<T> = std::result::Result<T, AzukiError>;
+
+/// Errors produced by azuki-server
+#[derive(Debug)]
+pub enum AzukiError {
+    /// Dictionary loading or lookup failed
+    Dictionary(DictionaryError),
+    /// Malformed frame, unparsable request or invalid request arguments
+    Protocol(String),
+    /// Neural conversion backend failed
+    #[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+    Zenzai(ZenzaiError),
+    /// Invalid configuration value
+    Config(String),
+    /// Unknown or invalid session
+    Session(String),
+    /// No backend could convert the reading
+    Conversion(String),
+    /// The client cancelled the request
+    Cancelled,
+    /// Transport-level I/O failure
+    Io(io::Error),
+}
+
+/// Dictionary-specific failures
+#[derive(Debug)]
+pub enum DictionaryError {
+    /// The dictionary file could not be read
+    Io { path: PathBuf, source: io::Error },
+    /// The dictionary file is malformed
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// Compiled dictionary data is malformed
+    Format(String),
+}
+
+/// Zenzai-specific failures
+#[derive(Debug)]
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+pub enum ZenzaiError {
+    /// Model file not found
+    ModelNotFound,
+    /// Backend not initialized
+    NotInitialized,
+    /// Model loading failed
+    LoadError(String),
+    /// Inference failed
+    InferenceError(String),
+    /// No model profile has this name
+    UnknownProfile(String),
+    /// The worker process running the model exited
+    WorkerCrashed(String),
+    /// Failure reported by the worker process
+    Worker(String),
+}
+
+impl AzukiError {
+    /// Protocol error code reported in `error` responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            AzukiError::Dictionary(_) => "dictionary_error",
+            AzukiError::Protocol(_) => "protocol_error",
+            AzukiError::Zenzai(_) => "zenzai_error",
+            AzukiError::Config(_) => "config_error",
+            AzukiError::Session(_) => "session_error",
+            AzukiError::Conversion(_) => "conversion_error",
+            AzukiError::Cancelled => "cancelled",
+            AzukiError::Io(_) => "io_error",
+        }
+    }
+}
+
+impl fmt::Display for AzukiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AzukiError::Dictionary(e) => write!(f, "{}", e),
+            AzukiError::Protocol(msg) => write!(f, "{}", msg),
+            AzukiError::Zenzai(e) => write!(f, "{}", e),
+            AzukiError::Config(msg) => write!(f, "Invalid configuration: {}", msg),
+            AzukiError::Session(msg) => write!(f, "Session error: {}", msg),
+            AzukiError::Conversion(msg) => write!(f, "Conversion failed: {}", msg),
+            AzukiError::Cancelled => write!(f, "Request cancelled"),
+            AzukiError::Io(e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for DictionaryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DictionaryError::Io { path, source } => {
+                write!(f, "IO error reading {}: {}", path.display(), source)
+            }
+            DictionaryError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "Parse error in {}:{}: {}", path.display(), line, message),
+            DictionaryError::Format(msg) => write!(f, "Malformed compiled dictionary: {}", msg),
+        }
+    }
+}
+
+impl fmt::Display for ZenzaiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZenzaiError::ModelNotFound => write!(f, "Zenzai model file not found"),
+            ZenzaiError::NotInitialized => write!(f, "Zenzai backend not initialized"),
+            ZenzaiError::LoadError(msg) => write!(f, "Failed to load Zenzai model: {}", msg),
+            ZenzaiError::InferenceError(msg) => write!(f, "Zenzai inference failed: {}", msg),
+            ZenzaiError::UnknownProfile(name) => write!(f, "Unknown Zenzai profile: {}", name),
+            ZenzaiError::WorkerCrashed(status) => write!(f, "Zenzai worker crashed: {}", status),
+            ZenzaiError::Worker(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for AzukiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AzukiError::Dictionary(e) => Some(e),
+            AzukiError::Zenzai(e) => Some(e),
+            AzukiError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for DictionaryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DictionaryError::Io { source, .. } => Some(source),
+            DictionaryError::Parse { .. } | DictionaryError::Format(_) => None,
+        }
+    }
+}
+
+impl std::error::Error for ZenzaiError {}
+
+impl From<DictionaryError> for AzukiError {
+    fn from(e: DictionaryError) -> Self {
+        AzukiError::Dictionary(e)
+    }
+}
+
+impl From<ZenzaiError> for AzukiError {
+    fn from(e: ZenzaiError) -> Self {
+        AzukiError::Zenzai(e)
+    }
+}
+
+impl From<io::Error> for AzukiError {
+    fn from(e: io::Error) -> Self {
+        AzukiError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes() {
+        let err = AzukiError::Protocol("bad".to_string());
+        assert_eq!(err.code(), "protocol_error");
+        assert_eq!(err.to_string(), "bad");
+
+        let err: AzukiError = ZenzaiError::ModelNotFound.into();
+        assert_eq!(err.code(), "zenzai_error");
+        assert_eq!(err.to_string(), "Zenzai model file not found");
+    }
+
+    #[test]
+    fn test_dictionary_error_source() {
+        let err: AzukiError = DictionaryError::Io {
+            path: PathBuf::from("/missing"),
+            source: io::Error::new(io::ErrorKind::NotFound, "not found"),
+        }
+        .into();
+        assert_eq!(err.code(), "dictionary_error");
+        assert!(err.to_string().contains("/missing"));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+}
@@ -0,0 +1,88 @@
+//! Fallback policy for readings no backend could convert
+
+use crate::error::{AzukiError, Result};
+use crate::kana;
+use crate::message::SegmentInfo;
+use serde::Deserialize;
+
+/// What to return when neither Zenzai nor the dictionary converted a reading
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackPolicy {
+    /// Return the reading as-is
+    #[default]
+    Reading,
+    /// Offer the katakana form first, then the reading
+    Katakana,
+    /// Fail with a `conversion_error` response
+    Error,
+}
+
+impl FallbackPolicy {
+    /// Apply the policy to an unconverted chunk's result
+    pub fn apply(
+        &self,
+        reading: &str,
+        candidates: &mut Vec<String>,
+        segments: &mut [SegmentInfo],
+    ) -> Result<()> {
+        match self {
+            FallbackPolicy::Reading => Ok(()),
+            FallbackPolicy::Katakana => {
+                prepend_katakana(reading, candidates);
+                for seg in segments {
+                    prepend_katakana(&seg.reading, &mut seg.candidates);
+                }
+                Ok(())
+            }
+            FallbackPolicy::Error => Err(AzukiError::Conversion(format!(
+                "No conversion found for: {}",
+                reading
+            ))),
+        }
+    }
+}
+
+/// Put the katakana form of `reading` at the front of `candidates`
+fn prepend_katakana(reading: &str, candidates: &mut Vec<String>) {
+    let katakana = kana::hiragana_to_katakana(reading);
+    candidates.retain(|c| *c != katakana);
+    candidates.insert(0, katakana);
+}
+
+/// Check whether a result is only the reading echoed back
+pub fn is_unconverted(reading: &str, candidates: &[String]) -> bool {
+    candidates.iter().all(|c| c == reading)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_katakana_policy() {
+        let mut candidates = vec!["ぱそこん".to_string()];
+        let mut segments = vec![SegmentInfo {
+            reading: "ぱそこん".to_string(),
+            start: 0,
+            length: 4,
+            candidates: vec!["ぱそこん".to_string()],
+            fixed: false,
+            annotations: Default::default(),
+            kinds: Default::default(),
+        }];
+        FallbackPolicy::Katakana
+            .apply("ぱそこん", &mut candidates, &mut segments)
+            .unwrap();
+        assert_eq!(candidates, vec!["パソコン", "ぱそこん"]);
+        assert_eq!(segments[0].candidates, vec!["パソコン", "ぱそこん"]);
+    }
+
+    #[test]
+    fn test_error_policy() {
+        let err = FallbackPolicy::Error
+            .apply("ぱそこん", &mut vec![], &mut [])
+            .unwrap_err();
+        assert_eq!(err.code(), "conversion_error");
+    }
+}
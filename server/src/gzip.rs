@@ -0,0 +1,86 @@
+//! gzip decompression for dictionary files
+//!
+//! Distributions commonly ship SKK dictionaries compressed
+//! (`SKK-JISYO.L.gz`). The stream is inflated as it is read, and the
+//! CRC-32 and length of every member are checked.
+
+use flate2::bufread::MultiGzDecoder;
+use std::io::{self, BufRead, Read};
+
+/// Bytes opening a gzip member
+pub const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Decompress every gzip member of a stream
+///
+/// Concatenated files are one stream of several members.
+pub fn decompress<R: BufRead>(reader: R) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    MultiGzDecoder::new(reader).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Crc;
+
+    /// Header flags
+    const FEXTRA: u8 = 1 << 2;
+    const FNAME: u8 = 1 << 3;
+
+    /// `gzip.compress(b"abc", mtime=0)`, fixed codes
+    const ABC: [u8; 23] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x4b, 0x4c, 0x4a, 0x06, 0x00,
+        0xc2, 0x41, 0x24, 0x35, 0x03, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_fixed_codes() {
+        assert_eq!(decompress(&ABC[..]).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn test_dynamic_codes() {
+        let expected = "ababaaaabbbaaabbaabbaabbbabbabbbabaabbbabaaababbaaabbbbaaabaaabbbaaaaaaabbbbaaaababbbaaabbbabbbbaaaaaabbbbabaa";
+        let stream = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x45, 0x8c, 0x87, 0x0d,
+            0x00, 0x30, 0x08, 0xc3, 0x6e, 0xb5, 0xff, 0x3f, 0xa2, 0x28, 0x65, 0x20, 0x56, 0x1c,
+            0x01, 0x22, 0x15, 0x6a, 0xfa, 0x94, 0x24, 0xe3, 0xf6, 0x20, 0x2c, 0xf2, 0xab, 0xb9,
+            0xe2, 0x20, 0xee, 0x27, 0x97, 0xb5, 0x5b, 0xfb, 0x03, 0x9e, 0x70, 0x5d, 0x9c, 0x6e,
+            0x00, 0x00, 0x00,
+        ];
+        assert_eq!(decompress(&stream[..]).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn test_stored_block_and_header_fields() {
+        let data = b"stored";
+        let mut stream = vec![0x1f, 0x8b, 0x08, FNAME | FEXTRA, 0, 0, 0, 0, 0, 0xff];
+        stream.extend([2, 0, 0xaa, 0xbb]);
+        stream.extend(b"SKK-JISYO.S\0");
+        stream.push(0x01);
+        stream.extend((data.len() as u16).to_le_bytes());
+        stream.extend((!(data.len() as u16)).to_le_bytes());
+        stream.extend(data);
+        let mut crc = Crc::new();
+        crc.update(data);
+        stream.extend(crc.sum().to_le_bytes());
+        stream.extend((data.len() as u32).to_le_bytes());
+        assert_eq!(decompress(&stream[..]).unwrap(), data);
+    }
+
+    #[test]
+    fn test_concatenated_members() {
+        let stream = [ABC, ABC].concat();
+        assert_eq!(decompress(&stream[..]).unwrap(), b"abcabc");
+    }
+
+    #[test]
+    fn test_corrupt_streams_are_rejected() {
+        let mut bad_crc = ABC;
+        bad_crc[15] ^= 1;
+        assert!(decompress(&bad_crc[..]).is_err());
+        assert!(decompress(&ABC[..ABC.len() - 3]).is_err());
+        assert!(decompress(&b"plain text"[..]).is_err());
+    }
+}
@@ -1,319 +1,4100 @@
 //! Request handler and server state
 
-use crate::config::load_dictionary;
-use crate::converter::{AdjustDirection, Converter, Segment};
-use crate::message::{Request, Response, SegmentInfo};
+use crate::blocklist::Blocklist;
+use crate::cancel::CancelToken;
+use crate::confidence::combined_confidence;
+use crate::config::{
+    load_blocklist, load_dictionaries_reporting, load_learning, load_user_dictionary,
+    DictionaryLoad, DictionarySource, ServerConfig, DEFAULT_SET,
+};
+use crate::converter::{
+    validate_reading, AdjustDirection, ConversionResult, Converter, GeneratedForms, Segment,
+    READING_SOFT_LIMIT,
+};
+use crate::delta::{diff_candidates, diff_segments};
+use crate::dictionary::Dictionary;
+use crate::error::AzukiError;
 #[cfg(feature = "zenzai")]
-use crate::zenzai::ZenzaiBackend;
-use crate::zenzai::ZenzaiConfig;
+use crate::error::ZenzaiError;
+use crate::fallback::{self, FallbackPolicy};
+use crate::kana;
+use crate::learning::LearningStore;
+use crate::log;
+#[cfg(feature = "zenzai")]
+use crate::message::Engine;
+use crate::message::{
+    BatchConversion, Candidate, CandidateKind, CandidateSource, Completion, CompositionInfo,
+    ConvertOptions, Notification, Request, ResourceWarningKind, Response, SegmentInfo,
+};
+use crate::metrics::{MemoryStats, Metrics};
+use crate::number_reading;
+use crate::remote_dict::{RemoteDictionary, RemoteDictionaryConfig};
+use crate::romaji::{RomajiConfig, RomajiTable};
+use crate::session::{
+    validate_session_id, Composition, Eviction, EvictionReason, InputMode, LiveSnapshot, Session,
+    SessionManager,
+};
+use crate::settings::{Scope, Settings};
+#[cfg(feature = "zenzai")]
+use crate::sidecar::ZenzaiSidecar;
+use crate::user_dict::UserDictionary;
+use crate::zenzai::{ZenzaiCandidate, ZenzaiConfig};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+#[cfg(feature = "zenzai")]
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Suffix for generated session ids
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(0);
+
+/// Candidates returned with each completion
+const COMPLETION_CANDIDATES: usize = 3;
+
+/// Upper bound on the readings a `complete` request may ask for
+const MAX_COMPLETIONS: usize = 100;
+
+/// Segment boundaries requested for one chunk of a reading
+#[derive(Debug, Clone, Copy, Default)]
+struct Boundaries<'a> {
+    /// Segment lengths the user chose earlier with `adjust_segment`
+    lengths: Option<&'a [usize]>,
+    /// Cursor position within the chunk, where a boundary should fall
+    cursor: Option<usize>,
+}
+
+/// What a conversion uses from the session it is for
+struct SessionInputs<'a> {
+    /// Session the conversion is for, whose Zenzai context is reused
+    #[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+    session_id: &'a str,
+    /// Dynamic providers enabled for the session
+    providers: &'a BTreeMap<String, bool>,
+    /// Text committed before the reading, given to Zenzai as context
+    #[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+    context: &'a str,
+    /// Zenzai model profile chosen for the session
+    #[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+    zenzai_profile: Option<&'a str>,
+}
+
+/// Result of converting one chunk of a reading
+struct ChunkConversion {
+    candidates: Vec<Candidate>,
+    segments: Vec<SegmentInfo>,
+    /// Confidence that the top candidate is right
+    confidence: f64,
+}
+
+/// Zenzai candidates and the confidence of the most likely one
+type ZenzaiOutput = (Vec<ZenzaiCandidate>, Option<f64>);
+
+/// Zenzai inference for a conversion, run without holding the server lock
+///
+/// Made by `Server::prefetch`; other requests are handled while it runs.
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+pub struct Prefetch {
+    #[cfg(feature = "zenzai")]
+    backend: Arc<Mutex<ZenzaiSidecar>>,
+    chunk: String,
+    #[cfg(feature = "zenzai")]
+    session_id: String,
+    #[cfg(feature = "zenzai")]
+    context: String,
+    #[cfg(feature = "zenzai")]
+    profile: Option<String>,
+    /// Seq of the request, if it asked for progress notifications
+    #[cfg(feature = "zenzai")]
+    stream: Option<u64>,
+}
+
+impl Prefetch {
+    /// Run the inference
+    ///
+    /// Progress notifications the request asked for go to `notify`.
+    pub fn run(self, cancel: &CancelToken, notify: &dyn Fn(Notification)) -> Prefetched {
+        #[cfg(feature = "zenzai")]
+        let output = {
+            let mut progress = |text: &str| {
+                if let Some(seq) = self.stream {
+                    notify(Notification::ConvertProgress {
+                        seq,
+                        session_id: self.session_id.clone(),
+                        text: text.to_string(),
+                    });
+                }
+            };
+            run_zenzai(
+                &self.backend,
+                &self.session_id,
+                self.profile.as_deref(),
+                &self.chunk,
+                &self.context,
+                cancel,
+                &mut progress,
+            )
+        };
+        #[cfg(not(feature = "zenzai"))]
+        let output = {
+            let _ = (cancel, notify);
+            None
+        };
+        Prefetched {
+            chunk: self.chunk,
+            output,
+        }
+    }
+}
+
+/// Result of a `Prefetch`, for the conversion it was made for
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+pub struct Prefetched {
+    chunk: String,
+    output: Option<ZenzaiOutput>,
+}
+
+/// Stores saved on every change whose last save failed
+#[derive(Debug, Clone, Copy, Default)]
+struct Unsaved {
+    user_dictionary: bool,
+    blocklist: bool,
+}
+
+/// Dictionaries of a named set, loaded when a session first selects it
+struct DictionarySet {
+    /// Dictionary loaded from disk, without user words
+    system: Option<Arc<Dictionary>>,
+    /// With user words. While a request of a session using the set is
+    /// handled, this holds the default dictionary and the converter this
+    /// one.
+    dictionary: Option<Arc<Dictionary>>,
+    /// Outcome of the latest load, per file tried
+    report: Vec<DictionaryLoad>,
+}
+
+/// A dictionary set to load in the background, see `Server::take_set_loads`
+pub struct SetLoad {
+    pub name: String,
+    pub sources: Vec<DictionarySource>,
+}
 
 /// Server state
 pub struct Server {
     converter: Converter,
+    /// Dictionary loaded from disk, without user words
+    system_dictionary: Option<Arc<Dictionary>>,
+    /// Dictionary files as configured, empty for the default search
+    dictionary_paths: Vec<DictionarySource>,
+    /// Outcome of the latest dictionary load, per file tried
+    dictionary_report: Vec<DictionaryLoad>,
+    /// Other dictionary sets as configured, by name
+    dictionary_sets: BTreeMap<String, Vec<DictionarySource>>,
+    /// Sets loaded so far, by name
+    loaded_sets: HashMap<String, DictionarySet>,
+    /// Sets being loaded in the background
+    loading_sets: HashSet<String>,
+    /// Set loads for the connection to start, see `take_set_loads`
+    set_loads: Vec<SetLoad>,
+    /// Set whose dictionary the converter holds, None for the default
+    active_set: Option<String>,
+    user_dictionary: UserDictionary,
+    blocklist: Blocklist,
+    learning: LearningStore,
+    fallback: FallbackPolicy,
+    /// Table for `to_kana` requests
+    romaji: RomajiTable,
+    sessions: SessionManager,
+    /// Connections being served, by id, for notifications meant for one
+    connections: HashMap<u64, mpsc::Sender<Notification>>,
+    next_connection: u64,
+    /// Connection whose request is being handled
+    connection: Option<u64>,
+    /// Connection that last made a request for each session
+    session_owners: HashMap<String, u64>,
+    /// Connections that opted in to server events, by subscription id
+    subscribers: HashMap<u64, mpsc::Sender<Notification>>,
+    next_subscriber: u64,
+    /// Dictionaries are still loading in the background
+    dictionary_loading: bool,
+    /// Token of the request being handled
+    cancel: CancelToken,
+    /// When the server was created, for `ping`
+    started: Instant,
+    /// Request counters and latencies, for `get_stats`
+    metrics: Metrics,
+    /// Settings of sessions that didn't change them with `set_config`
+    settings: Settings,
+    /// Zenzai result computed before the current request took the lock
+    prefetched: Option<Prefetched>,
+    /// Stores whose last save failed, saved again by `persist`
+    unsaved: Unsaved,
+    /// Only set once the model is loaded in its worker process; locked
+    /// separately so inference can run while other requests are handled
     #[cfg(feature = "zenzai")]
-    zenzai: Option<ZenzaiBackend>,
-    #[cfg(not(feature = "zenzai"))]
-    #[allow(dead_code)]
+    zenzai: Option<Arc<Mutex<ZenzaiSidecar>>>,
+    /// Zenzai configuration of the latest init
     zenzai_config: Option<ZenzaiConfig>,
+    /// Why Zenzai couldn't be initialized
+    #[cfg(feature = "zenzai")]
+    zenzai_error: Option<String>,
 }
 
 impl Server {
-    /// Create a new server with dictionary loaded from default paths
-    pub fn new() -> Self {
-        let dictionary = load_dictionary();
-        let converter = Converter::new(dictionary);
+    /// Create a new server from startup configuration
+    ///
+    /// Dictionaries come from the configuration, or the default search
+    /// paths if it names none.
+    pub fn new(config: ServerConfig) -> Self {
+        let (dictionary, report) = load_dictionaries_reporting(&config.dictionaries);
+        let mut server = Self::configured(config, dictionary.map(Arc::new));
+        server.dictionary_report = report;
+        server
+    }
+
+    /// Create a server whose dictionaries are loaded in the background
+    ///
+    /// Until `finish_loading` is called, readings pass through unconverted
+    /// regardless of the fallback policy.
+    pub fn loading(config: ServerConfig) -> Self {
+        let mut server = Self::configured(config, None);
+        server.dictionary_loading = true;
+        server
+    }
+
+    /// Switch to dictionaries loaded in the background
+    ///
+    /// Returns the `dictionary_ready` notification for the client.
+    pub fn finish_loading(
+        &mut self,
+        dictionary: Option<Arc<Dictionary>>,
+        elapsed: Duration,
+    ) -> Notification {
+        let entries = dictionary.as_deref().map_or(0, Dictionary::len);
+        self.system_dictionary = dictionary;
+        self.rebuild_dictionary();
+        self.dictionary_loading = false;
+        log_info!(
+            "[handler] Dictionaries ready: {} entries in {}ms",
+            entries,
+            elapsed.as_millis()
+        );
+        Notification::DictionaryReady {
+            has_dictionary: self.converter.has_dictionary(),
+            entries,
+            elapsed_ms: elapsed.as_millis() as u64,
+        }
+    }
+
+    /// Record the outcome of a dictionary load, for `init` and `dict_info`
+    pub fn set_dictionary_report(&mut self, report: Vec<DictionaryLoad>) {
+        self.dictionary_report = report;
+    }
+
+    /// Apply startup configuration to a server with the given dictionary
+    fn configured(config: ServerConfig, dictionary: Option<Arc<Dictionary>>) -> Self {
+        let mut server = Self::with_dictionary(dictionary);
+        server.dictionary_paths = config.dictionaries;
+        server.dictionary_sets = config.dictionary_sets;
+        if let Some(sessions) = config.sessions {
+            server.sessions.set_config(sessions);
+        }
+        if let Some(punctuation) = config.punctuation {
+            server.converter.set_punctuation(punctuation);
+        }
+        if let Some(fallback) = config.fallback {
+            server.fallback = fallback;
+        }
+        if let Some(providers) = config.providers {
+            server.converter.set_provider_config(providers);
+        }
+        if let Some(conversion) = config.conversion {
+            server.converter.set_conversion(conversion);
+        }
+        if let Some(remote) = config.remote_dictionary {
+            server.set_remote_dictionary(remote);
+        }
+        if let Some(romaji) = config.romaji {
+            server.set_romaji(&romaji);
+        }
+        if let Some(zenzai) = config.zenzai {
+            #[cfg(feature = "zenzai")]
+            server.init_zenzai(zenzai);
+            #[cfg(not(feature = "zenzai"))]
+            {
+                server.zenzai_config = Some(zenzai);
+            }
+        }
+        server.blocklist = load_blocklist();
+        server.user_dictionary = load_user_dictionary();
+        server.learning = load_learning();
+        if !server.user_dictionary.is_empty() {
+            server.rebuild_dictionary();
+        }
+        server
+    }
+
+    /// Create a new server sharing an already loaded dictionary
+    pub fn with_dictionary(dictionary: Option<Arc<Dictionary>>) -> Self {
+        let converter = Converter::with_shared(dictionary.clone());
         Self {
             converter,
+            system_dictionary: dictionary,
+            dictionary_paths: Vec::new(),
+            dictionary_report: Vec::new(),
+            dictionary_sets: BTreeMap::new(),
+            loaded_sets: HashMap::new(),
+            loading_sets: HashSet::new(),
+            set_loads: Vec::new(),
+            active_set: None,
+            user_dictionary: UserDictionary::new(),
+            blocklist: Blocklist::new(),
+            learning: LearningStore::new(),
+            fallback: FallbackPolicy::default(),
+            romaji: RomajiTable::default(),
+            sessions: SessionManager::new(),
+            connections: HashMap::new(),
+            next_connection: 0,
+            connection: None,
+            session_owners: HashMap::new(),
+            subscribers: HashMap::new(),
+            next_subscriber: 0,
+            dictionary_loading: false,
+            cancel: CancelToken::default(),
+            started: Instant::now(),
+            metrics: Metrics::new(),
+            settings: Settings::default(),
+            prefetched: None,
+            unsaved: Unsaved::default(),
             #[cfg(feature = "zenzai")]
             zenzai: None,
-            #[cfg(not(feature = "zenzai"))]
             zenzai_config: None,
+            #[cfg(feature = "zenzai")]
+            zenzai_error: None,
         }
     }
 
     /// Initialize Zenzai backend if configured
     #[cfg(feature = "zenzai")]
     fn init_zenzai(&mut self, config: ZenzaiConfig) -> bool {
+        self.zenzai_config = Some(config.clone());
         if !config.enabled {
-            eprintln!("[zenzai] Disabled by configuration");
+            log_info!("[zenzai] Disabled by configuration");
+            self.zenzai_error = Some("Disabled by configuration".to_string());
             return false;
         }
 
         if !config.is_usable() {
-            eprintln!("[zenzai] Model not found, falling back to dictionary-based conversion");
+            log_warn!("[zenzai] Model not found, falling back to dictionary-based conversion");
+            self.zenzai_error = Some(ZenzaiError::ModelNotFound.to_string());
             return false;
         }
 
-        let mut backend = ZenzaiBackend::new(config);
+        let started = Instant::now();
+        let mut backend = ZenzaiSidecar::new(config);
         match backend.initialize() {
             Ok(()) => {
-                self.zenzai = Some(backend);
-                eprintln!("[zenzai] Initialized successfully");
+                self.zenzai = Some(Arc::new(Mutex::new(backend)));
+                self.zenzai_error = None;
+                log_info!("[zenzai] Initialized successfully");
+                self.broadcast(Notification::ZenzaiReady {
+                    elapsed_ms: started.elapsed().as_millis() as u64,
+                });
                 true
             }
             Err(e) => {
-                eprintln!("[zenzai] Initialization failed: {}", e);
+                log_error!("[zenzai] Initialization failed: {}", e);
+                self.zenzai_error = Some(e.to_string());
                 false
             }
         }
     }
 
+    /// Answer `zenzai_status`
+    #[cfg(feature = "zenzai")]
+    fn zenzai_status(&self, seq: u64, session_id: String) -> Response {
+        let model_path = self
+            .zenzai_config
+            .as_ref()
+            .and_then(ZenzaiConfig::get_model_path)
+            .map(|path| path.display().to_string());
+        let (worker_pid, models, last_error) = match &self.zenzai {
+            Some(backend) => {
+                let mut backend = backend.lock().unwrap_or_else(PoisonError::into_inner);
+                let (models, error) = match backend.status() {
+                    Ok(models) => (models, None),
+                    Err(e) => (None, Some(e.to_string())),
+                };
+                let last_error = error.or_else(|| backend.last_error().map(String::from));
+                (backend.worker_pid(), models, last_error)
+            }
+            None => (None, None, self.zenzai_error.clone()),
+        };
+        Response::ZenzaiStatusResult {
+            seq,
+            session_id,
+            available: true,
+            enabled: self.zenzai.is_some(),
+            model_path,
+            worker_pid,
+            models,
+            last_error,
+        }
+    }
+
+    #[cfg(not(feature = "zenzai"))]
+    fn zenzai_status(&self, seq: u64, session_id: String) -> Response {
+        Response::ZenzaiStatusResult {
+            seq,
+            session_id,
+            available: false,
+            enabled: false,
+            model_path: self
+                .zenzai_config
+                .as_ref()
+                .and_then(|config| config.model_path.clone()),
+            worker_pid: None,
+            models: None,
+            last_error: Some("azuki-server was built without the zenzai feature".to_string()),
+        }
+    }
+
     /// Check if Zenzai is enabled and ready
     #[cfg(feature = "zenzai")]
     fn is_zenzai_enabled(&self) -> bool {
-        self.zenzai.as_ref().is_some_and(|z| z.is_ready())
+        self.zenzai.is_some()
     }
 
     #[cfg(not(feature = "zenzai"))]
-    #[allow(dead_code)]
     fn is_zenzai_enabled(&self) -> bool {
         false
     }
 
+    /// Whether a chunk is converted with Zenzai under these settings
+    ///
+    /// The request's engine can rule Zenzai out or ask for it regardless
+    /// of the settings. Prefix and suffix entries are dictionary-only.
+    #[cfg(feature = "zenzai")]
+    fn uses_zenzai(&self, chunk: &str, settings: &Settings, engine: Engine) -> bool {
+        let wanted = match engine {
+            Engine::Dict => false,
+            Engine::Zenzai => true,
+            Engine::Auto => settings.zenzai,
+        };
+        wanted && self.is_zenzai_enabled() && !crate::converter::is_affix_reading(chunk)
+    }
+
+    /// Zenzai inference a request needs, to run before it is handled
+    ///
+    /// Only conversions that go to Zenzai need any; the caller runs it
+    /// without the server lock and passes the result to
+    /// `handle_cancellable`.
+    pub fn prefetch(&mut self, request: &Request) -> Option<Prefetch> {
+        let Request::Convert {
+            seq,
+            session_id,
+            reading,
+            options,
+            continuation,
+            ..
+        } = request
+        else {
+            return None;
+        };
+        #[cfg(feature = "zenzai")]
+        {
+            let chunked = options.as_ref().is_some_and(|o| o.chunked);
+            let chunk = requested_chunk(reading, continuation.as_deref(), chunked)?;
+            let settings = self.settings_for(session_id);
+            let (mode, context, session_profile) = self
+                .sessions
+                .get_mut(session_id)
+                .map(|s| {
+                    let profile = s.zenzai_profile.clone();
+                    (s.input_mode, s.context().to_string(), profile)
+                })
+                .unwrap_or_default();
+            let engine = options.as_ref().map_or(Engine::Auto, |o| o.engine);
+            if !self.uses_zenzai(&chunk, &settings, engine) || mode != InputMode::Hiragana {
+                return None;
+            }
+            let backend = Arc::clone(self.zenzai.as_ref()?);
+            let stream = options.as_ref().is_some_and(|o| o.stream);
+            let profile = options
+                .as_ref()
+                .and_then(|o| o.zenzai_profile.clone())
+                .or(session_profile);
+            Some(Prefetch {
+                backend,
+                chunk,
+                session_id: session_id.clone(),
+                context,
+                profile,
+                stream: stream.then_some(*seq),
+            })
+        }
+        #[cfg(not(feature = "zenzai"))]
+        {
+            let _ = (seq, session_id, reading, options, continuation);
+            None
+        }
+    }
+
+    /// Settings in effect for a session
+    fn settings_for(&mut self, session_id: &str) -> Settings {
+        match self.sessions.get_mut(session_id) {
+            Some(session) => self.settings.with(&session.settings),
+            None => self.settings,
+        }
+    }
+
+    /// Answer `get_config` and `set_config` with the session's settings
+    fn config_result(&mut self, seq: u64, session_id: String) -> Response {
+        let session = self
+            .sessions
+            .get_mut(&session_id)
+            .map(|session| session.settings)
+            .unwrap_or_default();
+        Response::ConfigResult {
+            seq,
+            session_id,
+            global: self.settings,
+            session,
+            effective: self.settings.with(&session),
+        }
+    }
+
+    /// Register a connection, for notifications about its sessions
+    ///
+    /// Returns the id to pass to `handle_cancellable` and `disconnect`.
+    pub fn connect(&mut self, sender: mpsc::Sender<Notification>) -> u64 {
+        self.next_connection += 1;
+        self.connections.insert(self.next_connection, sender);
+        self.next_connection
+    }
+
+    pub fn disconnect(&mut self, id: u64) {
+        self.connections.remove(&id);
+    }
+
+    /// Send server events to a connection until it unsubscribes
+    ///
+    /// Returns the id to unsubscribe with.
+    pub fn subscribe(&mut self, sender: mpsc::Sender<Notification>) -> u64 {
+        self.next_subscriber += 1;
+        self.subscribers.insert(self.next_subscriber, sender);
+        self.next_subscriber
+    }
+
+    pub fn unsubscribe(&mut self, id: u64) {
+        self.subscribers.remove(&id);
+    }
+
+    /// Send a server event to every subscribed connection
+    ///
+    /// Connections that have gone away are dropped.
+    fn broadcast(&mut self, notification: Notification) {
+        self.subscribers
+            .retain(|_, sender| sender.send(notification.clone()).is_ok());
+    }
+
+    /// Warn subscribed connections about a resource problem
+    fn warn(&mut self, kind: ResourceWarningKind, message: String) {
+        self.broadcast(Notification::ResourceWarning { kind, message });
+    }
+
+    /// Tell the connections that owned evicted sessions
+    ///
+    /// Sessions not used over a connection (or whose connection has gone)
+    /// are evicted silently.
+    fn notify_evictions(&mut self, evictions: Vec<Eviction>) {
+        for eviction in evictions {
+            log_info!(
+                "[session] Evicted {} ({:?})",
+                eviction.session_id,
+                eviction.reason
+            );
+            if eviction.reason == EvictionReason::Capacity {
+                self.warn(
+                    ResourceWarningKind::Sessions,
+                    format!("Session limit reached; evicted {}", eviction.session_id),
+                );
+            }
+            let owner = self.session_owners.remove(&eviction.session_id);
+            if let Some(sender) = owner.and_then(|id| self.connections.get(&id)) {
+                let _ = sender.send(Notification::SessionEvicted {
+                    session_id: eviction.session_id,
+                    reason: eviction.reason,
+                });
+            }
+        }
+    }
+
+    /// Run session garbage collection and mark the request's session active
+    fn track_session(&mut self, session_id: &str) -> Result<(), AzukiError> {
+        validate_session_id(session_id)?;
+        let now = Instant::now();
+        let expired = self.sessions.collect_expired(now);
+        self.notify_evictions(expired);
+        let evicted = self.sessions.touch(session_id, now)?;
+        self.notify_evictions(evicted);
+        if let Some(connection) = self.connection {
+            self.session_owners
+                .insert(session_id.to_string(), connection);
+        }
+        Ok(())
+    }
+
     /// Handle a request and return a response
     pub fn handle_request(&mut self, request: Request) -> Response {
-        match request {
-            Request::Init {
-                seq,
-                session_id,
-                zenzai,
-            } => {
-                let session_id = session_id.unwrap_or_else(|| {
-                    format!(
-                        "session_{}",
-                        std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_millis()
-                    )
-                });
+        let kind = request.kind();
+        let started = Instant::now();
+        let response = self.handle_untimed(request);
+        let failed = matches!(response, Response::Error { .. });
+        self.metrics.record(kind, started.elapsed(), failed);
+        response
+    }
 
-                // Initialize Zenzai if requested
-                // Can't use map() here due to #[cfg] attributes inside
-                #[allow(clippy::manual_map)]
-                let zenzai_enabled = if let Some(config) = zenzai {
-                    #[cfg(feature = "zenzai")]
-                    {
-                        Some(self.init_zenzai(config))
-                    }
-                    #[cfg(not(feature = "zenzai"))]
-                    {
-                        self.zenzai_config = Some(config);
-                        eprintln!("[zenzai] Feature not enabled at compile time");
-                        Some(false)
-                    }
-                } else {
-                    None
-                };
+    /// Handle a request without recording it in the metrics
+    fn handle_untimed(&mut self, mut request: Request) -> Response {
+        // Init applies the session policy before its own session is tracked
+        // and assigns an id when the client didn't provide one
+        if let Request::Init {
+            session_id,
+            sessions,
+            punctuation,
+            fallback,
+            providers,
+            conversion,
+            remote_dictionary,
+            romaji,
+            ..
+        } = &mut request
+        {
+            if let Some(config) = romaji.take() {
+                self.set_romaji(&config);
+            }
+            if let Some(config) = remote_dictionary.take() {
+                self.set_remote_dictionary(config);
+            }
+            if let Some(config) = conversion.take() {
+                self.converter.set_conversion(config);
+            }
+            if let Some(config) = providers.take() {
+                self.converter.set_provider_config(config);
+            }
+            if let Some(policy) = fallback.take() {
+                self.fallback = policy;
+            }
+            if let Some(config) = sessions.take() {
+                self.sessions.set_config(config);
+            }
+            if let Some(config) = punctuation.take() {
+                self.converter.set_punctuation(config);
+            }
+            session_id.get_or_insert_with(|| {
+                // The counter keeps ids unique when clients of a shared
+                // server init within the same millisecond
+                format!(
+                    "session_{}_{}",
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis(),
+                    NEXT_SESSION.fetch_add(1, Ordering::Relaxed)
+                )
+            });
+        }
 
-                Response::InitResult {
+        if let Some(session_id) = request.session_id() {
+            if let Err(e) = self.track_session(session_id) {
+                let session_id = session_id.to_string();
+                return Response::error(request.seq(), Some(session_id), e);
+            }
+        }
+
+        // The converter holds the default dictionary between requests, and
+        // the session's set while one of its requests is handled
+        let set = request
+            .session_id()
+            .and_then(|id| self.sessions.get_mut(id)?.dictionary_set.clone());
+        self.enter_dictionary_set(set);
+        let response = self.dispatch(request);
+        self.enter_dictionary_set(None);
+        response
+    }
+
+    /// Answer a health check
+    ///
+    /// `queue_depth` is the number of other requests not yet answered,
+    /// which only the connection knows.
+    pub fn ping(&self, seq: u64, queue_depth: usize) -> Response {
+        Response::PingResult {
+            seq,
+            uptime_ms: self.started.elapsed().as_millis() as u64,
+            has_dictionary: self.converter.has_dictionary(),
+            dictionary_loading: self.dictionary_loading,
+            zenzai_ready: self.is_zenzai_enabled(),
+            queue_depth,
+        }
+    }
+
+    /// Handle a request the client may cancel while it is queued or runs
+    ///
+    /// A request cancelled before it starts isn't handled at all; long
+    /// conversions check the token between steps.
+    pub fn handle_cancellable(
+        &mut self,
+        connection: u64,
+        request: Request,
+        cancel: CancelToken,
+        prefetched: Option<Prefetched>,
+    ) -> Response {
+        if let Err(e) = cancel.check() {
+            let session_id = request.session_id().map(String::from);
+            return Response::error(request.seq(), session_id, e);
+        }
+        self.cancel = cancel;
+        self.prefetched = prefetched;
+        self.connection = Some(connection);
+        let response = self.handle_request(request);
+        self.cancel = CancelToken::default();
+        self.prefetched = None;
+        self.connection = None;
+        response
+    }
+
+    /// Answer `convert_batch`
+    ///
+    /// Zenzai converts the readings that go to it in one batch; each reading
+    /// then goes through `convert_chunk` with its output as if prefetched.
+    /// Readings must fit in one chunk. Sessions' compositions are left
+    /// alone.
+    fn convert_batch(
+        &mut self,
+        seq: u64,
+        session_id: String,
+        readings: Vec<String>,
+        contexts: Option<Vec<String>>,
+        options: Option<ConvertOptions>,
+    ) -> Response {
+        for reading in &readings {
+            if let Err(e) = validate_reading(reading) {
+                return Response::error(seq, Some(session_id), e);
+            }
+            let length = reading.chars().count();
+            if length > READING_SOFT_LIMIT {
+                return Response::error(
                     seq,
-                    session_id,
-                    version: env!("CARGO_PKG_VERSION").to_string(),
-                    has_dictionary: self.converter.has_dictionary(),
-                    zenzai_enabled,
-                }
+                    Some(session_id),
+                    AzukiError::Protocol(format!(
+                        "Reading of {} characters is too long for a batch (at most {})",
+                        length, READING_SOFT_LIMIT
+                    )),
+                );
             }
-            Request::Convert {
+        }
+        if let Some(contexts) = contexts.as_ref().filter(|c| c.len() != readings.len()) {
+            return Response::error(
                 seq,
-                session_id,
-                reading,
-                cursor: _,
-                options: _,
-            } => {
-                // Try Zenzai first if enabled
-                #[cfg(feature = "zenzai")]
-                let zenzai_result = if self.is_zenzai_enabled() {
-                    if let Some(ref mut zenzai) = self.zenzai {
-                        match zenzai.convert(&reading, None) {
-                            Ok(candidates) => {
-                                eprintln!("[handler] Zenzai conversion successful");
-                                Some(candidates)
-                            }
-                            Err(e) => {
-                                eprintln!("[handler] Zenzai conversion failed: {}, falling back to dictionary", e);
-                                None
-                            }
-                        }
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                };
+                Some(session_id),
+                AzukiError::Protocol(format!(
+                    "{} contexts for {} readings",
+                    contexts.len(),
+                    readings.len()
+                )),
+            );
+        }
 
-                #[cfg(not(feature = "zenzai"))]
-                let zenzai_result: Option<Vec<String>> = None;
-
-                // When Zenzai succeeds, use its result as a single segment
-                // This ensures the UI displays Zenzai candidates properly
-                if let Some(zenzai_candidates) = zenzai_result {
-                    // Create a single segment covering the entire reading
-                    let segment = SegmentInfo {
-                        reading: reading.clone(),
-                        start: 0,
-                        length: reading.chars().count(),
-                        candidates: zenzai_candidates.clone(),
-                    };
+        let options = options.unwrap_or_default();
+        let settings = self.settings_for(&session_id);
+        let (providers, context, mode, session_profile) = self
+            .sessions
+            .get_mut(&session_id)
+            .map(|session| {
+                (
+                    session.providers.clone(),
+                    session.context().to_string(),
+                    session.input_mode,
+                    session.zenzai_profile.clone(),
+                )
+            })
+            .unwrap_or_default();
+        let contexts = contexts.unwrap_or_else(|| vec![context; readings.len()]);
+        let profile = options.zenzai_profile.clone().or(session_profile);
 
-                    return Response::ConvertResult {
-                        seq,
-                        session_id,
-                        candidates: zenzai_candidates,
-                        segments: vec![segment],
-                    };
+        #[cfg(feature = "zenzai")]
+        let outputs = if mode == InputMode::Hiragana {
+            self.zenzai_batch(
+                &readings,
+                &contexts,
+                profile.as_deref(),
+                &settings,
+                &options,
+            )
+        } else {
+            vec![None; readings.len()]
+        };
+        #[cfg(not(feature = "zenzai"))]
+        let outputs: Vec<Option<ZenzaiOutput>> = vec![None; readings.len()];
+
+        let mut results = Vec::with_capacity(readings.len());
+        for ((reading, context), output) in readings.into_iter().zip(&contexts).zip(outputs) {
+            self.prefetched = Some(Prefetched {
+                chunk: reading.clone(),
+                output,
+            });
+            let conversion = match mode {
+                InputMode::Hiragana => self.convert_chunk(
+                    &reading,
+                    0,
+                    SessionInputs {
+                        session_id: &session_id,
+                        providers: &providers,
+                        context,
+                        zenzai_profile: profile.as_deref(),
+                    },
+                    &options,
+                    &settings,
+                    Boundaries::default(),
+                ),
+                mode => Ok(self.convert_in_mode(mode, &reading, 0, &options, &settings)),
+            };
+            match conversion {
+                Ok(conversion) => results.push(BatchConversion {
+                    reading,
+                    candidates: conversion.candidates,
+                    segments: conversion.segments,
+                    confidence: conversion.confidence,
+                }),
+                Err(e) => return Response::error(seq, Some(session_id), e),
+            }
+        }
+
+        if let Some(session) = self.sessions.get_mut(&session_id) {
+            session.stats.conversions += results.len() as u64;
+        }
+        Response::ConvertBatchResult {
+            seq,
+            session_id,
+            results,
+        }
+    }
+
+    /// Zenzai output of each reading of a batch, converted together
+    ///
+    /// None for readings that don't go to Zenzai, that it produced nothing
+    /// for, or for all of them if the batch failed.
+    #[cfg(feature = "zenzai")]
+    fn zenzai_batch(
+        &self,
+        readings: &[String],
+        contexts: &[String],
+        profile: Option<&str>,
+        settings: &Settings,
+        options: &ConvertOptions,
+    ) -> Vec<Option<ZenzaiOutput>> {
+        let mut outputs = vec![None; readings.len()];
+        let Some(backend) = &self.zenzai else {
+            return outputs;
+        };
+        let wanted: Vec<usize> = (0..readings.len())
+            .filter(|&i| self.uses_zenzai(&readings[i], settings, options.engine))
+            .collect();
+        if wanted.is_empty() {
+            return outputs;
+        }
+        let items: Vec<(&str, Option<&str>)> = wanted
+            .iter()
+            .map(|&i| (readings[i].as_str(), Some(contexts[i].as_str())))
+            .collect();
+        let mut zenzai = backend.lock().unwrap_or_else(PoisonError::into_inner);
+        match zenzai.convert_batch(profile, &items, &self.cancel) {
+            Ok(batch) => {
+                for (i, candidates) in wanted.into_iter().zip(batch) {
+                    let confidence = candidates.first().map(|c| c.confidence);
+                    if confidence.is_some() {
+                        outputs[i] = Some((candidates, confidence));
+                    }
                 }
+            }
+            Err(e) => log_warn!(
+                "[handler] Zenzai batch conversion failed: {}, falling back to dictionary",
+                e
+            ),
+        }
+        outputs
+    }
 
-                // Fallback to dictionary-based conversion
-                let dict_result = self.converter.convert_with_segments(&reading);
+    /// Convert one chunk of a reading, trying Zenzai before the dictionary
+    ///
+    /// Segment positions are offset so they index into the full reading.
+    /// Dynamic provider candidates are added for the chunk and each segment.
+    /// When nothing converts the chunk, the fallback policy decides the result.
+    /// Forms generated from the reading as requested by `options` come last.
+    fn convert_chunk(
+        &mut self,
+        chunk: &str,
+        offset: usize,
+        session: SessionInputs,
+        options: &ConvertOptions,
+        settings: &Settings,
+        boundaries: Boundaries,
+    ) -> Result<ChunkConversion, AzukiError> {
+        // Try Zenzai first if enabled, reusing a result computed before
+        // the request was handled
+        let prefetched = self.prefetched.take().filter(|p| p.chunk == chunk);
+        #[cfg(feature = "zenzai")]
+        let zenzai_result = if self.uses_zenzai(chunk, settings, options.engine) {
+            match (prefetched, &self.zenzai) {
+                (Some(prefetched), _) => prefetched.output,
+                (None, Some(backend)) => run_zenzai(
+                    backend,
+                    session.session_id,
+                    options.zenzai_profile.as_deref().or(session.zenzai_profile),
+                    chunk,
+                    session.context,
+                    &self.cancel,
+                    &mut |_| {},
+                ),
+                (None, None) => None,
+            }
+        } else {
+            None
+        };
 
-                Response::ConvertResult {
-                    seq,
-                    session_id,
-                    candidates: dict_result.combined_candidates,
-                    segments: dict_result
-                        .segments
+        #[cfg(not(feature = "zenzai"))]
+        let zenzai_result: Option<ZenzaiOutput> = {
+            let _ = prefetched;
+            None
+        };
+        // A cancelled Zenzai run fails like any other; don't fall back
+        self.cancel.check()?;
+
+        let (hypotheses, zenzai_confidence, (mut candidates, mut segments, scores)) =
+            match zenzai_result {
+                Some((zenzai_candidates, confidence)) => {
+                    let punctuation = self.converter.punctuation();
+                    let hypotheses: Vec<ZenzaiCandidate> = zenzai_candidates
                         .into_iter()
-                        .map(SegmentInfo::from)
-                        .collect(),
+                        .map(|c| ZenzaiCandidate {
+                            text: punctuation.apply(&c.text),
+                            ..c
+                        })
+                        .collect();
+                    let texts: Vec<String> = hypotheses.iter().map(|c| c.text.clone()).collect();
+                    let (dictionary_candidates, _, dictionary_scores) =
+                        self.dictionary_chunk(chunk, offset, boundaries);
+                    let result = self.zenzai_chunk(
+                        chunk,
+                        offset,
+                        &texts,
+                        (dictionary_candidates, dictionary_scores),
+                    );
+                    (hypotheses, confidence, result)
                 }
+                None => (
+                    Vec::new(),
+                    None,
+                    self.dictionary_chunk(chunk, offset, boundaries),
+                ),
+            };
+        self.cancel.check()?;
+        let confidence =
+            zenzai_confidence.unwrap_or_else(|| combined_confidence(&segments, &self.learning));
+
+        let mut dynamic = BTreeMap::new();
+        self.add_dynamic_candidates(chunk, &mut candidates, session.providers, &mut dynamic);
+        for seg in &mut segments {
+            self.annotate_segment(seg);
+            self.add_dynamic_candidates(
+                &seg.reading,
+                &mut seg.candidates,
+                session.providers,
+                &mut seg.annotations,
+            );
+        }
+        self.add_numeral_candidates(chunk, &mut candidates, &mut dynamic);
+        for seg in &mut segments {
+            self.add_numeral_candidates(&seg.reading, &mut seg.candidates, &mut seg.annotations);
+        }
+
+        let reading = self.converter.punctuation().apply(chunk);
+        // Whitespace and fixed tokens pass through regardless of the
+        // fallback policy
+        let passthrough = self.dictionary_loading
+            || chunk.chars().all(char::is_whitespace)
+            || (!segments.is_empty() && segments.iter().all(|s| s.fixed));
+        if !passthrough && fallback::is_unconverted(&reading, &candidates) {
+            self.fallback
+                .apply(&reading, &mut candidates, &mut segments)?;
+        }
+        let forms = GeneratedForms {
+            halfwidth_katakana: options.halfwidth_katakana || settings.halfwidth_katakana,
+            fullwidth_alphanumeric: options.fullwidth_alphanumeric,
+        };
+        add_generated(
+            &mut candidates,
+            Converter::generated_candidates(&reading, forms),
+        );
+        for seg in &mut segments {
+            add_generated(
+                &mut seg.candidates,
+                Converter::generated_candidates(&seg.reading, forms),
+            );
+        }
+        settings.limit(&mut candidates);
+        for seg in &mut segments {
+            settings.limit(&mut seg.candidates);
+        }
+        let mut candidates = describe_candidates(
+            candidates,
+            &scores,
+            &segments,
+            &dynamic,
+            CandidateSource::Dictionary,
+        );
+        for candidate in &mut candidates {
+            if candidate.source != CandidateSource::Dictionary {
+                continue;
             }
-            Request::Commit {
-                seq,
-                session_id,
-                reading: _,
-                candidate: _,
-            } => {
-                // In the future, this will update learning data
-                Response::CommitResult {
-                    seq,
-                    session_id,
-                    success: true,
-                }
+            // Punctuation may map two outputs to one text; the first is
+            // the more likely
+            if let Some(hypothesis) = hypotheses.iter().find(|h| h.text == candidate.text) {
+                candidate.source = CandidateSource::Zenzai;
+                candidate.log_prob = Some(hypothesis.log_prob);
+                candidate.confidence = Some(hypothesis.confidence);
             }
-            Request::Shutdown { seq, .. } => Response::ShutdownResult { seq },
-            Request::AdjustSegment {
-                seq,
-                session_id,
-                reading,
-                segments,
-                segment_index,
-                direction,
-            } => {
-                // Convert input segments to Segment structs
-                let current_segments: Vec<Segment> = segments
-                    .into_iter()
-                    .map(|s| Segment {
-                        reading: s.reading,
-                        start: s.start,
-                        length: s.length,
-                        candidates: s.candidates,
-                    })
-                    .collect();
+        }
+        Ok(ChunkConversion {
+            candidates,
+            segments,
+            confidence,
+        })
+    }
 
-                // Parse direction
-                let dir = match direction.as_str() {
-                    "shrink" => AdjustDirection::Shrink,
-                    "extend" => AdjustDirection::Extend,
-                    _ => {
-                        return Response::Error {
-                            seq,
-                            session_id: Some(session_id),
-                            error: format!("Invalid direction: {}", direction),
-                        };
-                    }
-                };
+    /// Convert a chunk as one segment in a mode other than hiragana
+    ///
+    /// Kana modes only change the script of the reading; abbrev mode looks
+    /// the whole reading up as an ASCII word. The reading itself is always
+    /// the last candidate.
+    fn convert_in_mode(
+        &self,
+        mode: InputMode,
+        chunk: &str,
+        offset: usize,
+        options: &ConvertOptions,
+        settings: &Settings,
+    ) -> ChunkConversion {
+        let reading = self.converter.punctuation().apply(chunk);
+        let (mut texts, source) = match mode {
+            InputMode::Katakana => (
+                vec![kana::hiragana_to_katakana(&reading)],
+                CandidateSource::Reading,
+            ),
+            InputMode::HalfwidthKatakana => (
+                vec![kana::to_halfwidth_katakana(&reading)],
+                CandidateSource::Reading,
+            ),
+            InputMode::Abbrev | InputMode::Hiragana => {
+                let mut candidates = self.converter.lookup_abbrev(chunk).unwrap_or_default();
+                self.blocklist.filter(chunk, &mut candidates);
+                self.learning.reorder(chunk, &mut candidates);
+                (candidates, CandidateSource::Dictionary)
+            }
+        };
+        let fullwidth = GeneratedForms {
+            halfwidth_katakana: false,
+            fullwidth_alphanumeric: options.fullwidth_alphanumeric,
+        };
+        add_generated(&mut texts, vec![reading.clone()]);
+        add_generated(
+            &mut texts,
+            Converter::generated_candidates(&reading, fullwidth),
+        );
+        settings.limit(&mut texts);
+        let scores: HashMap<String, f64> = match source {
+            CandidateSource::Dictionary => texts
+                .iter()
+                .filter(|text| **text != reading)
+                .map(|text| (text.clone(), 1.0))
+                .collect(),
+            _ => HashMap::new(),
+        };
+        let mut segment = SegmentInfo::from(Segment {
+            reading: chunk.to_string(),
+            start: offset,
+            length: chunk.chars().count(),
+            candidates: texts.clone(),
+            fixed: false,
+        });
+        self.annotate_segment(&mut segment);
+        let segments = vec![segment];
+        ChunkConversion {
+            candidates: describe_candidates(texts, &scores, &segments, &BTreeMap::new(), source),
+            segments,
+            confidence: 1.0,
+        }
+    }
 
-                // Adjust segments
-                let new_segments =
-                    self.converter
-                        .adjust_segment(&reading, &current_segments, segment_index, dir);
+    /// Build a chunk result from Zenzai candidates
+    ///
+    /// Zenzai's hypotheses come first, then the dictionary's candidates for
+    /// the chunk and finally the reading. They are used as a single segment
+    /// covering the chunk, so the UI displays its candidates properly.
+    fn zenzai_chunk(
+        &self,
+        chunk: &str,
+        offset: usize,
+        hypotheses: &[String],
+        dictionary: (Vec<String>, HashMap<String, f64>),
+    ) -> (Vec<String>, Vec<SegmentInfo>, HashMap<String, f64>) {
+        let mut candidates: Vec<String> = Vec::new();
+        add_generated(&mut candidates, hypotheses.to_vec());
+        // Scored by Zenzai's rank, as if each were one candidate step
+        let mut scores: HashMap<String, f64> = candidates
+            .iter()
+            .enumerate()
+            .map(|(rank, c)| (c.clone(), 1.0 / (1.0 + rank as f64)))
+            .collect();
+        self.blocklist.filter(chunk, &mut candidates);
 
-                Response::AdjustSegmentResult {
-                    seq,
-                    session_id,
-                    segments: new_segments.into_iter().map(SegmentInfo::from).collect(),
+        let (dictionary_candidates, dictionary_scores) = dictionary;
+        for text in dictionary_candidates {
+            if !candidates.contains(&text) {
+                if let Some(&score) = dictionary_scores.get(&text) {
+                    scores.insert(text.clone(), score);
                 }
+                candidates.push(text);
             }
         }
-    }
-}
+        add_generated(
+            &mut candidates,
+            vec![self.converter.punctuation().apply(chunk)],
+        );
+        self.learning.reorder(chunk, &mut candidates);
 
-impl Default for Server {
-    fn default() -> Self {
-        Self::new()
+        // Create a single segment covering the entire chunk
+        let segment = SegmentInfo {
+            reading: chunk.to_string(),
+            start: offset,
+            length: chunk.chars().count(),
+            candidates: candidates.clone(),
+            fixed: false,
+            annotations: BTreeMap::new(),
+            kinds: BTreeMap::new(),
+        };
+        (candidates, vec![segment], scores)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Build a chunk result from dictionary-based conversion
+    fn dictionary_chunk(
+        &self,
+        chunk: &str,
+        offset: usize,
+        boundaries: Boundaries,
+    ) -> (Vec<String>, Vec<SegmentInfo>, HashMap<String, f64>) {
+        // Drop blocked candidates and put frequently committed ones first,
+        // before the combined candidates are composed from the segments
+        let rerank = !self.blocklist.is_empty() || !self.learning.is_empty();
+        let rerank_segment = |seg: &mut Segment| {
+            self.blocklist.filter(&seg.reading, &mut seg.candidates);
+            self.learning.reorder(&seg.reading, &mut seg.candidates);
+        };
+        let mut dict_result = match boundaries.cursor {
+            Some(cursor) if boundaries.lengths.is_none() => self
+                .converter
+                .convert_with_boundary_using(chunk, cursor, rerank_segment),
+            _ if rerank => self
+                .converter
+                .convert_with_segments_using(chunk, rerank_segment),
+            _ => self.converter.convert_with_segments(chunk),
+        };
 
-    fn create_test_server() -> Server {
-        Server {
-            converter: Converter::new(None),
-            #[cfg(feature = "zenzai")]
-            zenzai: None,
-            #[cfg(not(feature = "zenzai"))]
-            zenzai_config: None,
+        // Boundaries the user adjusted for this reading replace the best
+        // segmentation
+        if let Some(mut segments) = boundaries.lengths.and_then(|lengths| {
+            self.converter
+                .resegment(chunk, lengths, &dict_result.segments)
+        }) {
+            if rerank {
+                segments.iter_mut().for_each(rerank_segment);
+            }
+            let (combined_candidates, scores) = self.converter.combine_candidates(chunk, &segments);
+            dict_result = ConversionResult {
+                combined_candidates,
+                segments,
+                scores,
+            };
         }
+        if rerank && !dict_result.segments.is_empty() {
+            self.blocklist
+                .filter(chunk, &mut dict_result.combined_candidates);
+            self.learning
+                .reorder(chunk, &mut dict_result.combined_candidates);
+        }
+
+        let segments = dict_result
+            .segments
+            .into_iter()
+            .map(|mut seg| {
+                seg.start += offset;
+                SegmentInfo::from(seg)
+            })
+            .collect();
+        (
+            dict_result.combined_candidates,
+            segments,
+            dict_result.scores,
+        )
+    }
+
+    /// Add dictionary annotations and kinds of a segment's candidates
+    fn annotate_segment(&self, seg: &mut SegmentInfo) {
+        for candidate in &seg.candidates {
+            if let Some(annotation) = self.converter.annotation(&seg.reading, candidate) {
+                seg.annotations
+                    .insert(candidate.clone(), annotation.to_string());
+            }
+            if let Some(kind) = CandidateKind::of(candidate) {
+                seg.kinds.insert(candidate.clone(), kind);
+            }
+        }
+    }
+
+    /// Query an skkserv in addition to local dictionaries
+    ///
+    /// An unchanged configuration keeps the current connection and cache.
+    fn set_remote_dictionary(&mut self, config: RemoteDictionaryConfig) {
+        if self
+            .converter
+            .remote_dictionary()
+            .map(RemoteDictionary::config)
+            == Some(&config)
+        {
+            return;
+        }
+        log_info!(
+            "[remote_dict] Using skkserv at {}:{}",
+            config.host,
+            config.port
+        );
+        self.converter
+            .set_remote_dictionary(Some(Arc::new(RemoteDictionary::new(config))));
+    }
+
+    /// Use the romaji table described by `config`, keeping the current one
+    /// if its rule file can't be read
+    fn set_romaji(&mut self, config: &RomajiConfig) {
+        match RomajiTable::new(config) {
+            Ok(table) => self.romaji = table,
+            Err(e) => log_error!("[romaji] Failed to build table: {}", e),
+        }
+    }
+
+    /// Dictionary candidates for an SKK key, as an skkserv client expects
+    ///
+    /// Keys ending in an ASCII letter (e.g. "かk") are okuri-ari keys. The
+    /// blocklist and learning apply as they do for conversion.
+    pub fn dictionary_candidates(&self, key: &str) -> Vec<String> {
+        let okuri_ari =
+            key.chars().count() > 1 && key.chars().last().is_some_and(|c| c.is_ascii_lowercase());
+        let found = if okuri_ari {
+            self.converter.shared_dictionary().and_then(|dictionary| {
+                let candidates = dictionary.lookup_okuri_ari(key, None)?;
+                Some(candidates.into_iter().map(String::from).collect())
+            })
+        } else {
+            self.converter.lookup_combined(key)
+        };
+        let mut candidates = found.unwrap_or_default();
+        self.blocklist.filter(key, &mut candidates);
+        self.learning.reorder(key, &mut candidates);
+        candidates
+    }
+
+    /// Okuri-nasi readings starting with a prefix, shortest first
+    pub fn dictionary_completions(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let Some(dictionary) = self.converter.shared_dictionary() else {
+            return Vec::new();
+        };
+        let mut readings = dictionary.readings_with_prefix(prefix, limit + 1);
+        readings.retain(|reading| reading != prefix);
+        readings.truncate(limit);
+        readings
+    }
+
+    /// Readings starting with a prefix, with their top candidates
+    ///
+    /// Readings committed more often come first, the rest shortest first.
+    /// Readings whose candidates are all blocked are left out.
+    pub fn completions(&self, prefix: &str, limit: usize) -> Vec<Completion> {
+        let Some(dictionary) = self.converter.shared_dictionary() else {
+            return Vec::new();
+        };
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+        let mut learned: Vec<(&str, u64)> = self.learning.readings_with_prefix(prefix).collect();
+        learned.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then(a.0.chars().count().cmp(&b.0.chars().count()))
+                .then(a.0.cmp(b.0))
+        });
+        let learned: Vec<String> = learned
+            .into_iter()
+            .map(|(reading, _)| reading.to_string())
+            .collect();
+
+        // Blocked readings drop out, so fetch more until `limit` are left
+        let mut fetched = limit;
+        loop {
+            let found = dictionary.readings_with_prefix(prefix, fetched);
+            let exhausted = found.len() < fetched;
+            let mut readings = learned.clone();
+            for reading in found {
+                if !readings.contains(&reading) {
+                    readings.push(reading);
+                }
+            }
+            let completions: Vec<Completion> = readings
+                .into_iter()
+                .filter_map(|reading| {
+                    let mut candidates = self.dictionary_candidates(&reading);
+                    // The blocklist leaves the reading when it blocks them all
+                    candidates.retain(|candidate| *candidate != reading);
+                    candidates.truncate(COMPLETION_CANDIDATES);
+                    (!candidates.is_empty()).then_some(Completion {
+                        reading,
+                        candidates,
+                    })
+                })
+                .take(limit)
+                .collect();
+            if completions.len() >= limit || exhausted {
+                return completions;
+            }
+            fetched = fetched.saturating_mul(2);
+        }
+    }
+
+    /// Insert dynamic provider candidates after the top candidate
+    fn add_dynamic_candidates(
+        &self,
+        reading: &str,
+        candidates: &mut Vec<String>,
+        providers: &BTreeMap<String, bool>,
+        annotations: &mut BTreeMap<String, String>,
+    ) {
+        let mut index = candidates.len().min(1);
+        for dynamic in self.converter.dynamic_candidates(reading, providers) {
+            if candidates.contains(&dynamic.text)
+                || self.blocklist.is_blocked(reading, &dynamic.text)
+            {
+                continue;
+            }
+            candidates.insert(index, dynamic.text.clone());
+            index += 1;
+            annotations.insert(dynamic.text, dynamic.annotation);
+        }
+    }
+
+    /// Add numeral forms of a reading that is a number ("ごじゅうよん", "54")
+    ///
+    /// Spelled-out numbers get them before the reading itself; numbers typed
+    /// in digits keep the digits first.
+    fn add_numeral_candidates(
+        &self,
+        reading: &str,
+        candidates: &mut Vec<String>,
+        annotations: &mut BTreeMap<String, String>,
+    ) {
+        let Some(number) = number_reading::parse(reading) else {
+            return;
+        };
+        let mut index = match number.spelled {
+            true => candidates
+                .iter()
+                .position(|c| c == reading)
+                .unwrap_or(candidates.len()),
+            false => candidates.len(),
+        };
+        for (text, annotation) in number.candidates(reading) {
+            if candidates.contains(&text) || self.blocklist.is_blocked(reading, &text) {
+                continue;
+            }
+            candidates.insert(index, text.clone());
+            index += 1;
+            annotations.insert(text, annotation.to_string());
+        }
+    }
+
+    /// Rebuild the converter's dictionary from the system and user dictionaries
+    ///
+    /// Needed when user words are removed, since they may shadow or reorder
+    /// system candidates.
+    fn rebuild_dictionary(&mut self) {
+        let active = self.active_set.clone();
+        self.enter_dictionary_set(None);
+        self.converter
+            .set_dictionary(self.with_user_words(&self.system_dictionary));
+        let names: Vec<String> = self.loaded_sets.keys().cloned().collect();
+        for name in names {
+            let dictionary = self.with_user_words(&self.loaded_sets[&name].system);
+            if let Some(set) = self.loaded_sets.get_mut(&name) {
+                set.dictionary = dictionary;
+            }
+        }
+        self.enter_dictionary_set(active);
+    }
+
+    /// A dictionary with the user dictionary's words applied
+    fn with_user_words(&self, system: &Option<Arc<Dictionary>>) -> Option<Arc<Dictionary>> {
+        if self.user_dictionary.is_empty() {
+            return system.clone();
+        }
+        let mut dictionary = system.as_deref().cloned().unwrap_or_default();
+        self.user_dictionary.apply_to(&mut dictionary);
+        Some(Arc::new(dictionary))
+    }
+
+    /// Let the converter hold the dictionary of a loaded set, or the
+    /// default one for None
+    fn enter_dictionary_set(&mut self, name: Option<String>) {
+        if self.active_set == name {
+            return;
+        }
+        if let Some(active) = self.active_set.take() {
+            if let Some(set) = self.loaded_sets.get_mut(&active) {
+                self.converter.swap_dictionary(&mut set.dictionary);
+            }
+        }
+        if let Some(set) = name
+            .as_ref()
+            .and_then(|name| self.loaded_sets.get_mut(name))
+        {
+            self.converter.swap_dictionary(&mut set.dictionary);
+            self.active_set = name;
+        }
+    }
+
+    /// Start loading a configured dictionary set unless already loaded
+    ///
+    /// Returns the set's name, None for the default set (empty or
+    /// `default`).
+    fn load_dictionary_set(&mut self, name: &str) -> Result<Option<String>, AzukiError> {
+        if name.is_empty() || name == DEFAULT_SET {
+            return Ok(None);
+        }
+        let Some(sources) = self.dictionary_sets.get(name) else {
+            return Err(AzukiError::Config(format!(
+                "Unknown dictionary set: {}",
+                name
+            )));
+        };
+        if !self.loaded_sets.contains_key(name) && self.loading_sets.insert(name.to_string()) {
+            log_info!("[handler] Loading dictionary set {}", name);
+            self.set_loads.push(SetLoad {
+                name: name.to_string(),
+                sources: sources.clone(),
+            });
+        }
+        Ok(Some(name.to_string()))
+    }
+
+    /// Dictionary sets selected since the last call, to be loaded off the
+    /// server lock
+    ///
+    /// Sessions on a set convert with the default dictionaries until
+    /// `finish_set_loading` is called for it.
+    pub fn take_set_loads(&mut self) -> Vec<SetLoad> {
+        std::mem::take(&mut self.set_loads)
+    }
+
+    /// Switch the sessions on a set to its dictionaries, loaded in the
+    /// background, and notify subscribed connections
+    pub fn finish_set_loading(
+        &mut self,
+        name: String,
+        system: Option<Arc<Dictionary>>,
+        report: Vec<DictionaryLoad>,
+        elapsed: Duration,
+    ) {
+        self.loading_sets.remove(&name);
+        let entries = system.as_deref().map_or(0, Dictionary::len);
+        log_info!(
+            "[handler] Dictionary set {} ready: {} entries in {}ms",
+            name,
+            entries,
+            elapsed.as_millis()
+        );
+        let dictionary = self.with_user_words(&system);
+        self.loaded_sets.insert(
+            name.clone(),
+            DictionarySet {
+                system,
+                dictionary,
+                report,
+            },
+        );
+        self.broadcast(Notification::DictionarySetReady {
+            name,
+            entries,
+            elapsed_ms: elapsed.as_millis() as u64,
+        });
+    }
+
+    /// Reload the sets loaded so far from disk, without user words
+    fn reload_dictionary_sets(&mut self) {
+        for (name, set) in &mut self.loaded_sets {
+            if let Some(sources) = self.dictionary_sets.get(name) {
+                let (system, report) = load_dictionaries_reporting(sources);
+                set.system = system.map(Arc::new);
+                set.report = report;
+            }
+        }
+    }
+
+    /// Register a user word in the dictionaries of every loaded set
+    fn add_entry_to_sets(&mut self, reading: &str, candidate: &str) {
+        self.converter.add_entry(reading, candidate);
+        for set in self.loaded_sets.values_mut() {
+            let dictionary = set
+                .dictionary
+                .get_or_insert_with(|| Arc::new(Dictionary::new()));
+            Arc::make_mut(dictionary).add_entry(reading, candidate);
+        }
+    }
+
+    /// Swap in dictionaries reloaded from disk
+    ///
+    /// `None` keeps the current one. Conversions already answered are not
+    /// affected; the next request uses the new dictionaries. Subscribed
+    /// connections are sent `dictionary_reloaded`.
+    pub fn replace_dictionaries(
+        &mut self,
+        system: Option<Option<Arc<Dictionary>>>,
+        user: Option<UserDictionary>,
+    ) {
+        let reloaded = (system.is_some(), user.is_some());
+        if let Some(system) = system {
+            self.system_dictionary = system;
+        }
+        if let Some(user) = user {
+            self.user_dictionary = user;
+        }
+        self.rebuild_dictionary();
+        self.broadcast(Notification::DictionaryReloaded {
+            system: reloaded.0,
+            user: reloaded.1,
+            entries: self.system_dictionary.as_deref().map_or(0, Dictionary::len),
+        });
+    }
+
+    /// Persist the user dictionary, logging failures
+    fn save_user_dictionary(&mut self) {
+        let result = self.user_dictionary.save();
+        self.unsaved.user_dictionary = result.is_err();
+        if let Err(e) = result {
+            log_error!("[user_dict] Failed to save: {}", e);
+            self.warn(
+                ResourceWarningKind::Storage,
+                format!("Failed to save user dictionary: {}", e),
+            );
+        }
+    }
+
+    /// Flush persistent state before exiting
+    ///
+    /// Learning data is only saved periodically; the user dictionary and
+    /// blocklist are saved again if their last save failed. Every store is
+    /// tried even if one fails; the failures are returned.
+    pub fn persist(&mut self) -> Vec<String> {
+        let mut failures = Vec::new();
+        if let Err(e) = self.learning.save() {
+            failures.push(format!("learning data: {}", e));
+        }
+        if self.unsaved.user_dictionary {
+            match self.user_dictionary.save() {
+                Ok(()) => self.unsaved.user_dictionary = false,
+                Err(e) => failures.push(format!("user dictionary: {}", e)),
+            }
+        }
+        if self.unsaved.blocklist {
+            match self.blocklist.save() {
+                Ok(()) => self.unsaved.blocklist = false,
+                Err(e) => failures.push(format!("blocklist: {}", e)),
+            }
+        }
+        for failure in &failures {
+            log_error!("[handler] Failed to save {}", failure);
+        }
+        failures
+    }
+
+    /// Persist learning data, logging failures
+    fn save_learning(&mut self) {
+        match self.learning.save() {
+            Ok(()) => self.broadcast(Notification::LearningSaved {
+                entries: self.learning.len(),
+            }),
+            Err(e) => {
+                log_error!("[learning] Failed to save: {}", e);
+                self.warn(
+                    ResourceWarningKind::Storage,
+                    format!("Failed to save learning data: {}", e),
+                );
+            }
+        }
+    }
+
+    /// Persist the blocklist, logging failures
+    fn save_blocklist(&mut self) {
+        let result = self.blocklist.save();
+        self.unsaved.blocklist = result.is_err();
+        if let Err(e) = result {
+            log_error!("[blocklist] Failed to save: {}", e);
+            self.warn(
+                ResourceWarningKind::Storage,
+                format!("Failed to save blocklist: {}", e),
+            );
+        }
+    }
+
+    /// Handle one request of a batch
+    ///
+    /// Batches don't nest, and shutting down is left to a request of its
+    /// own so the connection knows to close.
+    fn handle_batched(&mut self, request: Request) -> Response {
+        match request {
+            Request::Batch { .. } | Request::Shutdown { .. } => Response::error(
+                request.seq(),
+                request.session_id().map(String::from),
+                AzukiError::Protocol("batch and shutdown requests can't be batched".to_string()),
+            ),
+            request => self.handle_request(request),
+        }
+    }
+
+    /// Dispatch a request to its handler
+    fn dispatch(&mut self, request: Request) -> Response {
+        match request {
+            Request::Init {
+                seq,
+                session_id,
+                zenzai,
+                settings,
+                ..
+            } => {
+                let session_id = session_id.unwrap_or_default();
+                if let (Some(settings), Some(session)) =
+                    (settings, self.sessions.get_mut(&session_id))
+                {
+                    session.settings.merge(&settings);
+                }
+
+                // Initialize Zenzai if requested
+                // Can't use map() here due to #[cfg] attributes inside
+                #[allow(clippy::manual_map)]
+                let zenzai_enabled = if let Some(config) = zenzai {
+                    #[cfg(feature = "zenzai")]
+                    {
+                        Some(self.init_zenzai(*config))
+                    }
+                    #[cfg(not(feature = "zenzai"))]
+                    {
+                        self.zenzai_config = Some(*config);
+                        log_warn!("[zenzai] Feature not enabled at compile time");
+                        Some(false)
+                    }
+                } else {
+                    None
+                };
+
+                Response::InitResult {
+                    seq,
+                    session_id,
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    has_dictionary: self.converter.has_dictionary(),
+                    dictionary_loading: self.dictionary_loading,
+                    zenzai_enabled,
+                    // Chosen by the connection, which compresses
+                    compression: None,
+                    dictionaries: self.dictionary_report.clone(),
+                }
+            }
+            Request::Convert {
+                seq,
+                session_id,
+                reading,
+                cursor,
+                options,
+                continuation,
+            } => {
+                if let Err(e) = validate_reading(&reading) {
+                    return Response::error(seq, Some(session_id), e);
+                }
+                let length = reading.chars().count();
+                if let Some(cursor) = cursor.filter(|&cursor| cursor > length) {
+                    return Response::error(
+                        seq,
+                        Some(session_id),
+                        AzukiError::Protocol(format!(
+                            "Cursor {} is beyond the reading ({} characters)",
+                            cursor, length
+                        )),
+                    );
+                }
+
+                // Clients that ask for it get long readings chunk by chunk;
+                // the continuation token tells us where the previous chunk ended
+                let options = options.unwrap_or_default();
+                let chunked = options.chunked || continuation.is_some();
+                let chars: Vec<char> = reading.chars().collect();
+                let offset = match continuation {
+                    Some(token) => match parse_continuation(&token, &reading) {
+                        Some(offset) => offset,
+                        None => {
+                            return Response::error(
+                                seq,
+                                Some(session_id),
+                                AzukiError::Protocol(format!(
+                                    "Invalid continuation token: {}",
+                                    token
+                                )),
+                            );
+                        }
+                    },
+                    None => 0,
+                };
+                let end = match chunked {
+                    true => Converter::chunk_end(&chars, offset, READING_SOFT_LIMIT),
+                    false => chars.len(),
+                };
+                let chunk: String = chars[offset..end].iter().collect();
+                let continuation = (end < chars.len()).then(|| make_continuation(end, &reading));
+
+                let settings = self.settings_for(&session_id);
+                let (providers, context, lengths, mode, zenzai_profile) = self
+                    .sessions
+                    .get_mut(&session_id)
+                    .map(|session| {
+                        let lengths = session.segmentation(&chunk).map(<[usize]>::to_vec);
+                        (
+                            session.providers.clone(),
+                            session.context().to_string(),
+                            lengths,
+                            session.input_mode,
+                            session.zenzai_profile.clone(),
+                        )
+                    })
+                    .unwrap_or_default();
+                let conversion = match mode {
+                    InputMode::Hiragana => self.convert_chunk(
+                        &chunk,
+                        offset,
+                        SessionInputs {
+                            session_id: &session_id,
+                            providers: &providers,
+                            context: &context,
+                            zenzai_profile: zenzai_profile.as_deref(),
+                        },
+                        &options,
+                        &settings,
+                        Boundaries {
+                            lengths: lengths.as_deref(),
+                            cursor: cursor
+                                .filter(|&cursor| cursor >= offset && cursor <= end)
+                                .map(|cursor| cursor - offset),
+                        },
+                    ),
+                    mode => Ok(self.convert_in_mode(mode, &chunk, offset, &options, &settings)),
+                };
+                let ChunkConversion {
+                    candidates,
+                    segments,
+                    confidence,
+                } = match conversion {
+                    Ok(result) => result,
+                    Err(e) => return Response::error(seq, Some(session_id), e),
+                };
+                let active_segment = cursor.and_then(|cursor| active_segment(&segments, cursor));
+
+                if let Some(session) = self.sessions.get_mut(&session_id) {
+                    session.stats.conversions += 1;
+                    session.last_candidates = candidates.iter().map(|c| c.text.clone()).collect();
+                    // Only whole readings can be adjusted later
+                    session.composition = (offset == 0 && continuation.is_none()).then(|| {
+                        let segments = segments.iter().cloned().map(Segment::from).collect();
+                        Composition::new(reading.clone(), segments)
+                    });
+                }
+
+                // Live results are remembered per session as the base for deltas;
+                // partial (chunked) results are always sent in full
+                if options.live && offset == 0 && continuation.is_none() {
+                    let snapshot = LiveSnapshot {
+                        seq,
+                        candidates: candidates.clone(),
+                        segments: segments.clone(),
+                    };
+                    let previous = self
+                        .sessions
+                        .get_mut(&session_id)
+                        .and_then(|session| session.last_live.replace(snapshot));
+
+                    if let Some(previous) = previous.filter(|_| options.delta) {
+                        if let Some(delta) = diff_candidates(&previous.candidates, &candidates) {
+                            return Response::ConvertDelta {
+                                seq,
+                                session_id,
+                                base_seq: previous.seq,
+                                removed: delta.removed,
+                                added: delta.added,
+                                segments: diff_segments(&previous.segments, &segments),
+                                segment_count: segments.len(),
+                                active_segment,
+                                confidence,
+                            };
+                        }
+                    }
+                }
+
+                Response::ConvertResult {
+                    seq,
+                    session_id,
+                    candidates,
+                    segments,
+                    continuation,
+                    active_segment,
+                    confidence,
+                }
+            }
+            Request::ConvertBatch {
+                seq,
+                session_id,
+                readings,
+                contexts,
+                options,
+            } => self.convert_batch(seq, session_id, readings, contexts, options),
+            Request::Commit {
+                seq,
+                session_id,
+                reading,
+                candidate,
+            } => {
+                let learned = self.settings_for(&session_id).learning;
+                if learned {
+                    self.learning.record(&reading, &candidate);
+                    if self.learning.save_due() {
+                        self.save_learning();
+                    }
+                }
+
+                // The composition is finished; the next live result starts fresh
+                if let Some(session) = self.sessions.get_mut(&session_id) {
+                    session.last_live = None;
+                    let index = session.last_candidates.iter().position(|c| *c == candidate);
+                    session.stats.record_commit(index);
+                    session.last_candidates.clear();
+                    session.composition = None;
+                    // Committed text is what the next reading follows
+                    session.append_context(&candidate);
+                    session.last_commit = learned.then_some((reading, candidate));
+                }
+                Response::CommitResult {
+                    seq,
+                    session_id,
+                    success: true,
+                }
+            }
+            Request::UndoCommit { seq, session_id } => {
+                let last_commit = self
+                    .sessions
+                    .get_mut(&session_id)
+                    .and_then(|session| session.last_commit.take());
+                let undone = last_commit
+                    .as_ref()
+                    .is_some_and(|(reading, candidate)| self.learning.forget(reading, candidate));
+                let (reading, candidate) = last_commit.filter(|_| undone).unzip();
+                Response::UndoCommitResult {
+                    seq,
+                    session_id,
+                    undone,
+                    reading,
+                    candidate,
+                }
+            }
+            Request::Shutdown { seq, .. } => {
+                let errors = self.persist();
+                Response::ShutdownResult {
+                    seq,
+                    flushed: errors.is_empty(),
+                    errors,
+                }
+            }
+            Request::AdjustSegment {
+                seq,
+                session_id,
+                reading,
+                segments,
+                segment_index,
+                direction,
+            } => {
+                let composition = self
+                    .sessions
+                    .get_mut(&session_id)
+                    .and_then(|session| session.composition.clone());
+                let (reading, current_segments) = match resolve_composition(
+                    reading,
+                    segments.map(|segments| segments.into_iter().map(Segment::from).collect()),
+                    composition,
+                ) {
+                    Ok(resolved) => resolved,
+                    Err(e) => return Response::error(seq, Some(session_id), e),
+                };
+
+                // Parse direction
+                let dir = match direction.as_str() {
+                    "shrink" => AdjustDirection::Shrink,
+                    "extend" => AdjustDirection::Extend,
+                    _ => {
+                        return Response::error(
+                            seq,
+                            Some(session_id),
+                            AzukiError::Protocol(format!("Invalid direction: {}", direction)),
+                        );
+                    }
+                };
+
+                if let Err(e) = validate_reading(&reading)
+                    .and_then(|()| validate_segments(&reading, &current_segments, segment_index))
+                {
+                    return Response::error(seq, Some(session_id), e);
+                }
+
+                // Adjust segments
+                let new_segments =
+                    self.converter
+                        .adjust_segment(&reading, &current_segments, segment_index, dir);
+
+                // The next convert of this reading keeps these boundaries
+                if let Some(session) = self.sessions.get_mut(&session_id) {
+                    let lengths = new_segments.iter().map(|seg| seg.length).collect();
+                    session.remember_segmentation(&reading, lengths);
+                    session.composition =
+                        Some(Composition::new(reading.clone(), new_segments.clone()));
+                }
+
+                let (candidates, scores) =
+                    self.converter.combine_candidates(&reading, &new_segments);
+                let mut segments: Vec<SegmentInfo> =
+                    new_segments.into_iter().map(SegmentInfo::from).collect();
+                segments
+                    .iter_mut()
+                    .for_each(|seg| self.annotate_segment(seg));
+                Response::AdjustSegmentResult {
+                    seq,
+                    session_id,
+                    candidates: describe_candidates(
+                        candidates,
+                        &scores,
+                        &segments,
+                        &BTreeMap::new(),
+                        CandidateSource::Dictionary,
+                    ),
+                    segments,
+                }
+            }
+            Request::ConvertSegment {
+                seq,
+                session_id,
+                reading,
+                segments,
+                segment_index,
+            } => {
+                let segments: Vec<Segment> = segments.into_iter().map(Segment::from).collect();
+                if let Err(e) = validate_reading(&reading)
+                    .and_then(|()| validate_segments(&reading, &segments, segment_index))
+                {
+                    return Response::error(seq, Some(session_id), e);
+                }
+
+                let mut segment =
+                    self.converter
+                        .convert_segment(&reading, &segments, segment_index);
+                self.blocklist
+                    .filter(&segment.reading, &mut segment.candidates);
+                self.learning
+                    .reorder(&segment.reading, &mut segment.candidates);
+                let providers = self
+                    .sessions
+                    .get_mut(&session_id)
+                    .map(|session| session.providers.clone())
+                    .unwrap_or_default();
+                let mut segment = SegmentInfo::from(segment);
+                self.annotate_segment(&mut segment);
+                self.add_dynamic_candidates(
+                    &segment.reading,
+                    &mut segment.candidates,
+                    &providers,
+                    &mut segment.annotations,
+                );
+                self.add_numeral_candidates(
+                    &segment.reading,
+                    &mut segment.candidates,
+                    &mut segment.annotations,
+                );
+                Response::ConvertSegmentResult {
+                    seq,
+                    session_id,
+                    segment_index,
+                    segment,
+                }
+            }
+            Request::BlockCandidate {
+                seq,
+                session_id,
+                reading,
+                candidate,
+            } => {
+                let success = self.blocklist.block(&reading, &candidate);
+                self.save_blocklist();
+                Response::BlockCandidateResult {
+                    seq,
+                    session_id,
+                    success,
+                }
+            }
+            Request::UnblockCandidate {
+                seq,
+                session_id,
+                reading,
+                candidate,
+            } => {
+                let success = self.blocklist.unblock(&reading, &candidate);
+                self.save_blocklist();
+                Response::UnblockCandidateResult {
+                    seq,
+                    session_id,
+                    success,
+                }
+            }
+            Request::ExportBlocklist { seq, session_id } => Response::ExportBlocklistResult {
+                seq,
+                session_id,
+                content: self.blocklist.to_text(),
+                count: self.blocklist.len(),
+            },
+            Request::ImportBlocklist {
+                seq,
+                session_id,
+                content,
+                replace,
+            } => {
+                if replace {
+                    self.blocklist.clear();
+                }
+                let imported = self.blocklist.merge_text(&content);
+                self.save_blocklist();
+                Response::ImportBlocklistResult {
+                    seq,
+                    session_id,
+                    imported,
+                    count: self.blocklist.len(),
+                }
+            }
+            Request::SetProviders {
+                seq,
+                session_id,
+                enabled,
+            } => {
+                let known = self.converter.providers().names();
+                if let Some(unknown) = enabled.keys().find(|name| !known.contains(&name.as_str())) {
+                    return Response::error(
+                        seq,
+                        Some(session_id),
+                        AzukiError::Protocol(format!("Unknown provider: {}", unknown)),
+                    );
+                }
+                let Some(session) = self.sessions.get_mut(&session_id) else {
+                    return Response::error(
+                        seq,
+                        Some(session_id.clone()),
+                        AzukiError::Session(format!("Unknown session: {}", session_id)),
+                    );
+                };
+                session.providers.extend(enabled);
+                let providers = self.converter.providers().state(&session.providers);
+                Response::SetProvidersResult {
+                    seq,
+                    session_id,
+                    providers,
+                }
+            }
+            Request::SessionStats { seq, session_id } => {
+                let stats = self
+                    .sessions
+                    .get_mut(&session_id)
+                    .map(|session| session.stats.clone())
+                    .unwrap_or_default();
+                Response::SessionStatsResult {
+                    seq,
+                    session_id,
+                    stats,
+                }
+            }
+            Request::SessionState {
+                seq,
+                session_id,
+                context,
+                select,
+                zenzai_profile,
+                dictionary_set,
+            } => {
+                let dictionary_set = match dictionary_set {
+                    Some(name) => match self.load_dictionary_set(&name) {
+                        Ok(set) => Some(set),
+                        Err(e) => return Response::error(seq, Some(session_id), e),
+                    },
+                    None => None,
+                };
+                // Tracked on every request, so the session exists
+                let Some(session) = self.sessions.get_mut(&session_id) else {
+                    return Response::error(
+                        seq,
+                        Some(session_id),
+                        AzukiError::Session("Unknown session".to_string()),
+                    );
+                };
+                if let Some(select) = select {
+                    let result = match &mut session.composition {
+                        Some(composition) => composition.select(select.segment, select.candidate),
+                        None => Err(AzukiError::Session("No composition".to_string())),
+                    };
+                    if let Err(e) = result {
+                        return Response::error(seq, Some(session_id), e);
+                    }
+                }
+                if let Some(context) = context {
+                    session.set_context(&context);
+                }
+                if let Some(profile) = zenzai_profile {
+                    session.zenzai_profile = (!profile.is_empty()).then_some(profile);
+                }
+                if let Some(set) = dictionary_set {
+                    session.dictionary_set = set;
+                }
+                let loading = session
+                    .dictionary_set
+                    .as_ref()
+                    .is_some_and(|name| self.loading_sets.contains(name));
+                session_state(seq, session_id, session, loading)
+            }
+            Request::SetMode {
+                seq,
+                session_id,
+                mode,
+            } => {
+                if let Some(session) = self.sessions.get_mut(&session_id) {
+                    session.input_mode = mode;
+                }
+                Response::ModeResult {
+                    seq,
+                    session_id,
+                    mode,
+                }
+            }
+            Request::ListSessions { seq, session_id } => Response::ListSessionsResult {
+                seq,
+                session_id,
+                sessions: self.sessions.summaries(Instant::now()),
+            },
+            Request::GetStats { seq, session_id } => {
+                let dictionary = self.system_dictionary.as_deref();
+                let memory = MemoryStats {
+                    dictionary_bytes: dictionary.map_or(0, Dictionary::estimated_bytes),
+                    mapped_bytes: dictionary.map_or(0, Dictionary::mapped_bytes),
+                    user_entries: self.user_dictionary.len(),
+                    learning_entries: self.learning.len(),
+                    sessions: self.sessions.len(),
+                };
+                Response::StatsResult {
+                    seq,
+                    session_id,
+                    uptime_ms: self.started.elapsed().as_millis() as u64,
+                    requests: self.metrics.request_stats(),
+                    remote_cache: self
+                        .converter
+                        .remote_dictionary()
+                        .map(RemoteDictionary::cache_stats),
+                    memory,
+                }
+            }
+            Request::GetConfig { seq, session_id } => self.config_result(seq, session_id),
+            Request::SetConfig {
+                seq,
+                session_id,
+                scope,
+                settings,
+            } => {
+                match scope {
+                    Scope::Global => self.settings = self.settings.with(&settings),
+                    Scope::Session => {
+                        let Some(session) = self.sessions.get_mut(&session_id) else {
+                            return Response::error(
+                                seq,
+                                Some(session_id.clone()),
+                                AzukiError::Session(format!("Unknown session: {}", session_id)),
+                            );
+                        };
+                        session.settings.merge(&settings);
+                    }
+                }
+                log_info!("[handler] Settings changed ({:?}): {:?}", scope, settings);
+                self.config_result(seq, session_id)
+            }
+            Request::AddEntry {
+                seq,
+                session_id,
+                reading,
+                candidate,
+            } => {
+                if let Err(e) = validate_entry(&reading, &candidate) {
+                    return Response::error(seq, Some(session_id), e);
+                }
+                self.user_dictionary.add(&reading, &candidate);
+                self.add_entry_to_sets(&reading, &candidate);
+                self.save_user_dictionary();
+                Response::AddEntryResult {
+                    seq,
+                    session_id,
+                    success: true,
+                }
+            }
+            Request::RemoveEntry {
+                seq,
+                session_id,
+                reading,
+                candidate,
+            } => {
+                let success = self.user_dictionary.remove(&reading, &candidate);
+                if success {
+                    self.rebuild_dictionary();
+                    self.save_user_dictionary();
+                }
+                Response::RemoveEntryResult {
+                    seq,
+                    session_id,
+                    success,
+                }
+            }
+            Request::ReloadDictionaries { seq, session_id } => {
+                let (dictionary, dictionaries) =
+                    load_dictionaries_reporting(&self.dictionary_paths);
+                self.reload_dictionary_sets();
+                let user = load_user_dictionary();
+                let user_entries = user.len();
+                self.replace_dictionaries(Some(dictionary.map(Arc::new)), Some(user));
+                self.dictionary_report = dictionaries.clone();
+                Response::ReloadDictionariesResult {
+                    seq,
+                    session_id,
+                    dictionaries,
+                    user_entries,
+                }
+            }
+            Request::DictInfo { seq, session_id } => {
+                let dictionary_set = self.active_set.clone();
+                let set_loading = self
+                    .sessions
+                    .get_mut(&session_id)
+                    .and_then(|session| session.dictionary_set.as_ref())
+                    .is_some_and(|name| self.loading_sets.contains(name));
+                let dictionaries = match dictionary_set
+                    .as_ref()
+                    .and_then(|name| self.loaded_sets.get(name))
+                {
+                    Some(set) => set.report.clone(),
+                    None => self.dictionary_report.clone(),
+                };
+                Response::DictInfoResult {
+                    seq,
+                    session_id,
+                    dictionaries,
+                    dictionary_set,
+                    dictionary_sets: self.dictionary_sets.keys().cloned().collect(),
+                    loading: self.dictionary_loading || set_loading,
+                    user_entries: self.user_dictionary.len(),
+                }
+            }
+            Request::UnloadModel { seq, session_id } => {
+                #[cfg(feature = "zenzai")]
+                let unloaded = self.zenzai.as_ref().is_some_and(|backend| {
+                    backend
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .unload()
+                });
+                #[cfg(not(feature = "zenzai"))]
+                let unloaded = false;
+                Response::UnloadModelResult {
+                    seq,
+                    session_id,
+                    unloaded,
+                }
+            }
+            Request::ZenzaiStatus { seq, session_id } => self.zenzai_status(seq, session_id),
+            Request::Complete {
+                seq,
+                session_id,
+                prefix,
+                limit,
+            } => {
+                if let Err(e) = validate_reading(&prefix) {
+                    return Response::error(seq, Some(session_id), e);
+                }
+                let completions = self.completions(&prefix, limit.min(MAX_COMPLETIONS));
+                Response::CompleteResult {
+                    seq,
+                    session_id,
+                    completions,
+                }
+            }
+            Request::ToKana {
+                seq,
+                session_id,
+                romaji,
+            } => {
+                let (kana, pending) = self.romaji.to_kana(&romaji);
+                Response::ToKanaResult {
+                    seq,
+                    session_id,
+                    kana,
+                    pending,
+                }
+            }
+            // The connection answers cancels while requests are queued; one
+            // handled here has nothing left to cancel
+            Request::Cancel {
+                seq, target_seq, ..
+            } => Response::CancelResult {
+                seq,
+                target_seq,
+                cancelled: false,
+            },
+            Request::Ping { seq } => self.ping(seq, 0),
+            Request::SetLogLevel { seq, level } => {
+                if let Some(level) = level {
+                    log::set_level(level);
+                    log_info!("[handler] Log level set to {}", level.name());
+                }
+                Response::LogLevelResult {
+                    seq,
+                    level: log::level(),
+                    file: log::file_path(),
+                }
+            }
+            Request::Batch { seq, requests } => Response::BatchResult {
+                seq,
+                responses: requests
+                    .into_iter()
+                    .map(|request| self.handle_batched(request))
+                    .collect(),
+            },
+            Request::Transform {
+                seq,
+                session_id,
+                text,
+                direction,
+            } => {
+                let text = match direction.as_str() {
+                    "katakana_to_hiragana" => kana::katakana_to_hiragana(&text),
+                    "hiragana_to_katakana" => kana::hiragana_to_katakana(&text),
+                    "halfwidth_to_fullwidth" => kana::halfwidth_to_fullwidth(&text),
+                    _ => {
+                        return Response::error(
+                            seq,
+                            Some(session_id),
+                            AzukiError::Protocol(format!(
+                                "Invalid transform direction: {}",
+                                direction
+                            )),
+                        );
+                    }
+                };
+
+                Response::TransformResult {
+                    seq,
+                    session_id,
+                    text,
+                }
+            }
+        }
+    }
+}
+
+/// Index of the segment that ends at or contains the cursor
+///
+/// A cursor at the start of the reading is in the first segment.
+fn active_segment(segments: &[SegmentInfo], cursor: usize) -> Option<usize> {
+    segments
+        .iter()
+        .position(|seg| seg.start < cursor && cursor <= seg.start + seg.length)
+        .or_else(|| {
+            segments
+                .first()
+                .filter(|seg| seg.start == cursor)
+                .map(|_| 0)
+        })
+}
+
+/// Attach source, score and annotation to whole-phrase candidates
+///
+/// Candidates without a score were not ranked by conversion: they come
+/// from dynamic providers (`dynamic` holds their annotations) or are the
+/// reading. A phrase covering a single segment takes that segment's
+/// annotation.
+fn describe_candidates(
+    texts: Vec<String>,
+    scores: &HashMap<String, f64>,
+    segments: &[SegmentInfo],
+    dynamic: &BTreeMap<String, String>,
+    source: CandidateSource,
+) -> Vec<Candidate> {
+    texts
+        .into_iter()
+        .map(|text| {
+            let (source, score, annotation) = match (scores.get(&text), dynamic.get(&text)) {
+                (Some(&score), _) => {
+                    let annotation = match segments {
+                        [segment] => segment.annotations.get(&text).cloned(),
+                        _ => None,
+                    };
+                    (source, score, annotation)
+                }
+                (None, Some(annotation)) => {
+                    (CandidateSource::Provider, 0.0, Some(annotation.clone()))
+                }
+                (None, None) => (CandidateSource::Reading, 0.0, None),
+            };
+            Candidate {
+                kind: CandidateKind::of(&text),
+                text,
+                annotation,
+                source,
+                score,
+                log_prob: None,
+                confidence: None,
+            }
+        })
+        .collect()
+}
+
+/// Append candidates generated from the reading unless already present
+fn add_generated(candidates: &mut Vec<String>, generated: Vec<String>) {
+    for text in generated {
+        if !candidates.contains(&text) {
+            candidates.push(text);
+        }
+    }
+}
+
+/// Check that a word can be stored in the SKK-format user dictionary
+fn validate_entry(reading: &str, candidate: &str) -> Result<(), AzukiError> {
+    validate_reading(reading)?;
+    if reading.contains(char::is_whitespace) {
+        return Err(AzukiError::Protocol(format!(
+            "Reading must not contain whitespace: {:?}",
+            reading
+        )));
+    }
+    if candidate.is_empty() || candidate.contains(['/', ';', '\n']) {
+        return Err(AzukiError::Protocol(format!(
+            "Candidate must be non-empty and must not contain '/', ';' or newlines: {:?}",
+            candidate
+        )));
+    }
+    Ok(())
+}
+
+/// Check that client-provided segments tile the reading and the index is valid
+/// Reading and segments to adjust: those of the request, with missing ones
+/// taken from the session's composition
+fn resolve_composition(
+    reading: Option<String>,
+    segments: Option<Vec<Segment>>,
+    composition: Option<Composition>,
+) -> Result<(String, Vec<Segment>), AzukiError> {
+    match (reading, segments, composition) {
+        (Some(reading), Some(segments), _) => Ok((reading, segments)),
+        (None, None, Some(composition)) => Ok((composition.reading, composition.segments)),
+        (Some(reading), None, Some(composition)) if composition.reading == reading => {
+            Ok((reading, composition.segments))
+        }
+        (None, Some(_), _) => Err(AzukiError::Protocol(
+            "segments need the reading they split".to_string(),
+        )),
+        _ => Err(AzukiError::Session(
+            "No composition of this reading to adjust; send its segments".to_string(),
+        )),
+    }
+}
+
+/// Answer a `session_state` request
+fn session_state(
+    seq: u64,
+    session_id: String,
+    session: &Session,
+    dictionary_set_loading: bool,
+) -> Response {
+    let composition = session.composition.as_ref().map(|composition| {
+        let text = composition
+            .segments
+            .iter()
+            .zip(&composition.selected)
+            .map(|(seg, &index)| seg.candidates.get(index).unwrap_or(&seg.reading).as_str())
+            .collect();
+        CompositionInfo {
+            reading: composition.reading.clone(),
+            segments: composition
+                .segments
+                .iter()
+                .cloned()
+                .map(SegmentInfo::from)
+                .collect(),
+            selected: composition.selected.clone(),
+            text,
+        }
+    });
+    Response::SessionStateResult {
+        seq,
+        session_id,
+        input_mode: session.input_mode,
+        context: session.context().to_string(),
+        composition,
+        zenzai_profile: session.zenzai_profile.clone(),
+        dictionary_set: session.dictionary_set.clone(),
+        dictionary_set_loading,
+    }
+}
+
+fn validate_segments(
+    reading: &str,
+    segments: &[Segment],
+    segment_index: usize,
+) -> Result<(), AzukiError> {
+    if segment_index >= segments.len() {
+        return Err(AzukiError::Protocol(format!(
+            "Segment index {} out of range ({} segments)",
+            segment_index,
+            segments.len()
+        )));
+    }
+    let mut pos = 0;
+    for seg in segments {
+        if seg.start != pos || seg.length == 0 {
+            return Err(AzukiError::Protocol(format!(
+                "Segment at {} does not continue from {}",
+                seg.start, pos
+            )));
+        }
+        pos += seg.length;
+    }
+    let len = reading.chars().count();
+    if pos != len {
+        return Err(AzukiError::Protocol(format!(
+            "Segments cover {} characters, reading has {}",
+            pos, len
+        )));
+    }
+    Ok(())
+}
+
+/// Build a continuation token for resuming conversion at `offset`
+///
+/// The token is bound to the reading so a stale token sent with an edited
+/// reading is rejected instead of silently converting the wrong range.
+fn make_continuation(offset: usize, reading: &str) -> String {
+    format!("{}:{:016x}", offset, reading_fingerprint(reading))
+}
+
+/// Parse a continuation token, returning the offset if it matches the reading
+/// Convert a chunk with Zenzai, logging failures
+#[cfg(feature = "zenzai")]
+fn run_zenzai(
+    backend: &Mutex<ZenzaiSidecar>,
+    session_id: &str,
+    profile: Option<&str>,
+    chunk: &str,
+    context: &str,
+    cancel: &CancelToken,
+    progress: &mut dyn FnMut(&str),
+) -> Option<ZenzaiOutput> {
+    let mut zenzai = backend.lock().unwrap_or_else(PoisonError::into_inner);
+    match zenzai.convert(session_id, profile, chunk, Some(context), cancel, progress) {
+        Ok(candidates) if candidates.is_empty() => {
+            log_debug!("[handler] Zenzai produced no conversion");
+            None
+        }
+        Ok(candidates) => {
+            log_debug!("[handler] Zenzai conversion successful");
+            Some((candidates, zenzai.last_confidence()))
+        }
+        Err(e) => {
+            log_warn!(
+                "[handler] Zenzai conversion failed: {}, falling back to dictionary",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Chunk of a reading a convert request asks for, if the request is valid
+///
+/// The whole reading unless the request is `chunked` or continues one.
+#[cfg(feature = "zenzai")]
+fn requested_chunk(reading: &str, continuation: Option<&str>, chunked: bool) -> Option<String> {
+    validate_reading(reading).ok()?;
+    let chars: Vec<char> = reading.chars().collect();
+    let offset = match continuation {
+        Some(token) => parse_continuation(token, reading)?,
+        None if !chunked => return Some(reading.to_string()),
+        None => 0,
+    };
+    let end = Converter::chunk_end(&chars, offset, READING_SOFT_LIMIT);
+    Some(chars[offset..end].iter().collect())
+}
+
+fn parse_continuation(token: &str, reading: &str) -> Option<usize> {
+    let (offset, fingerprint) = token.split_once(':')?;
+    let offset: usize = offset.parse().ok()?;
+    let fingerprint = u64::from_str_radix(fingerprint, 16).ok()?;
+    if fingerprint != reading_fingerprint(reading) || offset >= reading.chars().count() {
+        return None;
+    }
+    Some(offset)
+}
+
+/// Stable fingerprint of a reading (FNV-1a)
+fn reading_fingerprint(reading: &str) -> u64 {
+    reading.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new(ServerConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(candidates: &[Candidate]) -> Vec<&str> {
+        candidates.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    fn test_dictionary() -> Dictionary {
+        Dictionary::load(
+            std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures/test-dict.utf8"),
+        )
+        .unwrap()
+    }
+
+    fn server_with_test_dictionary() -> Server {
+        Server {
+            converter: Converter::new(Some(test_dictionary())),
+            ..create_test_server()
+        }
+    }
+
+    fn create_test_server() -> Server {
+        Server {
+            converter: Converter::new(None),
+            system_dictionary: None,
+            dictionary_paths: Vec::new(),
+            dictionary_report: Vec::new(),
+            dictionary_sets: BTreeMap::new(),
+            loaded_sets: HashMap::new(),
+            loading_sets: HashSet::new(),
+            set_loads: Vec::new(),
+            active_set: None,
+            user_dictionary: UserDictionary::new(),
+            blocklist: Blocklist::new(),
+            learning: LearningStore::new(),
+            fallback: FallbackPolicy::default(),
+            romaji: RomajiTable::default(),
+            sessions: SessionManager::new(),
+            connections: HashMap::new(),
+            next_connection: 0,
+            connection: None,
+            session_owners: HashMap::new(),
+            subscribers: HashMap::new(),
+            next_subscriber: 0,
+            dictionary_loading: false,
+            cancel: CancelToken::default(),
+            started: Instant::now(),
+            metrics: Metrics::new(),
+            settings: Settings::default(),
+            prefetched: None,
+            unsaved: Unsaved::default(),
+            #[cfg(feature = "zenzai")]
+            zenzai: None,
+            zenzai_config: None,
+            #[cfg(feature = "zenzai")]
+            zenzai_error: None,
+        }
+    }
+
+    #[test]
+    fn test_init_request() {
+        let mut server = create_test_server();
+        let json = r#"{"type":"init","seq":1}"#;
+        let request: Request = serde_json::from_str(json).unwrap();
+        let response = server.handle_request(request);
+        match response {
+            Response::InitResult {
+                seq,
+                version,
+                has_dictionary,
+                ..
+            } => {
+                assert_eq!(seq, 1);
+                assert!(!version.is_empty());
+                assert!(!has_dictionary);
+            }
+            _ => panic!("Expected InitResult"),
+        }
+    }
+
+    #[test]
+    fn test_convert_request() {
+        let mut server = create_test_server();
+        let json = r#"{"type":"convert","seq":42,"session_id":"abc","reading":"きょうは"}"#;
+        let request: Request = serde_json::from_str(json).unwrap();
+        let response = server.handle_request(request);
+        match response {
+            Response::ConvertResult {
+                seq,
+                session_id,
+                candidates,
+                ..
+            } => {
+                assert_eq!(seq, 42);
+                assert_eq!(session_id, "abc");
+                assert!(!candidates.is_empty());
+                // Without dictionary, should return reading as-is
+                assert_eq!(candidates[0].text, "きょうは");
+                assert_eq!(candidates[0].source, CandidateSource::Reading);
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+    }
+
+    #[test]
+    fn test_shutdown_request() {
+        let mut server = create_test_server();
+        let json = r#"{"type":"shutdown","seq":99}"#;
+        let request: Request = serde_json::from_str(json).unwrap();
+        let response = server.handle_request(request);
+        match response {
+            Response::ShutdownResult {
+                seq,
+                flushed,
+                errors,
+            } => {
+                assert_eq!(seq, 99);
+                assert!(flushed);
+                assert!(errors.is_empty());
+            }
+            _ => panic!("Expected ShutdownResult"),
+        }
+    }
+
+    #[test]
+    fn test_shutdown_reports_failed_flush() {
+        let mut server = Server {
+            blocklist: Blocklist::load("/dev/null/azuki/blocklist.txt").unwrap(),
+            ..create_test_server()
+        };
+        server.save_blocklist();
+        assert!(server.unsaved.blocklist);
+
+        let request: Request = serde_json::from_str(r#"{"type":"shutdown","seq":1}"#).unwrap();
+        match server.handle_request(request) {
+            Response::ShutdownResult {
+                flushed, errors, ..
+            } => {
+                assert!(!flushed);
+                assert_eq!(errors.len(), 1);
+                assert!(errors[0].starts_with("blocklist"));
+            }
+            _ => panic!("Expected ShutdownResult"),
+        }
+    }
+
+    #[test]
+    fn test_convert_long_reading_in_chunks() {
+        let mut server = create_test_server();
+        let reading = "あ".repeat(READING_SOFT_LIMIT + 10);
+        let mut continuation: Option<String> = None;
+        let mut converted = 0;
+
+        for seq in 0..3 {
+            let request = Request::Convert {
+                seq,
+                session_id: "abc".to_string(),
+                reading: reading.clone(),
+                cursor: None,
+                options: Some(ConvertOptions {
+                    chunked: true,
+                    ..Default::default()
+                }),
+                continuation: continuation.take(),
+            };
+            match server.handle_request(request) {
+                Response::ConvertResult {
+                    segments,
+                    continuation: next,
+                    ..
+                } => {
+                    assert_eq!(segments[0].start, converted);
+                    converted += segments.iter().map(|s| s.length).sum::<usize>();
+                    continuation = next;
+                }
+                _ => panic!("Expected ConvertResult"),
+            }
+            if continuation.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(converted, READING_SOFT_LIMIT + 10);
+        assert!(continuation.is_none());
+    }
+
+    #[test]
+    fn test_convert_long_reading_whole_unless_chunked() {
+        let mut server = create_test_server();
+        let reading = "あ".repeat(READING_SOFT_LIMIT + 10);
+        let request = Request::Convert {
+            seq: 1,
+            session_id: "abc".to_string(),
+            reading: reading.clone(),
+            cursor: None,
+            options: None,
+            continuation: None,
+        };
+        match server.handle_request(request) {
+            Response::ConvertResult {
+                segments,
+                continuation,
+                ..
+            } => {
+                assert!(continuation.is_none());
+                assert_eq!(
+                    segments.iter().map(|s| s.length).sum::<usize>(),
+                    READING_SOFT_LIMIT + 10
+                );
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+        // The whole reading can be adjusted
+        assert!(server
+            .sessions
+            .get_mut("abc")
+            .unwrap()
+            .composition
+            .is_some());
+    }
+
+    #[test]
+    fn test_convert_rejects_stale_continuation() {
+        let mut server = create_test_server();
+        let token = make_continuation(3, "あいうえお");
+        let json = format!(
+            r#"{{"type":"convert","seq":1,"session_id":"abc","reading":"かきくけこ","continuation":"{}"}}"#,
+            token
+        );
+        let request: Request = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            server.handle_request(request),
+            Response::Error { seq: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_cancelled_request_is_not_handled() {
+        let mut server = create_test_server();
+        let cancel = CancelToken::default();
+        cancel.cancel();
+        let json =
+            r#"{"type":"commit","seq":5,"session_id":"abc","reading":"きょう","candidate":"今日"}"#;
+        let response =
+            server.handle_cancellable(0, serde_json::from_str(json).unwrap(), cancel, None);
+        match response {
+            Response::Error { seq, code, .. } => {
+                assert_eq!(seq, 5);
+                assert_eq!(code, "cancelled");
+            }
+            _ => panic!("Expected Error"),
+        }
+        assert!(server.learning.is_empty());
+
+        // Nothing is in flight for a cancel that reaches the server
+        let json = r#"{"type":"cancel","seq":6,"target_seq":5}"#;
+        let response = server.handle_request(serde_json::from_str(json).unwrap());
+        assert!(matches!(
+            response,
+            Response::CancelResult {
+                seq: 6,
+                target_seq: 5,
+                cancelled: false
+            }
+        ));
+    }
+
+    #[test]
+    fn test_get_stats_counts_requests() {
+        let mut server = create_test_server();
+        for json in [
+            r#"{"type":"convert","seq":1,"session_id":"abc","reading":"きょう"}"#,
+            r#"{"type":"convert","seq":2,"session_id":"abc","reading":""}"#,
+            r#"{"type":"commit","seq":3,"session_id":"abc","reading":"きょう","candidate":"今日"}"#,
+        ] {
+            server.handle_request(serde_json::from_str(json).unwrap());
+        }
+
+        let json = r#"{"type":"get_stats","seq":4,"session_id":"abc"}"#;
+        match server.handle_request(serde_json::from_str(json).unwrap()) {
+            Response::StatsResult {
+                seq,
+                requests,
+                remote_cache,
+                memory,
+                ..
+            } => {
+                assert_eq!(seq, 4);
+                assert_eq!(requests["convert"].count, 2);
+                assert_eq!(requests["convert"].errors, 1);
+                assert_eq!(requests["commit"].count, 1);
+                // Recorded once it has been answered
+                assert!(!requests.contains_key("get_stats"));
+                assert_eq!(remote_cache, None);
+                assert_eq!(memory.sessions, 1);
+                assert_eq!(memory.learning_entries, 1);
+            }
+            _ => panic!("Expected StatsResult"),
+        }
+    }
+
+    #[test]
+    fn test_set_config_per_session_and_globally() {
+        let mut server = server_with_test_dictionary();
+        let request = |json: &str| serde_json::from_str::<Request>(json).unwrap();
+        let candidates = |response: Response| match response {
+            Response::ConvertResult { candidates, .. } => candidates,
+            _ => panic!("Expected ConvertResult"),
+        };
+        let convert = r#"{"type":"convert","seq":1,"session_id":"abc","reading":"きょう"}"#;
+        assert!(candidates(server.handle_request(request(convert))).len() > 1);
+
+        let set = r#"{"type":"set_config","seq":2,"session_id":"abc","settings":{"candidate_limit":1,"learning":false}}"#;
+        match server.handle_request(request(set)) {
+            Response::ConfigResult {
+                global,
+                session,
+                effective,
+                ..
+            } => {
+                assert_eq!(global, Settings::default());
+                assert_eq!(session.candidate_limit, Some(1));
+                assert_eq!(effective.candidate_limit, 1);
+                assert!(!effective.learning);
+            }
+            _ => panic!("Expected ConfigResult"),
+        }
+        assert_eq!(candidates(server.handle_request(request(convert))).len(), 1);
+        let commit =
+            r#"{"type":"commit","seq":3,"session_id":"abc","reading":"きょう","candidate":"京"}"#;
+        server.handle_request(request(commit));
+        assert!(server.learning.is_empty());
+
+        // Other sessions only see global changes
+        let other = r#"{"type":"convert","seq":4,"session_id":"xyz","reading":"きょう"}"#;
+        assert!(candidates(server.handle_request(request(other))).len() > 1);
+        let set = r#"{"type":"set_config","seq":5,"session_id":"xyz","scope":"global","settings":{"candidate_limit":2}}"#;
+        server.handle_request(request(set));
+        assert_eq!(candidates(server.handle_request(request(other))).len(), 2);
+        assert_eq!(candidates(server.handle_request(request(convert))).len(), 1);
+
+        let get = r#"{"type":"get_config","seq":6,"session_id":"xyz"}"#;
+        assert!(matches!(
+            server.handle_request(request(get)),
+            Response::ConfigResult {
+                effective: Settings {
+                    candidate_limit: 2,
+                    learning: true,
+                    ..
+                },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_ping_request() {
+        let mut server = create_test_server();
+        let request: Request = serde_json::from_str(r#"{"type":"ping","seq":7}"#).unwrap();
+        match server.handle_request(request) {
+            Response::PingResult {
+                seq,
+                has_dictionary,
+                dictionary_loading,
+                zenzai_ready,
+                queue_depth,
+                ..
+            } => {
+                assert_eq!(seq, 7);
+                assert!(!has_dictionary);
+                assert!(!dictionary_loading);
+                assert!(!zenzai_ready);
+                assert_eq!(queue_depth, 0);
+            }
+            _ => panic!("Expected PingResult"),
+        }
+        assert!(matches!(
+            server.ping(8, 3),
+            Response::PingResult { queue_depth: 3, .. }
+        ));
+    }
+
+    #[test]
+    fn test_batch_handles_requests_in_order() {
+        let mut server = create_test_server();
+        let json = r#"{"type":"batch","seq":10,"requests":[
+            {"type":"commit","seq":11,"session_id":"abc","reading":"きょう","candidate":"今日"},
+            {"type":"convert","seq":12,"session_id":"abc","reading":"にほんご"},
+            {"type":"shutdown","seq":13},
+            {"type":"batch","seq":14,"requests":[]}
+        ]}"#;
+        let response = server.handle_request(serde_json::from_str(json).unwrap());
+        let Response::BatchResult { seq, responses } = response else {
+            panic!("Expected BatchResult");
+        };
+        assert_eq!(seq, 10);
+        assert_eq!(responses.len(), 4);
+        assert!(matches!(
+            responses[0],
+            Response::CommitResult { seq: 11, .. }
+        ));
+        assert!(matches!(
+            responses[1],
+            Response::ConvertResult { seq: 12, .. }
+        ));
+        assert!(matches!(responses[2], Response::Error { seq: 13, .. }));
+        assert!(matches!(responses[3], Response::Error { seq: 14, .. }));
+    }
+
+    #[test]
+    fn test_convert_cursor_marks_active_segment() {
+        let mut server = server_with_test_dictionary();
+        let mut convert = |seq: u64, cursor: usize| {
+            let json = format!(
+                r#"{{"type":"convert","seq":{},"session_id":"abc","reading":"きょうはいしゃ","cursor":{}}}"#,
+                seq, cursor
+            );
+            server.handle_request(serde_json::from_str(&json).unwrap())
+        };
+
+        for (seq, cursor, active) in [(1, 0, 0), (2, 3, 0), (3, 4, 1), (4, 7, 2)] {
+            match convert(seq, cursor) {
+                Response::ConvertResult {
+                    active_segment,
+                    segments,
+                    ..
+                } => {
+                    assert_eq!(segments.len(), 3);
+                    assert_eq!(active_segment, Some(active), "cursor {}", cursor);
+                }
+                _ => panic!("Expected ConvertResult"),
+            }
+        }
+
+        // The cursor inside "いしゃ" moves a boundary there
+        match convert(5, 5) {
+            Response::ConvertResult {
+                active_segment,
+                segments,
+                ..
+            } => {
+                let active = &segments[active_segment.unwrap()];
+                assert_eq!(active.start + active.length, 5);
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+
+        assert!(matches!(convert(6, 8), Response::Error { seq: 6, .. }));
+    }
+
+    #[test]
+    fn test_live_convert_delta() {
+        let mut server = server_with_test_dictionary();
+        // A single whole-phrase candidate keeps the lists short
+        server
+            .converter
+            .set_conversion(crate::converter::ConversionConfig {
+                n_best: 1,
+                ..Default::default()
+            });
+        let convert = |seq: u64, reading: &str| {
+            serde_json::from_str::<Request>(&format!(
+                r#"{{"type":"convert","seq":{},"session_id":"abc","reading":"{}","options":{{"live":true,"delta":true}}}}"#,
+                seq, reading
+            ))
+            .unwrap()
+        };
+
+        // No base yet: full result
+        assert!(matches!(
+            server.handle_request(convert(1, "きょう")),
+            Response::ConvertResult { .. }
+        ));
+
+        match server.handle_request(convert(2, "きょうは")) {
+            Response::ConvertDelta {
+                base_seq,
+                removed,
+                added,
+                segments,
+                segment_count,
+                ..
+            } => {
+                assert_eq!(base_seq, 1);
+                assert_eq!(removed, vec!["今日", "きょう"]);
+                assert_eq!(added.len(), 2);
+                assert_eq!(added[0].candidate.text, "今日は");
+                // Only the new "は" segment changed
+                assert_eq!(segments.len(), 1);
+                assert_eq!(segments[0].index, 1);
+                assert_eq!(segment_count, 2);
+            }
+            other => panic!("Expected ConvertDelta, got {:?}", other),
+        }
+
+        // Commit ends the composition, so the next result is full again
+        let commit = r#"{"type":"commit","seq":3,"session_id":"abc","reading":"きょうは","candidate":"今日は"}"#;
+        server.handle_request(serde_json::from_str(commit).unwrap());
+        assert!(matches!(
+            server.handle_request(convert(4, "きょう")),
+            Response::ConvertResult { .. }
+        ));
+    }
+
+    #[test]
+    fn test_candidate_details() {
+        let mut server = Server::with_dictionary(Some(Arc::new(test_dictionary())));
+        let convert = r#"{"type":"convert","seq":1,"session_id":"abc","reading":"かんじ"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult {
+                candidates,
+                segments,
+                ..
+            } => {
+                assert_eq!(
+                    candidates[0],
+                    Candidate {
+                        text: "漢字".to_string(),
+                        annotation: Some("kanji".to_string()),
+                        source: CandidateSource::Dictionary,
+                        kind: None,
+                        score: 1.0,
+                        log_prob: None,
+                        confidence: None,
+                    }
+                );
+                assert_eq!(candidates[1].annotation, None);
+                assert!(candidates[1].score < 1.0);
+                let reading = candidates.last().unwrap();
+                assert_eq!(reading.text, "かんじ");
+                assert_eq!(reading.source, CandidateSource::Reading);
+                assert_eq!(segments[0].annotations["漢字"], "kanji");
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+    }
+
+    #[test]
+    fn test_dynamic_providers() {
+        let mut server = create_test_server();
+        let init = r#"{"type":"init","seq":1,"session_id":"abc","providers":{"enabled":{"greeting":true}}}"#;
+        server.handle_request(serde_json::from_str(init).unwrap());
+
+        let convert = r#"{"type":"convert","seq":2,"session_id":"abc","reading":"あいさつ"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult {
+                candidates,
+                segments,
+                ..
+            } => {
+                assert_eq!(candidates.len(), 2);
+                assert_eq!(candidates[0].text, "あいさつ");
+                assert_eq!(candidates[1].source, CandidateSource::Provider);
+                assert_eq!(candidates[1].annotation.as_deref(), Some("時間帯の挨拶"));
+                assert_eq!(segments[0].candidates, texts(&candidates));
+                assert_eq!(segments[0].annotations[&candidates[1].text], "時間帯の挨拶");
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+
+        // Disabling for the session overrides the global config
+        let set =
+            r#"{"type":"set_providers","seq":3,"session_id":"abc","enabled":{"greeting":false}}"#;
+        match server.handle_request(serde_json::from_str(set).unwrap()) {
+            Response::SetProvidersResult { providers, .. } => {
+                assert!(!providers["greeting"]);
+            }
+            _ => panic!("Expected SetProvidersResult"),
+        }
+        let convert = r#"{"type":"convert","seq":4,"session_id":"abc","reading":"あいさつ"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult {
+                candidates,
+                segments,
+                ..
+            } => {
+                assert_eq!(texts(&candidates), vec!["あいさつ"]);
+                assert!(segments[0].annotations.is_empty());
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+
+        let set =
+            r#"{"type":"set_providers","seq":5,"session_id":"abc","enabled":{"clipboard":true}}"#;
+        match server.handle_request(serde_json::from_str(set).unwrap()) {
+            Response::Error { code, .. } => assert_eq!(code, "protocol_error"),
+            _ => panic!("Expected Error"),
+        }
+    }
+
+    #[test]
+    fn test_complete_ranks_learned_readings() {
+        let mut server = Server::with_dictionary(Some(Arc::new(test_dictionary())));
+        let complete = r#"{"type":"complete","seq":1,"session_id":"abc","prefix":"に"}"#;
+        let readings = |response: Response| match response {
+            Response::CompleteResult { completions, .. } => completions
+                .into_iter()
+                .map(|c| (c.reading, c.candidates))
+                .collect::<Vec<_>>(),
+            _ => panic!("Expected CompleteResult"),
+        };
+        assert_eq!(
+            readings(server.handle_request(serde_json::from_str(complete).unwrap())),
+            vec![
+                ("にほんご".to_string(), vec!["日本語".to_string()]),
+                ("にゅうりょく".to_string(), vec!["入力".to_string()]),
+            ]
+        );
+
+        let commit = r#"{"type":"commit","seq":2,"session_id":"abc","reading":"にゅうりょく","candidate":"入力"}"#;
+        server.handle_request(serde_json::from_str(commit).unwrap());
+        let ranked = readings(server.handle_request(serde_json::from_str(complete).unwrap()));
+        assert_eq!(ranked[0].0, "にゅうりょく");
+        assert_eq!(ranked.len(), 2);
+
+        let limited = r#"{"type":"complete","seq":3,"session_id":"abc","prefix":"に","limit":1}"#;
+        assert_eq!(
+            readings(server.handle_request(serde_json::from_str(limited).unwrap())).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_complete_fills_the_limit_past_blocked_readings() {
+        let mut dictionary = Dictionary::new();
+        for (reading, word) in [("かぎ", "鍵"), ("かさ", "傘"), ("かめ", "亀")] {
+            dictionary.add_entry(reading, word);
+        }
+        let mut server = Server::with_dictionary(Some(Arc::new(dictionary)));
+        server.blocklist.block("かぎ", "鍵");
+        let complete = r#"{"type":"complete","seq":1,"session_id":"abc","prefix":"か","limit":2}"#;
+        match server.handle_request(serde_json::from_str(complete).unwrap()) {
+            Response::CompleteResult { completions, .. } => {
+                let readings: Vec<&str> = completions.iter().map(|c| c.reading.as_str()).collect();
+                assert_eq!(readings, ["かさ", "かめ"]);
+            }
+            _ => panic!("Expected CompleteResult"),
+        }
+    }
+
+    #[test]
+    fn test_replace_dictionaries() {
+        let mut server = Server::with_dictionary(None);
+        let mut system = crate::dictionary::Dictionary::new();
+        system.add_entry("さくら", "桜");
+        server.replace_dictionaries(Some(Some(Arc::new(system))), None);
+        assert_eq!(server.dictionary_candidates("さくら"), vec!["桜"]);
+
+        let mut user = UserDictionary::new();
+        user.add("さくら", "佐倉");
+        server.replace_dictionaries(None, Some(user));
+        assert_eq!(server.dictionary_candidates("さくら"), vec!["佐倉", "桜"]);
+
+        server.replace_dictionaries(Some(None), Some(UserDictionary::new()));
+        assert!(server.dictionary_candidates("さくら").is_empty());
     }
 
     #[test]
-    fn test_init_request() {
+    fn test_add_and_remove_entry() {
+        let mut server = Server::with_dictionary(Some(Arc::new(test_dictionary())));
+        let convert = r#"{"type":"convert","seq":1,"session_id":"abc","reading":"きょう"}"#;
+        let top_candidate = |server: &mut Server| match server
+            .handle_request(serde_json::from_str(convert).unwrap())
+        {
+            Response::ConvertResult { candidates, .. } => candidates[0].text.clone(),
+            _ => panic!("Expected ConvertResult"),
+        };
+        assert_eq!(top_candidate(&mut server), "今日");
+
+        let add = r#"{"type":"add_entry","seq":2,"session_id":"abc","reading":"きょう","candidate":"京"}"#;
+        assert!(matches!(
+            server.handle_request(serde_json::from_str(add).unwrap()),
+            Response::AddEntryResult { success: true, .. }
+        ));
+        assert_eq!(top_candidate(&mut server), "京");
+
+        let remove = r#"{"type":"remove_entry","seq":3,"session_id":"abc","reading":"きょう","candidate":"京"}"#;
+        assert!(matches!(
+            server.handle_request(serde_json::from_str(remove).unwrap()),
+            Response::RemoveEntryResult { success: true, .. }
+        ));
+        // The system dictionary's order is restored, not the word deleted
+        assert_eq!(top_candidate(&mut server), "今日");
+
+        let add = r#"{"type":"add_entry","seq":4,"session_id":"abc","reading":"きょう","candidate":"a/b"}"#;
+        assert!(matches!(
+            server.handle_request(serde_json::from_str(add).unwrap()),
+            Response::Error { .. }
+        ));
+    }
+
+    #[test]
+    fn test_commit_reorders_candidates() {
+        let mut server = server_with_test_dictionary();
+        let commit =
+            r#"{"type":"commit","seq":1,"session_id":"abc","reading":"きょう","candidate":"教"}"#;
+        server.handle_request(serde_json::from_str(commit).unwrap());
+
+        let convert = r#"{"type":"convert","seq":2,"session_id":"abc","reading":"きょう"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult {
+                candidates,
+                segments,
+                confidence,
+                ..
+            } => {
+                assert_eq!(segments[0].candidates[0], "教");
+                assert_eq!(candidates[0].text, "教");
+                // One of two commits chose 教 among 京/教/今日: (1 + 1) / (1 + 3)
+                assert!((confidence - 0.5).abs() < 1e-9);
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+    }
+
+    #[test]
+    fn test_session_stats() {
         let mut server = create_test_server();
-        let json = r#"{"type":"init","seq":1}"#;
-        let request: Request = serde_json::from_str(json).unwrap();
-        let response = server.handle_request(request);
-        match response {
-            Response::InitResult {
-                seq,
-                version,
-                has_dictionary,
+        let requests = [
+            r#"{"type":"convert","seq":1,"session_id":"abc","reading":"あ"}"#,
+            r#"{"type":"commit","seq":2,"session_id":"abc","reading":"あ","candidate":"あ"}"#,
+            r#"{"type":"convert","seq":3,"session_id":"abc","reading":"い"}"#,
+            r#"{"type":"commit","seq":4,"session_id":"abc","reading":"い","candidate":"胃"}"#,
+        ];
+        for json in requests {
+            server.handle_request(serde_json::from_str(json).unwrap());
+        }
+
+        let json = r#"{"type":"session_stats","seq":5,"session_id":"abc"}"#;
+        match server.handle_request(serde_json::from_str(json).unwrap()) {
+            Response::SessionStatsResult { stats, .. } => {
+                assert_eq!(stats.conversions, 2);
+                assert_eq!(stats.commits, 2);
+                assert_eq!(stats.candidate_index_counts, vec![1]);
+                assert_eq!(stats.unlisted_commits, 1);
+                assert_eq!(stats.average_rank, Some(0.0));
+            }
+            _ => panic!("Expected SessionStatsResult"),
+        }
+    }
+
+    #[test]
+    fn test_adjust_segment_uses_session_composition() {
+        let mut server = create_test_server();
+        let mut dictionary = crate::dictionary::Dictionary::new();
+        dictionary.add_entry("にほん", "日本");
+        dictionary.add_entry("ご", "語");
+        server.replace_dictionaries(Some(Some(Arc::new(dictionary))), None);
+        let adjust = r#"{"type":"adjust_segment","seq":1,"session_id":"abc","segment_index":0,"direction":"shrink"}"#;
+        match server.handle_request(serde_json::from_str(adjust).unwrap()) {
+            Response::Error { code, .. } => assert_eq!(code, "session_error"),
+            _ => panic!("Expected Error"),
+        }
+
+        let convert = r#"{"type":"convert","seq":2,"session_id":"abc","reading":"にほんご"}"#;
+        server.handle_request(serde_json::from_str(convert).unwrap());
+        match server.handle_request(serde_json::from_str(adjust).unwrap()) {
+            Response::AdjustSegmentResult { segments, .. } => {
+                assert_eq!(segments[0].reading, "にほ");
+                assert_eq!(segments[1].reading, "んご");
+            }
+            _ => panic!("Expected AdjustSegmentResult"),
+        }
+
+        let mode = r#"{"type":"set_mode","seq":3,"session_id":"abc","mode":"katakana"}"#;
+        server.handle_request(serde_json::from_str(mode).unwrap());
+        let state = r#"{"type":"session_state","seq":3,"session_id":"abc","context":"今日は","select":{"segment":1,"candidate":0}}"#;
+        match server.handle_request(serde_json::from_str(state).unwrap()) {
+            Response::SessionStateResult {
+                input_mode,
+                context,
+                composition,
+                ..
+            } => {
+                assert_eq!(input_mode, InputMode::Katakana);
+                assert_eq!(context, "今日は");
+                let composition = composition.unwrap();
+                assert_eq!(composition.reading, "にほんご");
+                assert_eq!(composition.segments[0].reading, "にほ");
+                assert_eq!(composition.selected, vec![0, 0]);
+            }
+            _ => panic!("Expected SessionStateResult"),
+        }
+
+        let commit = r#"{"type":"commit","seq":4,"session_id":"abc","reading":"にほんご","candidate":"にほんご"}"#;
+        server.handle_request(serde_json::from_str(commit).unwrap());
+        let state = r#"{"type":"session_state","seq":5,"session_id":"abc"}"#;
+        match server.handle_request(serde_json::from_str(state).unwrap()) {
+            Response::SessionStateResult { composition, .. } => assert!(composition.is_none()),
+            _ => panic!("Expected SessionStateResult"),
+        }
+    }
+
+    #[test]
+    fn test_undo_commit() {
+        let mut server = create_test_server();
+        let commit = |seq: u64, candidate: &str| {
+            format!(
+                r#"{{"type":"commit","seq":{},"session_id":"abc","reading":"きょう","candidate":"{}"}}"#,
+                seq, candidate
+            )
+        };
+        let undo = r#"{"type":"undo_commit","seq":9,"session_id":"abc"}"#;
+        server.handle_request(serde_json::from_str(&commit(1, "今日")).unwrap());
+        server.handle_request(serde_json::from_str(&commit(2, "京")).unwrap());
+
+        match server.handle_request(serde_json::from_str(undo).unwrap()) {
+            Response::UndoCommitResult {
+                undone, candidate, ..
+            } => {
+                assert!(undone);
+                assert_eq!(candidate.as_deref(), Some("京"));
+            }
+            _ => panic!("Expected UndoCommitResult"),
+        }
+        let mut candidates = vec!["京".to_string(), "今日".to_string()];
+        server.learning.reorder("きょう", &mut candidates);
+        assert_eq!(candidates, ["今日", "京"]);
+
+        // Only the latest commit can be undone
+        match server.handle_request(serde_json::from_str(undo).unwrap()) {
+            Response::UndoCommitResult { undone, .. } => assert!(!undone),
+            _ => panic!("Expected UndoCommitResult"),
+        }
+    }
+
+    #[test]
+    fn test_commits_extend_session_context() {
+        let mut server = create_test_server();
+        let set_context =
+            r#"{"type":"session_state","seq":1,"session_id":"abc","context":"昨日は"}"#;
+        server.handle_request(serde_json::from_str(set_context).unwrap());
+        let commit =
+            r#"{"type":"commit","seq":2,"session_id":"abc","reading":"きょう","candidate":"今日"}"#;
+        server.handle_request(serde_json::from_str(commit).unwrap());
+
+        let state = r#"{"type":"session_state","seq":3,"session_id":"abc"}"#;
+        match server.handle_request(serde_json::from_str(state).unwrap()) {
+            Response::SessionStateResult { context, .. } => assert_eq!(context, "昨日は今日"),
+            _ => panic!("Expected SessionStateResult"),
+        }
+    }
+
+    #[test]
+    fn test_session_state_sets_zenzai_profile() {
+        let mut server = create_test_server();
+        let set_profile = |seq: u64, profile: &str| {
+            format!(
+                r#"{{"type":"session_state","seq":{},"session_id":"abc","zenzai_profile":"{}"}}"#,
+                seq, profile
+            )
+        };
+
+        match server.handle_request(serde_json::from_str(&set_profile(1, "small")).unwrap()) {
+            Response::SessionStateResult { zenzai_profile, .. } => {
+                assert_eq!(zenzai_profile.as_deref(), Some("small"))
+            }
+            _ => panic!("Expected SessionStateResult"),
+        }
+        // An empty name goes back to the default profile
+        match server.handle_request(serde_json::from_str(&set_profile(2, "")).unwrap()) {
+            Response::SessionStateResult { zenzai_profile, .. } => {
+                assert!(zenzai_profile.is_none())
+            }
+            _ => panic!("Expected SessionStateResult"),
+        }
+    }
+
+    #[test]
+    fn test_sessions_convert_with_their_dictionary_set() {
+        let mut server = create_test_server();
+        let mut dictionary = Dictionary::new();
+        dictionary.add_entry("きょう", "今日");
+        server.replace_dictionaries(Some(Some(Arc::new(dictionary))), None);
+        let path = std::env::temp_dir().join(format!("azuki-set-{}.dict", std::process::id()));
+        std::fs::write(&path, ";; okuri-nasi entries.\nきょう /京子/\n").unwrap();
+        server
+            .dictionary_sets
+            .insert("names".to_string(), vec![path.clone().into()]);
+
+        let select = |session: &str, name: &str| {
+            format!(
+                r#"{{"type":"session_state","seq":1,"session_id":"{}","dictionary_set":"{}"}}"#,
+                session, name
+            )
+        };
+        let first = |server: &mut Server, session: &str| {
+            let convert = format!(
+                r#"{{"type":"convert","seq":2,"session_id":"{}","reading":"きょう"}}"#,
+                session
+            );
+            match server.handle_request(serde_json::from_str(&convert).unwrap()) {
+                Response::ConvertResult { candidates, .. } => candidates[0].text.clone(),
+                _ => panic!("Expected ConvertResult"),
+            }
+        };
+
+        let (sender, events) = mpsc::channel();
+        server.subscribe(sender);
+        match server.handle_request(serde_json::from_str(&select("a", "names")).unwrap()) {
+            Response::SessionStateResult {
+                dictionary_set,
+                dictionary_set_loading,
                 ..
             } => {
+                assert_eq!(dictionary_set.as_deref(), Some("names"));
+                assert!(dictionary_set_loading);
+            }
+            _ => panic!("Expected SessionStateResult"),
+        }
+        // Until the set is loaded the session uses the default dictionaries
+        assert_eq!(first(&mut server, "a"), "今日");
+        // Selecting it again doesn't load it twice
+        server.handle_request(serde_json::from_str(&select("c", "names")).unwrap());
+        let loads = server.take_set_loads();
+        assert_eq!(loads.len(), 1);
+        let (dictionary, report) = load_dictionaries_reporting(&loads[0].sources);
+        server.finish_set_loading(
+            loads[0].name.clone(),
+            dictionary.map(Arc::new),
+            report,
+            Duration::ZERO,
+        );
+        match events.try_recv().unwrap() {
+            Notification::DictionarySetReady { name, entries, .. } => {
+                assert_eq!(name, "names");
+                assert_eq!(entries, 1);
+            }
+            _ => panic!("Expected DictionarySetReady"),
+        }
+        match server.handle_request(serde_json::from_str(&select("a", "names")).unwrap()) {
+            Response::SessionStateResult {
+                dictionary_set_loading,
+                ..
+            } => assert!(!dictionary_set_loading),
+            _ => panic!("Expected SessionStateResult"),
+        }
+        assert!(server.take_set_loads().is_empty());
+        assert_eq!(first(&mut server, "a"), "京子");
+        assert_eq!(first(&mut server, "b"), "今日");
+        // Between requests the converter is back on the default set
+        assert_eq!(server.dictionary_candidates("きょう"), ["今日"]);
+        match server.handle_request(serde_json::from_str(&select("a", "nope")).unwrap()) {
+            Response::Error { code, .. } => assert_eq!(code, "config_error"),
+            _ => panic!("Expected Error"),
+        }
+        assert_eq!(first(&mut server, "a"), "京子");
+
+        server.handle_request(serde_json::from_str(&select("a", "default")).unwrap());
+        assert_eq!(first(&mut server, "a"), "今日");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_zenzai_hypotheses_precede_dictionary_candidates() {
+        let server = create_test_server();
+        let hypotheses = vec!["今日".to_string(), "京".to_string()];
+        let dictionary = (
+            vec!["京".to_string(), "経".to_string()],
+            HashMap::from([("京".to_string(), 0.9), ("経".to_string(), 0.5)]),
+        );
+        let (candidates, segments, scores) =
+            server.zenzai_chunk("きょう", 0, &hypotheses, dictionary);
+        assert_eq!(candidates, ["今日", "京", "経", "きょう"]);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].candidates, candidates);
+        assert_eq!(scores["京"], 0.5);
+        assert_eq!(scores["経"], 0.5);
+        assert!(!scores.contains_key("きょう"));
+    }
+
+    #[test]
+    fn test_unload_model_without_a_model() {
+        let mut server = create_test_server();
+        let request = r#"{"type":"unload_model","seq":1,"session_id":"abc"}"#;
+        match server.handle_request(serde_json::from_str(request).unwrap()) {
+            Response::UnloadModelResult { seq, unloaded, .. } => {
                 assert_eq!(seq, 1);
-                assert!(!version.is_empty());
-                assert!(!has_dictionary);
+                assert!(!unloaded);
             }
-            _ => panic!("Expected InitResult"),
+            _ => panic!("Expected UnloadModelResult"),
         }
     }
 
     #[test]
-    fn test_convert_request() {
+    fn test_input_modes() {
         let mut server = create_test_server();
-        let json = r#"{"type":"convert","seq":42,"session_id":"abc","reading":"きょうは"}"#;
-        let request: Request = serde_json::from_str(json).unwrap();
-        let response = server.handle_request(request);
-        match response {
+        let mut dictionary = crate::dictionary::Dictionary::new();
+        dictionary.add_entry("file", "ファイル");
+        dictionary.add_entry("あずき", "小豆");
+        server.replace_dictionaries(Some(Some(Arc::new(dictionary))), None);
+        let convert = |server: &mut Server, mode: &str, reading: &str| {
+            let json = format!(
+                r#"{{"type":"set_mode","seq":1,"session_id":"abc","mode":"{}"}}"#,
+                mode
+            );
+            match server.handle_request(serde_json::from_str(&json).unwrap()) {
+                Response::ModeResult { mode: set, .. } => {
+                    assert_eq!(serde_json::to_value(set).unwrap(), mode)
+                }
+                _ => panic!("Expected ModeResult"),
+            }
+            let json = format!(
+                r#"{{"type":"convert","seq":2,"session_id":"abc","reading":"{}"}}"#,
+                reading
+            );
+            match server.handle_request(serde_json::from_str(&json).unwrap()) {
+                Response::ConvertResult {
+                    candidates,
+                    segments,
+                    ..
+                } => {
+                    assert_eq!(segments.len(), 1);
+                    texts(&candidates)
+                        .into_iter()
+                        .map(String::from)
+                        .collect::<Vec<_>>()
+                }
+                _ => panic!("Expected ConvertResult"),
+            }
+        };
+
+        assert_eq!(
+            convert(&mut server, "katakana", "あずき"),
+            ["アズキ", "あずき"]
+        );
+        assert_eq!(
+            convert(&mut server, "halfwidth_katakana", "あずき"),
+            ["ｱｽﾞｷ", "あずき"]
+        );
+        assert_eq!(
+            convert(&mut server, "abbrev", "File"),
+            ["ファイル", "File", "Ｆｉｌｅ"]
+        );
+        assert_eq!(convert(&mut server, "hiragana", "あずき")[0], "小豆");
+    }
+
+    #[test]
+    fn test_init_settings_apply_to_the_session() {
+        let mut server = create_test_server();
+        let init = r#"{"type":"init","seq":1,"session_id":"a","settings":{"candidate_limit":1,"learning":false}}"#;
+        server.handle_request(serde_json::from_str(init).unwrap());
+        let settings = server.settings_for("a");
+        assert_eq!(settings.candidate_limit, 1);
+        assert!(!settings.learning);
+        assert_eq!(server.settings_for("b"), Settings::default());
+
+        let commit =
+            r#"{"type":"commit","seq":2,"session_id":"a","reading":"あ","candidate":"亜"}"#;
+        server.handle_request(serde_json::from_str(commit).unwrap());
+        assert_eq!(server.learning.len(), 0);
+
+        let init = r#"{"type":"init","seq":3,"session_id":"a","settings":{"bogus":1}}"#;
+        assert!(serde_json::from_str::<Request>(init).is_err());
+    }
+
+    #[test]
+    fn test_list_sessions() {
+        let mut server = create_test_server();
+        let convert = r#"{"type":"convert","seq":1,"session_id":"a","reading":"あ"}"#;
+        server.handle_request(serde_json::from_str(convert).unwrap());
+        let list = r#"{"type":"list_sessions","seq":2,"session_id":"b"}"#;
+        match server.handle_request(serde_json::from_str(list).unwrap()) {
+            Response::ListSessionsResult { sessions, .. } => {
+                assert_eq!(sessions.len(), 2);
+                let a = sessions.iter().find(|s| s.session_id == "a").unwrap();
+                assert_eq!(a.reading.as_deref(), Some("あ"));
+            }
+            _ => panic!("Expected ListSessionsResult"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_session_id_is_rejected() {
+        let mut server = create_test_server();
+        let convert = r#"{"type":"convert","seq":1,"session_id":"","reading":"あ"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::Error { code, .. } => assert_eq!(code, "session_error"),
+            _ => panic!("Expected Error"),
+        }
+        assert!(!server.sessions.contains(""));
+    }
+
+    #[test]
+    fn test_fallback_policy() {
+        let mut server = create_test_server();
+        let init = r#"{"type":"init","seq":1,"session_id":"abc","fallback":"katakana"}"#;
+        server.handle_request(serde_json::from_str(init).unwrap());
+
+        let convert = r#"{"type":"convert","seq":2,"session_id":"abc","reading":"ぱそこん"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult { candidates, .. } => {
+                assert_eq!(texts(&candidates), vec!["パソコン", "ぱそこん"]);
+                assert_eq!(candidates[0].source, CandidateSource::Reading);
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+
+        let init = r#"{"type":"init","seq":3,"session_id":"abc","fallback":"error"}"#;
+        server.handle_request(serde_json::from_str(init).unwrap());
+        let convert = r#"{"type":"convert","seq":4,"session_id":"abc","reading":"ぱそこん"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::Error { code, .. } => assert_eq!(code, "conversion_error"),
+            _ => panic!("Expected Error"),
+        }
+    }
+
+    #[test]
+    fn test_fullwidth_alphanumeric_candidates() {
+        let mut server = create_test_server();
+        let convert = r#"{"type":"convert","seq":1,"session_id":"abc","reading":"abc123"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult { candidates, .. } => {
+                assert_eq!(texts(&candidates), vec!["abc123", "ａｂｃ１２３"])
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+
+        let convert = r#"{"type":"convert","seq":2,"session_id":"abc","reading":"abc123","options":{"fullwidth_alphanumeric":false}}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult { candidates, .. } => {
+                assert_eq!(texts(&candidates), vec!["abc123"])
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+    }
+
+    #[test]
+    fn test_numeric_entry_candidates_are_annotated() {
+        let mut server = server_with_test_dictionary();
+        let convert = r#"{"type":"convert","seq":1,"session_id":"abc","reading":"12がつ"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult { candidates, .. } => {
+                let annotation = |text: &str| {
+                    let candidate = candidates.iter().find(|c| c.text == text).unwrap();
+                    candidate.annotation.clone()
+                };
+                assert_eq!(annotation("十二月").as_deref(), Some("漢数字"));
+                assert_eq!(annotation("１２月").as_deref(), Some("全角数字"));
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+    }
+
+    #[test]
+    fn test_number_reading_candidates() {
+        let mut server = create_test_server();
+        let convert = r#"{"type":"convert","seq":1,"session_id":"abc","reading":"ごじゅうよん"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult { candidates, .. } => {
+                let texts = texts(&candidates);
+                assert_eq!(texts[0], "五十四");
+                let numeral = texts.iter().position(|t| *t == "54").unwrap();
+                let reading = texts.iter().position(|t| *t == "ごじゅうよん").unwrap();
+                assert!(numeral < reading);
+                assert_eq!(candidates[0].annotation.as_deref(), Some("漢数字"));
+                assert_eq!(candidates[numeral].annotation.as_deref(), Some("数字"));
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+
+        let convert = r#"{"type":"convert","seq":2,"session_id":"abc","reading":"54"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult { candidates, .. } => {
+                assert_eq!(texts(&candidates), vec!["54", "五十四", "五四", "５４"])
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+    }
+
+    #[test]
+    fn test_halfwidth_katakana_option() {
+        let mut server = create_test_server();
+        let convert = r#"{"type":"convert","seq":1,"session_id":"abc","reading":"あずき"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult { candidates, .. } => {
+                assert!(!texts(&candidates).contains(&"ｱｽﾞｷ"))
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+
+        let convert = r#"{"type":"convert","seq":2,"session_id":"abc","reading":"あずき","options":{"halfwidth_katakana":true}}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
             Response::ConvertResult {
-                seq,
-                session_id,
                 candidates,
+                segments,
                 ..
             } => {
-                assert_eq!(seq, 42);
-                assert_eq!(session_id, "abc");
-                assert!(!candidates.is_empty());
-                // Without dictionary, should return reading as-is
-                assert_eq!(candidates[0], "きょうは");
+                let last = candidates.last().unwrap();
+                assert_eq!(last.text, "ｱｽﾞｷ");
+                assert_eq!(last.source, CandidateSource::Reading);
+                assert_eq!(segments[0].candidates.last().unwrap(), "ｱｽﾞｷ");
             }
             _ => panic!("Expected ConvertResult"),
         }
     }
 
     #[test]
-    fn test_shutdown_request() {
+    fn test_readings_pass_through_while_loading() {
+        let mut server = Server {
+            dictionary_loading: true,
+            ..create_test_server()
+        };
+        let init = r#"{"type":"init","seq":1,"session_id":"abc","fallback":"error"}"#;
+        match server.handle_request(serde_json::from_str(init).unwrap()) {
+            Response::InitResult {
+                has_dictionary,
+                dictionary_loading,
+                ..
+            } => assert!(!has_dictionary && dictionary_loading),
+            _ => panic!("Expected InitResult"),
+        }
+        let convert = r#"{"type":"convert","seq":2,"session_id":"abc","reading":"きょう"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult { candidates, .. } => {
+                assert_eq!(texts(&candidates), vec!["きょう"])
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+
+        let dictionary = test_dictionary();
+        let entries = dictionary.len();
+        match server.finish_loading(Some(Arc::new(dictionary)), Duration::from_millis(5)) {
+            Notification::DictionaryReady {
+                has_dictionary,
+                entries: loaded,
+                elapsed_ms,
+            } => {
+                assert!(has_dictionary);
+                assert_eq!(loaded, entries);
+                assert_eq!(elapsed_ms, 5);
+            }
+            _ => panic!("Expected DictionaryReady"),
+        }
+        let convert = r#"{"type":"convert","seq":3,"session_id":"abc","reading":"きょう"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult { candidates, .. } => {
+                assert_eq!(candidates[0].text, "今日")
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+    }
+
+    #[test]
+    fn test_blocked_candidates_are_filtered() {
+        let mut server = server_with_test_dictionary();
+        let block = r#"{"type":"block_candidate","seq":1,"session_id":"abc","reading":"きょう","candidate":"今日"}"#;
+        assert!(matches!(
+            server.handle_request(serde_json::from_str(block).unwrap()),
+            Response::BlockCandidateResult { success: true, .. }
+        ));
+
+        let convert = r#"{"type":"convert","seq":2,"session_id":"abc","reading":"きょうは"}"#;
+        match server.handle_request(serde_json::from_str(convert).unwrap()) {
+            Response::ConvertResult {
+                candidates,
+                segments,
+                ..
+            } => {
+                assert_eq!(segments[0].candidates, vec!["京", "教", "きょう"]);
+                assert_eq!(candidates[0].text, "京は");
+            }
+            _ => panic!("Expected ConvertResult"),
+        }
+    }
+
+    #[test]
+    fn test_blocklist_export_import() {
         let mut server = create_test_server();
-        let json = r#"{"type":"shutdown","seq":99}"#;
+        let import = r#"{"type":"import_blocklist","seq":1,"session_id":"abc","content":"きょう /京/教/\n"}"#;
+        match server.handle_request(serde_json::from_str(import).unwrap()) {
+            Response::ImportBlocklistResult {
+                imported, count, ..
+            } => {
+                assert_eq!(imported, 2);
+                assert_eq!(count, 2);
+            }
+            _ => panic!("Expected ImportBlocklistResult"),
+        }
+
+        let export = r#"{"type":"export_blocklist","seq":2,"session_id":"abc"}"#;
+        match server.handle_request(serde_json::from_str(export).unwrap()) {
+            Response::ExportBlocklistResult { content, count, .. } => {
+                assert_eq!(count, 2);
+                assert!(content.contains("きょう /京/教/"));
+            }
+            _ => panic!("Expected ExportBlocklistResult"),
+        }
+
+        let import = r#"{"type":"import_blocklist","seq":3,"session_id":"abc","content":"かんじ /幹事/","replace":true}"#;
+        match server.handle_request(serde_json::from_str(import).unwrap()) {
+            Response::ImportBlocklistResult { count, .. } => assert_eq!(count, 1),
+            _ => panic!("Expected ImportBlocklistResult"),
+        }
+    }
+
+    #[test]
+    fn test_transform_request() {
+        let mut server = create_test_server();
+        let json = r#"{"type":"transform","seq":5,"session_id":"abc","text":"ｱｽﾞｷ","direction":"katakana_to_hiragana"}"#;
         let request: Request = serde_json::from_str(json).unwrap();
-        let response = server.handle_request(request);
-        match response {
-            Response::ShutdownResult { seq } => {
-                assert_eq!(seq, 99);
+        match server.handle_request(request) {
+            Response::TransformResult { seq, text, .. } => {
+                assert_eq!(seq, 5);
+                assert_eq!(text, "あずき");
             }
-            _ => panic!("Expected ShutdownResult"),
+            _ => panic!("Expected TransformResult"),
+        }
+
+        let json =
+            r#"{"type":"transform","seq":6,"session_id":"abc","text":"a","direction":"sideways"}"#;
+        let request: Request = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            server.handle_request(request),
+            Response::Error { seq: 6, .. }
+        ));
+    }
+
+    #[test]
+    fn test_session_eviction_notification() {
+        let mut server = create_test_server();
+        let (sender, first_events) = mpsc::channel();
+        let first = server.connect(sender);
+        let (sender, second_events) = mpsc::channel();
+        let second = server.connect(sender);
+        let handle = |server: &mut Server, connection: u64, json: &str| {
+            let request = serde_json::from_str(json).unwrap();
+            server.handle_cancellable(connection, request, CancelToken::default(), None);
+        };
+        handle(
+            &mut server,
+            first,
+            r#"{"type":"init","seq":1,"session_id":"a","sessions":{"max_sessions":1}}"#,
+        );
+        assert!(first_events.try_recv().is_err());
+
+        // The connection that used the session hears of its eviction
+        handle(
+            &mut server,
+            second,
+            r#"{"type":"init","seq":2,"session_id":"b"}"#,
+        );
+        match first_events.try_recv().unwrap() {
+            Notification::SessionEvicted { session_id, .. } => assert_eq!(session_id, "a"),
+            _ => panic!("Expected SessionEvicted"),
+        }
+        assert!(first_events.try_recv().is_err());
+        assert!(second_events.try_recv().is_err());
+
+        // Nobody hears of sessions whose connection has gone
+        server.disconnect(second);
+        handle(
+            &mut server,
+            first,
+            r#"{"type":"init","seq":3,"session_id":"a"}"#,
+        );
+        assert!(first_events.try_recv().is_err());
+        assert!(second_events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_server_events_reach_subscribers() {
+        let mut server = Server {
+            blocklist: Blocklist::load("/dev/null/azuki/blocklist.txt").unwrap(),
+            ..create_test_server()
+        };
+        let (sender, events) = mpsc::channel();
+        let id = server.subscribe(sender);
+
+        server.replace_dictionaries(None, Some(UserDictionary::new()));
+        match events.try_recv().unwrap() {
+            Notification::DictionaryReloaded { system, user, .. } => {
+                assert!(!system);
+                assert!(user);
+            }
+            _ => panic!("Expected DictionaryReloaded"),
+        }
+        server.save_blocklist();
+        match events.try_recv().unwrap() {
+            Notification::ResourceWarning { kind, .. } => {
+                assert_eq!(kind, ResourceWarningKind::Storage)
+            }
+            _ => panic!("Expected ResourceWarning"),
+        }
+
+        server.unsubscribe(id);
+        server.save_blocklist();
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_session_limit_rejects_new() {
+        let mut server = create_test_server();
+        let json = r#"{"type":"init","seq":1,"session_id":"a","sessions":{"max_sessions":1,"eviction":"reject_new"}}"#;
+        server.handle_request(serde_json::from_str(json).unwrap());
+
+        let json = r#"{"type":"convert","seq":2,"session_id":"b","reading":"あ"}"#;
+        match server.handle_request(serde_json::from_str(json).unwrap()) {
+            Response::Error { seq, code, .. } => {
+                assert_eq!(seq, 2);
+                assert_eq!(code, "session_error");
+            }
+            _ => panic!("Expected Error"),
         }
     }
 
@@ -0,0 +1,198 @@
+//! JSON dictionary format
+//!
+//! Project-specific vocabularies are easier to review as plain JSON than as
+//! SKK lines. A dictionary is either one object from readings to
+//! candidates:
+//!
+//! ```json
+//! {"きょう": ["今日", {"text": "京", "annotation": "みやこ"}], "かk": ["書"]}
+//! ```
+//!
+//! or JSON Lines, one record per line:
+//!
+//! ```text
+//! {"reading": "きょう", "candidates": ["今日", "京"]}
+//! ```
+//!
+//! Readings ending in an okuri consonant (`かk`) are okuri-ari entries whose
+//! candidates are stems, as in SKK dictionaries.
+
+use crate::dictionary::AnnotatedCandidate;
+use crate::error::{DictionaryError, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Layout of a JSON dictionary file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One object from readings to candidates
+    Object,
+    /// One record per line
+    Lines,
+}
+
+/// Candidate as written: its text, or text with an annotation
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Candidate {
+    Text(String),
+    Annotated {
+        text: String,
+        #[serde(default)]
+        annotation: Option<String>,
+    },
+}
+
+impl From<Candidate> for AnnotatedCandidate {
+    fn from(candidate: Candidate) -> Self {
+        match candidate {
+            Candidate::Text(text) => (text, None),
+            Candidate::Annotated { text, annotation } => (text, annotation),
+        }
+    }
+}
+
+/// Line of a JSON Lines dictionary
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Record {
+    reading: String,
+    candidates: Vec<Candidate>,
+}
+
+/// Format of a dictionary file, or `None` for an SKK dictionary
+///
+/// `.json` and `.jsonl` files are read as such. Other files are JSON if
+/// they open with `{`, which no SKK line does: a single object if the
+/// whole file parses as one, JSON Lines otherwise.
+pub fn detect(path: &Path, content: &str) -> Option<Format> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => return Some(Format::Object),
+        Some("jsonl" | "ndjson") => return Some(Format::Lines),
+        _ => {}
+    }
+    if !content.trim_start().starts_with('{') {
+        return None;
+    }
+    let object = serde_json::from_str::<BTreeMap<String, Vec<Candidate>>>(content).is_ok();
+    Some(if object {
+        Format::Object
+    } else {
+        Format::Lines
+    })
+}
+
+/// Entries of a JSON dictionary
+///
+/// Candidates of a reading repeated in JSON Lines are appended in order.
+pub fn parse(
+    path: &Path,
+    format: Format,
+    content: &str,
+) -> Result<Vec<(String, Vec<AnnotatedCandidate>)>> {
+    let error = |line: usize, e: serde_json::Error| DictionaryError::Parse {
+        path: path.to_path_buf(),
+        line,
+        message: e.to_string(),
+    };
+    let records: Vec<Record> = match format {
+        Format::Object => {
+            let object: BTreeMap<String, Vec<Candidate>> =
+                serde_json::from_str(content).map_err(|e| error(e.line(), e))?;
+            object
+                .into_iter()
+                .map(|(reading, candidates)| Record {
+                    reading,
+                    candidates,
+                })
+                .collect()
+        }
+        Format::Lines => content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| serde_json::from_str(line).map_err(|e| error(i + 1, e)))
+            .collect::<std::result::Result<_, _>>()?,
+    };
+
+    let mut entries: Vec<(String, Vec<AnnotatedCandidate>)> = Vec::new();
+    let mut index = HashMap::new();
+    for record in records {
+        let candidates = record
+            .candidates
+            .into_iter()
+            .map(AnnotatedCandidate::from)
+            .filter(|(text, _)| !text.is_empty());
+        let at = *index.entry(record.reading.clone()).or_insert_with(|| {
+            entries.push((record.reading, Vec::new()));
+            entries.len() - 1
+        });
+        let existing = &mut entries[at].1;
+        for candidate in candidates {
+            if !existing.iter().any(|(text, _)| *text == candidate.0) {
+                existing.push(candidate);
+            }
+        }
+    }
+    entries.retain(|(reading, candidates)| !reading.is_empty() && !candidates.is_empty());
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let skk = ";; okuri-nasi entries.\nきょう /今日/\n";
+        assert_eq!(detect(Path::new("SKK-JISYO.L"), skk), None);
+        assert_eq!(detect(Path::new("words.json"), "{}"), Some(Format::Object));
+        assert_eq!(detect(Path::new("words.jsonl"), ""), Some(Format::Lines));
+        assert_eq!(
+            detect(Path::new("words"), r#" {"きょう": ["今日"]}"#),
+            Some(Format::Object)
+        );
+        let lines = "{\"reading\": \"きょう\", \"candidates\": [\"今日\"]}\n";
+        assert_eq!(detect(Path::new("words"), lines), Some(Format::Lines));
+    }
+
+    #[test]
+    fn test_parse_object() {
+        let content = r#"{"きょう": ["今日", {"text": "京", "annotation": "みやこ"}], "なし": []}"#;
+        let entries = parse(Path::new("words.json"), Format::Object, content).unwrap();
+        assert_eq!(
+            entries,
+            vec![(
+                "きょう".to_string(),
+                vec![
+                    ("今日".to_string(), None),
+                    ("京".to_string(), Some("みやこ".to_string()))
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_lines_appends_repeated_readings() {
+        let content = concat!(
+            "{\"reading\": \"きょう\", \"candidates\": [\"今日\"]}\n",
+            "\n",
+            "{\"reading\": \"きょう\", \"candidates\": [\"京\", \"今日\"]}\n",
+        );
+        let entries = parse(Path::new("words.jsonl"), Format::Lines, content).unwrap();
+        let texts: Vec<_> = entries[0].1.iter().map(|(text, _)| text.as_str()).collect();
+        assert_eq!(texts, ["今日", "京"]);
+    }
+
+    #[test]
+    fn test_parse_errors_name_the_line() {
+        let content = "{\"reading\": \"きょう\", \"candidates\": [\"今日\"]}\n{\"reading\": 1}\n";
+        match parse(Path::new("words.jsonl"), Format::Lines, content) {
+            Err(crate::error::AzukiError::Dictionary(DictionaryError::Parse { line, .. })) => {
+                assert_eq!(line, 2)
+            }
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+    }
+}
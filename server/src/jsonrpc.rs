@@ -0,0 +1,263 @@
+//! JSON-RPC 2.0 dialect (`--jsonrpc`)
+//!
+//! Requests are translated to the native messages: `method` becomes the
+//! `type`, the `params` object the other fields, and each request gets a
+//! `seq` of its own. Responses go back as `result` or `error` with the `id`
+//! of their request; requests without an `id` are handled but not answered.
+//! Server notifications keep their `type` as `method`. `$/cancelRequest`
+//! cancels a request by `id`. Batches (arrays) aren't supported; the
+//! `batch` method takes native requests instead.
+
+use crate::message::{Notification, Request, Response};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Errors reported by the handler; `data.code` has the native error code
+const SERVER_ERROR: i64 = -32000;
+
+/// Method cancelling an earlier request by `id`
+const CANCEL_METHOD: &str = "$/cancelRequest";
+
+/// JSON-RPC state of one connection
+#[derive(Debug)]
+pub struct JsonRpc {
+    next_seq: AtomicU64,
+    /// `id` of each request awaiting its response, by `seq`
+    ids: Mutex<HashMap<u64, Value>>,
+}
+
+impl JsonRpc {
+    pub fn new() -> Self {
+        Self {
+            next_seq: AtomicU64::new(1),
+            ids: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Translate a JSON-RPC message to a request
+    ///
+    /// A message that isn't a valid request yields the error response to
+    /// send instead.
+    pub fn decode(&self, msg: &str) -> Result<Request, String> {
+        let message: Value = serde_json::from_str(msg)
+            .map_err(|e| error(&Value::Null, PARSE_ERROR, format!("Parse error: {}", e)))?;
+        let Value::Object(mut message) = message else {
+            return Err(error(
+                &Value::Null,
+                INVALID_REQUEST,
+                "Expected a request object; batches are not supported".to_string(),
+            ));
+        };
+        let id = message.remove("id");
+        let reply_id = id.clone().unwrap_or(Value::Null);
+        if message.get("jsonrpc") != Some(&json!("2.0")) {
+            return Err(error(
+                &reply_id,
+                INVALID_REQUEST,
+                "jsonrpc must be \"2.0\"".to_string(),
+            ));
+        }
+        let Some(Value::String(method)) = message.remove("method") else {
+            return Err(error(
+                &reply_id,
+                INVALID_REQUEST,
+                "method must be a string".to_string(),
+            ));
+        };
+        let mut params = match message.remove("params") {
+            None => Map::new(),
+            Some(Value::Object(params)) => params,
+            Some(_) => {
+                return Err(error(
+                    &reply_id,
+                    INVALID_PARAMS,
+                    "params must be an object".to_string(),
+                ))
+            }
+        };
+
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let kind = match method.as_str() {
+            CANCEL_METHOD => {
+                let target = params.remove("id").unwrap_or(Value::Null);
+                params.insert("target_seq".to_string(), json!(self.seq_of(&target)));
+                "cancel"
+            }
+            method => method,
+        };
+        params.insert("type".to_string(), json!(kind));
+        params.insert("seq".to_string(), json!(seq));
+        let request = serde_json::from_value::<Request>(Value::Object(params)).map_err(|e| {
+            // Unknown `type` values are the only unknown variants at the top
+            if e.to_string().starts_with("unknown variant") {
+                error(
+                    &reply_id,
+                    METHOD_NOT_FOUND,
+                    format!("Method not found: {}", method),
+                )
+            } else {
+                error(&reply_id, INVALID_PARAMS, format!("Invalid params: {}", e))
+            }
+        })?;
+        if let Some(id) = id {
+            self.ids().insert(seq, id);
+        }
+        Ok(request)
+    }
+
+    /// Translate a response, or None if its request had no `id`
+    pub fn encode(&self, response: &Response) -> Option<String> {
+        let Value::Object(mut result) = serde_json::to_value(response).ok()? else {
+            return None;
+        };
+        let seq = result.remove("seq")?.as_u64()?;
+        let id = self.ids().remove(&seq)?;
+        if result.remove("type") != Some(json!("error")) {
+            return Some(json!({"jsonrpc": "2.0", "id": id, "result": result}).to_string());
+        }
+        let message = result.remove("error").unwrap_or(Value::Null);
+        let message = message.as_str().unwrap_or_default().to_string();
+        Some(
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": SERVER_ERROR, "message": message, "data": result},
+            })
+            .to_string(),
+        )
+    }
+
+    /// `seq` of the request with `id`, or 0, which no request has
+    fn seq_of(&self, id: &Value) -> u64 {
+        self.ids()
+            .iter()
+            .find(|(_, pending)| *pending == id)
+            .map_or(0, |(seq, _)| *seq)
+    }
+
+    fn ids(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Value>> {
+        self.ids.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Translate a server notification
+pub fn notification(notification: &Notification) -> String {
+    let mut params = match serde_json::to_value(notification) {
+        Ok(Value::Object(params)) => params,
+        _ => Map::new(),
+    };
+    let method = params.remove("type").unwrap_or(Value::Null);
+    json!({"jsonrpc": "2.0", "method": method, "params": params}).to_string()
+}
+
+fn error(id: &Value, code: i64, message: String) -> String {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}}).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(message: &str) -> Value {
+        serde_json::from_str(message).unwrap()
+    }
+
+    #[test]
+    fn test_request_and_response() {
+        let rpc = JsonRpc::new();
+        let request = rpc
+            .decode(r#"{"jsonrpc":"2.0","id":"a","method":"ping"}"#)
+            .unwrap();
+        let Request::Ping { seq } = request else {
+            panic!("Expected Ping");
+        };
+        let response = Response::CancelResult {
+            seq,
+            target_seq: 0,
+            cancelled: false,
+        };
+        let reply = parse(&rpc.encode(&response).unwrap());
+        assert_eq!(reply["id"], "a");
+        assert_eq!(
+            reply["result"],
+            json!({"target_seq": 0, "cancelled": false})
+        );
+        // Answered once
+        assert!(rpc.encode(&response).is_none());
+    }
+
+    #[test]
+    fn test_notifications_are_not_answered() {
+        let rpc = JsonRpc::new();
+        let request = rpc.decode(r#"{"jsonrpc":"2.0","method":"ping"}"#).unwrap();
+        let response = Response::ShutdownResult {
+            seq: request.seq(),
+            flushed: true,
+            errors: Vec::new(),
+        };
+        assert!(rpc.encode(&response).is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let rpc = JsonRpc::new();
+        let code =
+            |message: &str| parse(&rpc.decode(message).unwrap_err())["error"]["code"].clone();
+        assert_eq!(code("{"), PARSE_ERROR);
+        assert_eq!(
+            code(r#"[{"jsonrpc":"2.0","id":1,"method":"ping"}]"#),
+            INVALID_REQUEST
+        );
+        assert_eq!(code(r#"{"id":1,"method":"ping"}"#), INVALID_REQUEST);
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","id":1,"method":"fly"}"#),
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","id":1,"method":"convert","params":{}}"#),
+            INVALID_PARAMS
+        );
+
+        let request = rpc
+            .decode(r#"{"jsonrpc":"2.0","id":7,"method":"ping"}"#)
+            .unwrap();
+        let response = Response::error(request.seq(), None, crate::error::AzukiError::Cancelled);
+        let reply = parse(&rpc.encode(&response).unwrap());
+        assert_eq!(reply["id"], 7);
+        assert_eq!(reply["error"]["code"], SERVER_ERROR);
+        assert_eq!(reply["error"]["data"]["code"], "cancelled");
+    }
+
+    #[test]
+    fn test_cancel_by_id() {
+        let rpc = JsonRpc::new();
+        let convert = rpc
+            .decode(r#"{"jsonrpc":"2.0","id":"c","method":"convert","params":{"session_id":"s","reading":"かな"}}"#)
+            .unwrap();
+        let cancel = rpc
+            .decode(r#"{"jsonrpc":"2.0","method":"$/cancelRequest","params":{"id":"c"}}"#)
+            .unwrap();
+        match cancel {
+            Request::Cancel { target_seq, .. } => assert_eq!(target_seq, convert.seq()),
+            _ => panic!("Expected Cancel"),
+        }
+    }
+
+    #[test]
+    fn test_notification() {
+        let message = parse(&notification(&Notification::DictionaryReady {
+            has_dictionary: true,
+            entries: 3,
+            elapsed_ms: 5,
+        }));
+        assert_eq!(message["method"], "dictionary_ready");
+        assert_eq!(message["params"]["entries"], 3);
+        assert!(message.get("id").is_none());
+    }
+}
@@ -0,0 +1,264 @@
+//! Kana character-class transforms (hiragana, katakana, half/full width)
+
+/// Offset between a hiragana code point and its katakana counterpart
+const KATAKANA_OFFSET: u32 = 0x60;
+
+/// Half-width katakana (U+FF66..U+FF9D) mapped to full-width katakana
+const HALFWIDTH_KATAKANA: [char; 56] = [
+    'ヲ', 'ァ', 'ィ', 'ゥ', 'ェ', 'ォ', 'ャ', 'ュ', 'ョ', 'ッ', 'ー', 'ア', 'イ', 'ウ', 'エ', 'オ',
+    'カ', 'キ', 'ク', 'ケ', 'コ', 'サ', 'シ', 'ス', 'セ', 'ソ', 'タ', 'チ', 'ツ', 'テ', 'ト', 'ナ',
+    'ニ', 'ヌ', 'ネ', 'ノ', 'ハ', 'ヒ', 'フ', 'ヘ', 'ホ', 'マ', 'ミ', 'ム', 'メ', 'モ', 'ヤ', 'ユ',
+    'ヨ', 'ラ', 'リ', 'ル', 'レ', 'ロ', 'ワ', 'ン',
+];
+
+/// Convert katakana to hiragana
+///
+/// Half-width katakana is widened first, so pasted text such as "ｱｽﾞｷ"
+/// normalizes to "あずき". Characters without a hiragana form (e.g. "ー")
+/// are kept as-is.
+pub fn katakana_to_hiragana(text: &str) -> String {
+    widen(text, false).chars().map(hiragana_char).collect()
+}
+
+/// Hiragana of a full-width katakana, other characters unchanged
+pub fn hiragana_char(c: char) -> char {
+    match c {
+        'ァ'..='ヶ' | 'ヽ' | 'ヾ' => char::from_u32(c as u32 - KATAKANA_OFFSET).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// Convert hiragana to katakana
+pub fn hiragana_to_katakana(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            'ぁ'..='ゖ' | 'ゝ' | 'ゞ' => {
+                char::from_u32(c as u32 + KATAKANA_OFFSET).unwrap_or(c)
+            }
+            _ => c,
+        })
+        .collect()
+}
+
+/// Convert half-width characters to their full-width forms
+///
+/// Covers printable ASCII, the ASCII space, half-width katakana (with
+/// voiced/semi-voiced sound marks composed into a single character) and
+/// half-width CJK punctuation.
+pub fn halfwidth_to_fullwidth(text: &str) -> String {
+    widen(text, true)
+}
+
+/// Convert hiragana and katakana to half-width katakana
+///
+/// Voiced and semi-voiced kana become two characters ("ガ" -> "ｶﾞ").
+/// Kana without a half-width form (e.g. "ヶ") and other characters are
+/// kept as-is.
+pub fn to_halfwidth_katakana(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in hiragana_to_katakana(text).chars() {
+        match c {
+            '。' => result.push('｡'),
+            '「' => result.push('｢'),
+            '」' => result.push('｣'),
+            '、' => result.push('､'),
+            '・' => result.push('･'),
+            '゛' => result.push('ﾞ'),
+            '゜' => result.push('ﾟ'),
+            _ => {
+                if let Some(narrow) = narrow_katakana(c) {
+                    result.push(narrow);
+                } else if let Some(base) = HALFWIDTH_KATAKANA
+                    .iter()
+                    .find(|&&base| compose_voiced(base) == Some(c))
+                {
+                    result.extend(narrow_katakana(*base));
+                    result.push('ﾞ');
+                } else if let Some(base) = HALFWIDTH_KATAKANA
+                    .iter()
+                    .find(|&&base| compose_semi_voiced(base) == Some(c))
+                {
+                    result.extend(narrow_katakana(*base));
+                    result.push('ﾟ');
+                } else {
+                    result.push(c);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Hiragana by the vowel they end in, in the order a, i, u, e, o
+const VOWEL_ROWS: [&str; 5] = [
+    "あかさたなはまやらわがざだばぱぁゃゎ",
+    "いきしちにひみりぎじぢびぴぃ",
+    "うくすつぬふむゆるぐずづぶぷゔぅゅ",
+    "えけせてねへめれげぜでべぺぇ",
+    "おこそとのほもよろをごぞどぼぽぉょ",
+];
+
+/// Kana that lengthen a vowel when written after it, by vowel
+const VOWEL_EXTENDERS: [&str; 5] = ["あ", "い", "う", "いえ", "うお"];
+
+/// Reading with spelling variants folded together, for fuzzy lookup
+///
+/// Lengthened vowels become "ー" ("とうきょう", "とーきょー" and
+/// "とおきょお" -> "とーきょー"), and "づ"/"ぢ" become "ず"/"じ". Two
+/// readings that fold to the same form are taken to be the same word.
+pub fn fuzzy_reading(reading: &str) -> String {
+    let mut result = String::with_capacity(reading.len());
+    let mut vowel: Option<usize> = None;
+    for c in reading.chars() {
+        if let Some(v) = vowel {
+            if c == 'ー' || VOWEL_EXTENDERS[v].contains(c) {
+                result.push('ー');
+                continue;
+            }
+        }
+        vowel = VOWEL_ROWS.iter().position(|row| row.contains(c));
+        result.push(match c {
+            'づ' => 'ず',
+            'ぢ' => 'じ',
+            _ => c,
+        });
+    }
+    result
+}
+
+/// Half-width form of a full-width katakana without sound marks
+fn narrow_katakana(c: char) -> Option<char> {
+    let index = HALFWIDTH_KATAKANA.iter().position(|&k| k == c)?;
+    char::from_u32(0xFF66 + index as u32)
+}
+
+/// Widen half-width kana and punctuation, and optionally ASCII
+fn widen(text: &str, include_ascii: bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' if include_ascii => result.push('\u{3000}'),
+            '!'..='~' if include_ascii => {
+                result.push(char::from_u32(c as u32 - 0x21 + 0xFF01).unwrap_or(c))
+            }
+            '｡' => result.push('。'),
+            '｢' => result.push('「'),
+            '｣' => result.push('」'),
+            '､' => result.push('、'),
+            '･' => result.push('・'),
+            'ｦ'..='ﾝ' => {
+                let base = HALFWIDTH_KATAKANA[(c as u32 - 0xFF66) as usize];
+                match chars.peek() {
+                    Some('ﾞ') => {
+                        if let Some(voiced) = compose_voiced(base) {
+                            result.push(voiced);
+                            chars.next();
+                            continue;
+                        }
+                        result.push(base);
+                    }
+                    Some('ﾟ') => {
+                        if let Some(semi_voiced) = compose_semi_voiced(base) {
+                            result.push(semi_voiced);
+                            chars.next();
+                            continue;
+                        }
+                        result.push(base);
+                    }
+                    _ => result.push(base),
+                }
+            }
+            'ﾞ' => result.push('゛'),
+            'ﾟ' => result.push('゜'),
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// Compose a katakana with the voiced sound mark (e.g. カ -> ガ)
+fn compose_voiced(c: char) -> Option<char> {
+    match c {
+        'ウ' => Some('ヴ'),
+        'カ' | 'キ' | 'ク' | 'ケ' | 'コ' | 'サ' | 'シ' | 'ス' | 'セ' | 'ソ' | 'タ' | 'チ'
+        | 'ツ' | 'テ' | 'ト' | 'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' => {
+            char::from_u32(c as u32 + 1)
+        }
+        'ワ' => Some('ヷ'),
+        'ヲ' => Some('ヺ'),
+        _ => None,
+    }
+}
+
+/// Compose a katakana with the semi-voiced sound mark (e.g. ハ -> パ)
+fn compose_semi_voiced(c: char) -> Option<char> {
+    match c {
+        'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' => char::from_u32(c as u32 + 2),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_katakana_to_hiragana() {
+        assert_eq!(katakana_to_hiragana("アズキ"), "あずき");
+        assert_eq!(katakana_to_hiragana("コーヒー"), "こーひー");
+        assert_eq!(katakana_to_hiragana("漢字とカナ"), "漢字とかな");
+    }
+
+    #[test]
+    fn test_katakana_to_hiragana_halfwidth() {
+        assert_eq!(katakana_to_hiragana("ｱｽﾞｷ"), "あずき");
+        assert_eq!(katakana_to_hiragana("ﾊﾟﾝ"), "ぱん");
+        // ASCII is left alone
+        assert_eq!(katakana_to_hiragana("Gitﾌﾟｯｼｭ"), "Gitぷっしゅ");
+    }
+
+    #[test]
+    fn test_hiragana_to_katakana() {
+        assert_eq!(hiragana_to_katakana("あずき"), "アズキ");
+        assert_eq!(hiragana_to_katakana("ゔぁ"), "ヴァ");
+    }
+
+    #[test]
+    fn test_halfwidth_to_fullwidth() {
+        assert_eq!(halfwidth_to_fullwidth("abc 123"), "ａｂｃ\u{3000}１２３");
+        assert_eq!(halfwidth_to_fullwidth("ｶﾞｯｺｳ｡"), "ガッコウ。");
+        // Sound mark that cannot compose is kept as a standalone mark
+        assert_eq!(halfwidth_to_fullwidth("ｱﾞ"), "ア゛");
+    }
+
+    #[test]
+    fn test_fuzzy_reading() {
+        for variant in ["とうきょう", "とーきょー", "とおきょお"] {
+            assert_eq!(fuzzy_reading(variant), "とーきょー");
+        }
+        assert_eq!(fuzzy_reading("おおきい"), fuzzy_reading("おうきい"));
+        assert_eq!(fuzzy_reading("せんせい"), "せんせー");
+        assert_eq!(fuzzy_reading("つづく"), fuzzy_reading("つずく"));
+        assert_eq!(fuzzy_reading("ちぢむ"), "ちじむ");
+        // Vowels that don't lengthen the one before them are kept
+        assert_eq!(fuzzy_reading("かう"), "かう");
+        assert_eq!(fuzzy_reading("いう"), "いう");
+        assert_eq!(fuzzy_reading("んー"), "んー");
+    }
+
+    #[test]
+    fn test_to_halfwidth_katakana() {
+        assert_eq!(to_halfwidth_katakana("あずき"), "ｱｽﾞｷ");
+        assert_eq!(to_halfwidth_katakana("ぱーてぃー。"), "ﾊﾟｰﾃｨｰ｡");
+        assert_eq!(to_halfwidth_katakana("ヴァイオリン"), "ｳﾞｧｲｵﾘﾝ");
+        // No half-width form
+        assert_eq!(to_halfwidth_katakana("ヶ月abc"), "ヶ月abc");
+        assert_eq!(
+            halfwidth_to_fullwidth(&to_halfwidth_katakana("がっこう")),
+            "ガッコウ"
+        );
+    }
+}
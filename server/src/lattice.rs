@@ -0,0 +1,302 @@
+//! Lattice segmentation of convertible runs
+//!
+//! Every dictionary match and every particle in a run becomes a node of a
+//! lattice; characters no node covers become single-character unknown
+//! nodes. The cheapest path through the lattice (Viterbi) is the
+//! segmentation. Costs are hand-tuned rather than trained: long words are
+//! cheaper per character than short ones, and two content words in a row
+//! cost more than a content word followed by a particle, so
+//! "きょうはいしゃにいく" splits as きょう|は|いしゃ|に|… rather than
+//! きょう|はいしゃ|に|…. Words imported with a cost (Mozc dictionaries)
+//! use that cost instead of the length-based one.
+
+/// Readings treated as particles (function words between content words)
+const PARTICLES: &[&str] = &[
+    "は", "が", "を", "に", "で", "と", "も", "の", "へ", "や", "から", "まで", "より",
+];
+
+/// Fixed part of a dictionary word's cost
+const WORD_COST: u32 = 20;
+/// Extra word cost divided by the word's length, penalizing short words
+const SHORT_WORD_COST: u32 = 40;
+const PARTICLE_COST: u32 = 10;
+/// Mozc cost units per unit of word cost here; Mozc costs of common words
+/// (3000-9000) then fall around the hand-tuned costs of 2-4 character words
+const MOZC_COST_SCALE: i32 = 150;
+/// Cost of leaving one character unconverted
+const UNKNOWN_COST: u32 = 70;
+
+/// Part-of-speech class of a lattice node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeClass {
+    /// Dictionary word
+    Content,
+    /// Particle from [`PARTICLES`]
+    Particle,
+    /// Character without any dictionary match
+    Unknown,
+}
+
+impl NodeClass {
+    fn cost(self, length: usize) -> u32 {
+        match self {
+            NodeClass::Content => WORD_COST + SHORT_WORD_COST / length as u32,
+            NodeClass::Particle => PARTICLE_COST,
+            NodeClass::Unknown => UNKNOWN_COST,
+        }
+    }
+}
+
+/// Word cost of a word imported with a Mozc cost
+pub fn mozc_word_cost(cost: i32) -> u32 {
+    (cost / MOZC_COST_SCALE).max(0) as u32
+}
+
+/// Cost of following a node of class `prev` (None = start) with `next`
+fn connection_cost(prev: Option<NodeClass>, next: NodeClass) -> u32 {
+    use NodeClass::*;
+    match (prev, next) {
+        // Sentences rarely start with a particle
+        (None, Particle) => 20,
+        (None, _) => 0,
+        (Some(Content), Content) => 30,
+        (Some(Particle), Particle) => 15,
+        (Some(Unknown), _) | (Some(_), Unknown) => 10,
+        (Some(Content), Particle) | (Some(Particle), Content) => 0,
+    }
+}
+
+/// A segment on the best path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node {
+    pub start: usize,
+    pub length: usize,
+    pub class: NodeClass,
+    /// Cost of the node itself, without its connection
+    pub cost: u32,
+}
+
+/// Check whether a reading is a particle
+pub fn is_particle(reading: &str) -> bool {
+    PARTICLES.contains(&reading)
+}
+
+/// A segmentation and its total cost
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    pub cost: u32,
+    pub nodes: Vec<Node>,
+}
+
+/// Lattice entry: a node reached through one of the entries at its start
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    cost: u32,
+    node: Node,
+    /// Index of the previous entry among those ending at `node.start`
+    prev: usize,
+}
+
+/// Find up to `limit` cheapest segmentations of `chars`, cheapest first
+///
+/// `is_word` reports whether a reading has dictionary candidates, and
+/// `word_cost` the cost imported with a word, if any. Each
+/// position keeps its `limit` cheapest entries, so the result is the
+/// exact k-best list. Adjacent unknown characters are returned as one
+/// node. An empty input has one empty segmentation.
+pub fn best_paths(
+    chars: &[char],
+    is_word: impl Fn(&str) -> bool,
+    word_cost: impl Fn(&str) -> Option<u32>,
+    limit: usize,
+) -> Vec<Path> {
+    let n = chars.len();
+    let limit = limit.max(1);
+    if n == 0 {
+        return vec![Path {
+            cost: 0,
+            nodes: Vec::new(),
+        }];
+    }
+    let mut table: Vec<Vec<Entry>> = vec![Vec::new(); n + 1];
+
+    for start in 0..n {
+        let prefixes: Vec<(u32, Option<NodeClass>)> = match start {
+            0 => vec![(0, None)],
+            _ => table[start]
+                .iter()
+                .map(|e| (e.cost, Some(e.node.class)))
+                .collect(),
+        };
+        if prefixes.is_empty() {
+            continue;
+        }
+
+        for node in nodes_at(chars, start, &is_word, &word_cost) {
+            let end = node.start + node.length;
+            for (prev, &(prefix_cost, prev_class)) in prefixes.iter().enumerate() {
+                let cost = prefix_cost + connection_cost(prev_class, node.class) + node.cost;
+                let entries = &mut table[end];
+                let at = entries.partition_point(|e| e.cost <= cost);
+                if at < limit {
+                    entries.insert(at, Entry { cost, node, prev });
+                    entries.truncate(limit);
+                }
+            }
+        }
+    }
+
+    table[n]
+        .iter()
+        .map(|last| {
+            let mut nodes = vec![last.node];
+            let mut entry = *last;
+            while entry.node.start > 0 {
+                entry = table[entry.node.start][entry.prev];
+                nodes.push(entry.node);
+            }
+            nodes.reverse();
+            Path {
+                cost: last.cost,
+                nodes: merge_unknown(nodes),
+            }
+        })
+        .collect()
+}
+
+/// Merge runs of unknown characters into one node, so unmatched text is
+/// a single pass-through segment rather than one per character
+fn merge_unknown(nodes: Vec<Node>) -> Vec<Node> {
+    let mut merged: Vec<Node> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        match merged.last_mut() {
+            Some(last) if last.class == NodeClass::Unknown && node.class == NodeClass::Unknown => {
+                last.length += node.length;
+            }
+            _ => merged.push(node),
+        }
+    }
+    merged
+}
+
+/// Nodes starting at `start`
+///
+/// A single-character unknown node is added when no other node covers
+/// just that character, so every position stays reachable.
+fn nodes_at(
+    chars: &[char],
+    start: usize,
+    is_word: &impl Fn(&str) -> bool,
+    word_cost: &impl Fn(&str) -> Option<u32>,
+) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut covered = false;
+    for end in start + 1..=chars.len() {
+        let reading: String = chars[start..end].iter().collect();
+        let length = end - start;
+        let (class, cost) = if is_particle(&reading) {
+            (NodeClass::Particle, NodeClass::Particle.cost(length))
+        } else if is_word(&reading) {
+            let cost = word_cost(&reading).unwrap_or_else(|| NodeClass::Content.cost(length));
+            (NodeClass::Content, cost)
+        } else {
+            continue;
+        };
+        covered |= end == start + 1;
+        nodes.push(Node {
+            start,
+            length,
+            class,
+            cost,
+        });
+    }
+    if !covered {
+        nodes.push(Node {
+            start,
+            length: 1,
+            class: NodeClass::Unknown,
+            cost: NodeClass::Unknown.cost(1),
+        });
+    }
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(reading: &str, words: &[&str]) -> Vec<String> {
+        let chars: Vec<char> = reading.chars().collect();
+        best_paths(&chars, |r| words.contains(&r), |_| None, 1)
+            .swap_remove(0)
+            .nodes
+            .into_iter()
+            .map(|node| chars[node.start..node.start + node.length].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_particles_split_content_words() {
+        let words = ["きょう", "はいしゃ", "いしゃ", "いく"];
+        assert_eq!(
+            segment("きょうはいしゃにいく", &words),
+            vec!["きょう", "は", "いしゃ", "に", "いく"]
+        );
+    }
+
+    #[test]
+    fn test_prefers_long_words() {
+        let words = ["へんかん", "にゅうりょく", "へん", "かん"];
+        assert_eq!(
+            segment("へんかんにゅうりょく", &words),
+            vec!["へんかん", "にゅうりょく"]
+        );
+        // The particle に doesn't break a word that contains it
+        assert_eq!(segment("にほんご", &["にほんご"]), vec!["にほんご"]);
+    }
+
+    #[test]
+    fn test_best_paths() {
+        let chars: Vec<char> = "きょうはいしゃに".chars().collect();
+        let words = ["きょう", "はいしゃ", "いしゃ"];
+        let paths = best_paths(&chars, |r| words.contains(&r), |_| None, 3);
+        assert_eq!(paths.len(), 3);
+        assert!(paths.windows(2).all(|w| w[0].cost <= w[1].cost));
+        // The runner-up uses はいしゃ
+        let lengths: Vec<usize> = paths[1].nodes.iter().map(|n| n.length).collect();
+        assert_eq!(lengths, vec![3, 4, 1]);
+    }
+
+    #[test]
+    fn test_imported_costs_override_length_costs() {
+        let chars: Vec<char> = "へんかん".chars().collect();
+        let words = ["へんかん", "へん", "かん"];
+        let lengths = |cost: fn(&str) -> Option<u32>| -> Vec<usize> {
+            best_paths(&chars, |r| words.contains(&r), cost, 1)[0]
+                .nodes
+                .iter()
+                .map(|n| n.length)
+                .collect()
+        };
+        assert_eq!(lengths(|_| None), vec![4]);
+        // A rare long word loses to two common short ones
+        assert_eq!(
+            lengths(|r| Some(mozc_word_cost(if r == "へんかん" {
+                12000
+            } else {
+                1500
+            }))),
+            vec![2, 2]
+        );
+    }
+
+    #[test]
+    fn test_unknown_characters() {
+        assert_eq!(segment("あいう", &[]), vec!["あいう"]);
+        assert_eq!(
+            segment("あいきょうえお", &["きょう"]),
+            vec!["あい", "きょう", "えお"]
+        );
+        assert!(segment("", &[]).is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+//! Candidate learning from committed conversions
+//!
+//! Every commit records how often a candidate was chosen for a reading.
+//! Subsequent conversions move frequently chosen candidates to the front.
+//!
+//! The counts are persisted as JSON (`{"version":1,"counts":{...}}`) so
+//! learning survives restarts. The server saves every [`SAVE_INTERVAL`]
+//! commits and once more on shutdown.
+
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Version of the persisted format
+const FORMAT_VERSION: u32 = 1;
+
+/// Commits between periodic saves
+pub const SAVE_INTERVAL: u64 = 20;
+
+/// On-disk representation
+#[derive(Serialize, Deserialize)]
+struct LearningFile {
+    version: u32,
+    counts: HashMap<String, HashMap<String, u64>>,
+}
+
+/// Reading → candidate selection counts
+#[derive(Debug, Default)]
+pub struct LearningStore {
+    counts: HashMap<String, HashMap<String, u64>>,
+    /// File the store is persisted to, if any
+    path: Option<PathBuf>,
+    /// Commits recorded since the last save
+    unsaved: u64,
+}
+
+impl LearningStore {
+    /// Create an empty in-memory store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the store from `path`, persisting later changes there
+    ///
+    /// A missing file yields an empty store.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut store = Self {
+            path: Some(path.to_path_buf()),
+            ..Self::default()
+        };
+        if path.exists() {
+            let content = fs::read_to_string(path)?;
+            let file: LearningFile = serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if file.version != FORMAT_VERSION {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported learning data version {}", file.version),
+                )
+                .into());
+            }
+            store.counts = file.counts;
+        }
+        Ok(store)
+    }
+
+    /// Record that `candidate` was committed for `reading`
+    pub fn record(&mut self, reading: &str, candidate: &str) {
+        *self
+            .counts
+            .entry(reading.to_string())
+            .or_default()
+            .entry(candidate.to_string())
+            .or_default() += 1;
+        self.unsaved += 1;
+    }
+
+    /// Take back one commit of `candidate` for `reading`
+    ///
+    /// Returns false if there was none to take back.
+    pub fn forget(&mut self, reading: &str, candidate: &str) -> bool {
+        let Some(counts) = self.counts.get_mut(reading) else {
+            return false;
+        };
+        let Some(count) = counts.get_mut(candidate) else {
+            return false;
+        };
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(candidate);
+            if counts.is_empty() {
+                self.counts.remove(reading);
+            }
+        }
+        self.unsaved += 1;
+        true
+    }
+
+    /// Smoothed share of commits of `reading` that chose `candidate`
+    ///
+    /// `alternatives` is the number of candidates on offer. Returns None if
+    /// nothing was learned for the reading.
+    pub fn selection_share(
+        &self,
+        reading: &str,
+        candidate: &str,
+        alternatives: usize,
+    ) -> Option<f64> {
+        let counts = self.counts.get(reading)?;
+        let total: u64 = counts.values().sum();
+        let chosen = counts.get(candidate).copied().unwrap_or(0);
+        Some((chosen + 1) as f64 / (total + alternatives.max(1) as u64) as f64)
+    }
+
+    /// Learned readings starting with `prefix`, with their commit counts
+    pub fn readings_with_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (&'a str, u64)> + 'a {
+        self.counts
+            .iter()
+            .filter(move |(reading, _)| reading.starts_with(prefix))
+            .map(|(reading, counts)| (reading.as_str(), counts.values().sum()))
+    }
+
+    /// Move frequently chosen candidates to the front
+    ///
+    /// The sort is stable, so candidates that were never chosen (and ties)
+    /// keep their original order.
+    pub fn reorder(&self, reading: &str, candidates: &mut [String]) {
+        let Some(counts) = self.counts.get(reading) else {
+            return;
+        };
+        candidates.sort_by_key(|c| std::cmp::Reverse(counts.get(c).copied().unwrap_or(0)));
+    }
+
+    /// Check whether nothing has been learned
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Number of readings with learned selections
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Check whether enough commits accumulated for a periodic save
+    pub fn save_due(&self) -> bool {
+        self.unsaved >= SAVE_INTERVAL
+    }
+
+    /// Write the store to its file if it changed since the last save
+    ///
+    /// The file is replaced atomically so a crash can't truncate it.
+    pub fn save(&mut self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if self.unsaved == 0 {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = LearningFile {
+            version: FORMAT_VERSION,
+            counts: self.counts.clone(),
+        };
+        let json = serde_json::to_string(&file).map_err(io::Error::from)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, path)?;
+        self.unsaved = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_reorder_by_frequency() {
+        let mut store = LearningStore::new();
+        store.record("きょう", "京");
+        store.record("きょう", "教");
+        store.record("きょう", "京");
+
+        let mut candidates = strings(&["今日", "教", "京", "きょう"]);
+        store.reorder("きょう", &mut candidates);
+        assert_eq!(candidates, strings(&["京", "教", "今日", "きょう"]));
+    }
+
+    #[test]
+    fn test_forget() {
+        let mut store = LearningStore::new();
+        store.record("きょう", "京");
+        store.record("きょう", "京");
+        store.record("きょう", "今日");
+        assert!(store.forget("きょう", "京"));
+        assert!(store.forget("きょう", "京"));
+        assert!(!store.forget("きょう", "京"));
+        let mut candidates = strings(&["京", "今日"]);
+        store.reorder("きょう", &mut candidates);
+        assert_eq!(candidates, strings(&["今日", "京"]));
+
+        assert!(store.forget("きょう", "今日"));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_selection_share() {
+        let mut store = LearningStore::new();
+        assert_eq!(store.selection_share("きょう", "今日", 3), None);
+        store.record("きょう", "今日");
+        store.record("きょう", "今日");
+        store.record("きょう", "京");
+        assert_eq!(store.selection_share("きょう", "今日", 3), Some(0.5));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!(
+            "azuki-learning-{}/learning.json",
+            std::process::id()
+        ));
+        let mut store = LearningStore::load(&path).unwrap();
+        assert!(store.is_empty());
+        store.record("きょう", "京");
+        store.record("きょう", "京");
+        assert!(!store.save_due());
+        store.save().unwrap();
+
+        let loaded = LearningStore::load(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        let mut candidates = strings(&["今日", "京"]);
+        loaded.reorder("きょう", &mut candidates);
+        assert_eq!(candidates, strings(&["京", "今日"]));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_save_due_after_interval() {
+        let mut store = LearningStore::new();
+        for _ in 0..SAVE_INTERVAL {
+            store.record("きょう", "今日");
+        }
+        assert!(store.save_due());
+        store.save().unwrap();
+    }
+
+    #[test]
+    fn test_reorder_unknown_reading_is_noop() {
+        let mut store = LearningStore::new();
+        store.record("きょう", "京");
+        let mut candidates = strings(&["日本語", "にほんご"]);
+        store.reorder("にほんご", &mut candidates);
+        assert_eq!(candidates, strings(&["日本語", "にほんご"]));
+    }
+}
@@ -0,0 +1,227 @@
+//! Minimal leveled logging
+//!
+//! stdout carries protocol frames, so diagnostics go to a log file
+//! (`$XDG_STATE_HOME/azuki/azuki.log`), or to stderr until one is opened.
+//! Each line has a UTC timestamp, the level and the process ID, since
+//! several servers may share the file. The level comes from `--log-level`
+//! or the config file and can be changed with `set_log_level`.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Size above which the log file is moved to `azuki.log.1` on startup
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Log verbosity, from least to most verbose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    /// Parse a level name as given on the command line
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+
+    /// Name accepted by [`LogLevel::parse`]
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+
+    /// Label written before messages of this level
+    fn label(self) -> &'static str {
+        match self {
+            LogLevel::Off => "OFF",
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => LogLevel::Off,
+            1 => LogLevel::Error,
+            2 => LogLevel::Warn,
+            3 => LogLevel::Info,
+            _ => LogLevel::Debug,
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Open log file and its path
+static FILE: Mutex<Option<(File, PathBuf)>> = Mutex::new(None);
+
+/// Set the most verbose level that is printed
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Most verbose level that is printed
+pub fn level() -> LogLevel {
+    LogLevel::from_u8(LEVEL.load(Ordering::Relaxed))
+}
+
+/// Default log file (`$XDG_STATE_HOME/azuki/azuki.log`)
+pub fn default_path() -> Option<PathBuf> {
+    crate::config::state_dir().map(|dir| dir.join("azuki.log"))
+}
+
+/// Append later messages to `path` instead of stderr
+///
+/// A file grown past [`MAX_FILE_BYTES`] is moved aside first, replacing
+/// the previous one.
+pub fn open_file(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() > MAX_FILE_BYTES) {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(".1");
+        fs::rename(path, rotated)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    *FILE.lock().unwrap_or_else(PoisonError::into_inner) = Some((file, path.to_path_buf()));
+    Ok(())
+}
+
+/// Path of the open log file, if any
+pub fn file_path() -> Option<PathBuf> {
+    FILE.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .map(|(_, path)| path.clone())
+}
+
+/// Write one message; used by the `log_*!` macros
+pub fn write(level: LogLevel, args: fmt::Arguments) {
+    let line = format!(
+        "{} {:<5} {} {}\n",
+        timestamp(SystemTime::now()),
+        level.label(),
+        std::process::id(),
+        args
+    );
+    let mut file = FILE.lock().unwrap_or_else(PoisonError::into_inner);
+    match file.as_mut() {
+        // Nowhere left to report a failed write
+        Some((file, _)) => drop(file.write_all(line.as_bytes())),
+        None => eprint!("{}", line),
+    }
+}
+
+/// RFC 3339 UTC time with milliseconds
+fn timestamp(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        elapsed.subsec_millis()
+    )
+}
+
+/// Proleptic Gregorian date of a day count since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Check whether messages at `level` are printed
+pub fn enabled(level: LogLevel) -> bool {
+    level != LogLevel::Off && level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, format_args!($($arg)*));
+        }
+    };
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => { log_at!($crate::log::LogLevel::Error, $($arg)*) };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => { log_at!($crate::log::LogLevel::Warn, $($arg)*) };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => { log_at!($crate::log::LogLevel::Info, $($arg)*) };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => { log_at!($crate::log::LogLevel::Debug, $($arg)*) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_round_trip() {
+        for level in [
+            LogLevel::Off,
+            LogLevel::Error,
+            LogLevel::Warn,
+            LogLevel::Info,
+            LogLevel::Debug,
+        ] {
+            assert_eq!(LogLevel::parse(level.name()), Some(level));
+        }
+        assert_eq!(LogLevel::parse("verbose"), None);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let time = UNIX_EPOCH + std::time::Duration::from_millis(1_709_251_199_250);
+        assert_eq!(timestamp(time), "2024-02-29T23:59:59.250Z");
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+}
@@ -1,65 +1,664 @@
 //! azuki-server: Japanese input method conversion server
 //!
-//! Communicates via stdio using length-prefixed JSON protocol.
+//! Communicates via stdio (or a Unix or TCP socket with `--listen`) using
+//! a length-prefixed JSON protocol. With `--skkserv` it also answers
+//! classic skkserv dictionary requests.
 
+#[macro_use]
+mod log;
+
+mod bench;
+mod blocklist;
+mod cancel;
+mod check;
+mod cli;
+mod compile;
+mod compress;
+mod confidence;
 mod config;
 mod converter;
+#[cfg(unix)]
+mod daemon;
+mod delta;
 mod dictionary;
+mod error;
+mod fallback;
+mod gzip;
 mod handler;
+mod json_dict;
+mod jsonrpc;
+mod kana;
+mod lattice;
+mod learning;
+mod mapped_dict;
 mod message;
+mod metrics;
+mod mozc_dict;
+mod number_reading;
+mod numeric;
 mod protocol;
+mod provider;
+mod punctuation;
+mod remote_dict;
+mod reverse_index;
+mod romaji;
+mod session;
+mod settings;
+mod sidecar;
+#[cfg(unix)]
+mod signal;
+mod skkserv;
+mod symbol;
+mod tokenizer;
+mod trie;
+mod user_dict;
+mod watch;
+mod work_queue;
 mod zenzai;
 
-use handler::Server;
-use message::{extract_seq, Request, Response};
-use protocol::{read_message, write_message};
-use std::io::{self, BufReader};
+use cancel::CancelToken;
+use cli::{Cli, Command, Listen};
+use config::ServerConfig;
+use error::AzukiError;
+use handler::{Server, SetLoad};
+use jsonrpc::JsonRpc;
+use message::{extract_seq, Notification, Request, Response};
+use protocol::{
+    Dialect, Framing, Transport, MAX_MESSAGE_SIZE, MIN_MESSAGE_SIZE, PROTOCOL_VERSIONS,
+};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Instant;
+use work_queue::WorkQueue;
+
+fn main() -> error::Result<()> {
+    let cli = Cli::parse_args(std::env::args_os()).unwrap_or_else(|e| e.exit());
+    if let Some(level) = cli.log_level {
+        log::set_level(level);
+    }
+
+    match &cli.command {
+        #[cfg(unix)]
+        Some(Command::Daemon { action }) => return daemon::run(*action, &cli),
+        Some(Command::CompileDict { inputs, output }) => {
+            if let Err(e) = compile::run(inputs, output) {
+                eprintln!("azuki-server: {}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::CheckDict { paths }) => match check::run(paths) {
+            Ok(0) => return Ok(()),
+            Ok(_) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("azuki-server: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::ZenzaiWorker) => {
+            open_log_file();
+            return sidecar::run_worker();
+        }
+        None | Some(Command::BenchZenzai) => {}
+    }
+
+    let mut config = match &cli.config {
+        Some(path) => match ServerConfig::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("azuki-server: {}", e);
+                std::process::exit(2);
+            }
+        },
+        None => ServerConfig::default(),
+    };
+    // Dictionaries on the command line replace those of the config file
+    if !cli.dictionaries.is_empty() {
+        config.dictionaries = cli.dictionaries.iter().cloned().map(Into::into).collect();
+    }
+    // So does the log level
+    if let (None, Some(level)) = (cli.log_level, config.log_level) {
+        log::set_level(level);
+    }
+    if cli.command == Some(Command::BenchZenzai) {
+        return bench::run(config);
+    }
+    open_log_file();
+
+    let max_message_size = cli
+        .max_message_size
+        .or(config.max_message_size)
+        .unwrap_or(MAX_MESSAGE_SIZE);
+    if max_message_size < MIN_MESSAGE_SIZE {
+        eprintln!(
+            "azuki-server: max message size must be at least {} bytes",
+            MIN_MESSAGE_SIZE
+        );
+        std::process::exit(2);
+    }
+    let transport = Transport {
+        framing: match cli.ndjson || cli.jsonrpc {
+            true => Framing::Ndjson,
+            false => Framing::LengthPrefixed,
+        },
+        dialect: match cli.jsonrpc {
+            true => Dialect::JsonRpc,
+            false => Dialect::Native,
+        },
+        max_message_size,
+        compression_threshold: config
+            .compression_threshold
+            .unwrap_or(compress::DEFAULT_THRESHOLD),
+    };
+
+    // Held until exit when running as the daemon
+    #[cfg(unix)]
+    let _instance = daemon::claim(cli.listen.as_ref())?;
+    // Bind before loading dictionaries so address errors surface at once
+    let listener = cli.listen.as_ref().map(bind).transpose()?;
+    let skk_listener = cli.skkserv.as_deref().map(skkserv::bind).transpose()?;
+    if listener.is_none() && skk_listener.is_none() {
+        return run_stdio(config, transport);
+    }
 
-fn main() -> io::Result<()> {
+    let reloader = watch::Reloader::new(&config);
+    let server = Arc::new(Mutex::new(Server::new(config)));
+    reloader.spawn(Arc::clone(&server));
+    #[cfg(unix)]
+    signal::flush_on_sigterm(Arc::clone(&server));
+    if let Some(skk_listener) = skk_listener {
+        match listener {
+            None => {
+                skkserv::accept_loop(skk_listener, server);
+                return Ok(());
+            }
+            Some(_) => {
+                let server = Arc::clone(&server);
+                thread::spawn(move || skkserv::accept_loop(skk_listener, server));
+            }
+        }
+    }
+    if let Some(listener) = listener {
+        match listener {
+            #[cfg(unix)]
+            Listener::Unix(listener) => accept_loop(listener.incoming(), server, transport),
+            Listener::Tcp(listener) => {
+                let incoming = listener.incoming().map(|stream| {
+                    // Requests are small and latency-bound
+                    stream.inspect(|s| {
+                        let _ = s.set_nodelay(true);
+                    })
+                });
+                accept_loop(incoming, server, transport);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write log messages to the log file, or keep them on stderr if it can't
+/// be opened
+fn open_log_file() {
+    let Some(path) = log::default_path() else {
+        return;
+    };
+    if let Err(e) = log::open_file(&path) {
+        log_warn!("Failed to open log file {}: {}", path.display(), e);
+    }
+}
+
+/// Serve a single client over stdin/stdout
+///
+/// Requests are answered at once while the dictionaries load in the
+/// background; the client is sent `dictionary_ready` when they are in use.
+fn run_stdio(config: ServerConfig, transport: Transport) -> error::Result<()> {
     let stdin = io::stdin();
-    let stdout = io::stdout();
     let mut reader = BufReader::new(stdin.lock());
-    let mut writer = stdout.lock();
+    let writer = Arc::new(Mutex::new(io::stdout()));
 
-    eprintln!("azuki-server v{} started", env!("CARGO_PKG_VERSION"));
+    if hello_enabled() {
+        send_hello(
+            &mut *writer.lock().unwrap_or_else(PoisonError::into_inner),
+            transport,
+        )?;
+    } else {
+        log_info!("azuki-server v{} started", env!("CARGO_PKG_VERSION"));
+    }
 
-    let mut server = Server::new();
+    let reloader = watch::Reloader::new(&config);
+    let dictionaries = config.dictionaries.clone();
+    let server = Arc::new(Mutex::new(Server::loading(config)));
+    load_in_background(
+        dictionaries,
+        Arc::clone(&server),
+        Arc::clone(&writer),
+        transport,
+    );
+    reloader.spawn(Arc::clone(&server));
+    #[cfg(unix)]
+    signal::flush_on_sigterm(Arc::clone(&server));
+    serve(&mut reader, &writer, &server, transport)
+}
 
-    loop {
-        let msg = match read_message(&mut reader)? {
-            Some(msg) => msg,
-            None => {
-                eprintln!("EOF received, shutting down");
-                break;
+/// Load dictionaries on a background thread, then switch the server to
+/// them and notify the client
+fn load_in_background<W: Write + Send + 'static>(
+    dictionaries: Vec<config::DictionarySource>,
+    server: Arc<Mutex<Server>>,
+    writer: Arc<Mutex<W>>,
+    transport: Transport,
+) {
+    thread::spawn(move || {
+        let started = Instant::now();
+        let (dictionary, report) = config::load_dictionaries_reporting(&dictionaries);
+        let mut server = server.lock().unwrap_or_else(PoisonError::into_inner);
+        server.set_dictionary_report(report);
+        let notification = server.finish_loading(dictionary.map(Arc::new), started.elapsed());
+        drop(server);
+        let notification_json = notification_json(transport.dialect, &notification);
+        let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(e) = transport.write(&mut *writer, &notification_json) {
+            log_error!("Failed to send dictionary_ready: {}", e);
+        }
+    });
+}
+
+/// Load a dictionary set selected by a session on a background thread,
+/// then switch the session to it and notify subscribed connections
+fn load_set_in_background(load: SetLoad, server: Arc<Mutex<Server>>) {
+    thread::spawn(move || {
+        let started = Instant::now();
+        let (dictionary, report) = config::load_dictionaries_reporting(&load.sources);
+        server
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .finish_set_loading(
+                load.name,
+                dictionary.map(Arc::new),
+                report,
+                started.elapsed(),
+            );
+    });
+}
+
+/// A client connection accepted by a listener
+trait Connection: Read + Write + Send + Sized + 'static {
+    /// A second handle to read from while writing to this one
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+}
+
+impl Connection for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+}
+
+/// A bound `--listen` socket
+enum Listener {
+    #[cfg(unix)]
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+/// Bind the `--listen` address and print it to stdout
+fn bind(listen: &Listen) -> error::Result<Listener> {
+    match listen {
+        #[cfg(unix)]
+        Listen::Unix(path) => {
+            daemon::prepare_socket(path)?;
+            let listener = UnixListener::bind(path)?;
+            announce(listen);
+            Ok(Listener::Unix(listener))
+        }
+        Listen::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            let local = listener.local_addr()?;
+            if !local.ip().is_loopback() {
+                log_warn!(
+                    "Listening on non-loopback address {}; the protocol has no authentication",
+                    local
+                );
+            }
+            announce(&Listen::Tcp(local.to_string()));
+            Ok(Listener::Tcp(listener))
+        }
+    }
+}
+
+fn announce(listen: &Listen) {
+    log_info!(
+        "azuki-server v{} listening on {}",
+        env!("CARGO_PKG_VERSION"),
+        listen.to_arg()
+    );
+    println!("{}", listen.to_arg());
+}
+
+/// Serve each accepted connection on its own thread
+///
+/// All connections share one server, so dictionary, learning and sessions
+/// are shared between editors; each client keeps its own session ids. A
+/// `shutdown` request only ends its own connection; the process runs until
+/// it is killed.
+fn accept_loop<C: Connection>(
+    incoming: impl Iterator<Item = io::Result<C>>,
+    server: Arc<Mutex<Server>>,
+    transport: Transport,
+) {
+    for stream in incoming {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log_error!("Failed to accept connection: {}", e);
+                continue;
             }
         };
+        let server = Arc::clone(&server);
+        thread::spawn(move || {
+            let result = stream
+                .try_clone()
+                .map_err(AzukiError::from)
+                .and_then(|reader| {
+                    if hello_enabled() {
+                        send_hello(&mut stream, transport)?;
+                    }
+                    serve(
+                        &mut BufReader::new(reader),
+                        &Mutex::new(stream),
+                        &server,
+                        transport,
+                    )
+                });
+            if let Err(e) = result {
+                log_error!("Connection closed with error: {}", e);
+            }
+        });
+    }
+}
 
-        let response = match serde_json::from_str::<Request>(&msg) {
-            Ok(request) => {
-                let is_shutdown = matches!(request, Request::Shutdown { .. });
-                let response = server.handle_request(request);
-                if is_shutdown {
-                    let response_json =
-                        serde_json::to_string(&response).expect("Failed to serialize response");
-                    write_message(&mut writer, &response_json)?;
-                    eprintln!("Shutdown requested, exiting");
-                    break;
+/// Workers handling the requests of one client
+///
+/// Zenzai inference runs on one worker at a time; the others keep
+/// answering pings and the requests of other sessions meanwhile.
+const WORKERS: usize = 4;
+
+/// Handle requests from one client until EOF or `shutdown`
+///
+/// Frames are read on this thread and handled by a pool of workers, so a
+/// slow conversion doesn't hold up the requests behind it. Requests that
+/// change a session still run one at a time in arrival order (see
+/// `work_queue`). Responses are sent as requests finish, possibly out of
+/// order; clients match them by `seq`. A `cancel` request reaches requests that are queued or running
+/// and is answered at once. `shutdown` waits for the requests before it.
+/// Notifications about a session are sent to the client that last used
+/// it; server events are sent to clients that opted in with `init`. The writer is locked per frame so other threads can send
+/// notifications of their own between responses.
+fn serve<R: BufRead, W: Write + Send>(
+    reader: &mut R,
+    writer: &Mutex<W>,
+    server: &Arc<Mutex<Server>>,
+    transport: Transport,
+) -> error::Result<()> {
+    let wire = Wire::new(transport.dialect);
+    // Negotiated by the client's last `init`
+    let encoding = Mutex::new(None);
+    let encoding = || encoding.lock().unwrap_or_else(PoisonError::into_inner);
+    let send = |message: &str| {
+        transport.write(
+            &mut *writer.lock().unwrap_or_else(PoisonError::into_inner),
+            message,
+        )
+    };
+    let reply = |response: &Response| match wire.response(response) {
+        Some(message) => transport
+            .response_frames(message, *encoding())
+            .iter()
+            .try_for_each(|frame| send(frame)),
+        None => Ok(()),
+    };
+    let lock = || server.lock().unwrap_or_else(PoisonError::into_inner);
+    // Tokens of requests read but not yet answered, by seq
+    let pending: Mutex<HashMap<u64, CancelToken>> = Mutex::new(HashMap::new());
+    let pending = || pending.lock().unwrap_or_else(PoisonError::into_inner);
+    let queue = WorkQueue::new();
+    let (events, received_events) = mpsc::channel::<Notification>();
+    // Notifications about this connection's sessions come through `events`
+    let connection = lock().connect(events.clone());
+    // Subscription of this connection to server events
+    let mut subscription = None;
+
+    let work = || -> error::Result<()> {
+        // Dropping `_running` lets the next request of the session run
+        while let Some((job, _running)) = queue.take() {
+            let response = match job {
+                Job::Invalid(message) => {
+                    send(&message)?;
+                    continue;
+                }
+                Job::Request(Request::Ping { seq }, _) => {
+                    pending().remove(&seq);
+                    lock().ping(seq, pending().len())
+                }
+                Job::Request(request, cancel) => {
+                    let seq = request.seq();
+                    let offered = match &request {
+                        Request::Init { compression, .. } => Some(compression.clone()),
+                        _ => None,
+                    };
+                    // Zenzai runs without the lock so other workers can go on
+                    let prefetch = lock().prefetch(&request);
+                    // Progress goes out as it comes; a failed write shows
+                    // up again with the response
+                    let progress = |notification: Notification| {
+                        let _ = send(&notification_json(transport.dialect, &notification));
+                    };
+                    let prefetched = prefetch.map(|prefetch| prefetch.run(&cancel, &progress));
+                    let mut response =
+                        lock().handle_cancellable(connection, request, cancel, prefetched);
+                    pending().remove(&seq);
+                    if let (Some(offered), Response::InitResult { compression, .. }) =
+                        (offered, &mut response)
+                    {
+                        *compression = transport.negotiate(&offered);
+                    }
+                    response
                 }
-                response
+            };
+            match &response {
+                // Responses after `init_result` are compressed as it
+                // announced; holding the encoding while it is sent keeps other
+                // workers from answering in between with the old one
+                Response::InitResult { compression, .. } => {
+                    let mut encoding = encoding();
+                    if let Some(message) = wire.response(&response) {
+                        for frame in transport.response_frames(message, *encoding) {
+                            send(&frame)?;
+                        }
+                    }
+                    *encoding = *compression;
+                }
+                _ => reply(&response)?,
             }
-            Err(e) => {
-                let seq = extract_seq(&msg).unwrap_or(0);
-                Response::Error {
-                    seq,
-                    session_id: None,
-                    error: format!("Failed to parse request: {}", e),
+            for load in lock().take_set_loads() {
+                load_set_in_background(load, Arc::clone(server));
+            }
+        }
+        Ok(())
+    };
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..WORKERS).map(|_| scope.spawn(work)).collect();
+        // Ends once the server and this function drop their senders
+        let send = &send;
+        scope.spawn(move || {
+            for notification in received_events {
+                if send(&notification_json(transport.dialect, &notification)).is_err() {
+                    break;
                 }
             }
+        });
+        // Returns the shutdown request, if the client sent one
+        let mut read = || -> error::Result<Option<Request>> {
+            while let Some(msg) = transport.read(reader)? {
+                let job = match wire.request(&msg) {
+                    Ok(Request::Cancel {
+                        seq, target_seq, ..
+                    }) => {
+                        let target = pending().get(&target_seq).cloned();
+                        if let Some(token) = &target {
+                            token.cancel();
+                        }
+                        reply(&Response::CancelResult {
+                            seq,
+                            target_seq,
+                            cancelled: target.is_some(),
+                        })?;
+                        continue;
+                    }
+                    Ok(request @ Request::Shutdown { .. }) => return Ok(Some(request)),
+                    Ok(request) => {
+                        if let Request::Init {
+                            notifications: true,
+                            ..
+                        } = request
+                        {
+                            if subscription.is_none() {
+                                subscription = Some(lock().subscribe(events.clone()));
+                            }
+                        }
+                        let cancel = CancelToken::default();
+                        pending().insert(request.seq(), cancel.clone());
+                        let sessions = request
+                            .ordered_sessions()
+                            .into_iter()
+                            .map(String::from)
+                            .collect();
+                        (Job::Request(request, cancel), sessions)
+                    }
+                    Err(message) => (Job::Invalid(message), Vec::new()),
+                };
+                queue.push(job.0, job.1);
+            }
+            Ok(None)
         };
+        let read_result = read();
+        queue.close();
+        let work_result = workers.into_iter().try_for_each(|worker| {
+            worker.join().unwrap_or_else(|_| {
+                Err(AzukiError::Protocol("Request handler panicked".to_string()))
+            })
+        });
+        if let Some(id) = subscription {
+            lock().unsubscribe(id);
+        }
+        lock().disconnect(connection);
+        drop(events);
+        match (read_result, work_result) {
+            (Ok(Some(shutdown)), Ok(())) => {
+                reply(&lock().handle_request(shutdown))?;
+                log_info!("Shutdown requested, exiting");
+                Ok(())
+            }
+            (read_result, work_result) => {
+                log_info!("EOF received, shutting down");
+                lock().persist();
+                read_result.and(work_result)
+            }
+        }
+    })
+}
+
+/// A frame read from the client, queued for the workers in arrival order
+// Moved once through the queue, so boxing requests would gain nothing
+#[allow(clippy::large_enum_variant)]
+enum Job {
+    Request(Request, CancelToken),
+    /// A frame that didn't parse, answered with this error message
+    Invalid(String),
+}
+
+/// Message format of one connection
+enum Wire {
+    Native,
+    JsonRpc(JsonRpc),
+}
 
-        let response_json = serde_json::to_string(&response).expect("Failed to serialize response");
-        write_message(&mut writer, &response_json)?;
+impl Wire {
+    fn new(dialect: Dialect) -> Self {
+        match dialect {
+            Dialect::Native => Wire::Native,
+            Dialect::JsonRpc => Wire::JsonRpc(JsonRpc::new()),
+        }
     }
 
-    Ok(())
+    /// Parse a request, or build the error message answering it
+    fn request(&self, msg: &str) -> Result<Request, String> {
+        match self {
+            Wire::Native => serde_json::from_str(msg).map_err(|e| {
+                let seq = extract_seq(msg).unwrap_or(0);
+                to_json(&Response::error(
+                    seq,
+                    None,
+                    AzukiError::Protocol(format!("Failed to parse request: {}", e)),
+                ))
+            }),
+            Wire::JsonRpc(rpc) => rpc.decode(msg),
+        }
+    }
+
+    /// Message carrying a response, or None if it isn't sent
+    fn response(&self, response: &Response) -> Option<String> {
+        match self {
+            Wire::Native => Some(to_json(response)),
+            Wire::JsonRpc(rpc) => rpc.encode(response),
+        }
+    }
+}
+
+fn notification_json(dialect: Dialect, notification: &Notification) -> String {
+    match dialect {
+        Dialect::Native => to_json(notification),
+        Dialect::JsonRpc => jsonrpc::notification(notification),
+    }
+}
+
+fn to_json<T: serde::Serialize>(message: &T) -> String {
+    serde_json::to_string(message).expect("Failed to serialize message")
+}
+
+/// Whether the client asked for a `hello` frame via `AZUKI_HELLO`
+fn hello_enabled() -> bool {
+    std::env::var("AZUKI_HELLO").is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Send the `hello` notification to a newly connected client
+fn send_hello<W: Write>(writer: &mut W, transport: Transport) -> error::Result<()> {
+    transport.write(writer, &notification_json(transport.dialect, &hello()))
+}
+
+/// Build the startup `hello` notification
+fn hello() -> Notification {
+    let display = |path: std::path::PathBuf| path.display().to_string();
+    Notification::Hello {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        pid: std::process::id(),
+        protocol_versions: PROTOCOL_VERSIONS.to_vec(),
+        data_dir: config::data_dir().map(display),
+        dictionary_paths: config::default_dictionary_paths()
+            .into_iter()
+            .map(display)
+            .collect(),
+    }
 }
@@ -0,0 +1,570 @@
+//! Memory-mapped indexed dictionary
+//!
+//! Large SKK dictionaries have around two million candidates; parsing them
+//! into heap `String`s at every startup costs seconds and hundreds of
+//! megabytes. This format keeps sorted entry tables and their text in one
+//! file that is memory-mapped as is, so lookups binary-search the tables
+//! and return slices borrowed from the mapping.
+//!
+//! Layout (all integers big-endian):
+//!
+//! ```text
+//! magic "AZKD" | u32 version | u32 okuri-nasi count | u32 okuri-ari count
+//! | u32 text length
+//! table:  entry... (okuri-nasi entries, then okuri-ari entries)
+//! entry:  u32 key offset | u32 key length | u32 value offset | u32 value length
+//! text:   UTF-8 keys and values
+//! reverse index section (see `reverse_index`)
+//! ```
+//!
+//! Keys are sorted by their UTF-8 bytes within each table. A value is the
+//! candidates separated by U+001E, each optionally followed by U+001F and
+//! its annotation. An okuri-ari value may end with okurigana blocks, each
+//! U+001D, the okurigana, U+001E and the block's candidates in the same
+//! form. Offsets are relative to the start of the text. Version 1 files,
+//! which have no blocks, and version 2 files, which have no reverse index,
+//! are still read.
+//!
+//! The reverse index is only parsed the first time it is asked for, so
+//! opening a large dictionary stays a mapping rather than a heap build.
+//!
+//! The mapping is read-only and private, but the file must not be
+//! rewritten in place while mapped; replace it by renaming a new file
+//! over it instead.
+
+use crate::dictionary::Dictionary;
+use crate::error::{DictionaryError, Result};
+use crate::reverse_index::{self, ReverseIndex};
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use memmap2::Mmap;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Magic bytes opening an indexed dictionary file
+pub const MAGIC: &[u8; 4] = b"AZKD";
+
+/// Format version
+const VERSION: u32 = 3;
+
+/// First version with a reverse index section after the text
+const REVERSE_INDEX_VERSION: u32 = 3;
+
+/// Header size in bytes
+const HEADER_LEN: usize = 20;
+
+/// Table entry size in bytes
+const ENTRY_LEN: usize = 16;
+
+/// Separates candidates in a value
+const CANDIDATE_SEPARATOR: char = '\u{1e}';
+
+/// Separates a candidate from its annotation
+const ANNOTATION_SEPARATOR: char = '\u{1f}';
+
+/// Opens an okurigana block in an okuri-ari value
+const BLOCK_SEPARATOR: char = '\u{1d}';
+
+/// Indexed dictionary backed by a file mapping
+pub struct MappedDictionary {
+    storage: Storage,
+    okuri_nasi_count: usize,
+    okuri_ari_count: usize,
+    /// Byte offset of the text in `storage`
+    text_start: usize,
+    /// Byte offset just past the text, where the reverse index begins
+    text_end: usize,
+    /// Reverse index section, parsed on first use
+    reverse_index: OnceLock<Option<ReverseIndex>>,
+}
+
+impl fmt::Debug for MappedDictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedDictionary")
+            .field("bytes", &self.storage.len())
+            .field("okuri_nasi_count", &self.okuri_nasi_count)
+            .field("okuri_ari_count", &self.okuri_ari_count)
+            .finish()
+    }
+}
+
+impl MappedDictionary {
+    /// Map an indexed dictionary file
+    ///
+    /// Falls back to reading the file into memory where mapping isn't
+    /// available.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let io_error = |source| DictionaryError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let mut file = File::open(path).map_err(io_error)?;
+        let storage = match Storage::map(&file) {
+            Some(storage) => storage,
+            None => {
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes).map_err(io_error)?;
+                Storage::Heap(bytes)
+            }
+        };
+        Self::from_storage(storage)
+    }
+
+    /// Use indexed dictionary data already in memory
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_storage(Storage::Heap(bytes))
+    }
+
+    /// Build the indexed form of a dictionary in memory
+    pub fn from_dictionary(dictionary: &Dictionary) -> Result<Self> {
+        let mut bytes = Vec::new();
+        write_dictionary(dictionary, &mut bytes)?;
+        Self::from_bytes(bytes)
+    }
+
+    fn from_storage(storage: Storage) -> Result<Self> {
+        let format = |message: &str| DictionaryError::Format(message.to_string());
+        if storage.len() < HEADER_LEN || &storage[..4] != MAGIC {
+            return Err(format("Not an indexed dictionary").into());
+        }
+        let version = BigEndian::read_u32(&storage[4..8]);
+        if !(1..=VERSION).contains(&version) {
+            return Err(DictionaryError::Format(format!(
+                "Unsupported indexed dictionary version: {}",
+                version
+            ))
+            .into());
+        }
+        let okuri_nasi_count = BigEndian::read_u32(&storage[8..12]) as usize;
+        let okuri_ari_count = BigEndian::read_u32(&storage[12..16]) as usize;
+        let text_len = BigEndian::read_u32(&storage[16..20]) as usize;
+        let text_start = (okuri_nasi_count + okuri_ari_count)
+            .checked_mul(ENTRY_LEN)
+            .and_then(|table_len| table_len.checked_add(HEADER_LEN))
+            .unwrap_or(usize::MAX);
+        let text_end = text_start.saturating_add(text_len);
+        let section_len = storage.len().checked_sub(text_end);
+        let complete = match version {
+            REVERSE_INDEX_VERSION.. => {
+                section_len.is_some() && storage[text_end..].starts_with(reverse_index::MAGIC)
+            }
+            _ => section_len == Some(0),
+        };
+        if !complete {
+            return Err(format("Truncated indexed dictionary").into());
+        }
+        // Checked once so lookups can slice the text without decoding
+        std::str::from_utf8(&storage[text_start..text_end])
+            .map_err(|_| format("Invalid UTF-8 in indexed dictionary"))?;
+        Ok(Self {
+            storage,
+            okuri_nasi_count,
+            okuri_ari_count,
+            text_start,
+            text_end,
+            reverse_index: OnceLock::new(),
+        })
+    }
+
+    /// Look up candidates for a reading
+    pub fn lookup(&self, reading: &str) -> Option<Vec<&str>> {
+        let value = self.find(Table::OkuriNasi, reading)?;
+        Some(candidates(value).map(|(candidate, _)| candidate).collect())
+    }
+
+    /// Look up okuri-ari candidates by key (stem reading + okuri consonant)
+    ///
+    /// With `okurigana`, the entry's block for it replaces the candidates
+    /// when there is one.
+    pub fn lookup_okuri_ari(&self, key: &str, okurigana: Option<&str>) -> Option<Vec<&str>> {
+        let value = self.find(Table::OkuriAri, key)?;
+        let block = okurigana.and_then(|okurigana| {
+            okuri_blocks(value).find_map(|(o, candidates)| (o == okurigana).then_some(candidates))
+        });
+        Some(match block {
+            Some(candidates) => candidates,
+            None => candidates(value).map(|(candidate, _)| candidate).collect(),
+        })
+    }
+
+    /// Okurigana blocks of an okuri-ari entry
+    pub fn okuri_blocks(&self, key: &str) -> Vec<(&str, Vec<&str>)> {
+        self.find(Table::OkuriAri, key)
+            .map(|value| okuri_blocks(value).collect())
+            .unwrap_or_default()
+    }
+
+    /// Check whether a reading has an entry
+    pub fn contains(&self, reading: &str) -> bool {
+        self.find(Table::OkuriNasi, reading).is_some()
+    }
+
+    /// Annotation of a candidate
+    pub fn annotation(&self, reading: &str, candidate: &str) -> Option<&str> {
+        let value = self
+            .find(Table::OkuriNasi, reading)
+            .or_else(|| self.find(Table::OkuriAri, reading))?;
+        candidates(value)
+            .find(|(text, _)| *text == candidate)
+            .and_then(|(_, annotation)| annotation)
+    }
+
+    /// Okuri-nasi readings starting with `prefix`, in key order
+    pub fn readings_with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+        let first = self.partition_point(Table::OkuriNasi, |key| key < prefix);
+        (first..self.count(Table::OkuriNasi))
+            .map(move |i| self.entry(Table::OkuriNasi, i).0)
+            .take_while(move |key| key.starts_with(prefix))
+    }
+
+    /// Iterate over okuri-nasi entries (reading, candidates)
+    pub fn entries(&self) -> impl Iterator<Item = (&str, Vec<&str>)> {
+        self.table_entries(Table::OkuriNasi)
+    }
+
+    /// Iterate over okuri-ari entries (stem reading + okuri consonant, candidates)
+    pub fn okuri_ari_entries(&self) -> impl Iterator<Item = (&str, Vec<&str>)> {
+        self.table_entries(Table::OkuriAri)
+    }
+
+    /// Number of okuri-nasi entries
+    pub fn len(&self) -> usize {
+        self.okuri_nasi_count
+    }
+
+    /// Size of the file in memory
+    pub fn byte_len(&self) -> usize {
+        self.storage.len()
+    }
+
+    /// Candidate → reading index stored with the dictionary
+    ///
+    /// `None` for files compiled before the index was added, or whose
+    /// section is malformed.
+    #[allow(dead_code)]
+    pub fn reverse_index(&self) -> Option<&ReverseIndex> {
+        self.reverse_index
+            .get_or_init(|| {
+                let mut section = &self.storage[self.text_end..];
+                if section.is_empty() {
+                    return None;
+                }
+                ReverseIndex::read_from(&mut section).ok()
+            })
+            .as_ref()
+    }
+
+    fn table_entries(&self, table: Table) -> impl Iterator<Item = (&str, Vec<&str>)> {
+        (0..self.count(table)).map(move |i| {
+            let (key, value) = self.entry(table, i);
+            (key, candidates(value).map(|(c, _)| c).collect())
+        })
+    }
+
+    fn count(&self, table: Table) -> usize {
+        match table {
+            Table::OkuriNasi => self.okuri_nasi_count,
+            Table::OkuriAri => self.okuri_ari_count,
+        }
+    }
+
+    /// Value of `key` in a table
+    fn find(&self, table: Table, key: &str) -> Option<&str> {
+        let i = self.partition_point(table, |k| k < key);
+        (i < self.count(table))
+            .then(|| self.entry(table, i))
+            .filter(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Index of the first entry whose key doesn't satisfy `before`
+    fn partition_point<F: Fn(&str) -> bool>(&self, table: Table, before: F) -> usize {
+        let (mut low, mut high) = (0, self.count(table));
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match before(self.entry(table, mid).0) {
+                true => low = mid + 1,
+                false => high = mid,
+            }
+        }
+        low
+    }
+
+    /// Key and value of the `i`th entry of a table
+    ///
+    /// Out-of-range offsets in a corrupt file yield empty strings.
+    fn entry(&self, table: Table, i: usize) -> (&str, &str) {
+        let index = match table {
+            Table::OkuriNasi => i,
+            Table::OkuriAri => self.okuri_nasi_count + i,
+        };
+        let at = HEADER_LEN + index * ENTRY_LEN;
+        let field = |n: usize| BigEndian::read_u32(&self.storage[at + n * 4..]) as usize;
+        (self.text(field(0), field(1)), self.text(field(2), field(3)))
+    }
+
+    fn text(&self, offset: usize, len: usize) -> &str {
+        // SAFETY: the whole text was validated as UTF-8 when opened
+        let text =
+            unsafe { std::str::from_utf8_unchecked(&self.storage[self.text_start..self.text_end]) };
+        offset
+            .checked_add(len)
+            .and_then(|end| text.get(offset..end))
+            .unwrap_or("")
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Table {
+    OkuriNasi,
+    OkuriAri,
+}
+
+/// Candidates of a value with their annotations
+fn candidates(value: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    let main = value.split(BLOCK_SEPARATOR).next().unwrap_or_default();
+    main.split(CANDIDATE_SEPARATOR)
+        .filter(|c| !c.is_empty())
+        .map(|c| match c.split_once(ANNOTATION_SEPARATOR) {
+            Some((candidate, annotation)) => (candidate, Some(annotation)),
+            None => (c, None),
+        })
+}
+
+/// Okurigana blocks of a value with their candidates
+fn okuri_blocks(value: &str) -> impl Iterator<Item = (&str, Vec<&str>)> {
+    value.split(BLOCK_SEPARATOR).skip(1).filter_map(|block| {
+        let (okurigana, candidates) = block.split_once(CANDIDATE_SEPARATOR)?;
+        let candidates = candidates
+            .split(CANDIDATE_SEPARATOR)
+            .filter(|c| !c.is_empty())
+            .collect();
+        Some((okurigana, candidates))
+    })
+}
+
+/// Write a dictionary in the indexed format, with its reverse index
+///
+/// Candidates containing the separator characters are left out; SKK
+/// dictionaries have no control characters.
+pub fn write_dictionary<W: Write>(dictionary: &Dictionary, writer: &mut W) -> Result<()> {
+    let usable =
+        |s: &str| !s.contains([CANDIDATE_SEPARATOR, ANNOTATION_SEPARATOR, BLOCK_SEPARATOR]);
+    let value = |key: &str, candidates: Vec<&str>| {
+        let mut value = String::new();
+        for candidate in candidates.into_iter().filter(|c| usable(c)) {
+            value.push_str(candidate);
+            if let Some(annotation) = dictionary.annotation(key, candidate).filter(|a| usable(a)) {
+                value.push(ANNOTATION_SEPARATOR);
+                value.push_str(annotation);
+            }
+            value.push(CANDIDATE_SEPARATOR);
+        }
+        value
+    };
+    let okuri_nasi: BTreeMap<&str, String> = dictionary
+        .entries()
+        .map(|(key, candidates)| (key, value(key, candidates)))
+        .collect();
+    let okuri_ari: BTreeMap<&str, String> = dictionary
+        .okuri_ari_entries()
+        .map(|(key, candidates)| {
+            let mut value = value(key, candidates);
+            for (okurigana, candidates) in dictionary.okuri_blocks(key) {
+                if !usable(okurigana) {
+                    continue;
+                }
+                value.push(BLOCK_SEPARATOR);
+                value.push_str(okurigana);
+                value.push(CANDIDATE_SEPARATOR);
+                for candidate in candidates.into_iter().filter(|c| usable(c)) {
+                    value.push_str(candidate);
+                    value.push(CANDIDATE_SEPARATOR);
+                }
+            }
+            (key, value)
+        })
+        .collect();
+
+    let mut table = Vec::with_capacity((okuri_nasi.len() + okuri_ari.len()) * ENTRY_LEN);
+    let mut text = String::new();
+    for (key, value) in okuri_nasi.iter().chain(okuri_ari.iter()) {
+        for s in [*key, value.as_str()] {
+            table.write_u32::<BigEndian>(offset(text.len())?)?;
+            table.write_u32::<BigEndian>(offset(s.len())?)?;
+            text.push_str(s);
+        }
+    }
+
+    writer.write_all(MAGIC)?;
+    writer.write_u32::<BigEndian>(VERSION)?;
+    writer.write_u32::<BigEndian>(offset(okuri_nasi.len())?)?;
+    writer.write_u32::<BigEndian>(offset(okuri_ari.len())?)?;
+    writer.write_u32::<BigEndian>(offset(text.len())?)?;
+    writer.write_all(&table)?;
+    writer.write_all(text.as_bytes())?;
+    ReverseIndex::build(dictionary).write_to(writer)
+}
+
+fn offset(n: usize) -> Result<u32> {
+    u32::try_from(n)
+        .map_err(|_| DictionaryError::Format("Dictionary too large to index".to_string()).into())
+}
+
+/// Bytes of an indexed dictionary
+enum Storage {
+    /// Read-only mapping of the whole file
+    Mapped(Mmap),
+    Heap(Vec<u8>),
+}
+
+impl Storage {
+    /// Map a file, or `None` if it can't be mapped
+    fn map(file: &File) -> Option<Self> {
+        if file.metadata().ok()?.len() == 0 {
+            return None;
+        }
+        // SAFETY: the mapping is only read, and files are replaced rather
+        // than rewritten in place (see above)
+        match unsafe { Mmap::map(file) } {
+            Ok(mmap) => Some(Storage::Mapped(mmap)),
+            Err(e) => {
+                log_warn!("[mapped_dict] mmap failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Deref for Storage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Storage::Mapped(mmap) => mmap,
+            Storage::Heap(bytes) => bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture() -> Dictionary {
+        Dictionary::load(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/test-dict.utf8"),
+        )
+        .unwrap()
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("azuki-{}-{}.azk", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_lookup_from_mapped_file() {
+        let path = temp_file("mapped");
+        let mut file = File::create(&path).unwrap();
+        write_dictionary(&fixture(), &mut file).unwrap();
+        drop(file);
+
+        let mapped = MappedDictionary::open(&path).unwrap();
+        assert_eq!(mapped.lookup("きょう").unwrap(), vec!["今日", "京", "教"]);
+        assert_eq!(
+            mapped.lookup_okuri_ari("かk", None).unwrap(),
+            vec!["書", "欠"]
+        );
+        assert_eq!(
+            mapped.lookup_okuri_ari("かk", Some("け")).unwrap(),
+            vec!["欠"]
+        );
+        assert_eq!(
+            mapped.okuri_blocks("かk"),
+            vec![("く", vec!["書"]), ("け", vec!["欠"])]
+        );
+        assert!(mapped.okuri_blocks("いt").is_empty());
+        assert!(mapped.lookup("かk").is_none());
+        assert!(mapped.lookup("そんざいしない").is_none());
+        assert!(mapped.contains("にほんご"));
+        assert_eq!(mapped.annotation("かんじ", "漢字"), Some("kanji"));
+        assert_eq!(mapped.annotation("かんじ", "感じ"), None);
+        assert_eq!(mapped.len(), fixture().len());
+        assert_eq!(
+            mapped.readings_with_prefix("に").collect::<Vec<_>>(),
+            vec!["にほんご", "にゅうりょく"]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_keeps_entries() {
+        let dictionary = fixture();
+        let mapped = MappedDictionary::from_dictionary(&dictionary).unwrap();
+        let mut expected: Vec<(&str, Vec<&str>)> = dictionary.entries().collect();
+        expected.sort();
+        let actual: Vec<(&str, Vec<&str>)> = mapped.entries().collect();
+        assert_eq!(actual, expected);
+        assert_eq!(
+            mapped.okuri_ari_entries().count(),
+            dictionary.okuri_ari_entries().count()
+        );
+    }
+
+    #[test]
+    fn test_rejects_malformed_data() {
+        assert!(MappedDictionary::from_bytes(b"AZKD".to_vec()).is_err());
+        assert!(MappedDictionary::from_bytes(b";; okuri-ari entries.\n".to_vec()).is_err());
+
+        let mut bytes = Vec::new();
+        write_dictionary(&fixture(), &mut bytes).unwrap();
+        let text_end = MappedDictionary::from_bytes(bytes.clone())
+            .unwrap()
+            .text_end;
+        let mut truncated = bytes.clone();
+        truncated.truncate(text_end - 1);
+        assert!(MappedDictionary::from_bytes(truncated).is_err());
+        bytes[5] = 9;
+        assert!(MappedDictionary::from_bytes(bytes).is_err());
+    }
+
+    #[test]
+    fn test_reverse_index_section() {
+        let dictionary = fixture();
+        let mapped = MappedDictionary::from_dictionary(&dictionary).unwrap();
+        assert_eq!(
+            mapped.reverse_index(),
+            Some(&ReverseIndex::build(&dictionary))
+        );
+        assert_eq!(mapped.reverse_index().unwrap().lookup("漢字"), ["かんじ"]);
+
+        // A damaged section leaves the dictionary itself usable
+        let mut bytes = Vec::new();
+        write_dictionary(&dictionary, &mut bytes).unwrap();
+        bytes.pop();
+        let mapped = MappedDictionary::from_bytes(bytes).unwrap();
+        assert!(mapped.reverse_index().is_none());
+        assert!(mapped.contains("にほんご"));
+    }
+
+    #[test]
+    fn test_reads_version_2_without_reverse_index() {
+        let mut bytes = Vec::new();
+        write_dictionary(&fixture(), &mut bytes).unwrap();
+        let text_end = MappedDictionary::from_bytes(bytes.clone())
+            .unwrap()
+            .text_end;
+        bytes.truncate(text_end);
+        bytes[4..8].copy_from_slice(&2u32.to_be_bytes());
+
+        let mapped = MappedDictionary::from_bytes(bytes).unwrap();
+        assert!(mapped.reverse_index().is_none());
+        assert_eq!(mapped.lookup("きょう").unwrap(), vec!["今日", "京", "教"]);
+    }
+}
@@ -1,8 +1,24 @@
 //! Request and Response message types for the azuki protocol
 
-use crate::converter::Segment;
-use crate::zenzai::ZenzaiConfig;
+use crate::compress::Encoding;
+use crate::config::DictionaryLoad;
+use crate::converter::{ConversionConfig, Segment};
+use crate::delta::{AddedCandidate, SegmentChange};
+use crate::error::AzukiError;
+use crate::fallback::FallbackPolicy;
+use crate::log::LogLevel;
+use crate::metrics::{CacheStats, MemoryStats, RequestStats};
+use crate::provider::ProviderConfig;
+use crate::punctuation::PunctuationConfig;
+use crate::remote_dict::RemoteDictionaryConfig;
+use crate::romaji::RomajiConfig;
+use crate::session::{EvictionReason, InputMode, SessionConfig, SessionStats, SessionSummary};
+use crate::settings::{Scope, Settings, SettingsPatch};
+use crate::symbol;
+use crate::zenzai::{ModelStatus, ZenzaiConfig};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
 /// Request types from the client
 /// Fields marked with allow(dead_code) will be used in future phases
@@ -15,16 +31,62 @@ pub enum Request {
         #[serde(default)]
         session_id: Option<String>,
         #[serde(default)]
-        zenzai: Option<ZenzaiConfig>,
+        zenzai: Option<Box<ZenzaiConfig>>,
+        #[serde(default)]
+        sessions: Option<SessionConfig>,
+        #[serde(default)]
+        punctuation: Option<PunctuationConfig>,
+        /// What to return when no backend converts a reading
+        #[serde(default)]
+        fallback: Option<FallbackPolicy>,
+        /// Dynamic candidate providers (dates, greetings, ...)
+        #[serde(default)]
+        providers: Option<ProviderConfig>,
+        /// Whole-phrase candidate generation
+        #[serde(default)]
+        conversion: Option<ConversionConfig>,
+        /// skkserv queried in addition to local dictionaries
+        #[serde(default)]
+        remote_dictionary: Option<RemoteDictionaryConfig>,
+        /// Table for `to_kana` requests
+        #[serde(default)]
+        romaji: Option<RomajiConfig>,
+        /// Encodings the client can decode, in order of preference
+        #[serde(default)]
+        compression: Vec<String>,
+        /// Settings for this session only, as `set_config` with the
+        /// session scope
+        #[serde(default)]
+        settings: Option<SettingsPatch>,
+        /// Receive server events on this connection
+        #[serde(default)]
+        notifications: bool,
     },
     Convert {
         seq: u64,
         session_id: String,
         reading: String,
+        /// Cursor position in characters; a segment boundary is placed
+        /// there and the segment before it reported as active
         #[serde(default)]
         cursor: Option<usize>,
         #[serde(default)]
         options: Option<ConvertOptions>,
+        /// Continuation token from a previous partial ConvertResult
+        #[serde(default)]
+        continuation: Option<String>,
+    },
+    /// Convert several readings at once, e.g. regions of a buffer or an
+    /// evaluation corpus; Zenzai decodes them together
+    ConvertBatch {
+        seq: u64,
+        session_id: String,
+        readings: Vec<String>,
+        /// Text before each reading, instead of the session's context
+        #[serde(default)]
+        contexts: Option<Vec<String>>,
+        #[serde(default)]
+        options: Option<ConvertOptions>,
     },
     Commit {
         seq: u64,
@@ -32,45 +94,545 @@ pub enum Request {
         reading: String,
         candidate: String,
     },
+    /// Take back what the session's latest commit taught the learning
+    /// store
+    UndoCommit {
+        seq: u64,
+        session_id: String,
+    },
     Shutdown {
         seq: u64,
         #[serde(default)]
         session_id: Option<String>,
     },
+    /// `reading` and `segments` default to the session's composition
     AdjustSegment {
+        seq: u64,
+        session_id: String,
+        #[serde(default)]
+        reading: Option<String>,
+        #[serde(default)]
+        segments: Option<Vec<SegmentInput>>,
+        segment_index: usize,
+        direction: String,
+    },
+    Transform {
+        seq: u64,
+        session_id: String,
+        text: String,
+        direction: String,
+    },
+    BlockCandidate {
+        seq: u64,
+        session_id: String,
+        reading: String,
+        candidate: String,
+    },
+    UnblockCandidate {
+        seq: u64,
+        session_id: String,
+        reading: String,
+        candidate: String,
+    },
+    ExportBlocklist {
+        seq: u64,
+        session_id: String,
+    },
+    ImportBlocklist {
+        seq: u64,
+        session_id: String,
+        /// Blocklist in file format (`reading /candidate1/candidate2/`)
+        content: String,
+        /// Replace the current blocklist instead of merging into it
+        #[serde(default)]
+        replace: bool,
+    },
+    /// Enable or disable dynamic providers for this session only
+    SetProviders {
+        seq: u64,
+        session_id: String,
+        enabled: BTreeMap<String, bool>,
+    },
+    SessionStats {
+        seq: u64,
+        session_id: String,
+    },
+    /// The session's composition, input mode and context, after applying
+    /// the given changes
+    SessionState {
+        seq: u64,
+        session_id: String,
+        /// Committed text before the composition
+        #[serde(default)]
+        context: Option<String>,
+        /// Candidate to choose in a segment of the composition
+        #[serde(default)]
+        select: Option<Selection>,
+        /// Zenzai model profile for the session's conversions; empty for
+        /// the default one
+        #[serde(default)]
+        zenzai_profile: Option<String>,
+        /// Dictionary set for the session's conversions; empty for the
+        /// default one
+        #[serde(default)]
+        dictionary_set: Option<String>,
+    },
+    /// Change how the session's readings are converted
+    SetMode {
+        seq: u64,
+        session_id: String,
+        mode: InputMode,
+    },
+    /// Every live session, for troubleshooting shared servers
+    ListSessions {
+        seq: u64,
+        session_id: String,
+    },
+    /// Request counters, latencies, cache hit rates and memory use
+    GetStats {
+        seq: u64,
+        session_id: String,
+    },
+    /// Settings in effect for the session
+    GetConfig {
+        seq: u64,
+        session_id: String,
+    },
+    /// Change settings for the session or every session
+    SetConfig {
+        seq: u64,
+        session_id: String,
+        #[serde(default)]
+        scope: Scope,
+        settings: SettingsPatch,
+    },
+    /// Register a word in the user dictionary
+    AddEntry {
+        seq: u64,
+        session_id: String,
+        reading: String,
+        candidate: String,
+    },
+    /// Remove a word from the user dictionary
+    RemoveEntry {
+        seq: u64,
+        session_id: String,
+        reading: String,
+        candidate: String,
+    },
+    /// Load the dictionaries and the user dictionary again from disk
+    ReloadDictionaries {
+        seq: u64,
+        session_id: String,
+    },
+    /// Dictionaries loaded, with entry counts, encodings and failures
+    DictInfo {
+        seq: u64,
+        session_id: String,
+    },
+    /// Drop the loaded Zenzai model to free memory; the next conversion
+    /// that needs it loads it again
+    UnloadModel {
+        seq: u64,
+        session_id: String,
+    },
+    /// Model, worker and cache state of Zenzai, and why it isn't working
+    ZenzaiStatus {
+        seq: u64,
+        session_id: String,
+    },
+    /// Readings starting with a partial reading, for prediction
+    Complete {
+        seq: u64,
+        session_id: String,
+        prefix: String,
+        /// Maximum number of readings returned
+        #[serde(default = "default_completion_limit")]
+        limit: usize,
+    },
+    /// Look up the candidates of one segment again
+    ConvertSegment {
         seq: u64,
         session_id: String,
         reading: String,
         segments: Vec<SegmentInput>,
         segment_index: usize,
-        direction: String,
     },
+    /// Convert romaji input to hiragana
+    ToKana {
+        seq: u64,
+        session_id: String,
+        romaji: String,
+    },
+    /// Several requests handled in order, answered together
+    Batch {
+        seq: u64,
+        requests: Vec<Request>,
+    },
+    /// Abandon an earlier request that is queued or being handled
+    Cancel {
+        seq: u64,
+        #[serde(default)]
+        session_id: Option<String>,
+        /// `seq` of the request to cancel
+        target_seq: u64,
+    },
+    /// Health check, answered by the next free worker
+    Ping {
+        seq: u64,
+    },
+    /// Change the log level of the server; without `level`, report it
+    SetLogLevel {
+        seq: u64,
+        #[serde(default)]
+        level: Option<LogLevel>,
+    },
+}
+
+fn default_completion_limit() -> usize {
+    10
+}
+
+impl Request {
+    /// Sequence number of the request
+    pub fn seq(&self) -> u64 {
+        match self {
+            Request::Init { seq, .. }
+            | Request::Convert { seq, .. }
+            | Request::ConvertBatch { seq, .. }
+            | Request::Commit { seq, .. }
+            | Request::UndoCommit { seq, .. }
+            | Request::Shutdown { seq, .. }
+            | Request::AdjustSegment { seq, .. }
+            | Request::Transform { seq, .. }
+            | Request::BlockCandidate { seq, .. }
+            | Request::UnblockCandidate { seq, .. }
+            | Request::ExportBlocklist { seq, .. }
+            | Request::ImportBlocklist { seq, .. }
+            | Request::SetProviders { seq, .. }
+            | Request::SessionStats { seq, .. }
+            | Request::SessionState { seq, .. }
+            | Request::ListSessions { seq, .. }
+            | Request::SetMode { seq, .. }
+            | Request::GetStats { seq, .. }
+            | Request::GetConfig { seq, .. }
+            | Request::SetConfig { seq, .. }
+            | Request::AddEntry { seq, .. }
+            | Request::RemoveEntry { seq, .. }
+            | Request::ReloadDictionaries { seq, .. }
+            | Request::DictInfo { seq, .. }
+            | Request::UnloadModel { seq, .. }
+            | Request::ZenzaiStatus { seq, .. }
+            | Request::Complete { seq, .. }
+            | Request::ConvertSegment { seq, .. }
+            | Request::ToKana { seq, .. }
+            | Request::Batch { seq, .. }
+            | Request::Cancel { seq, .. }
+            | Request::Ping { seq }
+            | Request::SetLogLevel { seq, .. } => *seq,
+        }
+    }
+
+    /// Request type, as named by the `type` field
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Request::Init { .. } => "init",
+            Request::Convert { .. } => "convert",
+            Request::ConvertBatch { .. } => "convert_batch",
+            Request::Commit { .. } => "commit",
+            Request::UndoCommit { .. } => "undo_commit",
+            Request::Shutdown { .. } => "shutdown",
+            Request::AdjustSegment { .. } => "adjust_segment",
+            Request::Transform { .. } => "transform",
+            Request::BlockCandidate { .. } => "block_candidate",
+            Request::UnblockCandidate { .. } => "unblock_candidate",
+            Request::ExportBlocklist { .. } => "export_blocklist",
+            Request::ImportBlocklist { .. } => "import_blocklist",
+            Request::SetProviders { .. } => "set_providers",
+            Request::SessionStats { .. } => "session_stats",
+            Request::SessionState { .. } => "session_state",
+            Request::ListSessions { .. } => "list_sessions",
+            Request::SetMode { .. } => "set_mode",
+            Request::GetStats { .. } => "get_stats",
+            Request::GetConfig { .. } => "get_config",
+            Request::SetConfig { .. } => "set_config",
+            Request::AddEntry { .. } => "add_entry",
+            Request::RemoveEntry { .. } => "remove_entry",
+            Request::ReloadDictionaries { .. } => "reload_dictionaries",
+            Request::DictInfo { .. } => "dict_info",
+            Request::UnloadModel { .. } => "unload_model",
+            Request::ZenzaiStatus { .. } => "zenzai_status",
+            Request::Complete { .. } => "complete",
+            Request::ConvertSegment { .. } => "convert_segment",
+            Request::ToKana { .. } => "to_kana",
+            Request::Batch { .. } => "batch",
+            Request::Cancel { .. } => "cancel",
+            Request::Ping { .. } => "ping",
+            Request::SetLogLevel { .. } => "set_log_level",
+        }
+    }
+
+    /// Session the request belongs to, if any
+    pub fn session_id(&self) -> Option<&str> {
+        match self {
+            Request::Init { session_id, .. }
+            | Request::Shutdown { session_id, .. }
+            | Request::Cancel { session_id, .. } => session_id.as_deref(),
+            Request::Convert { session_id, .. }
+            | Request::ConvertBatch { session_id, .. }
+            | Request::Commit { session_id, .. }
+            | Request::UndoCommit { session_id, .. }
+            | Request::AdjustSegment { session_id, .. }
+            | Request::Transform { session_id, .. }
+            | Request::BlockCandidate { session_id, .. }
+            | Request::UnblockCandidate { session_id, .. }
+            | Request::ExportBlocklist { session_id, .. }
+            | Request::ImportBlocklist { session_id, .. }
+            | Request::SetProviders { session_id, .. }
+            | Request::GetStats { session_id, .. }
+            | Request::GetConfig { session_id, .. }
+            | Request::SetConfig { session_id, .. }
+            | Request::SessionStats { session_id, .. }
+            | Request::SessionState { session_id, .. }
+            | Request::ListSessions { session_id, .. }
+            | Request::SetMode { session_id, .. }
+            | Request::AddEntry { session_id, .. }
+            | Request::RemoveEntry { session_id, .. }
+            | Request::ReloadDictionaries { session_id, .. }
+            | Request::DictInfo { session_id, .. }
+            | Request::UnloadModel { session_id, .. }
+            | Request::ZenzaiStatus { session_id, .. }
+            | Request::Complete { session_id, .. }
+            | Request::ConvertSegment { session_id, .. }
+            | Request::ToKana { session_id, .. } => Some(session_id),
+            // Each request of a batch names its own session
+            Request::Batch { .. } => None,
+            Request::Ping { .. } | Request::SetLogLevel { .. } => None,
+        }
+    }
+
+    /// Sessions whose state the request may read after changing it, so
+    /// it must run after the session's earlier requests
+    ///
+    /// Empty for requests that only report on the server.
+    pub fn ordered_sessions(&self) -> Vec<&str> {
+        match self {
+            Request::Batch { requests, .. } => requests
+                .iter()
+                .flat_map(Request::ordered_sessions)
+                .collect(),
+            Request::SessionStats { .. }
+            | Request::ListSessions { .. }
+            | Request::GetStats { .. }
+            | Request::GetConfig { .. }
+            | Request::DictInfo { .. }
+            | Request::ZenzaiStatus { .. }
+            | Request::ExportBlocklist { .. } => Vec::new(),
+            request => request.session_id().into_iter().collect(),
+        }
+    }
+}
+
+/// Candidate chosen in a `session_state` request
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Selection {
+    pub segment: usize,
+    pub candidate: usize,
 }
 
-/// Input segment for adjust_segment request
+/// Input segment for adjust_segment and convert_segment requests
 #[derive(Debug, Deserialize)]
 pub struct SegmentInput {
     pub reading: String,
     pub start: usize,
     pub length: usize,
     pub candidates: Vec<String>,
+    #[serde(default)]
+    pub fixed: bool,
+}
+
+impl From<SegmentInfo> for Segment {
+    fn from(seg: SegmentInfo) -> Self {
+        Self {
+            reading: seg.reading,
+            start: seg.start,
+            length: seg.length,
+            candidates: seg.candidates,
+            fixed: seg.fixed,
+        }
+    }
+}
+
+impl From<SegmentInput> for Segment {
+    fn from(seg: SegmentInput) -> Self {
+        Self {
+            reading: seg.reading,
+            start: seg.start,
+            length: seg.length,
+            candidates: seg.candidates,
+            fixed: seg.fixed,
+        }
+    }
 }
 
 /// Options for conversion (will be used in future phases)
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 pub struct ConvertOptions {
     #[serde(default)]
     pub live: bool,
+    /// In live mode, answer with a `convert_delta` against the previous
+    /// live result of the session when possible
+    #[serde(default)]
+    pub delta: bool,
+    /// Add the half-width katakana form of the reading ("ｱｽﾞｷ") as a candidate
+    #[serde(default)]
+    pub halfwidth_katakana: bool,
+    /// Add the full-width form of readings with ASCII letters or digits
+    /// ("abc" -> "ａｂｃ") as a candidate
+    #[serde(default = "default_fullwidth_alphanumeric")]
+    pub fullwidth_alphanumeric: bool,
+    /// Which conversion engine to use for this request
+    #[serde(default)]
+    pub engine: Engine,
+    /// Send `convert_progress` notifications with Zenzai's partial output
+    /// before the result
+    #[serde(default)]
+    pub stream: bool,
+    /// Zenzai model profile to convert with, instead of the session's
+    #[serde(default)]
+    pub zenzai_profile: Option<String>,
+    /// Convert readings longer than `READING_SOFT_LIMIT` one chunk at a
+    /// time, answering with a `continuation` token for the rest
+    #[serde(default)]
+    pub chunked: bool,
+}
+
+/// Conversion engine a `convert` request asks for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Engine {
+    /// Dictionary only, e.g. for latency-sensitive live preview
+    Dict,
+    /// Zenzai whenever its model is loaded, even if the `zenzai` setting is off
+    Zenzai,
+    /// Zenzai if the `zenzai` setting allows it
+    #[default]
+    Auto,
+}
+
+fn default_fullwidth_alphanumeric() -> bool {
+    true
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            live: false,
+            delta: false,
+            halfwidth_katakana: false,
+            fullwidth_alphanumeric: default_fullwidth_alphanumeric(),
+            engine: Engine::Auto,
+            stream: false,
+            zenzai_profile: None,
+            chunked: false,
+        }
+    }
+}
+
+/// Where a candidate comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateSource {
+    /// SKK dictionaries, including user words and remote dictionaries
+    Dictionary,
+    /// Zenzai neural conversion
+    Zenzai,
+    /// A dynamic provider (dates, greetings, ...)
+    Provider,
+    /// The reading itself, or its katakana form from the fallback policy
+    Reading,
+}
+
+/// What sort of text a candidate is, for candidates other than words
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateKind {
+    /// Emoji, e.g. from SKK-JISYO.emoji ("🍣")
+    Emoji,
+    /// Symbols ("☆", "→"); ordinary punctuation ("。") has no kind
+    Symbol,
+}
+
+impl CandidateKind {
+    /// Kind of a candidate's text, None for words
+    pub fn of(text: &str) -> Option<Self> {
+        if symbol::is_emoji(text) {
+            Some(CandidateKind::Emoji)
+        } else if symbol::is_symbol(text) {
+            Some(CandidateKind::Symbol)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whole-phrase candidate for response
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Candidate {
+    pub text: String,
+    /// Hint for the UI, e.g. "kanji" for "漢字;kanji" in the dictionary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<String>,
+    pub source: CandidateSource,
+    /// Emoji or symbol; absent for words
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<CandidateKind>,
+    /// Ranking score (0.0 - 1.0), higher is better
+    pub score: f64,
+    /// Zenzai's log-probability of the candidate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_prob: Option<f64>,
+    /// Zenzai's lowest token probability in the candidate (0.0 - 1.0)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+}
+
+/// Conversion of one reading of a `convert_batch` request
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchConversion {
+    pub reading: String,
+    pub candidates: Vec<Candidate>,
+    pub segments: Vec<SegmentInfo>,
+    /// Confidence (0.0 - 1.0) that the first candidate is right
+    pub confidence: f64,
+}
+
+/// Reading completing a prefix, with its best candidates
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Completion {
+    pub reading: String,
+    /// Top candidates after blocklist and learning, best first
+    pub candidates: Vec<String>,
 }
 
 /// Segment info for response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct SegmentInfo {
     pub reading: String,
     pub start: usize,
     pub length: usize,
     pub candidates: Vec<String>,
+    /// Latin or numeric run that passes through unconverted
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub fixed: bool,
+    /// Annotations of the segment's candidates, keyed by candidate text
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    /// Kinds of the segment's emoji and symbol candidates, keyed by
+    /// candidate text
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub kinds: BTreeMap<String, CandidateKind>,
 }
 
 impl From<Segment> for SegmentInfo {
@@ -80,10 +642,24 @@ impl From<Segment> for SegmentInfo {
             start: seg.start,
             length: seg.length,
             candidates: seg.candidates,
+            fixed: seg.fixed,
+            annotations: BTreeMap::new(),
+            kinds: BTreeMap::new(),
         }
     }
 }
 
+/// Composition of a session, for `session_state_result`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CompositionInfo {
+    pub reading: String,
+    pub segments: Vec<SegmentInfo>,
+    /// Candidate chosen in each segment, by index
+    pub selected: Vec<usize>,
+    /// Text of the chosen candidates
+    pub text: String,
+}
+
 /// Response types to the client
 #[derive(Debug, Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -93,36 +669,395 @@ pub enum Response {
         session_id: String,
         version: String,
         has_dictionary: bool,
+        /// Dictionaries are still loading; `dictionary_ready` follows
+        dictionary_loading: bool,
         #[serde(skip_serializing_if = "Option::is_none")]
         zenzai_enabled: Option<bool>,
+        /// Encoding of large responses on this connection from now on
+        #[serde(skip_serializing_if = "Option::is_none")]
+        compression: Option<Encoding>,
+        /// Every dictionary file tried, absent while still loading
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        dictionaries: Vec<DictionaryLoad>,
     },
     ConvertResult {
         seq: u64,
         session_id: String,
-        candidates: Vec<String>,
+        candidates: Vec<Candidate>,
         segments: Vec<SegmentInfo>,
+        /// Present when only part of a long reading was converted, which
+        /// `options.chunked` asks for; send it back with the same reading
+        /// to convert the next chunk
+        #[serde(skip_serializing_if = "Option::is_none")]
+        continuation: Option<String>,
+        /// Segment at the request's cursor
+        #[serde(skip_serializing_if = "Option::is_none")]
+        active_segment: Option<usize>,
+        /// Confidence (0.0 - 1.0) that the first candidate is right
+        confidence: f64,
+    },
+    ConvertBatchResult {
+        seq: u64,
+        session_id: String,
+        /// One per reading, in the order of the request
+        results: Vec<BatchConversion>,
+    },
+    /// Live conversion result expressed relative to the result of `base_seq`
+    ConvertDelta {
+        seq: u64,
+        session_id: String,
+        base_seq: u64,
+        removed: Vec<String>,
+        added: Vec<AddedCandidate>,
+        segments: Vec<SegmentChange>,
+        segment_count: usize,
+        /// Segment at the request's cursor
+        #[serde(skip_serializing_if = "Option::is_none")]
+        active_segment: Option<usize>,
+        /// Confidence (0.0 - 1.0) that the first candidate is right
+        confidence: f64,
     },
     AdjustSegmentResult {
         seq: u64,
         session_id: String,
+        /// Combined candidates recomputed from the adjusted segments
+        candidates: Vec<Candidate>,
         segments: Vec<SegmentInfo>,
     },
+    ConvertSegmentResult {
+        seq: u64,
+        session_id: String,
+        segment_index: usize,
+        /// The segment with refreshed candidates
+        segment: SegmentInfo,
+    },
     CommitResult {
         seq: u64,
         session_id: String,
         success: bool,
     },
+    /// `reading` and `candidate` are those of the undone commit
+    UndoCommitResult {
+        seq: u64,
+        session_id: String,
+        undone: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reading: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        candidate: Option<String>,
+    },
+    TransformResult {
+        seq: u64,
+        session_id: String,
+        text: String,
+    },
     ShutdownResult {
         seq: u64,
+        /// Learning data and other state were saved to disk
+        flushed: bool,
+        /// What couldn't be saved
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        errors: Vec<String>,
+    },
+    BlockCandidateResult {
+        seq: u64,
+        session_id: String,
+        /// False if the candidate was already blocked
+        success: bool,
+    },
+    UnblockCandidateResult {
+        seq: u64,
+        session_id: String,
+        /// False if the candidate wasn't blocked
+        success: bool,
+    },
+    ExportBlocklistResult {
+        seq: u64,
+        session_id: String,
+        content: String,
+        count: usize,
+    },
+    ImportBlocklistResult {
+        seq: u64,
+        session_id: String,
+        /// Number of newly blocked pairs
+        imported: usize,
+        /// Blocked pairs after the import
+        count: usize,
+    },
+    SetProvidersResult {
+        seq: u64,
+        session_id: String,
+        /// Effective enablement of every provider for the session
+        providers: BTreeMap<String, bool>,
+    },
+    SessionStatsResult {
+        seq: u64,
+        session_id: String,
+        stats: SessionStats,
+    },
+    SessionStateResult {
+        seq: u64,
+        session_id: String,
+        input_mode: InputMode,
+        context: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        composition: Option<CompositionInfo>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        zenzai_profile: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dictionary_set: Option<String>,
+        /// The dictionary set is still loading; until `dictionary_set_ready`
+        /// the session converts with the default dictionaries
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        dictionary_set_loading: bool,
+    },
+    ModeResult {
+        seq: u64,
+        session_id: String,
+        mode: InputMode,
+    },
+    ListSessionsResult {
+        seq: u64,
+        session_id: String,
+        sessions: Vec<SessionSummary>,
+    },
+    /// Answer to both `get_config` and `set_config`
+    ConfigResult {
+        seq: u64,
+        session_id: String,
+        /// Settings of sessions that didn't set their own
+        global: Settings,
+        /// Values set for this session only
+        session: SettingsPatch,
+        /// Settings used for this session's conversions
+        effective: Settings,
+    },
+    StatsResult {
+        seq: u64,
+        session_id: String,
+        uptime_ms: u64,
+        /// By request type
+        requests: BTreeMap<String, RequestStats>,
+        /// Cache of the remote dictionary, if one is configured
+        #[serde(skip_serializing_if = "Option::is_none")]
+        remote_cache: Option<CacheStats>,
+        memory: MemoryStats,
+    },
+    AddEntryResult {
+        seq: u64,
+        session_id: String,
+        success: bool,
+    },
+    RemoveEntryResult {
+        seq: u64,
+        session_id: String,
+        /// False if the word wasn't in the user dictionary
+        success: bool,
+    },
+    ReloadDictionariesResult {
+        seq: u64,
+        session_id: String,
+        /// Every dictionary file tried, in priority order
+        dictionaries: Vec<DictionaryLoad>,
+        /// Words in the user dictionary
+        user_entries: usize,
+    },
+    DictInfoResult {
+        seq: u64,
+        session_id: String,
+        /// Every dictionary file of the session's set tried by the latest
+        /// load, in priority order
+        dictionaries: Vec<DictionaryLoad>,
+        /// Set the session converts with, if not the default one
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dictionary_set: Option<String>,
+        /// Configured sets besides the default one
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        dictionary_sets: Vec<String>,
+        /// Dictionaries are still loading in the background
+        loading: bool,
+        /// Words in the user dictionary
+        user_entries: usize,
+    },
+    UnloadModelResult {
+        seq: u64,
+        session_id: String,
+        /// False if no model was loaded
+        unloaded: bool,
+    },
+    ZenzaiStatusResult {
+        seq: u64,
+        session_id: String,
+        /// The server was built with the zenzai feature
+        available: bool,
+        /// Zenzai is initialized and converts readings
+        enabled: bool,
+        /// Model file of the default profile, if one was found
+        #[serde(skip_serializing_if = "Option::is_none")]
+        model_path: Option<String>,
+        /// Process running the models, absent while it is stopped
+        #[serde(skip_serializing_if = "Option::is_none")]
+        worker_pid: Option<u32>,
+        /// Models loaded in the worker and their caches
+        #[serde(skip_serializing_if = "Option::is_none")]
+        models: Option<ModelStatus>,
+        /// Why Zenzai isn't enabled, or why its latest conversion failed
+        #[serde(skip_serializing_if = "Option::is_none")]
+        last_error: Option<String>,
+    },
+    CompleteResult {
+        seq: u64,
+        session_id: String,
+        /// Most often committed readings first, then shortest first
+        completions: Vec<Completion>,
+    },
+    ToKanaResult {
+        seq: u64,
+        session_id: String,
+        kana: String,
+        /// Trailing romaji that needs more input ("k", "n")
+        pending: String,
+    },
+    CancelResult {
+        seq: u64,
+        target_seq: u64,
+        /// Whether the target was still queued or being handled
+        cancelled: bool,
+    },
+    PingResult {
+        seq: u64,
+        /// Time since the server started
+        uptime_ms: u64,
+        has_dictionary: bool,
+        dictionary_loading: bool,
+        /// Zenzai model loaded and usable
+        zenzai_ready: bool,
+        /// Other requests received but not answered yet
+        queue_depth: usize,
+    },
+    LogLevelResult {
+        seq: u64,
+        level: LogLevel,
+        /// File messages are written to; stderr if absent
+        #[serde(skip_serializing_if = "Option::is_none")]
+        file: Option<PathBuf>,
+    },
+    /// Response of `size` bytes compressed with `encoding`, base64 encoded
+    Compressed {
+        seq: u64,
+        encoding: Encoding,
+        size: usize,
+        data: String,
+    },
+    /// Part of a response too large for one frame; the `data` of all
+    /// `count` parts, joined in `index` order, is the response
+    Chunk {
+        seq: u64,
+        index: usize,
+        count: usize,
+        data: String,
+    },
+    BatchResult {
+        seq: u64,
+        /// One response per request, in request order
+        responses: Vec<Response>,
     },
     Error {
         seq: u64,
         #[serde(skip_serializing_if = "Option::is_none")]
         session_id: Option<String>,
+        /// Machine-readable error code (see `AzukiError::code`)
+        code: &'static str,
         error: String,
     },
 }
 
+impl Response {
+    /// Build an error response from an `AzukiError`
+    pub fn error(seq: u64, session_id: Option<String>, err: AzukiError) -> Self {
+        Response::Error {
+            seq,
+            session_id,
+            code: err.code(),
+            error: err.to_string(),
+        }
+    }
+}
+
+/// Server-initiated messages, not correlated with any request `seq`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Notification {
+    /// Sent once at startup (opt-in via `AZUKI_HELLO`) before any request
+    Hello {
+        version: String,
+        pid: u32,
+        protocol_versions: Vec<u32>,
+        /// Directory for persistent user data
+        data_dir: Option<String>,
+        /// Dictionary paths searched when `AZUKI_DICTIONARY` is unset
+        dictionary_paths: Vec<String>,
+    },
+    SessionEvicted {
+        session_id: String,
+        reason: EvictionReason,
+    },
+    /// Dictionaries loaded in the background are now used for conversion
+    DictionaryReady {
+        has_dictionary: bool,
+        /// Okuri-nasi entries of the loaded dictionaries
+        entries: usize,
+        elapsed_ms: u64,
+    },
+    /// A dictionary set selected by a session was loaded in the background
+    DictionarySetReady {
+        name: String,
+        /// Okuri-nasi entries of the set's dictionaries
+        entries: usize,
+        elapsed_ms: u64,
+    },
+    /// Dictionaries were loaded again after their files changed or a
+    /// `reload_dictionaries` request
+    DictionaryReloaded {
+        system: bool,
+        user: bool,
+        /// Okuri-nasi entries of the dictionary now in use
+        entries: usize,
+    },
+    /// A Zenzai model finished loading
+    #[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+    ZenzaiReady { elapsed_ms: u64 },
+    /// Text Zenzai has generated so far for a `convert` request that asked
+    /// to stream; the result of the request follows
+    #[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+    ConvertProgress {
+        seq: u64,
+        session_id: String,
+        text: String,
+    },
+    /// Learning data was saved after enough commits
+    LearningSaved {
+        /// Readings with learned candidates
+        entries: usize,
+    },
+    /// Something the user may need to act on
+    ResourceWarning {
+        kind: ResourceWarningKind,
+        message: String,
+    },
+}
+
+/// What a `resource_warning` is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceWarningKind {
+    /// A store couldn't be written to disk
+    Storage,
+    /// Sessions were evicted because the session limit was reached
+    Sessions,
+}
+
 /// Extract seq from raw JSON string (for error handling when parse fails)
 pub fn extract_seq(json: &str) -> Option<u64> {
     let value: serde_json::Value = serde_json::from_str(json).ok()?;
@@ -156,4 +1091,50 @@ mod tests {
         let json = r#"{"seq":"not a number"}"#;
         assert_eq!(extract_seq(json), None);
     }
+
+    #[test]
+    fn test_convert_options_engine() {
+        let options: ConvertOptions = serde_json::from_str(r#"{"engine":"dict"}"#).unwrap();
+        assert_eq!(options.engine, Engine::Dict);
+        let options: ConvertOptions = serde_json::from_str("{}").unwrap();
+        assert_eq!(options.engine, Engine::Auto);
+        assert!(!options.stream);
+        assert!(serde_json::from_str::<ConvertOptions>(r#"{"engine":"neural"}"#).is_err());
+    }
+
+    #[test]
+    fn test_candidate_probabilities_only_for_zenzai() {
+        let mut candidate = Candidate {
+            text: "今日".to_string(),
+            annotation: None,
+            source: CandidateSource::Dictionary,
+            kind: None,
+            score: 1.0,
+            log_prob: None,
+            confidence: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&candidate).unwrap(),
+            r#"{"text":"今日","source":"dictionary","score":1.0}"#
+        );
+        candidate.source = CandidateSource::Zenzai;
+        candidate.log_prob = Some(-0.5);
+        candidate.confidence = Some(0.75);
+        assert_eq!(
+            serde_json::to_string(&candidate).unwrap(),
+            r#"{"text":"今日","source":"zenzai","score":1.0,"log_prob":-0.5,"confidence":0.75}"#
+        );
+    }
+
+    #[test]
+    fn test_candidate_kind_of_text() {
+        assert_eq!(CandidateKind::of("🍣"), Some(CandidateKind::Emoji));
+        assert_eq!(CandidateKind::of("☆"), Some(CandidateKind::Symbol));
+        assert_eq!(CandidateKind::of("寿司"), None);
+        assert_eq!(CandidateKind::of("。"), None);
+        assert_eq!(
+            serde_json::to_string(&CandidateKind::Emoji).unwrap(),
+            r#""emoji""#
+        );
+    }
 }
@@ -0,0 +1,165 @@
+//! Runtime metrics for `get_stats`
+//!
+//! Requests are counted by type, with the latency of the most recent ones
+//! kept for percentiles.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+/// Latencies kept per request type; older ones are dropped
+const MAX_SAMPLES: usize = 1024;
+
+/// Counters and recent latencies of one request type
+#[derive(Debug, Default)]
+struct RequestLog {
+    count: u64,
+    errors: u64,
+    samples: VecDeque<Duration>,
+}
+
+/// Metrics collected while handling requests
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: BTreeMap<&'static str, RequestLog>,
+}
+
+/// Summary of one request type
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RequestStats {
+    pub count: u64,
+    /// Requests answered with an error
+    pub errors: u64,
+    /// Latency percentiles over the most recent requests
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Hits and misses of a cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Share of lookups answered from the cache, absent before any lookup
+    pub hit_rate: Option<f64>,
+}
+
+impl CacheStats {
+    pub fn new(hits: u64, misses: u64) -> Self {
+        let total = hits + misses;
+        Self {
+            hits,
+            misses,
+            hit_rate: (total > 0).then(|| hits as f64 / total as f64),
+        }
+    }
+}
+
+/// Rough memory use of the server's data
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MemoryStats {
+    /// Estimated heap size of dictionary entries parsed into memory
+    pub dictionary_bytes: usize,
+    /// Size of memory-mapped dictionary files
+    pub mapped_bytes: usize,
+    pub user_entries: usize,
+    pub learning_entries: usize,
+    pub sessions: usize,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a handled request of type `kind`
+    pub fn record(&mut self, kind: &'static str, elapsed: Duration, failed: bool) {
+        let log = self.requests.entry(kind).or_default();
+        log.count += 1;
+        if failed {
+            log.errors += 1;
+        }
+        if log.samples.len() == MAX_SAMPLES {
+            log.samples.pop_front();
+        }
+        log.samples.push_back(elapsed);
+    }
+
+    /// Summary of every request type seen so far
+    pub fn request_stats(&self) -> BTreeMap<String, RequestStats> {
+        self.requests
+            .iter()
+            .map(|(kind, log)| {
+                let mut samples: Vec<Duration> = log.samples.iter().copied().collect();
+                samples.sort_unstable();
+                let stats = RequestStats {
+                    count: log.count,
+                    errors: log.errors,
+                    p50_ms: percentile(&samples, 50),
+                    p90_ms: percentile(&samples, 90),
+                    p99_ms: percentile(&samples, 99),
+                    max_ms: samples.last().map_or(0.0, |d| millis(*d)),
+                };
+                (kind.to_string(), stats)
+            })
+            .collect()
+    }
+}
+
+/// Nearest-rank percentile of sorted samples, in milliseconds
+pub fn percentile(sorted: &[Duration], percent: usize) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    millis(sorted[rank - 1])
+}
+
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_stats() {
+        let mut metrics = Metrics::new();
+        for ms in 1..=100 {
+            metrics.record("convert", Duration::from_millis(ms), ms == 100);
+        }
+        metrics.record("commit", Duration::from_millis(3), false);
+
+        let stats = metrics.request_stats();
+        assert_eq!(stats.keys().collect::<Vec<_>>(), ["commit", "convert"]);
+        let convert = &stats["convert"];
+        assert_eq!(convert.count, 100);
+        assert_eq!(convert.errors, 1);
+        assert_eq!(convert.p50_ms, 50.0);
+        assert_eq!(convert.p90_ms, 90.0);
+        assert_eq!(convert.p99_ms, 99.0);
+        assert_eq!(convert.max_ms, 100.0);
+        assert_eq!(stats["commit"].p99_ms, 3.0);
+    }
+
+    #[test]
+    fn test_old_samples_are_dropped() {
+        let mut metrics = Metrics::new();
+        metrics.record("convert", Duration::from_secs(1), false);
+        for _ in 0..MAX_SAMPLES {
+            metrics.record("convert", Duration::from_millis(1), false);
+        }
+        let convert = &metrics.request_stats()["convert"];
+        assert_eq!(convert.count, MAX_SAMPLES as u64 + 1);
+        assert_eq!(convert.max_ms, 1.0);
+    }
+
+    #[test]
+    fn test_cache_hit_rate() {
+        assert_eq!(CacheStats::new(0, 0).hit_rate, None);
+        assert_eq!(CacheStats::new(3, 1).hit_rate, Some(0.75));
+    }
+}
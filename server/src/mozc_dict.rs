@@ -0,0 +1,128 @@
+//! Mozc dictionary format
+//!
+//! Mozc's system dictionary and the UT dictionaries built for it
+//! (mozcdic-ut) are lines of five tab-separated fields: reading, left
+//! id, right id, cost and surface (`きょう<TAB>1851<TAB>1851<TAB>3500<TAB>今日`),
+//! where the ids are part-of-speech classes and a lower cost means a more
+//! likely word. Readings are okuri-nasi; inflected forms are listed as
+//! separate words. The candidates of a reading are ordered by cost, and
+//! the lowest one becomes the reading's lattice word cost. Part-of-speech
+//! ids are Mozc-version specific and not used.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Columns of an entry line
+const COLUMNS: usize = 5;
+
+/// Whether a dictionary file is in the Mozc format
+///
+/// `.tsv` files are; others are if their first entry line has the
+/// numeric id and cost columns, which no SKK or JSON line has.
+pub fn detect(path: &Path, content: &str) -> bool {
+    if path.extension().is_some_and(|ext| ext == "tsv") {
+        return true;
+    }
+    content
+        .lines()
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .and_then(parse_line)
+        .is_some()
+}
+
+/// One line: reading, surface and cost
+pub fn parse_line(line: &str) -> Option<(&str, &str, i32)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < COLUMNS {
+        return None;
+    }
+    fields[1].parse::<u16>().ok()?;
+    fields[2].parse::<u16>().ok()?;
+    let cost = fields[3].parse().ok()?;
+    let (reading, surface) = (fields[0], fields[4]);
+    if reading.is_empty() || surface.is_empty() {
+        return None;
+    }
+    Some((reading, surface, cost))
+}
+
+/// Entries of a Mozc dictionary: reading, candidates cheapest first, and
+/// the cheapest cost
+///
+/// Malformed lines are skipped, as in SKK dictionaries. A surface listed
+/// under several parts of speech keeps its lowest cost.
+pub fn parse(path: &Path, content: &str) -> Vec<(String, Vec<String>, i32)> {
+    let mut words: HashMap<&str, Vec<(&str, i32)>> = HashMap::new();
+    let mut order = Vec::new();
+    let mut skipped = 0;
+    for line in content.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((reading, surface, cost)) = parse_line(line) else {
+            skipped += 1;
+            continue;
+        };
+        let candidates = words.entry(reading).or_insert_with(|| {
+            order.push(reading);
+            Vec::new()
+        });
+        match candidates.iter_mut().find(|(s, _)| *s == surface) {
+            Some((_, existing)) => *existing = (*existing).min(cost),
+            None => candidates.push((surface, cost)),
+        }
+    }
+    if skipped > 0 {
+        log_warn!("Skipped {} malformed lines in {}", skipped, path.display());
+    }
+    order
+        .into_iter()
+        .map(|reading| {
+            let mut candidates = words.remove(reading).unwrap_or_default();
+            // Stable, so equal costs keep the file's order
+            candidates.sort_by_key(|&(_, cost)| cost);
+            let cost = candidates[0].1;
+            let candidates = candidates.into_iter().map(|(s, _)| s.to_string()).collect();
+            (reading.to_string(), candidates, cost)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+# mozcdic-ut sample
+きょう\t1851\t1851\t6000\t京
+きょう\t1851\t1851\t3500\t今日
+きょう\t1920\t1920\t5000\t今日
+へんかん\t1851\t1851\t4200\t変換
+broken line
+";
+
+    #[test]
+    fn test_detect() {
+        assert!(detect(Path::new("mozcdic-ut-place.txt"), SAMPLE));
+        assert!(detect(Path::new("words.tsv"), ""));
+        let skk = ";; okuri-nasi entries.\nきょう /今日/\n";
+        assert!(!detect(Path::new("SKK-JISYO.L"), skk));
+        assert!(!detect(Path::new("words"), "きょう\t今日\t名詞\n"));
+    }
+
+    #[test]
+    fn test_parse_orders_candidates_by_cost() {
+        let entries = parse(Path::new("mozcdic-ut.txt"), SAMPLE);
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    "きょう".to_string(),
+                    vec!["今日".to_string(), "京".to_string()],
+                    3500
+                ),
+                ("へんかん".to_string(), vec!["変換".to_string()], 4200),
+            ]
+        );
+    }
+}
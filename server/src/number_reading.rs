@@ -0,0 +1,354 @@
+//! Numbers read in kana ("ごじゅうよん" -> 54) and numeral candidates
+//!
+//! Readings that are a number, written in digits or spelled out in kana,
+//! optionally followed by a counter ("さんびき", "54えん"), get candidates
+//! for the number in kanji with units, kanji digits, ASCII digits and
+//! full-width digits. Kana numbers need at least one unit (じゅう, せん,
+//! まん, ...) or a counter, so words like "さん" or "ご" aren't taken for
+//! numbers.
+
+use crate::numeric;
+
+/// Kana spellings of digits, including the forms before units and counters
+const DIGITS: [(&str, u64); 17] = [
+    ("ぜろ", 0),
+    ("れい", 0),
+    ("いち", 1),
+    ("いっ", 1),
+    ("に", 2),
+    ("さん", 3),
+    ("よん", 4),
+    ("し", 4),
+    ("ご", 5),
+    ("ろく", 6),
+    ("ろっ", 6),
+    ("なな", 7),
+    ("しち", 7),
+    ("はち", 8),
+    ("はっ", 8),
+    ("きゅう", 9),
+    ("く", 9),
+];
+
+/// Units within a group of four digits, with their sound changes
+const UNITS: [(&str, u64); 8] = [
+    ("じゅう", 10),
+    ("じゅっ", 10),
+    ("じっ", 10),
+    ("ひゃく", 100),
+    ("びゃく", 100),
+    ("ぴゃく", 100),
+    ("せん", 1000),
+    ("ぜん", 1000),
+];
+
+/// Units for groups of four digits
+const GROUPS: [(&str, u64); 4] = [
+    ("まん", 10_000),
+    ("おく", 100_000_000),
+    ("ちょう", 1_000_000_000_000),
+    ("けい", 10_000_000_000_000_000),
+];
+
+/// Counter readings (with sound changes) and how they are written
+const COUNTERS: [(&str, &str); 28] = [
+    ("こ", "個"),
+    ("ほん", "本"),
+    ("ぼん", "本"),
+    ("ぽん", "本"),
+    ("ひき", "匹"),
+    ("びき", "匹"),
+    ("ぴき", "匹"),
+    ("にん", "人"),
+    ("かい", "回"),
+    ("えん", "円"),
+    ("ねん", "年"),
+    ("がつ", "月"),
+    ("にち", "日"),
+    ("じ", "時"),
+    ("じかん", "時間"),
+    ("ふん", "分"),
+    ("ぷん", "分"),
+    ("びょう", "秒"),
+    ("まい", "枚"),
+    ("さつ", "冊"),
+    ("だい", "台"),
+    ("ど", "度"),
+    ("ばん", "番"),
+    ("さい", "歳"),
+    ("けん", "件"),
+    ("ばい", "倍"),
+    ("かげつ", "か月"),
+    ("ぱーせんと", "%"),
+];
+
+/// A number followed by an optional counter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumberReading {
+    /// The number in ASCII digits
+    pub digits: String,
+    /// How the counter is written, empty without one
+    pub counter: &'static str,
+    /// Whether the number was spelled out in kana
+    pub spelled: bool,
+}
+
+/// Parse a reading that is a number with an optional counter
+pub fn parse(reading: &str) -> Option<NumberReading> {
+    // The longest number prefix whose remainder is a counter
+    let boundaries: Vec<usize> = reading
+        .char_indices()
+        .map(|(i, _)| i)
+        .skip(1)
+        .chain([reading.len()])
+        .collect();
+    for &end in boundaries.iter().rev() {
+        let (number, rest) = reading.split_at(end);
+        let counter = match rest {
+            "" => "",
+            _ => match counter(rest) {
+                Some(counter) => counter,
+                None => continue,
+            },
+        };
+        if let Some(digits) = parse_digits(number) {
+            return Some(NumberReading {
+                digits,
+                counter,
+                spelled: false,
+            });
+        }
+        if let Some(value) = parse_kana(number, !counter.is_empty()) {
+            return Some(NumberReading {
+                digits: value.to_string(),
+                counter,
+                spelled: true,
+            });
+        }
+    }
+    None
+}
+
+impl NumberReading {
+    /// Numeral candidates for the reading this was parsed from, with the
+    /// annotation of their form
+    ///
+    /// Kanji with units come first, then kanji digits, ASCII digits and
+    /// full-width digits; forms equal to the reading are left out.
+    pub fn candidates(&self, reading: &str) -> Vec<(String, &'static str)> {
+        let mut templates = vec!["#3"];
+        if self.digits.len() > 1 {
+            templates.push("#2");
+        }
+        if self.spelled {
+            templates.push("#0");
+        }
+        templates.push("#1");
+
+        let mut result: Vec<(String, &'static str)> = Vec::new();
+        for template in templates {
+            let annotation = numeric::annotation(template).unwrap_or_default();
+            for form in numeric::expand(template, std::slice::from_ref(&self.digits), |_| None) {
+                let candidate = format!("{}{}", form, self.counter);
+                if candidate != reading && result.iter().all(|(c, _)| *c != candidate) {
+                    result.push((candidate, annotation));
+                }
+            }
+        }
+        result
+    }
+}
+
+fn counter(reading: &str) -> Option<&'static str> {
+    COUNTERS
+        .iter()
+        .find(|(kana, _)| *kana == reading)
+        .map(|(_, written)| *written)
+}
+
+/// ASCII or full-width digits
+fn parse_digits(text: &str) -> Option<String> {
+    let (key, numbers) = numeric::split_numbers(text)?;
+    (key == "#").then(|| numbers.into_iter().next()).flatten()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Token {
+    Digit(u64),
+    Unit(u64),
+    Group(u64),
+}
+
+/// Value of a number spelled in kana
+///
+/// Words written with a small っ ("いっ", "じゅっ") must be followed by a
+/// unit or, with `counter`, end the number.
+fn parse_kana(text: &str, counter: bool) -> Option<u64> {
+    let tokens = tokenize(text)?;
+    let has_unit = tokens
+        .iter()
+        .any(|(token, _)| matches!(token, Token::Unit(_) | Token::Group(_)));
+    if !has_unit {
+        // A lone digit before a counter; "し" and "く" are too ambiguous
+        // ("しかい", "くじ")
+        match tokens.as_slice() {
+            [(Token::Digit(_), kana)] if counter && !matches!(*kana, "し" | "く") => {}
+            _ => return None,
+        }
+    }
+
+    let mut total: u64 = 0;
+    let mut group: u64 = 0;
+    let mut pending: Option<u64> = None;
+    let mut last_unit = u64::MAX;
+    let mut last_group = u64::MAX;
+    for (i, &(token, kana)) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1).map(|(token, _)| *token);
+        if kana.ends_with('っ') {
+            let before_unit = matches!(token, Token::Digit(_))
+                && matches!(next, Some(Token::Unit(_) | Token::Group(_)));
+            if !before_unit && (next.is_some() || !counter) {
+                return None;
+            }
+        }
+        match token {
+            Token::Digit(d) => {
+                if pending.is_some() {
+                    return None;
+                }
+                pending = Some(d);
+            }
+            Token::Unit(unit) => {
+                // Voiced forms only follow a digit ("さんびゃく", not "びゃく")
+                if !kana.starts_with(['ひ', 'せ', 'じ']) && pending.is_none() {
+                    return None;
+                }
+                if unit >= last_unit {
+                    return None;
+                }
+                group += pending.take().unwrap_or(1) * unit;
+                last_unit = unit;
+            }
+            Token::Group(unit) => {
+                group += pending.take().unwrap_or(0);
+                if group == 0 || unit >= last_group {
+                    return None;
+                }
+                total = total.checked_add(group.checked_mul(unit)?)?;
+                group = 0;
+                last_unit = u64::MAX;
+                last_group = unit;
+            }
+        }
+    }
+    total.checked_add(group + pending.unwrap_or(0))
+}
+
+/// Split kana into number words, longest match first
+fn tokenize(text: &str) -> Option<Vec<(Token, &'static str)>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let words = DIGITS
+            .iter()
+            .map(|&(kana, value)| (kana, Token::Digit(value)))
+            .chain(
+                UNITS
+                    .iter()
+                    .map(|&(kana, value)| (kana, Token::Unit(value))),
+            )
+            .chain(
+                GROUPS
+                    .iter()
+                    .map(|&(kana, value)| (kana, Token::Group(value))),
+            );
+        let (kana, token) = words
+            .filter(|(kana, _)| rest.starts_with(kana))
+            .max_by_key(|(kana, _)| kana.len())?;
+        tokens.push((token, kana));
+        rest = &rest[kana.len()..];
+    }
+    Some(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(reading: &str) -> Option<String> {
+        parse(reading).map(|number| number.digits)
+    }
+
+    fn candidates(reading: &str) -> Vec<String> {
+        parse(reading)
+            .map(|number| number.candidates(reading))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(candidate, _)| candidate)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_kana_numbers() {
+        assert_eq!(value("せん").as_deref(), Some("1000"));
+        assert_eq!(value("ごじゅうよん").as_deref(), Some("54"));
+        assert_eq!(value("さんびゃくろくじゅうご").as_deref(), Some("365"));
+        assert_eq!(value("ろっぴゃく").as_deref(), Some("600"));
+        assert_eq!(value("はっせんきゅうひゃく").as_deref(), Some("8900"));
+        assert_eq!(value("にせんにじゅうろく").as_deref(), Some("2026"));
+        // Not numbers
+        assert_eq!(value("さん"), None);
+        assert_eq!(value("ご"), None);
+        assert_eq!(value("ぜん"), None);
+        assert_eq!(value("まん"), None);
+        assert_eq!(value("ひゃくせん"), None);
+        assert_eq!(value("ごよん"), None);
+        assert_eq!(value("いっ"), None);
+    }
+
+    #[test]
+    fn test_parse_large_values() {
+        assert_eq!(value("いちまん").as_deref(), Some("10000"));
+        assert_eq!(
+            value("さんおくにせんまんいっせん").as_deref(),
+            Some("320001000")
+        );
+        assert_eq!(
+            value("にちょうごせんおく").as_deref(),
+            Some("2500000000000")
+        );
+        assert_eq!(value("いちけい").as_deref(), Some("10000000000000000"));
+        // Beyond u64
+        assert_eq!(value("にせんけい"), None);
+        // Groups must decrease
+        assert_eq!(value("いちまんにおく"), None);
+    }
+
+    #[test]
+    fn test_parse_counters() {
+        let number = parse("さんびき").unwrap();
+        assert_eq!(number.digits, "3");
+        assert_eq!(number.counter, "匹");
+        assert_eq!(value("いっぽん").as_deref(), Some("1"));
+        assert_eq!(value("じゅっぽん").as_deref(), Some("10"));
+        assert_eq!(value("にじゅうにん").as_deref(), Some("20"));
+        assert_eq!(value("54えん").as_deref(), Some("54"));
+        // A counter alone is no number
+        assert_eq!(value("えん"), None);
+    }
+
+    #[test]
+    fn test_candidates() {
+        assert_eq!(
+            candidates("せん"),
+            vec!["千", "一〇〇〇", "1000", "１０００"]
+        );
+        assert_eq!(candidates("54"), vec!["五十四", "五四", "５４"]);
+        assert_eq!(candidates("5"), vec!["五", "５"]);
+        assert_eq!(candidates("さんびき"), vec!["三匹", "3匹", "３匹"]);
+        assert!(candidates("かんじ").is_empty());
+        let annotated = parse("ごじゅうよん").unwrap().candidates("ごじゅうよん");
+        assert_eq!(annotated[0], ("五十四".to_string(), "漢数字"));
+        assert_eq!(annotated[2], ("54".to_string(), "数字"));
+    }
+}
@@ -0,0 +1,261 @@
+//! SKK numeric conversion (`#0`–`#5` placeholders)
+//!
+//! SKK dictionaries store entries for numbers under keys where each digit
+//! run of the reading is replaced by `#` (e.g. "#がつ /#0月/#1月/#3月/").
+//! The candidate placeholders say how each number is written:
+//!
+//! - `#0`: as typed ("3")
+//! - `#1`: full-width digits ("３")
+//! - `#2`: kanji digits ("二〇二六")
+//! - `#3`: kanji numerals with units ("二千二十六")
+//! - `#4`: the number looked up again as a reading
+//! - `#5`: formal numerals (daiji, "弐阡弐拾六")
+
+/// Kanji for digits 0-9
+const KANJI_DIGITS: [char; 10] = ['〇', '一', '二', '三', '四', '五', '六', '七', '八', '九'];
+
+/// Formal numerals (daiji) for digits 0-9
+const DAIJI_DIGITS: [char; 10] = ['〇', '壱', '弐', '参', '四', '伍', '六', '七', '八', '九'];
+
+/// Units within a group of four digits: thousands, hundreds, tens
+const KANJI_UNITS: [&str; 3] = ["千", "百", "十"];
+const DAIJI_UNITS: [&str; 3] = ["阡", "百", "拾"];
+
+/// Units for groups of four digits
+const KANJI_GROUPS: [&str; 6] = ["", "万", "億", "兆", "京", "垓"];
+const DAIJI_GROUPS: [&str; 6] = ["", "萬", "億", "兆", "京", "垓"];
+
+/// Replace each digit run of a reading with `#`
+///
+/// Returns the dictionary key and the numbers (as ASCII digits), or None
+/// if the reading has no digits. Full-width digits count as digits.
+pub fn split_numbers(reading: &str) -> Option<(String, Vec<String>)> {
+    let mut key = String::with_capacity(reading.len());
+    let mut numbers: Vec<String> = Vec::new();
+    let mut in_number = false;
+    for c in reading.chars() {
+        match ascii_digit(c) {
+            Some(d) => {
+                if !in_number {
+                    key.push('#');
+                    numbers.push(String::new());
+                    in_number = true;
+                }
+                if let Some(number) = numbers.last_mut() {
+                    number.push(d);
+                }
+            }
+            None => {
+                key.push(c);
+                in_number = false;
+            }
+        }
+    }
+    (!numbers.is_empty()).then_some((key, numbers))
+}
+
+/// Whether a character is an ASCII or full-width digit
+pub fn is_digit(c: char) -> bool {
+    ascii_digit(c).is_some()
+}
+
+fn ascii_digit(c: char) -> Option<char> {
+    match c {
+        '0'..='9' => Some(c),
+        '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32),
+        _ => None,
+    }
+}
+
+/// What a candidate's numbers are written as, by its first placeholder
+///
+/// Shown as the annotation of expanded candidates ("#3月" -> "漢数字").
+pub fn annotation(template: &str) -> Option<&'static str> {
+    let at = template.find('#')?;
+    match template[at + 1..].chars().next()? {
+        '0' => Some("数字"),
+        '1' => Some("全角数字"),
+        '2' | '3' => Some("漢数字"),
+        '4' => Some("数値"),
+        '5' => Some("大字"),
+        _ => None,
+    }
+}
+
+/// Fill the placeholders of a candidate with numbers, in order
+///
+/// `lookup` resolves `#4`; each of its candidates yields one result.
+/// Returns nothing when the candidate uses an unsupported placeholder or
+/// more numbers than given.
+pub fn expand<F>(template: &str, numbers: &[String], lookup: F) -> Vec<String>
+where
+    F: Fn(&str) -> Option<Vec<String>>,
+{
+    let mut results = vec![String::new()];
+    let mut numbers = numbers.iter();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        let placeholder = match (c, chars.peek()) {
+            ('#', Some(&d)) if d.is_ascii_digit() => d,
+            _ => {
+                results.iter_mut().for_each(|r| r.push(c));
+                continue;
+            }
+        };
+        chars.next();
+        let Some(number) = numbers.next() else {
+            return Vec::new();
+        };
+        let forms = match placeholder {
+            '0' => vec![number.clone()],
+            '1' => vec![fullwidth(number)],
+            '2' => vec![number.chars().map(kanji_digit).collect()],
+            '3' => with_units(number, &KANJI_DIGITS, &KANJI_UNITS, &KANJI_GROUPS, false)
+                .into_iter()
+                .collect(),
+            '4' => lookup(number).unwrap_or_default(),
+            '5' => with_units(number, &DAIJI_DIGITS, &DAIJI_UNITS, &DAIJI_GROUPS, true)
+                .into_iter()
+                .collect(),
+            _ => Vec::new(),
+        };
+        if forms.is_empty() {
+            return Vec::new();
+        }
+        results = results
+            .iter()
+            .flat_map(|prefix| forms.iter().map(move |form| format!("{}{}", prefix, form)))
+            .collect();
+    }
+    results
+}
+
+fn fullwidth(number: &str) -> String {
+    number
+        .chars()
+        .filter_map(|d| char::from_u32(d as u32 - '0' as u32 + '０' as u32))
+        .collect()
+}
+
+fn kanji_digit(d: char) -> char {
+    KANJI_DIGITS[d.to_digit(10).unwrap_or(0) as usize]
+}
+
+/// Write a number with units ("2026" -> "二千二十六")
+///
+/// `explicit_one` keeps the one before units ("壱拾" instead of "拾").
+/// Returns None for numbers beyond the largest unit.
+fn with_units(
+    number: &str,
+    digits: &[char; 10],
+    units: &[&str; 3],
+    groups: &[&str; 6],
+    explicit_one: bool,
+) -> Option<String> {
+    let number = number.trim_start_matches('0');
+    if number.is_empty() {
+        return Some(digits[0].to_string());
+    }
+    let values: Vec<usize> = number
+        .chars()
+        .map(|d| d.to_digit(10).unwrap_or(0) as usize)
+        .collect();
+    if values.len() > groups.len() * 4 {
+        return None;
+    }
+
+    let mut result = String::new();
+    // Pad to whole groups of four, most significant first
+    let padded: Vec<usize> = std::iter::repeat_n(0, (4 - values.len() % 4) % 4)
+        .chain(values)
+        .collect();
+    let group_count = padded.len() / 4;
+    for (i, group) in padded.chunks(4).enumerate() {
+        if group.iter().all(|&d| d == 0) {
+            continue;
+        }
+        for (&d, unit) in group[..3].iter().zip(units) {
+            if d == 0 {
+                continue;
+            }
+            if d != 1 || explicit_one {
+                result.push(digits[d]);
+            }
+            result.push_str(unit);
+        }
+        if group[3] != 0 {
+            result.push(digits[group[3]]);
+        }
+        result.push_str(groups[group_count - 1 - i]);
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_all(template: &str, numbers: &[&str]) -> Vec<String> {
+        let numbers: Vec<String> = numbers.iter().map(|n| n.to_string()).collect();
+        expand(template, &numbers, |n| {
+            (n == "1").then(|| vec!["壱".to_string(), "一".to_string()])
+        })
+    }
+
+    #[test]
+    fn test_split_numbers() {
+        assert_eq!(
+            split_numbers("3がつ12にち"),
+            Some(("#がつ#にち".to_string(), vec!["3".into(), "12".into()]))
+        );
+        assert_eq!(
+            split_numbers("だい１０"),
+            Some(("だい#".to_string(), vec!["10".into()]))
+        );
+        assert_eq!(split_numbers("がつ"), None);
+    }
+
+    #[test]
+    fn test_expand_placeholders() {
+        assert_eq!(expand_all("#0月", &["3"]), vec!["3月"]);
+        assert_eq!(expand_all("#1月", &["12"]), vec!["１２月"]);
+        assert_eq!(expand_all("#2年", &["2026"]), vec!["二〇二六年"]);
+        assert_eq!(expand_all("#3年", &["2026"]), vec!["二千二十六年"]);
+        assert_eq!(expand_all("#4", &["1"]), vec!["壱", "一"]);
+        assert_eq!(expand_all("#5円", &["2026"]), vec!["弐阡弐拾六円"]);
+        assert_eq!(expand_all("#0/#0", &["3", "12"]), vec!["3/12"]);
+        assert_eq!(expand_all("C#", &[]), vec!["C#"]);
+    }
+
+    #[test]
+    fn test_unsupported_placeholders() {
+        assert!(expand_all("#9", &["3"]).is_empty());
+        assert!(expand_all("#0月#0日", &["3"]).is_empty());
+        assert!(expand_all("#4", &["2"]).is_empty());
+    }
+
+    #[test]
+    fn test_annotation() {
+        assert_eq!(annotation("#3月"), Some("漢数字"));
+        assert_eq!(annotation("第#1"), Some("全角数字"));
+        assert_eq!(annotation("#5円"), Some("大字"));
+        assert_eq!(annotation("月"), None);
+        assert_eq!(annotation("#9"), None);
+    }
+
+    #[test]
+    fn test_kanji_with_units() {
+        let kanji = |n: &str| with_units(n, &KANJI_DIGITS, &KANJI_UNITS, &KANJI_GROUPS, false);
+        assert_eq!(kanji("0").unwrap(), "〇");
+        assert_eq!(kanji("10").unwrap(), "十");
+        assert_eq!(kanji("111").unwrap(), "百十一");
+        assert_eq!(kanji("10000").unwrap(), "一万");
+        assert_eq!(kanji("100000001").unwrap(), "一億一");
+        assert_eq!(kanji("12345678").unwrap(), "千二百三十四万五千六百七十八");
+        assert!(kanji(&"9".repeat(25)).is_none());
+
+        let daiji = |n: &str| with_units(n, &DAIJI_DIGITS, &DAIJI_UNITS, &DAIJI_GROUPS, true);
+        assert_eq!(daiji("10").unwrap(), "壱拾");
+        assert_eq!(daiji("10000").unwrap(), "壱萬");
+    }
+}
@@ -1,27 +1,242 @@
 //! Length-prefixed JSON protocol for stdio communication
+//!
+//! With `--ndjson` the same messages are sent one per line instead, for
+//! clients without binary framing code. Large responses are compressed for
+//! clients that negotiated it, and responses still larger than the maximum
+//! message size are sent as several `chunk` frames.
 
+use crate::compress::{self, Encoding};
+use crate::error::{AzukiError, Result};
+use crate::message::{extract_seq, Response};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use std::io::{self, Read, Write};
+use std::io::{self, BufRead, Read, Write};
 
-/// Maximum message size (4MB)
+/// Protocol versions this server speaks
+pub const PROTOCOL_VERSIONS: &[u32] = &[1];
+
+/// Default maximum message size (4MB)
 pub const MAX_MESSAGE_SIZE: u32 = 4 * 1024 * 1024;
 
+/// Smallest maximum message size accepted, leaving room for chunk frames
+pub const MIN_MESSAGE_SIZE: u32 = 1024;
+
+/// Bytes of a `chunk` frame besides its data
+const CHUNK_OVERHEAD: usize = 128;
+
+/// How messages are delimited on a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// u32 big-endian length before each message
+    #[default]
+    LengthPrefixed,
+    /// One message per line (newline-delimited JSON)
+    Ndjson,
+}
+
+/// Message format spoken on a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// Messages tagged with `type`, matched by `seq`
+    #[default]
+    Native,
+    /// JSON-RPC 2.0 (see `jsonrpc`)
+    JsonRpc,
+}
+
+/// How messages are exchanged with a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transport {
+    pub framing: Framing,
+    pub dialect: Dialect,
+    /// Largest message read or written in one frame
+    pub max_message_size: u32,
+    /// Size from which responses are compressed
+    pub compression_threshold: usize,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self {
+            framing: Framing::default(),
+            dialect: Dialect::default(),
+            max_message_size: MAX_MESSAGE_SIZE,
+            compression_threshold: compress::DEFAULT_THRESHOLD,
+        }
+    }
+}
+
+impl Transport {
+    /// Read one message, or None on EOF
+    pub fn read<R: BufRead>(&self, reader: &mut R) -> Result<Option<String>> {
+        self.framing.read(reader, self.max_message_size)
+    }
+
+    /// Write one message
+    pub fn write<W: Write>(&self, writer: &mut W, msg: &str) -> Result<()> {
+        self.framing.write(writer, msg)
+    }
+
+    /// Encoding for a client offering `offered`, if any
+    ///
+    /// JSON-RPC clients know nothing of compressed frames or chunks.
+    pub fn negotiate(&self, offered: &[String]) -> Option<Encoding> {
+        match self.dialect {
+            Dialect::Native => Encoding::negotiate(offered),
+            Dialect::JsonRpc => None,
+        }
+    }
+
+    /// Frames carrying a serialized response
+    ///
+    /// It is compressed with `encoding` if large enough, then split if it
+    /// is too large for one frame.
+    pub fn response_frames(&self, msg: String, encoding: Option<Encoding>) -> Vec<String> {
+        if self.dialect == Dialect::JsonRpc {
+            return vec![msg];
+        }
+        let msg = match encoding {
+            Some(encoding) if msg.len() >= self.compression_threshold => {
+                compress_response(msg, encoding)
+            }
+            _ => msg,
+        };
+        if msg.len() <= self.max_message_size as usize {
+            return vec![msg];
+        }
+        chunk_response(&msg, self.max_message_size as usize)
+    }
+}
+
+/// Wrap a response in a `compressed` frame
+///
+/// The response goes out as it is if it can't be compressed.
+fn compress_response(msg: String, encoding: Encoding) -> String {
+    let data = match encoding.compress(msg.as_bytes()) {
+        Ok(data) => data,
+        Err(e) => {
+            log_warn!("Sending response uncompressed: {}", e);
+            return msg;
+        }
+    };
+    let compressed = Response::Compressed {
+        seq: extract_seq(&msg).unwrap_or(0),
+        encoding,
+        size: msg.len(),
+        data: compress::base64(&data),
+    };
+    serde_json::to_string(&compressed).expect("Failed to serialize compressed response")
+}
+
+/// Split a response into `chunk` frames of at most `max_size` bytes
+///
+/// Pieces of the text are sent as JSON strings, which escaping makes at
+/// most twice as long, so each piece takes under half a frame.
+fn chunk_response(msg: &str, max_size: usize) -> Vec<String> {
+    let seq = extract_seq(msg).unwrap_or(0);
+    let piece_size = (max_size - CHUNK_OVERHEAD) / 2;
+    let mut pieces = Vec::new();
+    let mut rest = msg;
+    while !rest.is_empty() {
+        let mut end = piece_size.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece);
+        rest = tail;
+    }
+    let count = pieces.len();
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| {
+            let chunk = Response::Chunk {
+                seq,
+                index,
+                count,
+                data: data.to_string(),
+            };
+            serde_json::to_string(&chunk).expect("Failed to serialize chunk")
+        })
+        .collect()
+}
+
+impl Framing {
+    /// Read one message of at most `max_size` bytes, or None on EOF
+    pub fn read<R: BufRead>(self, reader: &mut R, max_size: u32) -> Result<Option<String>> {
+        match self {
+            Framing::LengthPrefixed => read_message(reader, max_size),
+            Framing::Ndjson => read_line_message(reader, max_size),
+        }
+    }
+
+    /// Write one message
+    pub fn write<W: Write>(self, writer: &mut W, msg: &str) -> Result<()> {
+        match self {
+            Framing::LengthPrefixed => write_message(writer, msg),
+            Framing::Ndjson => write_line_message(writer, msg),
+        }
+    }
+}
+
+/// Read a newline-delimited message from a reader
+///
+/// Blank lines are skipped and a trailing `\r` is ignored. Returns None on
+/// EOF.
+pub fn read_line_message<R: BufRead>(reader: &mut R, max_size: u32) -> Result<Option<String>> {
+    loop {
+        let mut line = Vec::new();
+        let limit = u64::from(max_size) + 1;
+        let read = reader.by_ref().take(limit).read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        } else if read as u64 == limit {
+            return Err(AzukiError::Protocol(format!(
+                "Message too large: over {} bytes",
+                max_size
+            )));
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        let line = String::from_utf8(line)
+            .map_err(|e| AzukiError::Protocol(format!("Invalid UTF-8: {}", e)))?;
+        if !line.trim().is_empty() {
+            return Ok(Some(line));
+        }
+    }
+}
+
+/// Write a message as one line
+///
+/// Serialized JSON never contains a raw newline, so messages need no
+/// escaping.
+pub fn write_line_message<W: Write>(writer: &mut W, msg: &str) -> Result<()> {
+    writer.write_all(msg.as_bytes())?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
 /// Read a length-prefixed message from a reader
 ///
 /// Message format: [u32 big-endian length][JSON bytes]
 /// Returns None on EOF.
-pub fn read_message<R: Read>(reader: &mut R) -> io::Result<Option<String>> {
+pub fn read_message<R: Read>(reader: &mut R, max_size: u32) -> Result<Option<String>> {
     let len = match reader.read_u32::<BigEndian>() {
         Ok(len) => len,
         Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
-        Err(e) => return Err(e),
+        Err(e) => return Err(e.into()),
     };
 
-    if len > MAX_MESSAGE_SIZE {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!("Message too large: {} bytes", len),
-        ));
+    if len > max_size {
+        return Err(AzukiError::Protocol(format!(
+            "Message too large: {} bytes",
+            len
+        )));
     }
 
     let mut buf = vec![0u8; len as usize];
@@ -29,17 +244,18 @@ pub fn read_message<R: Read>(reader: &mut R) -> io::Result<Option<String>> {
 
     String::from_utf8(buf)
         .map(Some)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid UTF-8: {}", e)))
+        .map_err(|e| AzukiError::Protocol(format!("Invalid UTF-8: {}", e)))
 }
 
 /// Write a length-prefixed message to a writer
 ///
 /// Message format: [u32 big-endian length][JSON bytes]
-pub fn write_message<W: Write>(writer: &mut W, msg: &str) -> io::Result<()> {
+pub fn write_message<W: Write>(writer: &mut W, msg: &str) -> Result<()> {
     let bytes = msg.as_bytes();
     writer.write_u32::<BigEndian>(bytes.len() as u32)?;
     writer.write_all(bytes)?;
-    writer.flush()
+    writer.flush()?;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -54,24 +270,120 @@ mod tests {
         write_message(&mut buf, msg).unwrap();
 
         let mut cursor = Cursor::new(buf);
-        let read_msg = read_message(&mut cursor).unwrap().unwrap();
+        let read_msg = read_message(&mut cursor, MAX_MESSAGE_SIZE)
+            .unwrap()
+            .unwrap();
         assert_eq!(read_msg, msg);
     }
 
     #[test]
     fn test_read_eof() {
         let mut cursor = Cursor::new(Vec::new());
-        let result = read_message(&mut cursor).unwrap();
+        let result = read_message(&mut cursor, MAX_MESSAGE_SIZE).unwrap();
         assert!(result.is_none());
     }
 
+    #[test]
+    fn test_read_write_line_message() {
+        let mut buf = Vec::new();
+        write_line_message(&mut buf, r#"{"type":"init","seq":1}"#).unwrap();
+        buf.extend_from_slice(b"\r\n\n{\"type\":\"shutdown\",\"seq\":2}");
+        assert_eq!(buf.iter().filter(|&&b| b == b'\n').count(), 3);
+
+        let mut cursor = Cursor::new(buf);
+        let framing = Framing::Ndjson;
+        assert_eq!(
+            framing
+                .read(&mut cursor, MAX_MESSAGE_SIZE)
+                .unwrap()
+                .unwrap(),
+            r#"{"type":"init","seq":1}"#
+        );
+        // Blank lines are skipped; the last line needs no newline
+        assert_eq!(
+            framing
+                .read(&mut cursor, MAX_MESSAGE_SIZE)
+                .unwrap()
+                .unwrap(),
+            r#"{"type":"shutdown","seq":2}"#
+        );
+        assert!(framing
+            .read(&mut cursor, MAX_MESSAGE_SIZE)
+            .unwrap()
+            .is_none());
+    }
+
     #[test]
     fn test_message_too_large() {
         let mut buf = Vec::new();
         buf.write_u32::<BigEndian>(MAX_MESSAGE_SIZE + 1).unwrap();
 
         let mut cursor = Cursor::new(buf);
-        let result = read_message(&mut cursor);
-        assert!(result.is_err());
+        let result = read_message(&mut cursor, MAX_MESSAGE_SIZE);
+        assert!(matches!(result, Err(AzukiError::Protocol(_))));
+    }
+
+    #[test]
+    fn test_configured_limit() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &"x".repeat(2000)).unwrap();
+        let result = read_message(&mut Cursor::new(&buf), MIN_MESSAGE_SIZE);
+        assert!(matches!(result, Err(AzukiError::Protocol(_))));
+        assert!(read_message(&mut Cursor::new(&buf), 2000)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_large_responses_are_compressed() {
+        let transport = Transport {
+            compression_threshold: 64,
+            ..Transport::default()
+        };
+        let small = r#"{"type":"ping_result","seq":3}"#.to_string();
+        let frames = transport.response_frames(small.clone(), Some(Encoding::Deflate));
+        assert_eq!(frames, vec![small]);
+
+        let msg =
+            serde_json::json!({"type": "complete_result", "seq": 9, "text": "あ".repeat(100)})
+                .to_string();
+        assert_eq!(
+            transport.response_frames(msg.clone(), None),
+            vec![msg.clone()]
+        );
+        let frames = transport.response_frames(msg.clone(), Some(Encoding::Deflate));
+        let frame: serde_json::Value = serde_json::from_str(&frames[0]).unwrap();
+        assert_eq!(frame["type"], "compressed");
+        assert_eq!(frame["seq"], 9);
+        assert_eq!(frame["encoding"], "deflate");
+        assert_eq!(frame["size"], msg.len());
+        assert!(frames[0].len() < msg.len());
+    }
+
+    #[test]
+    fn test_large_responses_are_chunked() {
+        let transport = Transport {
+            max_message_size: MIN_MESSAGE_SIZE,
+            ..Transport::default()
+        };
+        let small = r#"{"type":"ping_result","seq":3}"#.to_string();
+        assert_eq!(transport.response_frames(small.clone(), None), vec![small]);
+
+        // Quotes double in size when escaped; multibyte text must not split
+        let text = "\"日本語\"".repeat(200);
+        let msg = serde_json::json!({"type": "convert_result", "seq": 7, "text": text}).to_string();
+        let frames = transport.response_frames(msg.clone(), None);
+        assert!(frames.len() > 1);
+        let mut joined = String::new();
+        for (index, frame) in frames.iter().enumerate() {
+            assert!(frame.len() <= MIN_MESSAGE_SIZE as usize);
+            let chunk: serde_json::Value = serde_json::from_str(frame).unwrap();
+            assert_eq!(chunk["type"], "chunk");
+            assert_eq!(chunk["seq"], 7);
+            assert_eq!(chunk["index"], index);
+            assert_eq!(chunk["count"], frames.len());
+            joined.push_str(chunk["data"].as_str().unwrap());
+        }
+        assert_eq!(joined, msg);
     }
 }
@@ -0,0 +1,304 @@
+//! Dynamic candidate providers
+//!
+//! Providers generate candidates that don't come from a dictionary (dates,
+//! greetings, and later numbers or snippets). They are registered in one
+//! [`ProviderRegistry`] owned by the converter and can be enabled or
+//! disabled by name, globally through config and per session.
+//!
+//! Every generated candidate carries an annotation explaining why it
+//! appeared (e.g. "今日の日付").
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A candidate generated by a provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynamicCandidate {
+    pub text: String,
+    /// Why this candidate was offered
+    pub annotation: String,
+}
+
+impl DynamicCandidate {
+    fn new(text: impl Into<String>, annotation: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            annotation: annotation.into(),
+        }
+    }
+}
+
+/// Inputs available to providers besides the reading
+#[derive(Debug, Clone, Copy)]
+pub struct ProviderContext {
+    pub now: SystemTime,
+    /// Local time offset from UTC
+    pub utc_offset_minutes: i64,
+}
+
+impl ProviderContext {
+    /// Seconds since the epoch in local time
+    fn local_secs(&self) -> i64 {
+        let secs = self
+            .now
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        secs + self.utc_offset_minutes * 60
+    }
+}
+
+/// A source of dynamic candidates
+pub trait CandidateProvider: Send + Sync {
+    /// Name used to enable or disable the provider in config
+    fn name(&self) -> &'static str;
+
+    /// Candidates for a reading, most likely first
+    fn provide(&self, reading: &str, context: &ProviderContext) -> Vec<DynamicCandidate>;
+}
+
+/// Provider configuration
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderConfig {
+    /// Providers to enable (true) or disable (false) by name
+    #[serde(default)]
+    pub enabled: BTreeMap<String, bool>,
+
+    /// Local time offset used by date and time providers (minutes)
+    #[serde(default = "default_utc_offset_minutes")]
+    pub utc_offset_minutes: i64,
+}
+
+fn default_utc_offset_minutes() -> i64 {
+    9 * 60
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: BTreeMap::new(),
+            utc_offset_minutes: default_utc_offset_minutes(),
+        }
+    }
+}
+
+/// Registered providers and their global enablement
+///
+/// Providers are disabled unless enabled in config, so new providers never
+/// change conversion results for existing users.
+#[derive(Clone)]
+pub struct ProviderRegistry {
+    providers: Vec<Arc<dyn CandidateProvider>>,
+    config: ProviderConfig,
+}
+
+impl Default for ProviderRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProviderRegistry {
+    /// Create a registry with the built-in providers
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Arc::new(DateProvider));
+        registry.register(Arc::new(GreetingProvider));
+        registry
+    }
+
+    /// Create a registry without any providers
+    pub fn empty() -> Self {
+        Self {
+            providers: Vec::new(),
+            config: ProviderConfig::default(),
+        }
+    }
+
+    /// Add a provider, replacing one with the same name
+    pub fn register(&mut self, provider: Arc<dyn CandidateProvider>) {
+        self.providers.retain(|p| p.name() != provider.name());
+        self.providers.push(provider);
+    }
+
+    /// Replace the global configuration
+    pub fn set_config(&mut self, config: ProviderConfig) {
+        self.config = config;
+    }
+
+    /// Names of registered providers, in registration order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.providers.iter().map(|p| p.name()).collect()
+    }
+
+    /// Whether a provider is enabled, given per-session overrides
+    pub fn is_enabled(&self, name: &str, overrides: &BTreeMap<String, bool>) -> bool {
+        overrides
+            .get(name)
+            .or_else(|| self.config.enabled.get(name))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Effective enablement of every provider, given per-session overrides
+    pub fn state(&self, overrides: &BTreeMap<String, bool>) -> BTreeMap<String, bool> {
+        self.names()
+            .into_iter()
+            .map(|name| (name.to_string(), self.is_enabled(name, overrides)))
+            .collect()
+    }
+
+    /// Collect candidates from all enabled providers
+    pub fn candidates(
+        &self,
+        reading: &str,
+        overrides: &BTreeMap<String, bool>,
+        now: SystemTime,
+    ) -> Vec<DynamicCandidate> {
+        let context = ProviderContext {
+            now,
+            utc_offset_minutes: self.config.utc_offset_minutes,
+        };
+        let mut candidates: Vec<DynamicCandidate> = Vec::new();
+        for provider in &self.providers {
+            if !self.is_enabled(provider.name(), overrides) {
+                continue;
+            }
+            for candidate in provider.provide(reading, &context) {
+                if !candidates.iter().any(|c| c.text == candidate.text) {
+                    candidates.push(candidate);
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/// Convert days since the epoch to (year, month, day)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+const WEEKDAYS: [char; 7] = ['日', '月', '火', '水', '木', '金', '土'];
+
+/// Today's, tomorrow's and yesterday's date for きょう / あした / きのう
+pub struct DateProvider;
+
+impl CandidateProvider for DateProvider {
+    fn name(&self) -> &'static str {
+        "date"
+    }
+
+    fn provide(&self, reading: &str, context: &ProviderContext) -> Vec<DynamicCandidate> {
+        let (delta, annotation) = match reading {
+            "きょう" | "ほんじつ" => (0, "今日の日付"),
+            "あした" | "あす" => (1, "明日の日付"),
+            "きのう" => (-1, "昨日の日付"),
+            _ => return vec![],
+        };
+        let days = context.local_secs().div_euclid(86_400) + delta;
+        let (year, month, day) = civil_from_days(days);
+        let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+        vec![
+            DynamicCandidate::new(format!("{}年{}月{}日", year, month, day), annotation),
+            DynamicCandidate::new(format!("{}月{}日({})", month, day, weekday), annotation),
+            DynamicCandidate::new(format!("{:04}-{:02}-{:02}", year, month, day), annotation),
+        ]
+    }
+}
+
+/// Greeting matching the time of day for あいさつ
+pub struct GreetingProvider;
+
+impl CandidateProvider for GreetingProvider {
+    fn name(&self) -> &'static str {
+        "greeting"
+    }
+
+    fn provide(&self, reading: &str, context: &ProviderContext) -> Vec<DynamicCandidate> {
+        if reading != "あいさつ" {
+            return vec![];
+        }
+        let hour = context.local_secs().rem_euclid(86_400) / 3600;
+        let greeting = match hour {
+            5..=9 => "おはようございます",
+            10..=17 => "こんにちは",
+            _ => "こんばんは",
+        };
+        vec![DynamicCandidate::new(greeting, "時間帯の挨拶")]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// 2026-10-15 03:00 UTC (12:00 JST, a Thursday)
+    fn noon_jst() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_792_033_200)
+    }
+
+    fn enabled(names: &[&str]) -> ProviderRegistry {
+        let mut registry = ProviderRegistry::new();
+        registry.set_config(ProviderConfig {
+            enabled: names.iter().map(|n| (n.to_string(), true)).collect(),
+            ..ProviderConfig::default()
+        });
+        registry
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn test_providers_disabled_by_default() {
+        let registry = ProviderRegistry::new();
+        assert!(registry
+            .candidates("きょう", &BTreeMap::new(), noon_jst())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_date_provider() {
+        let registry = enabled(&["date"]);
+        let candidates = registry.candidates("きょう", &BTreeMap::new(), noon_jst());
+        let texts: Vec<&str> = candidates.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["2026年10月15日", "10月15日(木)", "2026-10-15"]);
+        assert_eq!(candidates[0].annotation, "今日の日付");
+
+        let candidates = registry.candidates("あした", &BTreeMap::new(), noon_jst());
+        assert_eq!(candidates[0].text, "2026年10月16日");
+    }
+
+    #[test]
+    fn test_session_overrides() {
+        let registry = enabled(&["date"]);
+        let overrides =
+            BTreeMap::from([("date".to_string(), false), ("greeting".to_string(), true)]);
+        assert!(registry
+            .candidates("きょう", &overrides, noon_jst())
+            .is_empty());
+        let candidates = registry.candidates("あいさつ", &overrides, noon_jst());
+        assert_eq!(candidates[0].text, "こんにちは");
+        assert_eq!(
+            registry.state(&overrides),
+            BTreeMap::from([("date".to_string(), false), ("greeting".to_string(), true)])
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Punctuation and space width policy for composed candidates
+
+use serde::Deserialize;
+
+/// Punctuation policy configuration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct PunctuationConfig {
+    /// Which period/comma pair to use
+    #[serde(default)]
+    pub style: PunctuationStyle,
+
+    /// Width of spaces in composed text
+    #[serde(default)]
+    pub space: SpaceWidth,
+}
+
+/// Period and comma convention
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PunctuationStyle {
+    /// 。 and 、 (general writing)
+    #[default]
+    Japanese,
+    /// ． and ， (technical and academic writing)
+    Academic,
+    /// 。 and ， (official documents)
+    Mixed,
+}
+
+/// Space width convention
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpaceWidth {
+    /// Leave spaces as they were typed
+    #[default]
+    Keep,
+    /// Use the ideographic space (U+3000)
+    Full,
+    /// Use the ASCII space
+    Half,
+}
+
+impl PunctuationConfig {
+    /// Rewrite periods, commas and spaces in `text` according to the policy
+    pub fn apply(&self, text: &str) -> String {
+        let (period, comma) = match self.style {
+            PunctuationStyle::Japanese => ('。', '、'),
+            PunctuationStyle::Academic => ('．', '，'),
+            PunctuationStyle::Mixed => ('。', '，'),
+        };
+
+        text.chars()
+            .map(|c| match c {
+                '。' | '．' => period,
+                '、' | '，' => comma,
+                ' ' | '\u{3000}' => match self.space {
+                    SpaceWidth::Keep => c,
+                    SpaceWidth::Full => '\u{3000}',
+                    SpaceWidth::Half => ' ',
+                },
+                _ => c,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy_is_identity_for_japanese_text() {
+        let config = PunctuationConfig::default();
+        assert_eq!(config.apply("今日は、晴れ。 明日"), "今日は、晴れ。 明日");
+    }
+
+    #[test]
+    fn test_academic_style() {
+        let config = PunctuationConfig {
+            style: PunctuationStyle::Academic,
+            space: SpaceWidth::Full,
+        };
+        assert_eq!(
+            config.apply("今日は、晴れ。 明日"),
+            "今日は，晴れ．\u{3000}明日"
+        );
+    }
+
+    #[test]
+    fn test_mixed_style_half_space() {
+        let config = PunctuationConfig {
+            style: PunctuationStyle::Mixed,
+            space: SpaceWidth::Half,
+        };
+        assert_eq!(config.apply("あ，い．\u{3000}う"), "あ，い。 う");
+    }
+}
@@ -0,0 +1,319 @@
+//! Remote dictionary served by an external skkserv
+//!
+//! Lets users keep huge dictionaries on a server instead of local files.
+//! Lookups go over the skkserv protocol (`1<key> ` requests, EUC-JP
+//! responses) on one persistent connection. Answers are cached, and after
+//! a failure the server is left alone for a while so conversion does not
+//! stall on every lookup.
+
+use crate::dictionary::parse_entry;
+use crate::metrics::CacheStats;
+use encoding_rs::EUC_JP;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How long to stop querying the server after it failed
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Longest reading sent to the server
+///
+/// The lattice looks up every substring of a reading; longer ones are
+/// almost never words and would only add round trips.
+const MAX_KEY_CHARS: usize = 16;
+
+/// Remote dictionary configuration
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteDictionaryConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Connect and read timeout for each lookup
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Number of readings whose answers are cached
+    #[serde(default = "default_cache_size")]
+    pub cache_size: usize,
+}
+
+fn default_port() -> u16 {
+    1178
+}
+
+fn default_timeout_ms() -> u64 {
+    300
+}
+
+fn default_cache_size() -> usize {
+    10_000
+}
+
+/// Open connection to the server
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+/// Answers by reading, evicted oldest first
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<String, Option<Vec<String>>>,
+    order: VecDeque<String>,
+    hits: u64,
+    misses: u64,
+}
+
+/// Dictionary backed by an skkserv
+pub struct RemoteDictionary {
+    config: RemoteDictionaryConfig,
+    connection: Mutex<Option<Connection>>,
+    cache: Mutex<Cache>,
+    /// Set after a failure; no queries are made until then
+    retry_at: Mutex<Option<Instant>>,
+}
+
+impl RemoteDictionary {
+    /// Create a remote dictionary; the server is contacted on first lookup
+    pub fn new(config: RemoteDictionaryConfig) -> Self {
+        Self {
+            config,
+            connection: Mutex::new(None),
+            cache: Mutex::new(Cache::default()),
+            retry_at: Mutex::new(None),
+        }
+    }
+
+    /// Server configuration
+    pub fn config(&self) -> &RemoteDictionaryConfig {
+        &self.config
+    }
+
+    /// Look up candidates for a reading
+    ///
+    /// Returns `None` when the server has no entry or cannot be reached.
+    pub fn lookup(&self, reading: &str) -> Option<Vec<String>> {
+        if reading.is_empty() || reading.chars().count() > MAX_KEY_CHARS {
+            return None;
+        }
+        {
+            let mut cache = self.cache_lock();
+            if let Some(cached) = cache.entries.get(reading).cloned() {
+                cache.hits += 1;
+                return cached;
+            }
+            cache.misses += 1;
+        }
+        {
+            let mut retry_at = self.retry_at.lock().unwrap_or_else(PoisonError::into_inner);
+            match *retry_at {
+                Some(at) if Instant::now() < at => return None,
+                _ => *retry_at = None,
+            }
+        }
+
+        match self.query(reading) {
+            Ok(candidates) => {
+                self.insert(reading, candidates.clone());
+                candidates
+            }
+            Err(e) => {
+                log_warn!(
+                    "[remote_dict] {}:{} failed: {}",
+                    self.config.host,
+                    self.config.port,
+                    e
+                );
+                *self.retry_at.lock().unwrap_or_else(PoisonError::into_inner) =
+                    Some(Instant::now() + RETRY_DELAY);
+                None
+            }
+        }
+    }
+
+    /// Lookups answered from the cache and ones that weren't
+    pub fn cache_stats(&self) -> CacheStats {
+        let cache = self.cache_lock();
+        CacheStats::new(cache.hits, cache.misses)
+    }
+
+    /// Send one request, connecting first if needed
+    fn query(&self, reading: &str) -> io::Result<Option<Vec<String>>> {
+        let (key, _, unmappable) = EUC_JP.encode(reading);
+        if unmappable {
+            return Ok(None);
+        }
+        // Taken out while in use, so a failed connection is dropped
+        let mut slot = self.connection_lock();
+        let mut connection = match slot.take() {
+            Some(connection) => connection,
+            None => self.connect()?,
+        };
+
+        let mut request = Vec::with_capacity(key.len() + 2);
+        request.push(b'1');
+        request.extend_from_slice(&key);
+        request.push(b' ');
+        connection.writer.write_all(&request)?;
+        connection.writer.flush()?;
+
+        let mut line = Vec::new();
+        connection.reader.read_until(b'\n', &mut line)?;
+        if line.last() != Some(&b'\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed",
+            ));
+        }
+        *slot = Some(connection);
+        Ok(parse_response(reading, &line))
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        let timeout = Duration::from_millis(self.config.timeout_ms.max(1));
+        let addr = (self.config.host.as_str(), self.config.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+        let stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let _ = stream.set_nodelay(true);
+        log_info!("[remote_dict] Connected to {}", addr);
+        Ok(Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    fn insert(&self, reading: &str, candidates: Option<Vec<String>>) {
+        if self.config.cache_size == 0 {
+            return;
+        }
+        let mut cache = self.cache_lock();
+        while cache.order.len() >= self.config.cache_size {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.entries.remove(&oldest);
+            }
+        }
+        cache.order.push_back(reading.to_string());
+        cache.entries.insert(reading.to_string(), candidates);
+    }
+
+    fn cache_lock(&self) -> MutexGuard<'_, Cache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn connection_lock(&self) -> MutexGuard<'_, Option<Connection>> {
+        self.connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Parse `1/cand1/cand2/\n`; any other answer means not found
+fn parse_response(reading: &str, line: &[u8]) -> Option<Vec<String>> {
+    let rest = line.strip_prefix(b"1")?;
+    let (rest, _, _) = EUC_JP.decode(rest);
+    parse_entry(&format!("{} {}", reading, rest.trim_end())).map(|(_, candidates)| candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Serve `きょう` and count requests
+    fn fake_skkserv() -> (u16, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut writer = stream.unwrap();
+                let mut reader = BufReader::new(writer.try_clone().unwrap());
+                loop {
+                    let mut command = [0u8];
+                    if reader.read(&mut command).unwrap_or(0) == 0 {
+                        break;
+                    }
+                    let mut key = Vec::new();
+                    reader.read_until(b' ', &mut key).unwrap();
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let key = EUC_JP.decode(&key).0.trim_end().to_string();
+                    let response = match key.as_str() {
+                        "きょう" => EUC_JP.encode("1/今日/京;capital/\n").0.into_owned(),
+                        _ => [b"4".as_slice(), &EUC_JP.encode(&key).0, b" \n"].concat(),
+                    };
+                    writer.write_all(&response).unwrap();
+                }
+            }
+        });
+        (port, requests)
+    }
+
+    fn config(port: u16) -> RemoteDictionaryConfig {
+        RemoteDictionaryConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            timeout_ms: default_timeout_ms(),
+            cache_size: default_cache_size(),
+        }
+    }
+
+    #[test]
+    fn test_lookup_is_cached() {
+        let (port, requests) = fake_skkserv();
+        let remote = RemoteDictionary::new(config(port));
+        assert_eq!(remote.lookup("きょう").unwrap(), vec!["今日", "京"]);
+        assert_eq!(remote.lookup("ないよ"), None);
+        assert_eq!(remote.lookup("きょう").unwrap(), vec!["今日", "京"]);
+        assert_eq!(remote.lookup("ないよ"), None);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(remote.cache_stats(), CacheStats::new(2, 2));
+    }
+
+    #[test]
+    fn test_cache_evicts_oldest() {
+        let (port, requests) = fake_skkserv();
+        let remote = RemoteDictionary::new(RemoteDictionaryConfig {
+            cache_size: 1,
+            ..config(port)
+        });
+        remote.lookup("きょう");
+        remote.lookup("ないよ");
+        remote.lookup("きょう");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_unreachable_server_backs_off() {
+        // Bind and drop to get a port nobody listens on
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let remote = RemoteDictionary::new(config(port));
+        assert_eq!(remote.lookup("きょう"), None);
+        assert!(remote.retry_at.lock().unwrap().is_some());
+        // Skipped without trying to connect again
+        assert_eq!(remote.lookup("きょう"), None);
+    }
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(
+            parse_response("かk", &EUC_JP.encode("1/書/欠/\n").0),
+            Some(vec!["書".to_string(), "欠".to_string()])
+        );
+        assert_eq!(parse_response("ないよ", b"4\xa4\xca \n"), None);
+    }
+}
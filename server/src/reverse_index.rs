@@ -0,0 +1,186 @@
+//! Candidate → reading reverse index
+//!
+//! Used for reconversion and furigana, which start from converted text and
+//! need its readings. Building it means walking the whole dictionary, so it
+//! is stored as a binary section at the end of compiled dictionaries (see
+//! `mapped_dict`) and read back instead of rebuilt at startup.
+//!
+//! Section layout (all integers big-endian):
+//!
+//! ```text
+//! magic "AZRI" | u32 version | u32 entry count
+//! entry: str candidate | u32 reading count | str reading...
+//! str:   u32 byte length | UTF-8 bytes
+//! ```
+
+use crate::dictionary::Dictionary;
+use crate::error::{DictionaryError, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// Magic bytes opening the reverse index section
+pub const MAGIC: &[u8; 4] = b"AZRI";
+
+/// Section format version
+const VERSION: u32 = 1;
+
+/// Candidate text → readings that produce it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReverseIndex {
+    entries: BTreeMap<String, Vec<String>>,
+}
+
+#[allow(dead_code)]
+impl ReverseIndex {
+    /// Build the index from a dictionary
+    ///
+    /// Okuri-ari entries contribute their stem reading (e.g. 書 → か).
+    /// Readings are sorted so the output is deterministic.
+    pub fn build(dictionary: &Dictionary) -> Self {
+        let mut entries: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let okuri_ari = dictionary.okuri_ari_entries().map(|(key, candidates)| {
+            let stem = key.trim_end_matches(|c: char| c.is_ascii_lowercase());
+            (stem, candidates)
+        });
+        for (reading, candidates) in dictionary.entries().chain(okuri_ari) {
+            for candidate in candidates {
+                if candidate == reading {
+                    continue;
+                }
+                let readings = entries.entry(candidate.to_string()).or_default();
+                if !readings.iter().any(|r| r == reading) {
+                    readings.push(reading.to_string());
+                }
+            }
+        }
+        for readings in entries.values_mut() {
+            readings.sort();
+        }
+        Self { entries }
+    }
+
+    /// Readings that convert to `text`
+    pub fn lookup(&self, text: &str) -> &[String] {
+        self.entries.get(text).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Number of indexed candidates
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the index as a binary section
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_u32::<BigEndian>(VERSION)?;
+        writer.write_u32::<BigEndian>(self.entries.len() as u32)?;
+        for (candidate, readings) in &self.entries {
+            write_str(writer, candidate)?;
+            writer.write_u32::<BigEndian>(readings.len() as u32)?;
+            for reading in readings {
+                write_str(writer, reading)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read an index written by [`ReverseIndex::write_to`]
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(DictionaryError::Format("Not a reverse index section".to_string()).into());
+        }
+        let version = reader.read_u32::<BigEndian>()?;
+        if version != VERSION {
+            return Err(DictionaryError::Format(format!(
+                "Unsupported reverse index version: {}",
+                version
+            ))
+            .into());
+        }
+
+        let count = reader.read_u32::<BigEndian>()?;
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            let candidate = read_str(reader)?;
+            let reading_count = reader.read_u32::<BigEndian>()?;
+            let readings = (0..reading_count)
+                .map(|_| read_str(reader))
+                .collect::<Result<Vec<_>>>()?;
+            entries.insert(candidate, readings);
+        }
+        Ok(Self { entries })
+    }
+}
+
+fn write_str<W: Write>(writer: &mut W, s: &str) -> Result<()> {
+    writer.write_u32::<BigEndian>(s.len() as u32)?;
+    writer.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+fn read_str<R: Read>(reader: &mut R) -> Result<String> {
+    let len = reader.read_u32::<BigEndian>()?;
+    let mut buf = Vec::new();
+    reader.take(u64::from(len)).read_to_end(&mut buf)?;
+    if buf.len() != len as usize {
+        return Err(DictionaryError::Format("Truncated reverse index section".to_string()).into());
+    }
+    String::from_utf8(buf).map_err(|e| {
+        DictionaryError::Format(format!("Invalid UTF-8 in reverse index: {}", e)).into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary() -> Dictionary {
+        let mut dict = Dictionary::new();
+        dict.add_entry("きょう", "京");
+        dict.add_entry("きょう", "今日");
+        dict.add_entry("こんにち", "今日");
+        dict.add_entry("かく", "書く");
+        dict
+    }
+
+    #[test]
+    fn test_build_and_lookup() {
+        let index = ReverseIndex::build(&dictionary());
+        assert_eq!(index.lookup("今日"), ["きょう", "こんにち"]);
+        assert_eq!(index.lookup("京"), ["きょう"]);
+        assert_eq!(index.lookup("書く"), ["かく"]);
+        assert_eq!(index.lookup("書"), ["か"]);
+        assert!(index.lookup("明日").is_empty());
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let index = ReverseIndex::build(&dictionary());
+        let mut bytes = Vec::new();
+        index.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+
+        let loaded = ReverseIndex::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(loaded, index);
+    }
+
+    #[test]
+    fn test_rejects_bad_section() {
+        assert!(ReverseIndex::read_from(&mut &b"NOPE\0\0\0\x01"[..]).is_err());
+
+        let mut bytes = Vec::new();
+        ReverseIndex::build(&dictionary())
+            .write_to(&mut bytes)
+            .unwrap();
+        bytes.truncate(bytes.len() - 2);
+        assert!(ReverseIndex::read_from(&mut bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,554 @@
+//! Romaji to hiragana conversion
+//!
+//! The same table and rules as the Neovim client (`lua/azuki/romaji.lua`):
+//! longest match, doubled consonants become "っ", and "n" becomes "ん"
+//! before a consonant. Exposed through the `to_kana` request so clients
+//! don't each need their own table. The AZIK layout and user rules can
+//! replace or extend the table (see [`RomajiConfig`]).
+
+use crate::error::{AzukiError, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Standard romaji sequences and their hiragana
+const TABLE: [(&str, &str); 187] = [
+    // 4-character patterns
+    ("ltsu", "っ"),
+    ("xtsu", "っ"),
+    // 3-character patterns
+    ("kya", "きゃ"),
+    ("kyi", "きぃ"),
+    ("kyu", "きゅ"),
+    ("kye", "きぇ"),
+    ("kyo", "きょ"),
+    ("sha", "しゃ"),
+    ("shi", "し"),
+    ("shu", "しゅ"),
+    ("she", "しぇ"),
+    ("sho", "しょ"),
+    ("sya", "しゃ"),
+    ("syi", "しぃ"),
+    ("syu", "しゅ"),
+    ("sye", "しぇ"),
+    ("syo", "しょ"),
+    ("cha", "ちゃ"),
+    ("chi", "ち"),
+    ("chu", "ちゅ"),
+    ("che", "ちぇ"),
+    ("cho", "ちょ"),
+    ("tya", "ちゃ"),
+    ("tyi", "ちぃ"),
+    ("tyu", "ちゅ"),
+    ("tye", "ちぇ"),
+    ("tyo", "ちょ"),
+    ("tha", "てゃ"),
+    ("thi", "てぃ"),
+    ("thu", "てゅ"),
+    ("the", "てぇ"),
+    ("tho", "てょ"),
+    ("tsu", "つ"),
+    ("nya", "にゃ"),
+    ("nyi", "にぃ"),
+    ("nyu", "にゅ"),
+    ("nye", "にぇ"),
+    ("nyo", "にょ"),
+    ("hya", "ひゃ"),
+    ("hyi", "ひぃ"),
+    ("hyu", "ひゅ"),
+    ("hye", "ひぇ"),
+    ("hyo", "ひょ"),
+    ("mya", "みゃ"),
+    ("myi", "みぃ"),
+    ("myu", "みゅ"),
+    ("mye", "みぇ"),
+    ("myo", "みょ"),
+    ("rya", "りゃ"),
+    ("ryi", "りぃ"),
+    ("ryu", "りゅ"),
+    ("rye", "りぇ"),
+    ("ryo", "りょ"),
+    ("gya", "ぎゃ"),
+    ("gyi", "ぎぃ"),
+    ("gyu", "ぎゅ"),
+    ("gye", "ぎぇ"),
+    ("gyo", "ぎょ"),
+    ("jya", "じゃ"),
+    ("jyi", "じぃ"),
+    ("jyu", "じゅ"),
+    ("jye", "じぇ"),
+    ("jyo", "じょ"),
+    ("bya", "びゃ"),
+    ("byi", "びぃ"),
+    ("byu", "びゅ"),
+    ("bye", "びぇ"),
+    ("byo", "びょ"),
+    ("pya", "ぴゃ"),
+    ("pyi", "ぴぃ"),
+    ("pyu", "ぴゅ"),
+    ("pye", "ぴぇ"),
+    ("pyo", "ぴょ"),
+    ("xya", "ゃ"),
+    ("xyu", "ゅ"),
+    ("xyo", "ょ"),
+    ("lya", "ゃ"),
+    ("lyu", "ゅ"),
+    ("lyo", "ょ"),
+    ("xtu", "っ"),
+    ("ltu", "っ"),
+    ("xwa", "ゎ"),
+    ("lwa", "ゎ"),
+    // 2-character patterns
+    ("ka", "か"),
+    ("ki", "き"),
+    ("ku", "く"),
+    ("ke", "け"),
+    ("ko", "こ"),
+    ("sa", "さ"),
+    ("si", "し"),
+    ("su", "す"),
+    ("se", "せ"),
+    ("so", "そ"),
+    ("ta", "た"),
+    ("ti", "ち"),
+    ("tu", "つ"),
+    ("te", "て"),
+    ("to", "と"),
+    ("na", "な"),
+    ("ni", "に"),
+    ("nu", "ぬ"),
+    ("ne", "ね"),
+    ("no", "の"),
+    ("ha", "は"),
+    ("hi", "ひ"),
+    ("hu", "ふ"),
+    ("fu", "ふ"),
+    ("he", "へ"),
+    ("ho", "ほ"),
+    ("ma", "ま"),
+    ("mi", "み"),
+    ("mu", "む"),
+    ("me", "め"),
+    ("mo", "も"),
+    ("ya", "や"),
+    ("yi", "い"),
+    ("yu", "ゆ"),
+    ("ye", "いぇ"),
+    ("yo", "よ"),
+    ("ra", "ら"),
+    ("ri", "り"),
+    ("ru", "る"),
+    ("re", "れ"),
+    ("ro", "ろ"),
+    ("wa", "わ"),
+    ("wi", "うぃ"),
+    ("we", "うぇ"),
+    ("wo", "を"),
+    ("nn", "ん"),
+    ("n'", "ん"),
+    ("xn", "ん"),
+    // Voiced consonants (dakuon)
+    ("ga", "が"),
+    ("gi", "ぎ"),
+    ("gu", "ぐ"),
+    ("ge", "げ"),
+    ("go", "ご"),
+    ("za", "ざ"),
+    ("zi", "じ"),
+    ("ji", "じ"),
+    ("zu", "ず"),
+    ("ze", "ぜ"),
+    ("zo", "ぞ"),
+    ("da", "だ"),
+    ("di", "ぢ"),
+    ("du", "づ"),
+    ("de", "で"),
+    ("do", "ど"),
+    ("ba", "ば"),
+    ("bi", "び"),
+    ("bu", "ぶ"),
+    ("be", "べ"),
+    ("bo", "ぼ"),
+    ("pa", "ぱ"),
+    ("pi", "ぴ"),
+    ("pu", "ぷ"),
+    ("pe", "ぺ"),
+    ("po", "ぽ"),
+    ("ja", "じゃ"),
+    ("ju", "じゅ"),
+    ("je", "じぇ"),
+    ("jo", "じょ"),
+    ("fa", "ふぁ"),
+    ("fi", "ふぃ"),
+    ("fe", "ふぇ"),
+    ("fo", "ふぉ"),
+    ("va", "ゔぁ"),
+    ("vi", "ゔぃ"),
+    ("vu", "ゔ"),
+    ("ve", "ゔぇ"),
+    ("vo", "ゔぉ"),
+    // Small letters
+    ("xa", "ぁ"),
+    ("xi", "ぃ"),
+    ("xu", "ぅ"),
+    ("xe", "ぇ"),
+    ("xo", "ぉ"),
+    ("la", "ぁ"),
+    ("li", "ぃ"),
+    ("lu", "ぅ"),
+    ("le", "ぇ"),
+    ("lo", "ぉ"),
+    // 1-character patterns (vowels)
+    ("a", "あ"),
+    ("i", "い"),
+    ("u", "う"),
+    ("e", "え"),
+    ("o", "お"),
+    // Special
+    ("-", "ー"),
+    (">", ">"),
+];
+
+/// Consonants that form a sokuon ("っ") when doubled
+const SOKUON_CONSONANTS: &str = "kstmhyrwgzdbpcfj";
+
+/// Characters after which "n" is not yet "ん"
+const N_CONTINUES: &str = "aiueoyn";
+
+/// AZIK keys that follow a consonant: the vowel they stand for and the
+/// kana added after the syllable
+const AZIK_EXTENSIONS: [(char, char, &str); 9] = [
+    // Syllabic nasal
+    ('z', 'a', "ん"),
+    ('k', 'i', "ん"),
+    ('j', 'u', "ん"),
+    ('d', 'e', "ん"),
+    ('l', 'o', "ん"),
+    // Double vowels
+    ('q', 'a', "い"),
+    ('h', 'u', "う"),
+    ('w', 'e', "い"),
+    ('p', 'o', "う"),
+];
+
+/// Consonants the AZIK extensions apply to
+const AZIK_CONSONANTS: [&str; 14] = [
+    "k", "s", "t", "n", "h", "m", "y", "r", "w", "g", "z", "d", "b", "p",
+];
+
+/// Contracted-sound prefixes the AZIK extensions apply to (a, u, o only)
+const AZIK_CONTRACTED: [&str; 11] = [
+    "ky", "sy", "ty", "ny", "hy", "my", "ry", "gy", "jy", "by", "py",
+];
+
+/// AZIK rules that are not derived from the extensions
+const AZIK_EXTRAS: [(&str, &str); 6] = [
+    ("q", "ん"),
+    (";", "っ"),
+    ("ds", "です"),
+    ("ms", "ます"),
+    ("kt", "こと"),
+    ("mn", "もの"),
+];
+
+/// Built-in layout a romaji table starts from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RomajiLayout {
+    /// Standard romaji, as in the Neovim client
+    #[default]
+    Standard,
+    /// Standard romaji with the AZIK extensions ("kz" → かん, "kp" → こう)
+    Azik,
+}
+
+/// Romaji table configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomajiConfig {
+    #[serde(default)]
+    pub layout: RomajiLayout,
+    /// Rule file applied over the layout, one "romaji kana" pair per line
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Rules applied over the layout and the file; an empty kana removes
+    /// the rule
+    #[serde(default)]
+    pub rules: BTreeMap<String, String>,
+}
+
+/// Romaji to hiragana rules
+#[derive(Debug, Clone)]
+pub struct RomajiTable {
+    rules: HashMap<String, String>,
+    /// Longest romaji sequence, in characters
+    max_len: usize,
+}
+
+impl Default for RomajiTable {
+    fn default() -> Self {
+        Self::from_rules(standard_rules())
+    }
+}
+
+impl RomajiTable {
+    /// Build the table described by `config`
+    pub fn new(config: &RomajiConfig) -> Result<Self> {
+        let mut rules = match config.layout {
+            RomajiLayout::Standard => standard_rules(),
+            RomajiLayout::Azik => azik_rules(),
+        };
+        if let Some(path) = &config.file {
+            rules.extend(read_rules(path)?);
+        }
+        for (romaji, kana) in &config.rules {
+            rules.insert(romaji.to_ascii_lowercase(), kana.clone());
+        }
+        rules.retain(|romaji, kana| !romaji.is_empty() && !kana.is_empty());
+        Ok(Self::from_rules(rules))
+    }
+
+    fn from_rules(rules: HashMap<String, String>) -> Self {
+        let max_len = rules
+            .keys()
+            .map(|key| key.chars().count())
+            .max()
+            .unwrap_or(0);
+        Self { rules, max_len }
+    }
+
+    /// Convert romaji to hiragana
+    ///
+    /// Returns the hiragana and the unconverted tail that may still become
+    /// kana with more input ("k", "ky", a trailing "n"). Characters that
+    /// start no romaji sequence (digits, punctuation, kana) are passed
+    /// through.
+    pub fn to_kana(&self, input: &str) -> (String, String) {
+        let lower = input.to_ascii_lowercase();
+        let mut result = String::new();
+        let mut rest = lower.as_str();
+
+        while let Some(c) = rest.chars().next() {
+            let mut chars = rest.chars();
+            chars.next();
+            let next = chars.next();
+
+            // Longest match; a rule for a doubled consonant ("kk" in AZIK)
+            // takes precedence over the sokuon
+            let ends: Vec<usize> = rest
+                .char_indices()
+                .map(|(i, c)| i + c.len_utf8())
+                .take(self.max_len)
+                .collect();
+            let matched = ends
+                .into_iter()
+                .rev()
+                .find_map(|len| self.rules.get(&rest[..len]).map(|kana| (len, kana)));
+            let sokuon = next == Some(c) && SOKUON_CONSONANTS.contains(c);
+            match matched {
+                // A rule ending the input that a longer rule starts with
+                // may still change ("sh" before "sha" in AZIK)
+                Some((len, _)) if len == rest.len() && self.has_longer(rest) => break,
+                Some((len, kana)) if len > c.len_utf8() || !sokuon => {
+                    result.push_str(kana);
+                    rest = &rest[len..];
+                    continue;
+                }
+                _ => {}
+            }
+
+            if sokuon {
+                result.push('っ');
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+
+            if c == 'n' {
+                match next {
+                    None => break,
+                    Some(next) if N_CONTINUES.contains(next) => {}
+                    Some(_) => {
+                        result.push('ん');
+                        rest = &rest[1..];
+                        continue;
+                    }
+                }
+            }
+
+            if self.is_pending(rest) {
+                break;
+            }
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+
+        (result, rest.to_string())
+    }
+
+    /// Whether a rule longer than `romaji` starts with it
+    fn has_longer(&self, romaji: &str) -> bool {
+        self.rules
+            .keys()
+            .any(|key| key.len() > romaji.len() && key.starts_with(romaji))
+    }
+
+    /// Whether `rest` is the start of a romaji sequence that needs more input
+    fn is_pending(&self, rest: &str) -> bool {
+        rest.chars().count() < self.max_len
+            && (self.has_longer(rest) || (rest.len() == 1 && SOKUON_CONSONANTS.contains(rest)))
+    }
+}
+
+fn standard_rules() -> HashMap<String, String> {
+    TABLE
+        .iter()
+        .map(|&(romaji, kana)| (romaji.to_string(), kana.to_string()))
+        .collect()
+}
+
+fn azik_rules() -> HashMap<String, String> {
+    let mut rules = standard_rules();
+    let mut derived = Vec::new();
+    let prefixes = AZIK_CONSONANTS
+        .iter()
+        .map(|prefix| (prefix, "aiueo"))
+        .chain(AZIK_CONTRACTED.iter().map(|prefix| (prefix, "auo")));
+    for (prefix, vowels) in prefixes {
+        for &(key, vowel, suffix) in &AZIK_EXTENSIONS {
+            if !vowels.contains(vowel) {
+                continue;
+            }
+            if let Some(kana) = rules.get(&format!("{}{}", prefix, vowel)) {
+                derived.push((format!("{}{}", prefix, key), format!("{}{}", kana, suffix)));
+            }
+        }
+    }
+    rules.extend(derived);
+    for (romaji, kana) in AZIK_EXTRAS {
+        rules.insert(romaji.to_string(), kana.to_string());
+    }
+    rules
+}
+
+/// Read a rule file: "romaji kana" per line, `#` starts a comment line
+fn read_rules(path: &Path) -> Result<Vec<(String, String)>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| AzukiError::Config(format!("{}: {}", path.display(), e)))?;
+    let mut rules = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(romaji), Some(kana), None) => {
+                rules.push((romaji.to_ascii_lowercase(), kana.to_string()))
+            }
+            _ => {
+                return Err(AzukiError::Config(format!(
+                    "{}:{}: expected \"romaji kana\"",
+                    path.display(),
+                    number + 1
+                )))
+            }
+        }
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kana(input: &str) -> String {
+        let (kana, pending) = RomajiTable::default().to_kana(input);
+        assert_eq!(pending, "", "unexpected pending input for {}", input);
+        kana
+    }
+
+    #[test]
+    fn test_basic_syllables() {
+        assert_eq!(kana("aiueo"), "あいうえお");
+        assert_eq!(kana("kyouhaiitenki"), "きょうはいいてんき");
+        assert_eq!(kana("shinbunn"), "しんぶん");
+        assert_eq!(kana("tyotto"), "ちょっと");
+        assert_eq!(kana("Tokyo"), "ときょ");
+        assert_eq!(kana("ra-menn"), "らーめん");
+    }
+
+    #[test]
+    fn test_sokuon_and_n() {
+        assert_eq!(kana("kitte"), "きって");
+        assert_eq!(kana("macchi"), "まっち");
+        assert_eq!(kana("xtsu"), "っ");
+        assert_eq!(kana("kanji"), "かんじ");
+        assert_eq!(kana("hon'ya"), "ほんや");
+        assert_eq!(kana("konnnichiha"), "こんにちは");
+    }
+
+    #[test]
+    fn test_pending_input() {
+        let table = RomajiTable::default();
+        let pair = |kana: &str, pending: &str| (kana.to_string(), pending.to_string());
+        assert_eq!(table.to_kana("kan"), pair("か", "n"));
+        assert_eq!(table.to_kana("ky"), pair("", "ky"));
+        assert_eq!(table.to_kana("att"), pair("あっ", "t"));
+        assert_eq!(table.to_kana(""), pair("", ""));
+    }
+
+    #[test]
+    fn test_passes_through_other_characters() {
+        assert_eq!(kana("3ko"), "3こ");
+        assert_eq!(kana("kana."), "かな.");
+        assert_eq!(kana("かnji"), "かんじ");
+        // An incomplete sequence inside the input is kept as typed
+        assert_eq!(kana("kqa"), "kqあ");
+    }
+
+    #[test]
+    fn test_azik_layout() {
+        let config = RomajiConfig {
+            layout: RomajiLayout::Azik,
+            ..Default::default()
+        };
+        let table = RomajiTable::new(&config).unwrap();
+        let kana = |input: &str| table.to_kana(input).0;
+        assert_eq!(kana("kz"), "かん");
+        assert_eq!(kana("kk"), "きん");
+        assert_eq!(kana("kp"), "こう");
+        assert_eq!(kana("kyp"), "きょう");
+        assert_eq!(kana("sq"), "さい");
+        assert_eq!(kana("ds"), "です");
+        assert_eq!(kana("ka;ta"), "かった");
+        assert_eq!(kana("sha"), "しゃ");
+        // "sh" is すう, but could still become "sha"
+        assert_eq!(table.to_kana("sh"), (String::new(), "sh".to_string()));
+        assert_eq!(kana("shite"), "して");
+    }
+
+    #[test]
+    fn test_user_rules() {
+        let path = std::env::temp_dir().join(format!("azuki-romaji-{}", std::process::id()));
+        fs::write(&path, "# extra rules\nwyi ゐ\nwye ゑ\n\nka カ\n").unwrap();
+        let config = RomajiConfig {
+            file: Some(path.clone()),
+            rules: BTreeMap::from([
+                ("ka".to_string(), "か".to_string()),
+                ("-".to_string(), String::new()),
+                ("z/".to_string(), "・".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let table = RomajiTable::new(&config).unwrap();
+        assert_eq!(table.to_kana("wyika").0, "ゐか");
+        assert_eq!(table.to_kana("z/").0, "・");
+        // The removed rule passes "-" through
+        assert_eq!(table.to_kana("a-").0, "あ-");
+
+        fs::write(&path, "wyi\n").unwrap();
+        assert!(RomajiTable::new(&config).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,650 @@
+//! Session tracking and garbage collection
+//!
+//! Sessions are created on Init (or implicitly on first use) and evicted
+//! when they expire or when the session table is full, so a long-running
+//! shared server doesn't accumulate state for abandoned editors.
+
+use crate::converter::Segment;
+use crate::error::{AzukiError, Result};
+use crate::message::{Candidate, SegmentInfo};
+use crate::settings::SettingsPatch;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// Session garbage collection configuration
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionConfig {
+    /// Idle time after which a session is evicted (seconds, 0 = never)
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+
+    /// Maximum number of live sessions
+    #[serde(default = "default_max_sessions")]
+    pub max_sessions: usize,
+
+    /// What to do when a new session would exceed `max_sessions`
+    #[serde(default)]
+    pub eviction: EvictionPolicy,
+}
+
+fn default_ttl_secs() -> u64 {
+    60 * 60
+}
+
+fn default_max_sessions() -> usize {
+    64
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: default_ttl_secs(),
+            max_sessions: default_max_sessions(),
+            eviction: EvictionPolicy::default(),
+        }
+    }
+}
+
+/// Policy applied when the session table is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// Evict the session that has been idle the longest
+    #[default]
+    LeastRecentlyUsed,
+    /// Evict the session that was created first
+    Oldest,
+    /// Refuse to create new sessions
+    RejectNew,
+}
+
+/// Readings whose adjusted segmentation a session remembers
+const MAX_SEGMENTATIONS: usize = 64;
+
+/// Longest accepted session id, in bytes
+const MAX_SESSION_ID: usize = 128;
+
+/// Characters of preceding text a session keeps as its context
+const MAX_CONTEXT: usize = 256;
+
+/// Check that a client-chosen session id is usable
+///
+/// Ids are echoed in every response and log line, so they must be short
+/// printable strings.
+pub fn validate_session_id(session_id: &str) -> Result<()> {
+    if session_id.is_empty() {
+        return Err(AzukiError::Session("Session id is empty".to_string()));
+    }
+    if session_id.len() > MAX_SESSION_ID {
+        return Err(AzukiError::Session(format!(
+            "Session id is longer than {} bytes",
+            MAX_SESSION_ID
+        )));
+    }
+    if session_id.chars().any(char::is_control) {
+        return Err(AzukiError::Session(
+            "Session id contains control characters".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// How `convert` interprets the readings of a session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputMode {
+    /// Kana-kanji conversion
+    #[default]
+    Hiragana,
+    /// The reading in katakana, without dictionary conversion
+    Katakana,
+    /// The reading in half-width katakana
+    HalfwidthKatakana,
+    /// The reading is an ASCII word looked up whole ("file" -> "ファイル")
+    Abbrev,
+}
+
+/// Reading being converted and how it is segmented
+///
+/// Set by each complete conversion and segment adjustment and cleared on
+/// commit, so `adjust_segment` can work from it.
+#[derive(Debug, Clone)]
+pub struct Composition {
+    pub reading: String,
+    pub segments: Vec<Segment>,
+    /// Candidate chosen in each segment, by index
+    pub selected: Vec<usize>,
+}
+
+impl Composition {
+    pub fn new(reading: String, segments: Vec<Segment>) -> Self {
+        let selected = vec![0; segments.len()];
+        Self {
+            reading,
+            segments,
+            selected,
+        }
+    }
+
+    /// Choose the candidate at `candidate` in segment `segment`
+    pub fn select(&mut self, segment: usize, candidate: usize) -> Result<()> {
+        let Some(count) = self.segments.get(segment).map(|seg| seg.candidates.len()) else {
+            return Err(AzukiError::Session(format!(
+                "Segment {} is out of range ({} segments)",
+                segment,
+                self.segments.len()
+            )));
+        };
+        if candidate >= count {
+            return Err(AzukiError::Session(format!(
+                "Candidate {} is out of range ({} candidates)",
+                candidate, count
+            )));
+        }
+        self.selected[segment] = candidate;
+        Ok(())
+    }
+}
+
+/// Why a session was evicted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionReason {
+    /// Idle for longer than the configured TTL
+    Expired,
+    /// Evicted to make room for a new session
+    Capacity,
+}
+
+/// A session that was removed by garbage collection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eviction {
+    pub session_id: String,
+    pub reason: EvictionReason,
+}
+
+/// Last live conversion result, used as the base for deltas
+#[derive(Debug, Clone)]
+pub struct LiveSnapshot {
+    pub seq: u64,
+    pub candidates: Vec<Candidate>,
+    pub segments: Vec<SegmentInfo>,
+}
+
+/// Typing analytics for one session
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SessionStats {
+    /// Convert requests handled
+    pub conversions: u64,
+    /// Commit requests handled
+    pub commits: u64,
+    /// Commits per chosen candidate index (index 0 = first candidate)
+    pub candidate_index_counts: Vec<u64>,
+    /// Commits of text that wasn't in the last candidate list
+    pub unlisted_commits: u64,
+    /// Mean index of the chosen candidate, over listed commits
+    pub average_rank: Option<f64>,
+}
+
+impl SessionStats {
+    /// Record a commit of the candidate at `index`, if it was listed
+    pub fn record_commit(&mut self, index: Option<usize>) {
+        self.commits += 1;
+        let Some(index) = index else {
+            self.unlisted_commits += 1;
+            return;
+        };
+        if self.candidate_index_counts.len() <= index {
+            self.candidate_index_counts.resize(index + 1, 0);
+        }
+        self.candidate_index_counts[index] += 1;
+
+        let listed: u64 = self.candidate_index_counts.iter().sum();
+        let rank_sum: u64 = self
+            .candidate_index_counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| i as u64 * count)
+            .sum();
+        self.average_rank = Some(rank_sum as f64 / listed as f64);
+    }
+}
+
+/// A live session, for `list_sessions`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionSummary {
+    pub session_id: String,
+    /// Time since the session was created
+    pub age_ms: u64,
+    /// Time since its last request
+    pub idle_ms: u64,
+    /// Reading of the composition being converted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reading: Option<String>,
+    /// Estimated heap size of the session's state
+    pub memory_bytes: usize,
+}
+
+/// Per-session state
+#[derive(Debug)]
+pub struct Session {
+    pub created_at: Instant,
+    pub last_active: Instant,
+    /// Result of the latest live conversion of the current composition
+    pub last_live: Option<LiveSnapshot>,
+    /// Dynamic providers enabled (true) or disabled (false) for this session
+    pub providers: BTreeMap<String, bool>,
+    /// Candidates of the latest conversion, for ranking commits
+    pub last_candidates: Vec<String>,
+    /// Segment lengths the user chose with `adjust_segment`, per reading,
+    /// least recently adjusted first
+    segmentations: Vec<(String, Vec<usize>)>,
+    pub stats: SessionStats,
+    /// Settings changed for this session only
+    pub settings: SettingsPatch,
+    /// Composition being converted, if any
+    pub composition: Option<Composition>,
+    /// Reading and candidate of the latest commit, if it was learned, for
+    /// `undo_commit`
+    pub last_commit: Option<(String, String)>,
+    pub input_mode: InputMode,
+    /// Committed text before the composition, as sent by the client
+    context: String,
+    /// Zenzai model profile for conversions, the default one if None
+    pub zenzai_profile: Option<String>,
+    /// Dictionary set for conversions, the default one if None
+    pub dictionary_set: Option<String>,
+}
+
+impl Session {
+    fn new(now: Instant) -> Self {
+        Self {
+            created_at: now,
+            last_active: now,
+            last_live: None,
+            providers: BTreeMap::new(),
+            last_candidates: Vec::new(),
+            segmentations: Vec::new(),
+            stats: SessionStats::default(),
+            settings: SettingsPatch::default(),
+            composition: None,
+            last_commit: None,
+            input_mode: InputMode::default(),
+            context: String::new(),
+            zenzai_profile: None,
+            dictionary_set: None,
+        }
+    }
+
+    /// Estimated heap size of the state, strings and lists included
+    pub fn estimated_bytes(&self) -> usize {
+        fn string(s: &str) -> usize {
+            mem::size_of::<String>() + s.len()
+        }
+        fn strings<'a>(list: impl IntoIterator<Item = &'a String>) -> usize {
+            list.into_iter().map(|s| string(s)).sum()
+        }
+        fn segment(seg: &Segment) -> usize {
+            mem::size_of::<Segment>() + seg.reading.len() + strings(&seg.candidates)
+        }
+        let live = self.last_live.as_ref().map_or(0, |live| {
+            let candidates = live
+                .candidates
+                .iter()
+                .map(|c| mem::size_of::<Candidate>() + c.text.len())
+                .sum::<usize>();
+            let segments = live
+                .segments
+                .iter()
+                .map(|seg| {
+                    mem::size_of::<SegmentInfo>() + seg.reading.len() + strings(&seg.candidates)
+                })
+                .sum::<usize>();
+            candidates + segments
+        });
+        let providers = strings(self.providers.keys()) + self.providers.len();
+        let segmentations = self
+            .segmentations
+            .iter()
+            .map(|(reading, lengths)| string(reading) + lengths.len() * mem::size_of::<usize>())
+            .sum::<usize>();
+        let composition = self.composition.as_ref().map_or(0, |composition| {
+            string(&composition.reading)
+                + composition.segments.iter().map(segment).sum::<usize>()
+                + composition.selected.len() * mem::size_of::<usize>()
+        });
+        mem::size_of::<Session>()
+            + live
+            + providers
+            + strings(&self.last_candidates)
+            + segmentations
+            + self.stats.candidate_index_counts.len() * mem::size_of::<u64>()
+            + composition
+            + self.last_commit.as_ref().map_or(0, |(reading, candidate)| {
+                string(reading) + string(candidate)
+            })
+            + self.context.len()
+    }
+
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+
+    /// Replace the context, keeping only its last characters
+    pub fn set_context(&mut self, context: &str) {
+        let skip = context.chars().count().saturating_sub(MAX_CONTEXT);
+        self.context = context.chars().skip(skip).collect();
+    }
+
+    /// Add committed text to the end of the context
+    pub fn append_context(&mut self, text: &str) {
+        let context = format!("{}{}", self.context, text);
+        self.set_context(&context);
+    }
+
+    /// Remember the segment lengths chosen for `reading`
+    pub fn remember_segmentation(&mut self, reading: &str, lengths: Vec<usize>) {
+        self.segmentations.retain(|(r, _)| r != reading);
+        if self.segmentations.len() >= MAX_SEGMENTATIONS {
+            self.segmentations.remove(0);
+        }
+        self.segmentations.push((reading.to_string(), lengths));
+    }
+
+    /// Segment lengths last chosen for `reading`
+    pub fn segmentation(&self, reading: &str) -> Option<&[usize]> {
+        self.segmentations
+            .iter()
+            .find(|(r, _)| r == reading)
+            .map(|(_, lengths)| lengths.as_slice())
+    }
+}
+
+/// Owns all live sessions and applies the garbage collection policy
+#[derive(Debug, Default)]
+pub struct SessionManager {
+    sessions: HashMap<String, Session>,
+    config: SessionConfig,
+}
+
+impl SessionManager {
+    /// Create an empty session manager with default policy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the garbage collection policy
+    pub fn set_config(&mut self, config: SessionConfig) {
+        self.config = config;
+    }
+
+    /// Mark a session as active, creating it if needed
+    ///
+    /// Returns sessions evicted to make room. Fails if the table is full and
+    /// the policy is `RejectNew`.
+    pub fn touch(&mut self, session_id: &str, now: Instant) -> Result<Vec<Eviction>> {
+        if let Some(session) = self.sessions.get_mut(session_id) {
+            session.last_active = now;
+            return Ok(vec![]);
+        }
+
+        let mut evicted = Vec::new();
+        let max_sessions = self.config.max_sessions.max(1);
+        while self.sessions.len() >= max_sessions {
+            let victim = match self.config.eviction {
+                EvictionPolicy::LeastRecentlyUsed => self
+                    .sessions
+                    .iter()
+                    .min_by_key(|(_, s)| s.last_active)
+                    .map(|(id, _)| id.clone()),
+                EvictionPolicy::Oldest => self
+                    .sessions
+                    .iter()
+                    .min_by_key(|(_, s)| s.created_at)
+                    .map(|(id, _)| id.clone()),
+                EvictionPolicy::RejectNew => {
+                    return Err(AzukiError::Session(format!(
+                        "Session limit reached ({} sessions)",
+                        self.sessions.len()
+                    )));
+                }
+            };
+            let Some(victim) = victim else { break };
+            self.sessions.remove(&victim);
+            evicted.push(Eviction {
+                session_id: victim,
+                reason: EvictionReason::Capacity,
+            });
+        }
+
+        self.sessions
+            .insert(session_id.to_string(), Session::new(now));
+        Ok(evicted)
+    }
+
+    /// Evict sessions that have been idle longer than the TTL
+    pub fn collect_expired(&mut self, now: Instant) -> Vec<Eviction> {
+        if self.config.ttl_secs == 0 {
+            return vec![];
+        }
+        let ttl = Duration::from_secs(self.config.ttl_secs);
+
+        let expired: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| now.saturating_duration_since(s.last_active) > ttl)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        expired
+            .into_iter()
+            .map(|session_id| {
+                self.sessions.remove(&session_id);
+                Eviction {
+                    session_id,
+                    reason: EvictionReason::Expired,
+                }
+            })
+            .collect()
+    }
+
+    /// Get a session's state
+    pub fn get_mut(&mut self, session_id: &str) -> Option<&mut Session> {
+        self.sessions.get_mut(session_id)
+    }
+
+    /// Live sessions, most recently active first
+    pub fn summaries(&self, now: Instant) -> Vec<SessionSummary> {
+        let mut sessions: Vec<_> = self.sessions.iter().collect();
+        sessions
+            .sort_by(|(a_id, a), (b_id, b)| b.last_active.cmp(&a.last_active).then(a_id.cmp(b_id)));
+        sessions
+            .into_iter()
+            .map(|(session_id, session)| SessionSummary {
+                session_id: session_id.clone(),
+                age_ms: now
+                    .saturating_duration_since(session.created_at)
+                    .as_millis() as u64,
+                idle_ms: now
+                    .saturating_duration_since(session.last_active)
+                    .as_millis() as u64,
+                reading: session
+                    .composition
+                    .as_ref()
+                    .map(|composition| composition.reading.clone()),
+                memory_bytes: session.estimated_bytes(),
+            })
+            .collect()
+    }
+
+    /// Number of live sessions
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Check whether a session exists
+    #[allow(dead_code)]
+    pub fn contains(&self, session_id: &str) -> bool {
+        self.sessions.contains_key(session_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(max_sessions: usize, eviction: EvictionPolicy) -> SessionManager {
+        let mut manager = SessionManager::new();
+        manager.set_config(SessionConfig {
+            ttl_secs: 10,
+            max_sessions,
+            eviction,
+        });
+        manager
+    }
+
+    #[test]
+    fn test_validate_session_id() {
+        assert!(validate_session_id("nvim-1234").is_ok());
+        assert!(validate_session_id("").is_err());
+        assert!(validate_session_id("a\nb").is_err());
+        assert!(validate_session_id(&"a".repeat(MAX_SESSION_ID + 1)).is_err());
+    }
+
+    #[test]
+    fn test_context_keeps_last_characters() {
+        let mut session = Session::new(Instant::now());
+        session.set_context(&"あ".repeat(MAX_CONTEXT).replacen('あ', "い", 1));
+        assert!(session.context().starts_with('い'));
+        session.set_context(&format!("う{}", "あ".repeat(MAX_CONTEXT)));
+        assert_eq!(session.context(), "あ".repeat(MAX_CONTEXT));
+    }
+
+    #[test]
+    fn test_append_context() {
+        let mut session = Session::new(Instant::now());
+        session.append_context("今日は");
+        session.append_context("晴れ");
+        assert_eq!(session.context(), "今日は晴れ");
+        session.append_context(&"あ".repeat(MAX_CONTEXT - 1));
+        assert!(session.context().starts_with('れ'));
+        assert_eq!(session.context().chars().count(), MAX_CONTEXT);
+    }
+
+    #[test]
+    fn test_summaries() {
+        let mut sessions = SessionManager::new();
+        let start = Instant::now();
+        sessions.touch("a", start).unwrap();
+        sessions.touch("b", start + Duration::from_secs(1)).unwrap();
+        let session = sessions.get_mut("a").unwrap();
+        session.composition = Some(Composition::new("きょう".to_string(), Vec::new()));
+        let summaries = sessions.summaries(start + Duration::from_secs(3));
+
+        assert_eq!(summaries[0].session_id, "b");
+        assert_eq!(summaries[0].idle_ms, 2000);
+        assert_eq!(summaries[0].reading, None);
+        assert_eq!(summaries[1].session_id, "a");
+        assert_eq!(summaries[1].age_ms, 3000);
+        assert_eq!(summaries[1].reading.as_deref(), Some("きょう"));
+        assert!(summaries[1].memory_bytes > summaries[0].memory_bytes);
+    }
+
+    #[test]
+    fn test_touch_creates_session() {
+        let mut sessions = SessionManager::new();
+        let evicted = sessions.touch("a", Instant::now()).unwrap();
+        assert!(evicted.is_empty());
+        assert!(sessions.contains("a"));
+    }
+
+    #[test]
+    fn test_collect_expired() {
+        let mut sessions = manager(8, EvictionPolicy::LeastRecentlyUsed);
+        let start = Instant::now();
+        sessions.touch("idle", start).unwrap();
+        sessions.touch("busy", start).unwrap();
+        sessions
+            .touch("busy", start + Duration::from_secs(8))
+            .unwrap();
+
+        let evicted = sessions.collect_expired(start + Duration::from_secs(15));
+        assert_eq!(
+            evicted,
+            vec![Eviction {
+                session_id: "idle".to_string(),
+                reason: EvictionReason::Expired,
+            }]
+        );
+        assert!(sessions.contains("busy"));
+    }
+
+    #[test]
+    fn test_capacity_evicts_least_recently_used() {
+        let mut sessions = manager(2, EvictionPolicy::LeastRecentlyUsed);
+        let start = Instant::now();
+        sessions.touch("a", start).unwrap();
+        sessions.touch("b", start + Duration::from_secs(1)).unwrap();
+        sessions.touch("a", start + Duration::from_secs(2)).unwrap();
+
+        let evicted = sessions.touch("c", start + Duration::from_secs(3)).unwrap();
+        assert_eq!(evicted[0].session_id, "b");
+        assert_eq!(evicted[0].reason, EvictionReason::Capacity);
+        assert_eq!(sessions.len(), 2);
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let mut sessions = manager(2, EvictionPolicy::Oldest);
+        let start = Instant::now();
+        sessions.touch("a", start).unwrap();
+        sessions.touch("b", start + Duration::from_secs(1)).unwrap();
+        sessions.touch("a", start + Duration::from_secs(2)).unwrap();
+
+        let evicted = sessions.touch("c", start + Duration::from_secs(3)).unwrap();
+        assert_eq!(evicted[0].session_id, "a");
+    }
+
+    #[test]
+    fn test_stats_record_commit() {
+        let mut stats = SessionStats::default();
+        stats.record_commit(Some(0));
+        stats.record_commit(Some(2));
+        stats.record_commit(Some(0));
+        stats.record_commit(None);
+        assert_eq!(stats.commits, 4);
+        assert_eq!(stats.candidate_index_counts, vec![2, 0, 1]);
+        assert_eq!(stats.unlisted_commits, 1);
+        assert_eq!(stats.average_rank, Some(2.0 / 3.0));
+    }
+
+    #[test]
+    fn test_capacity_rejects_new() {
+        let mut sessions = manager(1, EvictionPolicy::RejectNew);
+        let now = Instant::now();
+        sessions.touch("a", now).unwrap();
+        assert!(sessions.touch("a", now).is_ok());
+        assert!(matches!(
+            sessions.touch("b", now),
+            Err(AzukiError::Session(_))
+        ));
+    }
+
+    #[test]
+    fn test_remember_segmentation() {
+        let mut session = Session::new(Instant::now());
+        session.remember_segmentation("きょうは", vec![3, 1]);
+        session.remember_segmentation("きょうは", vec![2, 2]);
+        assert_eq!(session.segmentation("きょうは"), Some(&[2, 2][..]));
+        assert_eq!(session.segmentation("あした"), None);
+
+        for i in 0..MAX_SEGMENTATIONS {
+            session.remember_segmentation(&format!("よみ{}", i), vec![3]);
+        }
+        assert_eq!(session.segmentation("きょうは"), None);
+        assert_eq!(session.segmentation("よみ0"), Some(&[3][..]));
+    }
+}
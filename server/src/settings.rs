@@ -0,0 +1,130 @@
+//! Settings the client can change while the server runs
+//!
+//! `set_config` changes them for every session or for one; values set for
+//! a session take precedence over the global ones.
+
+use serde::{Deserialize, Serialize};
+
+/// Settings in effect for a conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Settings {
+    /// Most candidates returned for a reading or segment, 0 for no limit
+    pub candidate_limit: usize,
+    /// Add the half-width katakana form of readings as a candidate
+    pub halfwidth_katakana: bool,
+    /// Convert with Zenzai when a model is loaded
+    pub zenzai: bool,
+    /// Learn candidate order from commits
+    pub learning: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            candidate_limit: 0,
+            halfwidth_katakana: false,
+            zenzai: true,
+            learning: true,
+        }
+    }
+}
+
+impl Settings {
+    /// These settings with the values of `patch` applied
+    pub fn with(mut self, patch: &SettingsPatch) -> Self {
+        if let Some(limit) = patch.candidate_limit {
+            self.candidate_limit = limit;
+        }
+        if let Some(halfwidth_katakana) = patch.halfwidth_katakana {
+            self.halfwidth_katakana = halfwidth_katakana;
+        }
+        if let Some(zenzai) = patch.zenzai {
+            self.zenzai = zenzai;
+        }
+        if let Some(learning) = patch.learning {
+            self.learning = learning;
+        }
+        self
+    }
+
+    /// Shorten a candidate list to the candidate limit
+    pub fn limit<T>(&self, candidates: &mut Vec<T>) {
+        if self.candidate_limit > 0 {
+            candidates.truncate(self.candidate_limit);
+        }
+    }
+}
+
+/// Settings to change; absent ones are left as they are
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsPatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub candidate_limit: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub halfwidth_katakana: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zenzai: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub learning: Option<bool>,
+}
+
+impl SettingsPatch {
+    /// Add the values of `other`, replacing ones set in both
+    pub fn merge(&mut self, other: &SettingsPatch) {
+        self.candidate_limit = other.candidate_limit.or(self.candidate_limit);
+        self.halfwidth_katakana = other.halfwidth_katakana.or(self.halfwidth_katakana);
+        self.zenzai = other.zenzai.or(self.zenzai);
+        self.learning = other.learning.or(self.learning);
+    }
+}
+
+/// Which sessions a `set_config` request applies to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Only the requesting session
+    #[default]
+    Session,
+    /// Every session, unless it set the value itself
+    Global,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_values_take_precedence() {
+        let global = Settings {
+            candidate_limit: 5,
+            ..Settings::default()
+        };
+        let mut session = SettingsPatch::default();
+        session.merge(&serde_json::from_str(r#"{"zenzai":false}"#).unwrap());
+        session.merge(&serde_json::from_str(r#"{"candidate_limit":3}"#).unwrap());
+
+        let effective = global.with(&session);
+        assert_eq!(effective.candidate_limit, 3);
+        assert!(!effective.zenzai);
+        assert!(effective.learning);
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut candidates = vec!["a", "b", "c"];
+        Settings::default().limit(&mut candidates);
+        assert_eq!(candidates.len(), 3);
+        let settings = Settings {
+            candidate_limit: 2,
+            ..Settings::default()
+        };
+        settings.limit(&mut candidates);
+        assert_eq!(candidates, ["a", "b"]);
+    }
+
+    #[test]
+    fn test_unknown_settings_are_rejected() {
+        assert!(serde_json::from_str::<SettingsPatch>(r#"{"limit":3}"#).is_err());
+    }
+}
@@ -0,0 +1,610 @@
+//! Zenzai inference in a child process (`azuki-server zenzai-worker`)
+//!
+//! llama.cpp aborts the process on a bad model file or when memory runs
+//! out, so the server runs the model in a worker and talks to it over the
+//! worker's stdin/stdout, one JSON message per line. When the worker dies
+//! the conversion fails and falls back to the dictionary; the next one
+//! starts a new worker, until it has crashed `MAX_CRASHES` times in a row.
+//!
+//! The server sends `init` with the Zenzai configuration, answered by
+//! `ready` once the default model is loaded, then `convert` and
+//! `convert_batch` requests. A conversion sends `progress` messages while
+//! decoding and ends with `result` (`batch_result`), `error` or
+//! `cancelled`; `cancel` stops the one in progress. `status` is answered
+//! by `status` with the loaded models.
+
+#[cfg(feature = "zenzai")]
+use crate::cancel::CancelToken;
+use crate::error::Result;
+#[cfg(feature = "zenzai")]
+use crate::error::{AzukiError, ZenzaiError};
+#[cfg(feature = "zenzai")]
+use crate::log;
+#[cfg(feature = "zenzai")]
+use crate::zenzai::ZenzaiBackend;
+use crate::zenzai::{ModelStatus, ZenzaiCandidate, ZenzaiConfig};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "zenzai")]
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(feature = "zenzai")]
+use std::process::{Child, ChildStdin, Command, Stdio};
+#[cfg(feature = "zenzai")]
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+#[cfg(feature = "zenzai")]
+use std::thread;
+#[cfg(feature = "zenzai")]
+use std::time::Duration;
+
+/// Consecutive crashes after which no new worker is started
+#[cfg(feature = "zenzai")]
+const MAX_CRASHES: u32 = 3;
+
+/// How often a conversion waiting on the worker checks for cancellation
+#[cfg(feature = "zenzai")]
+const CANCEL_POLL: Duration = Duration::from_millis(20);
+
+/// Message from the server to the worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+enum WorkerRequest {
+    /// Load the default model of this configuration
+    Init { config: Box<ZenzaiConfig> },
+    Convert {
+        session_id: String,
+        profile: Option<String>,
+        reading: String,
+        context: Option<String>,
+    },
+    /// Convert several readings, each with the text preceding it
+    ConvertBatch {
+        profile: Option<String>,
+        items: Vec<(String, Option<String>)>,
+    },
+    /// Stop the conversion in progress
+    Cancel,
+    /// Report the loaded models
+    Status,
+}
+
+/// Message from the worker to the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+enum WorkerReply {
+    /// The default model is loaded
+    Ready,
+    /// Most likely text so far of the conversion in progress
+    Progress {
+        text: String,
+    },
+    Result {
+        candidates: Vec<ZenzaiCandidate>,
+        confidence: Option<f64>,
+    },
+    /// Conversions of each reading of a `convert_batch`
+    BatchResult {
+        outputs: Vec<Vec<ZenzaiCandidate>>,
+    },
+    Cancelled,
+    Error {
+        message: String,
+    },
+    Status {
+        status: ModelStatus,
+    },
+}
+
+/// Zenzai conversions run by a supervised worker process
+///
+/// Has the conversion interface of [`ZenzaiBackend`]; the worker holds
+/// the models and contexts.
+#[cfg(feature = "zenzai")]
+pub struct ZenzaiSidecar {
+    config: ZenzaiConfig,
+    /// None until started, and after it exited or was unloaded
+    worker: Option<Worker>,
+    /// Crashes since the worker last converted anything
+    crashes: u32,
+    /// Confidence of the most recent conversion
+    last_confidence: Option<f64>,
+    /// Why the latest conversion or start of the worker failed
+    last_error: Option<String>,
+}
+
+/// A running worker process
+#[cfg(feature = "zenzai")]
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    /// Lines the worker writes, disconnected once it exits
+    replies: mpsc::Receiver<String>,
+}
+
+#[cfg(feature = "zenzai")]
+impl Worker {
+    /// Start a worker, without loading anything yet
+    fn spawn() -> Result<Self> {
+        let mut child = Command::new(std::env::current_exe()?)
+            .arg("--log-level")
+            .arg(log::level().name())
+            .arg("zenzai-worker")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            _ => return Err(ZenzaiError::WorkerCrashed("no stdio".to_string()).into()),
+        };
+        let (sender, replies) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        log_info!("[zenzai] Started worker process {}", child.id());
+        Ok(Worker {
+            child,
+            stdin,
+            replies,
+        })
+    }
+
+    fn send(&mut self, request: &WorkerRequest) -> io::Result<()> {
+        let mut line = serde_json::to_vec(request)?;
+        line.push(b'\n');
+        self.stdin.write_all(&line)?;
+        self.stdin.flush()
+    }
+
+    /// Next reply, None once the worker exited
+    ///
+    /// `cancel` is forwarded to the worker, once, if it is cancelled while
+    /// waiting.
+    fn recv(&mut self, cancel: &CancelToken, forwarded: &mut bool) -> Option<WorkerReply> {
+        loop {
+            match self.replies.recv_timeout(CANCEL_POLL) {
+                Ok(line) => match serde_json::from_str(&line) {
+                    Ok(reply) => return Some(reply),
+                    Err(e) => log_warn!("[zenzai] Malformed worker message: {}", e),
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if cancel.is_cancelled() && !*forwarded {
+                        *forwarded = true;
+                        // A worker that can't be written to is about to be
+                        // found dead
+                        let _ = self.send(&WorkerRequest::Cancel);
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// How the worker ended, waiting for it
+    fn exit_status(&mut self) -> String {
+        match self.child.wait() {
+            Ok(status) => status.to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+}
+
+/// Error for a reply that doesn't answer the request
+#[cfg(feature = "zenzai")]
+fn unexpected(reply: WorkerReply) -> AzukiError {
+    ZenzaiError::Worker(format!("unexpected reply {:?}", reply)).into()
+}
+
+#[cfg(feature = "zenzai")]
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(feature = "zenzai")]
+impl ZenzaiSidecar {
+    pub fn new(config: ZenzaiConfig) -> Self {
+        Self {
+            config,
+            worker: None,
+            crashes: 0,
+            last_confidence: None,
+            last_error: None,
+        }
+    }
+
+    /// Start the worker and load the default model
+    pub fn initialize(&mut self) -> Result<()> {
+        let started = self.worker().map(|_| ());
+        self.record(started)
+    }
+
+    /// Remember the error of a failed operation
+    ///
+    /// Cancellations aren't failures of Zenzai, and are not remembered.
+    fn record<T>(&mut self, result: Result<T>) -> Result<T> {
+        match &result {
+            Ok(_) => self.last_error = None,
+            Err(AzukiError::Cancelled) => {}
+            Err(e) => self.last_error = Some(e.to_string()),
+        }
+        result
+    }
+
+    /// The running worker, starting one if there is none
+    fn worker(&mut self) -> Result<&mut Worker> {
+        if self.worker.is_none() {
+            if self.crashes >= MAX_CRASHES {
+                return Err(ZenzaiError::WorkerCrashed(format!(
+                    "not restarted after {} crashes",
+                    self.crashes
+                ))
+                .into());
+            }
+            let mut worker = Worker::spawn()?;
+            let init = WorkerRequest::Init {
+                config: Box::new(self.config.clone()),
+            };
+            if worker.send(&init).is_err() {
+                return Err(self.crashed(worker));
+            }
+            match worker.recv(&CancelToken::default(), &mut false) {
+                Some(WorkerReply::Ready) => self.worker = Some(worker),
+                Some(WorkerReply::Error { message }) => {
+                    return Err(ZenzaiError::Worker(message).into())
+                }
+                Some(reply) => return Err(unexpected(reply)),
+                None => return Err(self.crashed(worker)),
+            }
+        }
+        Ok(self.worker.as_mut().expect("worker was just started"))
+    }
+
+    /// Record that `worker` exited unexpectedly
+    fn crashed(&mut self, mut worker: Worker) -> AzukiError {
+        self.crashes += 1;
+        let status = worker.exit_status();
+        log_error!(
+            "[zenzai] Worker crashed ({}), {} of {} before giving up",
+            status,
+            self.crashes,
+            MAX_CRASHES
+        );
+        ZenzaiError::WorkerCrashed(status).into()
+    }
+
+    /// Convert hiragana to kanji in the worker
+    ///
+    /// Behaves as [`ZenzaiBackend::convert`]; a crash of the worker fails
+    /// the conversion, and the next one starts a new worker.
+    pub fn convert(
+        &mut self,
+        session_id: &str,
+        profile: Option<&str>,
+        reading: &str,
+        context: Option<&str>,
+        cancel: &CancelToken,
+        progress: &mut dyn FnMut(&str),
+    ) -> Result<Vec<ZenzaiCandidate>> {
+        let request = WorkerRequest::Convert {
+            session_id: session_id.to_string(),
+            profile: profile.map(str::to_string),
+            reading: reading.to_string(),
+            context: context.map(str::to_string),
+        };
+        match self.request(&request, cancel, progress)? {
+            WorkerReply::Result {
+                candidates,
+                confidence,
+            } => {
+                self.last_confidence = confidence;
+                Ok(candidates)
+            }
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Convert several readings at once in the worker
+    ///
+    /// Behaves as [`ZenzaiBackend::convert_batch`].
+    pub fn convert_batch(
+        &mut self,
+        profile: Option<&str>,
+        items: &[(&str, Option<&str>)],
+        cancel: &CancelToken,
+    ) -> Result<Vec<Vec<ZenzaiCandidate>>> {
+        let request = WorkerRequest::ConvertBatch {
+            profile: profile.map(str::to_string),
+            items: items
+                .iter()
+                .map(|&(reading, context)| (reading.to_string(), context.map(str::to_string)))
+                .collect(),
+        };
+        match self.request(&request, cancel, &mut |_| {})? {
+            WorkerReply::BatchResult { outputs } => Ok(outputs),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Send a conversion to the worker and wait for its outcome
+    ///
+    /// Progress is passed to `progress`; the reply ending the conversion
+    /// is returned unless it is an error or a cancellation.
+    fn request(
+        &mut self,
+        request: &WorkerRequest,
+        cancel: &CancelToken,
+        progress: &mut dyn FnMut(&str),
+    ) -> Result<WorkerReply> {
+        let result = self.exchange(request, cancel, progress);
+        self.record(result)
+    }
+
+    fn exchange(
+        &mut self,
+        request: &WorkerRequest,
+        cancel: &CancelToken,
+        progress: &mut dyn FnMut(&str),
+    ) -> Result<WorkerReply> {
+        let worker = self.worker()?;
+        if worker.send(request).is_err() {
+            let worker = self.worker.take().expect("worker is running");
+            return Err(self.crashed(worker));
+        }
+        let mut forwarded = false;
+        loop {
+            let worker = self.worker.as_mut().expect("worker is running");
+            match worker.recv(cancel, &mut forwarded) {
+                Some(WorkerReply::Progress { text }) => progress(&text),
+                Some(WorkerReply::Ready) => log_warn!("[zenzai] Unexpected worker reply: ready"),
+                Some(WorkerReply::Cancelled) => return Err(AzukiError::Cancelled),
+                Some(WorkerReply::Error { message }) => {
+                    return Err(ZenzaiError::Worker(message).into())
+                }
+                Some(reply) => {
+                    self.crashes = 0;
+                    return Ok(reply);
+                }
+                None => {
+                    let worker = self.worker.take().expect("worker is running");
+                    return Err(self.crashed(worker));
+                }
+            }
+        }
+    }
+
+    /// Confidence of the most recent conversion (0.0 - 1.0)
+    pub fn last_confidence(&self) -> Option<f64> {
+        self.last_confidence
+    }
+
+    /// Models loaded in the worker, None if it isn't running
+    ///
+    /// Doesn't start a worker.
+    pub fn status(&mut self) -> Result<Option<ModelStatus>> {
+        if self.worker.is_none() {
+            return Ok(None);
+        }
+        match self.exchange(&WorkerRequest::Status, &CancelToken::default(), &mut |_| {})? {
+            WorkerReply::Status { status } => Ok(Some(status)),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Process id of the worker, if it is running
+    pub fn worker_pid(&self) -> Option<u32> {
+        self.worker.as_ref().map(|worker| worker.child.id())
+    }
+
+    /// Why the latest conversion failed, None if it succeeded
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    pub fn config(&self) -> &ZenzaiConfig {
+        &self.config
+    }
+
+    /// Stop the worker, freeing the memory of its models
+    ///
+    /// The next conversion starts it again. Returns false if it wasn't
+    /// running.
+    pub fn unload(&mut self) -> bool {
+        self.worker.take().is_some()
+    }
+}
+
+/// Serve Zenzai conversions to the server over stdin/stdout
+///
+/// Runs until stdin is closed, which is when the server exits or stops
+/// the worker.
+#[cfg(feature = "zenzai")]
+pub fn run_worker() -> Result<()> {
+    let (sender, requests) = mpsc::channel();
+    // Requests are read on their own thread so `cancel` reaches the
+    // conversion in progress; each conversion gets a fresh token
+    thread::spawn(move || {
+        let mut current = CancelToken::default();
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            let request: WorkerRequest = match serde_json::from_str(&line) {
+                Ok(request) => request,
+                Err(e) => {
+                    log_warn!("[zenzai] Malformed server message: {}", e);
+                    continue;
+                }
+            };
+            match request {
+                WorkerRequest::Cancel => current.cancel(),
+                WorkerRequest::Convert { .. } | WorkerRequest::ConvertBatch { .. } => {
+                    current = CancelToken::default();
+                    if sender.send((request, current.clone())).is_err() {
+                        break;
+                    }
+                }
+                WorkerRequest::Init { .. } | WorkerRequest::Status => {
+                    if sender.send((request, CancelToken::default())).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut backend: Option<Arc<Mutex<ZenzaiBackend>>> = None;
+    for (request, cancel) in requests {
+        let reply = match request {
+            WorkerRequest::Init { config } => {
+                let mut zenzai = ZenzaiBackend::new(*config);
+                match zenzai.initialize() {
+                    Ok(()) => {
+                        let zenzai = Arc::new(Mutex::new(zenzai));
+                        crate::zenzai::spawn_idle_unloader(&zenzai);
+                        backend = Some(zenzai);
+                        WorkerReply::Ready
+                    }
+                    Err(e) => WorkerReply::Error {
+                        message: e.to_string(),
+                    },
+                }
+            }
+            WorkerRequest::Convert {
+                session_id,
+                profile,
+                reading,
+                context,
+            } => match &backend {
+                Some(zenzai) => {
+                    let mut zenzai = zenzai.lock().unwrap_or_else(PoisonError::into_inner);
+                    let mut progress = |text: &str| {
+                        let text = text.to_string();
+                        // A server that went away closes stdin too
+                        let _ = write_reply(&mut out, &WorkerReply::Progress { text });
+                    };
+                    let result = zenzai.convert(
+                        &session_id,
+                        profile.as_deref(),
+                        &reading,
+                        context.as_deref(),
+                        &cancel,
+                        &mut progress,
+                    );
+                    match result {
+                        Ok(candidates) => WorkerReply::Result {
+                            candidates,
+                            confidence: zenzai.last_confidence(),
+                        },
+                        Err(AzukiError::Cancelled) => WorkerReply::Cancelled,
+                        Err(e) => WorkerReply::Error {
+                            message: e.to_string(),
+                        },
+                    }
+                }
+                None => WorkerReply::Error {
+                    message: ZenzaiError::NotInitialized.to_string(),
+                },
+            },
+            WorkerRequest::ConvertBatch { profile, items } => match &backend {
+                Some(zenzai) => {
+                    let mut zenzai = zenzai.lock().unwrap_or_else(PoisonError::into_inner);
+                    let items: Vec<(&str, Option<&str>)> = items
+                        .iter()
+                        .map(|(reading, context)| (reading.as_str(), context.as_deref()))
+                        .collect();
+                    match zenzai.convert_batch(profile.as_deref(), &items, &cancel) {
+                        Ok(outputs) => WorkerReply::BatchResult { outputs },
+                        Err(AzukiError::Cancelled) => WorkerReply::Cancelled,
+                        Err(e) => WorkerReply::Error {
+                            message: e.to_string(),
+                        },
+                    }
+                }
+                None => WorkerReply::Error {
+                    message: ZenzaiError::NotInitialized.to_string(),
+                },
+            },
+            WorkerRequest::Status => WorkerReply::Status {
+                status: backend
+                    .as_ref()
+                    .map_or_else(ModelStatus::default, |zenzai| {
+                        zenzai
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .status()
+                    }),
+            },
+            WorkerRequest::Cancel => continue,
+        };
+        write_reply(&mut out, &reply)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "zenzai"))]
+pub fn run_worker() -> Result<()> {
+    Err(crate::error::AzukiError::Config(
+        "azuki-server was built without the zenzai feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "zenzai")]
+fn write_reply(out: &mut impl Write, reply: &WorkerReply) -> io::Result<()> {
+    let mut line = serde_json::to_vec(reply)?;
+    line.push(b'\n');
+    out.write_all(&line)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_are_one_line_of_json() {
+        let request = WorkerRequest::Convert {
+            session_id: "s".to_string(),
+            profile: None,
+            reading: "かんじ\n".to_string(),
+            context: Some("前の文".to_string()),
+        };
+        let line = serde_json::to_string(&request).unwrap();
+        assert!(!line.contains('\n'));
+        assert!(line.starts_with(r#"{"type":"convert""#));
+
+        let init = WorkerRequest::Init {
+            config: Box::new(ZenzaiConfig::default()),
+        };
+        let line = serde_json::to_string(&init).unwrap();
+        match serde_json::from_str(&line).unwrap() {
+            WorkerRequest::Init { config } => assert_eq!(config.beam_width, 3),
+            other => panic!("unexpected request: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reply_round_trip() {
+        let reply = WorkerReply::Result {
+            candidates: vec![ZenzaiCandidate {
+                text: "漢字".to_string(),
+                log_prob: -0.5,
+                confidence: 0.75,
+            }],
+            confidence: Some(0.75),
+        };
+        let line = serde_json::to_string(&reply).unwrap();
+        assert_eq!(serde_json::from_str::<WorkerReply>(&line).unwrap(), reply);
+        assert_eq!(
+            serde_json::from_str::<WorkerReply>(r#"{"type":"cancelled"}"#).unwrap(),
+            WorkerReply::Cancelled
+        );
+    }
+}
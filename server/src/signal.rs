@@ -0,0 +1,35 @@
+//! SIGTERM handling
+//!
+//! The handler only sets a flag, since little else is safe inside a signal
+//! handler; a thread polling it flushes persistent state and exits.
+
+use crate::handler::Server;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+pub use signal_hook::consts::SIGTERM;
+
+/// How often the flag is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Flush the server's persistent state and exit when SIGTERM arrives
+pub fn flush_on_sigterm(server: Arc<Mutex<Server>>) {
+    let terminated = Arc::new(AtomicBool::new(false));
+    if let Err(e) = signal_hook::flag::register(SIGTERM, Arc::clone(&terminated)) {
+        log_warn!("Failed to handle SIGTERM: {}", e);
+        return;
+    }
+    thread::spawn(move || loop {
+        thread::sleep(POLL_INTERVAL);
+        if terminated.load(Ordering::SeqCst) {
+            log_info!("SIGTERM received, shutting down");
+            let failures = server
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .persist();
+            std::process::exit(i32::from(!failures.is_empty()));
+        }
+    });
+}
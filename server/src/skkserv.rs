@@ -0,0 +1,262 @@
+//! skkserv protocol compatibility
+//!
+//! Classic SKK clients (ddskk, skkeleton, ...) look words up through an
+//! skkserv over TCP. A request is a command byte, followed for lookups by
+//! an EUC-JP key terminated by a space:
+//!
+//! - `0`: end the connection
+//! - `1<key> `: candidates, answered `1/cand1/cand2/\n`, or `4<key> \n`
+//!   when there are none
+//! - `2`: server version, answered `azuki-server.<version> `
+//! - `3`: host, answered `<host>:<address>: `
+//! - `4<prefix> `: completion (an skkserv extension), answered
+//!   `1/reading1/reading2/\n`
+//!
+//! Candidates come from the same dictionaries, blocklist and learning as
+//! conversion.
+
+use crate::dictionary::encode_candidate;
+use crate::error;
+use crate::handler::Server;
+use encoding_rs::EUC_JP;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+
+/// Port skkserv clients connect to by default
+pub const DEFAULT_PORT: u16 = 1178;
+
+/// Maximum number of readings in a completion response
+const COMPLETION_LIMIT: usize = 100;
+
+/// Longest key accepted, in bytes; readings are far shorter, so a client
+/// sending more is dropped rather than buffered without bound
+const MAX_KEY_LEN: usize = 1024;
+
+/// Bind the skkserv address and print it to stdout
+///
+/// An address without a port uses [`DEFAULT_PORT`].
+pub fn bind(addr: &str) -> error::Result<TcpListener> {
+    let listener = if addr.contains(':') {
+        TcpListener::bind(addr)?
+    } else {
+        TcpListener::bind((addr, DEFAULT_PORT))?
+    };
+    let local = listener.local_addr()?;
+    if !local.ip().is_loopback() {
+        log_warn!("skkserv listening on non-loopback address {}", local);
+    }
+    log_info!("skkserv listening on {}", local);
+    println!("skkserv:{}", local);
+    Ok(listener)
+}
+
+/// Serve each skkserv client on its own thread
+pub fn accept_loop(listener: TcpListener, server: Arc<Mutex<Server>>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log_error!("[skkserv] Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        let server = Arc::clone(&server);
+        thread::spawn(move || {
+            if let Err(e) = serve(stream, &server) {
+                log_error!("[skkserv] Connection closed with error: {}", e);
+            }
+        });
+    }
+}
+
+/// Handle requests from one client until EOF or `0`
+fn serve(stream: TcpStream, server: &Mutex<Server>) -> io::Result<()> {
+    let host = stream.local_addr()?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    loop {
+        let mut command = [0u8];
+        if reader.read(&mut command)? == 0 {
+            break;
+        }
+        let response = match command[0] {
+            b'0' => break,
+            b'1' => {
+                let key = read_key(&mut reader)?;
+                let server = server.lock().unwrap_or_else(PoisonError::into_inner);
+                lookup_response(&server, &key)
+            }
+            b'2' => format!("azuki-server.{} ", env!("CARGO_PKG_VERSION")).into_bytes(),
+            b'3' => format!("azuki:{}: ", host).into_bytes(),
+            b'4' => {
+                let key = read_key(&mut reader)?;
+                let server = server.lock().unwrap_or_else(PoisonError::into_inner);
+                completion_response(&server, &key)
+            }
+            // Clients may send a newline after each request
+            b' ' | b'\n' | b'\r' => continue,
+            other => {
+                log_debug!("[skkserv] Unknown command {:?}", other as char);
+                read_key(&mut reader)?;
+                continue;
+            }
+        };
+        writer.write_all(&response)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Read a key up to its terminating space, without the space
+///
+/// Fails on keys longer than [`MAX_KEY_LEN`], which ends the connection.
+fn read_key<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut key = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_KEY_LEN as u64 + 1)
+        .read_until(b' ', &mut key)?;
+    if key.last() == Some(&b' ') {
+        key.pop();
+    }
+    if key.len() > MAX_KEY_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("key longer than {} bytes", MAX_KEY_LEN),
+        ));
+    }
+    Ok(key)
+}
+
+/// Answer a `1<key> ` request
+fn lookup_response(server: &Server, key: &[u8]) -> Vec<u8> {
+    let (reading, _, malformed) = EUC_JP.decode(key);
+    let candidates = if malformed {
+        Vec::new()
+    } else {
+        server.dictionary_candidates(&reading)
+    };
+    candidate_list(&candidates).unwrap_or_else(|| not_found(key))
+}
+
+/// Answer a `4<prefix> ` request
+fn completion_response(server: &Server, key: &[u8]) -> Vec<u8> {
+    let (prefix, _, malformed) = EUC_JP.decode(key);
+    let readings = if malformed || prefix.is_empty() {
+        Vec::new()
+    } else {
+        server.dictionary_completions(&prefix, COMPLETION_LIMIT)
+    };
+    candidate_list(&readings).unwrap_or_else(|| not_found(key))
+}
+
+/// Encode `1/a/b/\n`, or `None` if nothing can be encoded
+///
+/// Words containing `/` or `;` are sent as `(concat "...")`. Words that
+/// EUC-JP cannot represent are left out.
+fn candidate_list(words: &[String]) -> Option<Vec<u8>> {
+    let mut response = b"1/".to_vec();
+    let mut any = false;
+    for word in words {
+        let word = encode_candidate(word);
+        let (bytes, _, unmappable) = EUC_JP.encode(&word);
+        if unmappable {
+            continue;
+        }
+        response.extend_from_slice(&bytes);
+        response.push(b'/');
+        any = true;
+    }
+    response.push(b'\n');
+    any.then_some(response)
+}
+
+/// Encode `4<key> \n`
+fn not_found(key: &[u8]) -> Vec<u8> {
+    let mut response = vec![b'4'];
+    response.extend_from_slice(key);
+    response.extend_from_slice(b" \n");
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::Dictionary;
+    use std::io::Cursor;
+
+    fn test_server() -> Server {
+        let dictionary = Dictionary::load(
+            std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures/test-dict.utf8"),
+        )
+        .unwrap();
+        Server::with_dictionary(Some(Arc::new(dictionary)))
+    }
+
+    fn euc(text: &str) -> Vec<u8> {
+        EUC_JP.encode(text).0.into_owned()
+    }
+
+    #[test]
+    fn test_lookup_response() {
+        let server = test_server();
+        assert_eq!(
+            lookup_response(&server, &euc("きょう")),
+            euc("1/今日/京/教/\n")
+        );
+        assert_eq!(lookup_response(&server, &euc("かk")), euc("1/書/欠/\n"));
+        assert_eq!(lookup_response(&server, &euc("ないよ")), euc("4ないよ \n"));
+    }
+
+    #[test]
+    fn test_candidate_list_encodes_separators() {
+        let words = ["D/C", "a;b", "今日"].map(String::from);
+        assert_eq!(
+            candidate_list(&words).unwrap(),
+            euc(concat!(
+                r#"1/(concat "D\057C")/(concat "a\073b")/今日/"#,
+                "\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_completion_response() {
+        let server = test_server();
+        assert_eq!(
+            completion_response(&server, &euc("こ")),
+            euc("1/こんにちは/\n")
+        );
+        assert_eq!(completion_response(&server, &euc("ぬ")), euc("4ぬ \n"));
+    }
+
+    #[test]
+    fn test_unencodable_candidates_are_skipped() {
+        let words = vec!["🍣".to_string(), "寿司".to_string()];
+        assert_eq!(candidate_list(&words), Some(euc("1/寿司/\n")));
+        assert_eq!(candidate_list(&words[..1]), None);
+    }
+
+    #[test]
+    fn test_read_key() {
+        let mut reader = Cursor::new(b"abc 2".to_vec());
+        assert_eq!(read_key(&mut reader).unwrap(), b"abc");
+        assert_eq!(read_key(&mut reader).unwrap(), b"2");
+    }
+
+    #[test]
+    fn test_read_key_rejects_long_keys() {
+        let longest = [vec![b'a'; MAX_KEY_LEN], b" ".to_vec()].concat();
+        assert_eq!(
+            read_key(&mut Cursor::new(longest)).unwrap().len(),
+            MAX_KEY_LEN
+        );
+        let endless = vec![b'a'; MAX_KEY_LEN * 4];
+        assert!(read_key(&mut Cursor::new(endless)).is_err());
+    }
+}
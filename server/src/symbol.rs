@@ -0,0 +1,103 @@
+//! Emoji and symbol candidates
+//!
+//! Dictionaries such as SKK-JISYO.emoji map readings to emoji ("すし" ->
+//! "🍣"), and SKK-JISYO.L has symbol entries ("ほし" -> "☆"). Neither
+//! needs special loading; they are told apart from words by their text,
+//! so clients can show them differently or leave them out.
+
+/// Whether a character can be part of an emoji sequence
+///
+/// Besides pictographs this allows the symbols that take an emoji
+/// presentation selector ("❤️"), keycap bases ("1️⃣"), the zero width
+/// joiner of sequences ("👨‍👩‍👧") and tag characters of subdivision flags.
+fn is_emoji_component(c: char) -> bool {
+    matches!(
+        c,
+        '\u{1F000}'..='\u{1FAFF}'
+            // Punctuation (with the joiner), keycap mark, arrows, dingbats
+            | '\u{2000}'..='\u{2BFF}'
+            | '\u{3030}'
+            | '\u{303D}'
+            | '\u{3297}'
+            | '\u{3299}'
+            | '\u{00A9}'
+            | '\u{00AE}'
+            | '\u{FE0F}'
+            | '\u{E0020}'..='\u{E007F}'
+            | '0'..='9'
+            | '#'
+            | '*'
+    )
+}
+
+/// Whether a character is only ever shown as emoji, or makes the
+/// sequence it is in one
+fn is_emoji_presentation(c: char) -> bool {
+    matches!(c, '\u{1F000}'..='\u{1FAFF}' | '\u{FE0F}' | '\u{20E3}')
+}
+
+/// Whether text is a single emoji or a run of them
+///
+/// Symbols shown as text by default ("☆", "→") are not emoji unless they
+/// carry the emoji presentation selector.
+pub fn is_emoji(text: &str) -> bool {
+    text.chars().any(is_emoji_presentation) && text.chars().all(is_emoji_component)
+}
+
+/// Whether a character is punctuation used in ordinary text
+///
+/// ASCII punctuation, CJK punctuation ("。", "「", "〜") and their fullwidth
+/// and halfwidth forms ("！", "｡") are typed as often as words.
+fn is_text_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation()
+        || matches!(
+            c,
+            '\u{3000}'..='\u{303F}'
+                | '\u{FF01}'..='\u{FF0F}'
+                | '\u{FF1A}'..='\u{FF20}'
+                | '\u{FF3B}'..='\u{FF40}'
+                | '\u{FF5B}'..='\u{FF65}'
+        )
+}
+
+/// Whether text is made of symbols only ("☆", "→", "※")
+///
+/// Letters, digits, kana and kanji make a word; emoji are not symbols, and
+/// neither is ordinary punctuation ("。", "(^_^)").
+pub fn is_symbol(text: &str) -> bool {
+    !text.is_empty()
+        && !is_emoji(text)
+        && text.chars().all(|c| {
+            !c.is_alphanumeric() && !c.is_whitespace() && !c.is_control() && !is_text_punctuation(c)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_emoji() {
+        for emoji in ["🍣", "😀😀", "❤️", "1️⃣", "👨‍👩‍👧", "👍🏽", "🇯🇵"]
+        {
+            assert!(is_emoji(emoji), "{}", emoji);
+        }
+        for text in ["☆", "→", "寿司", "1", "#", ""] {
+            assert!(!is_emoji(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_is_symbol() {
+        for symbol in ["☆", "→", "※", "♪", "∞", "★★"] {
+            assert!(is_symbol(symbol), "{}", symbol);
+        }
+        for text in ["🍣", "❤️", "寿司", "ー", "ア", "Ａ", "3", "☆彡", ""] {
+            assert!(!is_symbol(text), "{}", text);
+        }
+        // Punctuation of ordinary text
+        for text in ["。", "、", "「」", "〜", "！", "｡", "(^_^)", "...", "☆。"] {
+            assert!(!is_symbol(text), "{}", text);
+        }
+    }
+}
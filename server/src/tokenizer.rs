@@ -0,0 +1,133 @@
+//! Script tokenizer for mixed input
+//!
+//! Readings can contain latin words and numbers between kana (e.g.
+//! 「ぎっとhubにぷっしゅ」). The tokenizer splits a reading into runs that
+//! are converted and runs that pass through unchanged, so the converter
+//! never tries to look up "hub" in a kana dictionary.
+
+/// Whether a token is converted or kept as typed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Kana and other text handed to the converter
+    Convertible,
+    /// Latin words, numbers and similar runs that pass through unconverted
+    Fixed,
+}
+
+/// A run of a reading, in character positions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub length: usize,
+}
+
+/// Splits readings into convertible and fixed tokens
+pub trait Tokenizer: Send + Sync {
+    /// Tokenize `chars`; the tokens must cover it contiguously
+    fn tokenize(&self, chars: &[char]) -> Vec<Token>;
+}
+
+/// Default tokenizer: ASCII and full-width alphanumeric runs are fixed
+///
+/// `-`, `_`, `.`, `+` and `#` continue a fixed run that started with an
+/// alphanumeric character, so words like "node.js" or "c++" stay whole.
+#[derive(Debug, Default)]
+pub struct ScriptTokenizer;
+
+fn is_alphanumeric(c: char) -> bool {
+    c.is_ascii_alphanumeric()
+        || ('０'..='９').contains(&c)
+        || ('Ａ'..='Ｚ').contains(&c)
+        || ('ａ'..='ｚ').contains(&c)
+}
+
+fn is_joiner(c: char) -> bool {
+    matches!(c, '-' | '_' | '.' | '+' | '#')
+}
+
+impl Tokenizer for ScriptTokenizer {
+    fn tokenize(&self, chars: &[char]) -> Vec<Token> {
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut push = |kind: TokenKind, start: usize, length: usize| match tokens.last_mut() {
+            Some(last) if last.kind == kind => last.length += length,
+            _ => tokens.push(Token {
+                kind,
+                start,
+                length,
+            }),
+        };
+
+        let mut pos = 0;
+        while pos < chars.len() {
+            if !is_alphanumeric(chars[pos]) {
+                push(TokenKind::Convertible, pos, 1);
+                pos += 1;
+                continue;
+            }
+            let mut end = pos + 1;
+            while end < chars.len() && (is_alphanumeric(chars[end]) || is_joiner(chars[end])) {
+                end += 1;
+            }
+            // Separators only count inside a word ("c++" and "c#" keep theirs)
+            while matches!(chars[end - 1], '-' | '_' | '.') {
+                end -= 1;
+            }
+            push(TokenKind::Fixed, pos, end - pos);
+            pos = end;
+        }
+        tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenize(text: &str) -> Vec<(TokenKind, String)> {
+        let chars: Vec<char> = text.chars().collect();
+        ScriptTokenizer
+            .tokenize(&chars)
+            .into_iter()
+            .map(|t| {
+                let text = chars[t.start..t.start + t.length].iter().collect();
+                (t.kind, text)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mixed_input() {
+        use TokenKind::*;
+        assert_eq!(
+            tokenize("ぎっとhubにぷっしゅ"),
+            vec![
+                (Convertible, "ぎっと".to_string()),
+                (Fixed, "hub".to_string()),
+                (Convertible, "にぷっしゅ".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokenize("node.jsと2024ねん"),
+            vec![
+                (Fixed, "node.js".to_string()),
+                (Convertible, "と".to_string()),
+                (Fixed, "2024".to_string()),
+                (Convertible, "ねん".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trailing_joiner_is_convertible() {
+        use TokenKind::*;
+        assert_eq!(
+            tokenize("c++.です"),
+            vec![
+                (Fixed, "c++".to_string()),
+                (Convertible, ".です".to_string()),
+            ]
+        );
+        assert_eq!(tokenize("ー"), vec![(Convertible, "ー".to_string())]);
+    }
+}
@@ -0,0 +1,127 @@
+//! Prefix index over dictionary readings
+//!
+//! A character trie answering "which readings start with this prefix"
+//! without scanning the whole dictionary. Nodes live in one vector and
+//! keep their children sorted, so lookups are binary searches and results
+//! come out in a stable order.
+
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    /// Child nodes by next character, sorted by character
+    children: Vec<(char, u32)>,
+    /// Whether a key ends at this node
+    terminal: bool,
+}
+
+/// Set of strings searchable by prefix
+#[derive(Debug, Clone)]
+pub struct Trie {
+    /// Node 0 is the root
+    nodes: Vec<Node>,
+}
+
+impl Default for Trie {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::default()],
+        }
+    }
+}
+
+impl Trie {
+    /// Add a key; adding it again has no effect
+    pub fn insert(&mut self, key: &str) {
+        let mut node = 0;
+        for c in key.chars() {
+            node = match self.child(node, c) {
+                Ok(child) => child,
+                Err(at) => {
+                    let child = self.nodes.len() as u32;
+                    self.nodes.push(Node::default());
+                    self.nodes[node].children.insert(at, (c, child));
+                    child as usize
+                }
+            };
+        }
+        self.nodes[node].terminal = true;
+    }
+
+    /// Check whether a key was added
+    #[allow(dead_code)]
+    pub fn contains(&self, key: &str) -> bool {
+        self.find(key).is_some_and(|node| self.nodes[node].terminal)
+    }
+
+    /// Up to `limit` keys starting with `prefix` (including the prefix
+    /// itself), shortest first and in character order within a length
+    pub fn with_prefix(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let mut result = Vec::new();
+        let Some(start) = self.find(prefix) else {
+            return result;
+        };
+        // Breadth-first, so shorter keys are found first
+        let mut queue = VecDeque::from([(start, prefix.to_string())]);
+        while let Some((node, key)) = queue.pop_front() {
+            if result.len() >= limit {
+                break;
+            }
+            if self.nodes[node].terminal {
+                result.push(key.clone());
+            }
+            for &(c, child) in &self.nodes[node].children {
+                let mut next = key.clone();
+                next.push(c);
+                queue.push_back((child as usize, next));
+            }
+        }
+        result
+    }
+
+    /// Node reached by following `key` from the root
+    fn find(&self, key: &str) -> Option<usize> {
+        key.chars().try_fold(0, |node, c| self.child(node, c).ok())
+    }
+
+    /// Child of `node` for `c`, or where it would be inserted
+    fn child(&self, node: usize, c: char) -> Result<usize, usize> {
+        let children = &self.nodes[node].children;
+        children
+            .binary_search_by_key(&c, |&(key, _)| key)
+            .map(|i| children[i].1 as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trie(keys: &[&str]) -> Trie {
+        let mut trie = Trie::default();
+        for key in keys {
+            trie.insert(key);
+        }
+        trie
+    }
+
+    #[test]
+    fn test_insert_and_contains() {
+        let trie = trie(&["きょう", "きょうと", "きょう"]);
+        assert!(trie.contains("きょう"));
+        assert!(!trie.contains("きょ"));
+        assert!(!trie.contains("あ"));
+    }
+
+    #[test]
+    fn test_with_prefix_shortest_first() {
+        let trie = trie(&["きょうと", "きょう", "きかい", "き", "かんじ"]);
+        assert_eq!(
+            trie.with_prefix("き", 10),
+            vec!["き", "きかい", "きょう", "きょうと"]
+        );
+        assert_eq!(trie.with_prefix("き", 2), vec!["き", "きかい"]);
+        assert!(trie.with_prefix("さ", 10).is_empty());
+        assert_eq!(trie.with_prefix("", 10).len(), 5);
+    }
+}
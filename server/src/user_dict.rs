@@ -0,0 +1,184 @@
+//! Writable user dictionary
+//!
+//! Words registered through `add_entry` are kept here and persisted in SKK
+//! format (`$XDG_DATA_HOME/azuki/user-dict`), separately from the read-only
+//! system dictionary. They are layered on top of the system dictionary so
+//! registered words are offered first.
+
+use crate::dictionary::{encode_candidate, parse_entry, Dictionary};
+use crate::error::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Header written at the top of the user dictionary file
+const HEADER: &str = ";; -*- coding: utf-8 -*-\n;; azuki user dictionary\n;; okuri-nasi entries.\n";
+
+/// User-registered words, most recently registered first per reading
+#[derive(Debug, Default)]
+pub struct UserDictionary {
+    entries: BTreeMap<String, Vec<String>>,
+    /// File the dictionary is persisted to, if any
+    path: Option<PathBuf>,
+}
+
+impl UserDictionary {
+    /// Create an empty in-memory user dictionary
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the user dictionary from `path`, persisting later changes there
+    ///
+    /// A missing file yields an empty dictionary.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut dict = Self {
+            entries: BTreeMap::new(),
+            path: Some(path.to_path_buf()),
+        };
+        if path.exists() {
+            let content = fs::read_to_string(path)?;
+            for line in content.lines().filter(|l| !l.starts_with(';')) {
+                if let Some((reading, candidates)) = parse_entry(line) {
+                    dict.entries.entry(reading).or_default().extend(candidates);
+                }
+            }
+        }
+        Ok(dict)
+    }
+
+    /// Register a word, moving it to the front if already registered
+    pub fn add(&mut self, reading: &str, candidate: &str) {
+        let candidates = self.entries.entry(reading.to_string()).or_default();
+        candidates.retain(|c| c != candidate);
+        candidates.insert(0, candidate.to_string());
+    }
+
+    /// Remove a registered word. Returns false if it wasn't registered.
+    pub fn remove(&mut self, reading: &str, candidate: &str) -> bool {
+        let Some(candidates) = self.entries.get_mut(reading) else {
+            return false;
+        };
+        let before = candidates.len();
+        candidates.retain(|c| c != candidate);
+        let removed = candidates.len() != before;
+        if candidates.is_empty() {
+            self.entries.remove(reading);
+        }
+        removed
+    }
+
+    /// Number of registered (reading, candidate) pairs
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    /// Check whether no words are registered
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Layer the registered words on top of a dictionary
+    pub fn apply_to(&self, dictionary: &mut Dictionary) {
+        for (reading, candidates) in &self.entries {
+            for candidate in candidates.iter().rev() {
+                dictionary.add_entry(reading, candidate);
+            }
+        }
+    }
+
+    /// Serialize to SKK format
+    pub fn to_text(&self) -> String {
+        let mut text = String::from(HEADER);
+        for (reading, candidates) in &self.entries {
+            text.push_str(reading);
+            text.push_str(" /");
+            for candidate in candidates {
+                text.push_str(&encode_candidate(candidate));
+                text.push('/');
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Write the dictionary to its file, if it has one
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.to_text())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut dict = UserDictionary::new();
+        dict.add("あずき", "小豆");
+        dict.add("あずき", "アズキ");
+        dict.add("あずき", "小豆");
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.entries["あずき"], vec!["小豆", "アズキ"]);
+
+        assert!(dict.remove("あずき", "アズキ"));
+        assert!(!dict.remove("あずき", "アズキ"));
+        assert!(dict.remove("あずき", "小豆"));
+        assert!(dict.is_empty());
+    }
+
+    #[test]
+    fn test_apply_to_prioritizes_user_words() {
+        let mut system = Dictionary::new();
+        system.add_entry("きょう", "京");
+        system.add_entry("きょう", "今日");
+
+        let mut user = UserDictionary::new();
+        user.add("きょう", "強");
+        user.add("かく", "書く");
+        user.apply_to(&mut system);
+
+        assert_eq!(system.lookup("きょう").unwrap(), vec!["強", "今日", "京"]);
+        assert_eq!(system.lookup_okuri_ari("かk", None).unwrap(), vec!["書"]);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("azuki-user-dict-{}", std::process::id()));
+        let mut dict = UserDictionary::load(&path).unwrap();
+        assert!(dict.is_empty());
+        dict.add("あずき", "小豆");
+        dict.add("あずき", "アズキ");
+        dict.save().unwrap();
+
+        let loaded = UserDictionary::load(&path).unwrap();
+        assert_eq!(loaded.entries["あずき"], vec!["アズキ", "小豆"]);
+        assert_eq!(loaded.to_text(), dict.to_text());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_slash_and_semicolon_survive_saving() {
+        let path = std::env::temp_dir().join(format!("azuki-user-concat-{}", std::process::id()));
+        let mut dict = UserDictionary::load(&path).unwrap();
+        dict.add("ゆーあーるえる", "http://");
+        dict.add("かっこ", "a;b");
+        assert!(dict
+            .to_text()
+            .contains(r#"ゆーあーるえる /(concat "http:\057\057")/"#));
+        dict.save().unwrap();
+
+        let loaded = UserDictionary::load(&path).unwrap();
+        assert_eq!(loaded.entries["ゆーあーるえる"], vec!["http://"]);
+        assert_eq!(loaded.entries["かっこ"], vec!["a;b"]);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,358 @@
+//! Dictionary hot reload
+//!
+//! A background thread waits for file system events (inotify, FSEvents,
+//! ...) on the directories of the dictionary files and the user
+//! dictionary. When one of the files changes, they are loaded again
+//! outside the server lock and the new dictionary is swapped in at once,
+//! so a long-running server picks up edits without a restart. A change is
+//! only acted on once the files have been quiet for one interval, so files
+//! still being written aren't half-loaded.
+//!
+//! Events aren't available everywhere: network and FUSE file systems often
+//! send none, and inotify runs out of watches on busy machines. There, and
+//! when `watch.poll` is set, the thread instead compares the modification
+//! time and size of the files every interval.
+
+use crate::config::{
+    dictionary_sources, load_dictionaries_reporting, load_user_dictionary, user_dictionary_path,
+    DictionarySource, ServerConfig,
+};
+use crate::handler::Server;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Hot reload configuration
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// How long files must be quiet before they are reloaded, and how
+    /// often they are checked when polling
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    /// Poll the files instead of waiting for file system events
+    #[serde(default)]
+    pub poll: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_interval_ms() -> u64 {
+    2000
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            interval_ms: default_interval_ms(),
+            poll: false,
+        }
+    }
+}
+
+/// Modification time and size; `None` while the file doesn't exist
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+struct WatchedFile {
+    path: PathBuf,
+    /// Stamp at the last poll
+    seen: Stamp,
+    /// Changed since the last reload, waiting to settle
+    pending: bool,
+}
+
+/// Checks a set of files for changes
+pub struct FileWatcher {
+    files: Vec<WatchedFile>,
+}
+
+impl FileWatcher {
+    /// Start watching files in their current state
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let files = paths
+            .into_iter()
+            .map(|path| WatchedFile {
+                seen: stamp(&path),
+                path,
+                pending: false,
+            })
+            .collect();
+        Self { files }
+    }
+
+    /// Watch another file in its current state, unless already watched
+    pub fn add(&mut self, path: &Path) {
+        if self.files.iter().any(|file| file.path == path) {
+            return;
+        }
+        self.files.push(WatchedFile {
+            seen: stamp(path),
+            path: path.to_path_buf(),
+            pending: false,
+        });
+    }
+
+    /// Files that changed and have not changed since the previous poll
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let mut settled = Vec::new();
+        for file in &mut self.files {
+            let current = stamp(&file.path);
+            if current != file.seen {
+                file.seen = current;
+                file.pending = true;
+            } else if file.pending {
+                file.pending = false;
+                settled.push(file.path.clone());
+            }
+        }
+        settled
+    }
+
+    /// Files that changed since they were last checked
+    ///
+    /// For use once events have shown the files to be settled.
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for file in &mut self.files {
+            let current = stamp(&file.path);
+            file.pending = false;
+            if current != file.seen {
+                file.seen = current;
+                changed.push(file.path.clone());
+            }
+        }
+        changed
+    }
+
+    /// Whether an event on `path` may change a watched file
+    ///
+    /// Files added to or removed from a watched directory count.
+    fn concerns(&self, path: &Path) -> bool {
+        self.files
+            .iter()
+            .any(|file| file.path == path || path.parent() == Some(file.path.as_path()))
+    }
+
+    /// Directories to receive events from: each watched directory, and the
+    /// directory of each watched file, since editors often replace a file
+    /// by renaming a new one over it
+    fn directories(&self) -> Vec<PathBuf> {
+        let mut directories: Vec<PathBuf> = Vec::new();
+        for file in &self.files {
+            let directory = match file.path.is_dir() {
+                true => Some(file.path.as_path()),
+                false => file.path.parent(),
+            };
+            let directory = directory
+                .filter(|d| d.is_dir())
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            if !directory.as_os_str().is_empty() && !directories.contains(&directory) {
+                directories.push(directory);
+            }
+        }
+        directories
+    }
+}
+
+/// What the watch thread waits on between checks
+enum Trigger {
+    /// File system events
+    Events {
+        events: Receiver<notify::Result<Event>>,
+        /// Sends the events for as long as it is kept
+        _notifier: RecommendedWatcher,
+    },
+    /// A timer
+    Poll,
+}
+
+impl Trigger {
+    /// Receive events for the directories of `watcher`'s files
+    ///
+    /// Directories that don't exist are left out; the files in them are
+    /// created by the server itself (the user dictionary) or not at all.
+    fn events(watcher: &FileWatcher) -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut notifier = notify::recommended_watcher(sender)?;
+        for directory in watcher.directories() {
+            notifier.watch(&directory, RecursiveMode::NonRecursive)?;
+        }
+        Ok(Trigger::Events {
+            events,
+            _notifier: notifier,
+        })
+    }
+
+    /// Wait for the next check and return the files that changed
+    ///
+    /// `None` once events stop coming for good.
+    fn wait(&self, watcher: &mut FileWatcher, interval: Duration) -> Option<Vec<PathBuf>> {
+        let events = match self {
+            Trigger::Events { events, .. } => events,
+            Trigger::Poll => {
+                thread::sleep(interval);
+                return Some(watcher.poll());
+            }
+        };
+        let concerns = |event: &notify::Result<Event>| {
+            event
+                .as_ref()
+                .is_ok_and(|event| event.paths.iter().any(|path| watcher.concerns(path)))
+        };
+        while !concerns(&events.recv().ok()?) {}
+        // Only events on the watched files delay the reload
+        let mut deadline = Instant::now() + interval;
+        loop {
+            match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(event) if concerns(&event) => deadline = Instant::now() + interval,
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => return Some(watcher.changed()),
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+}
+
+/// Reloads the dictionaries of a server when their files change
+pub struct Reloader {
+    config: WatchConfig,
+    /// Dictionaries as configured, empty for the default search
+    dictionaries: Vec<DictionarySource>,
+}
+
+impl Reloader {
+    /// Take the dictionary settings from the startup configuration
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            config: config.watch.clone().unwrap_or_default(),
+            dictionaries: config.dictionaries.clone(),
+        }
+    }
+
+    /// Start watching on a background thread, unless disabled
+    pub fn spawn(self, server: Arc<Mutex<Server>>) {
+        if !self.config.enabled {
+            return;
+        }
+        let mut sources = dictionary_sources(&self.dictionaries);
+        let user = user_dictionary_path();
+        let mut watcher = FileWatcher::new(sources.iter().cloned().chain(user.clone()).collect());
+        let interval = Duration::from_millis(self.config.interval_ms.max(10));
+        let trigger = match self.config.poll {
+            true => Trigger::Poll,
+            false => Trigger::events(&watcher).unwrap_or_else(|e| {
+                log_warn!("[watch] File events unavailable, polling instead: {}", e);
+                Trigger::Poll
+            }),
+        };
+
+        thread::spawn(move || loop {
+            let Some(changed) = trigger.wait(&mut watcher, interval) else {
+                log_warn!("[watch] File events stopped; dictionaries are no longer reloaded");
+                return;
+            };
+            if changed.is_empty() {
+                continue;
+            }
+            let system = changed.iter().any(|path| sources.contains(path)).then(|| {
+                log_info!("[watch] Reloading dictionaries");
+                // Files added to a dictionary directory are watched from now on
+                sources = dictionary_sources(&self.dictionaries);
+                for path in &sources {
+                    watcher.add(path);
+                }
+                load_dictionaries_reporting(&self.dictionaries)
+            });
+            let user_dictionary =
+                changed
+                    .iter()
+                    .any(|path| Some(path) == user.as_ref())
+                    .then(|| {
+                        log_info!("[watch] Reloading user dictionary");
+                        load_user_dictionary()
+                    });
+            let mut server = server.lock().unwrap_or_else(PoisonError::into_inner);
+            let system = system.map(|(dictionary, report)| {
+                server.set_dictionary_report(report);
+                dictionary.map(Arc::new)
+            });
+            server.replace_dictionaries(system, user_dictionary);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_are_reported_once_settled() {
+        let path = std::env::temp_dir().join(format!("azuki-watch-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut watcher = FileWatcher::new(vec![path.clone()]);
+        assert!(watcher.poll().is_empty());
+
+        fs::write(&path, "a").unwrap();
+        // Still settling
+        assert!(watcher.poll().is_empty());
+        assert_eq!(watcher.poll(), vec![path.clone()]);
+        assert!(watcher.poll().is_empty());
+
+        // Size changes are noticed even within the timestamp resolution
+        fs::write(&path, "ab").unwrap();
+        assert!(watcher.poll().is_empty());
+        assert_eq!(watcher.poll(), vec![path.clone()]);
+
+        fs::remove_file(&path).unwrap();
+        watcher.poll();
+        assert_eq!(watcher.poll(), vec![path]);
+    }
+
+    #[test]
+    fn test_events_report_settled_changes() {
+        let dir = std::env::temp_dir().join(format!("azuki-watch-events-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("SKK-JISYO.test");
+        fs::write(&path, "a").unwrap();
+        let mut watcher = FileWatcher::new(vec![path.clone()]);
+        let trigger = Trigger::events(&watcher).unwrap();
+
+        // Unrelated files in the directory don't count
+        fs::write(dir.join("other"), "x").unwrap();
+        fs::write(&path, "ab").unwrap();
+        let changed = trigger.wait(&mut watcher, Duration::from_millis(50));
+        assert_eq!(changed, Some(vec![path.clone()]));
+
+        // Replacing the file by a rename is seen as well
+        let temp = dir.join("SKK-JISYO.test.tmp");
+        fs::write(&temp, "abc").unwrap();
+        fs::rename(&temp, &path).unwrap();
+        let changed = trigger.wait(&mut watcher, Duration::from_millis(50));
+        assert_eq!(changed, Some(vec![path]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let config: WatchConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, WatchConfig::default());
+        assert!(config.enabled);
+        assert!(!config.poll);
+    }
+}
@@ -0,0 +1,181 @@
+//! Requests of one connection waiting for a worker
+//!
+//! Workers handle requests concurrently, but requests that change a
+//! session must see the session as the requests before them left it: a
+//! `convert` sent after `set_mode` has to convert in the new mode. Each
+//! job is queued with the sessions it changes; a worker takes the first
+//! job none of whose sessions has an earlier job queued or running. Jobs
+//! without sessions (pings, read-only requests) are taken as soon as a
+//! worker is free, so they can pass a slow conversion.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+/// Jobs in arrival order, handed out one session at a time
+pub struct WorkQueue<T> {
+    state: Mutex<State<T>>,
+    /// Signalled when a job is added, a session frees up or the queue closes
+    changed: Condvar,
+}
+
+struct State<T> {
+    jobs: VecDeque<(T, Vec<String>)>,
+    /// Sessions with a job being handled
+    running: HashSet<String>,
+    closed: bool,
+}
+
+/// Sessions of a job taken from the queue, freed when dropped
+pub struct Running<'a, T> {
+    queue: &'a WorkQueue<T>,
+    sessions: Vec<String>,
+}
+
+impl<T> WorkQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                jobs: VecDeque::new(),
+                running: HashSet::new(),
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Queue a job that changes `sessions`
+    pub fn push(&self, job: T, sessions: Vec<String>) {
+        self.lock().jobs.push_back((job, sessions));
+        self.changed.notify_all();
+    }
+
+    /// Stop accepting jobs; workers finish the queued ones and stop
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+
+    /// Wait for a job that can run now
+    ///
+    /// Keep the returned guard until the job is done. `None` once the
+    /// queue is closed and empty.
+    pub fn take(&self) -> Option<(T, Running<'_, T>)> {
+        let mut state = self.lock();
+        loop {
+            if let Some(i) = state.next_runnable() {
+                let (job, sessions) = state.jobs.remove(i)?;
+                state.running.extend(sessions.iter().cloned());
+                let running = Running {
+                    queue: self,
+                    sessions,
+                };
+                return Some((job, running));
+            }
+            if state.closed && state.jobs.is_empty() {
+                return None;
+            }
+            state = self
+                .changed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T> State<T> {
+    /// Index of the first job whose sessions are all free
+    fn next_runnable(&self) -> Option<usize> {
+        let mut waiting: HashSet<&str> = HashSet::new();
+        for (i, (_, sessions)) in self.jobs.iter().enumerate() {
+            let free = sessions
+                .iter()
+                .all(|s| !self.running.contains(s) && !waiting.contains(s.as_str()));
+            if free {
+                return Some(i);
+            }
+            waiting.extend(sessions.iter().map(String::as_str));
+        }
+        None
+    }
+}
+
+impl<T> Drop for Running<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.queue.lock();
+        for session in &self.sessions {
+            state.running.remove(session);
+        }
+        drop(state);
+        self.queue.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_session_jobs_wait_for_earlier_ones() {
+        let queue = WorkQueue::new();
+        queue.push("set_mode a", sessions(&["a"]));
+        queue.push("convert a", sessions(&["a"]));
+        queue.push("convert b", sessions(&["b"]));
+        queue.push("ping", vec![]);
+
+        let (job, first) = queue.take().unwrap();
+        assert_eq!(job, "set_mode a");
+        // "convert a" waits for "set_mode a"; the others go past it
+        assert_eq!(queue.take().unwrap().0, "convert b");
+        assert_eq!(queue.take().unwrap().0, "ping");
+        drop(first);
+        assert_eq!(queue.take().unwrap().0, "convert a");
+    }
+
+    #[test]
+    fn test_jobs_of_several_sessions_wait_for_each() {
+        let queue = WorkQueue::new();
+        queue.push("convert a", sessions(&["a"]));
+        queue.push("batch a b", sessions(&["a", "b"]));
+        queue.push("convert b", sessions(&["b"]));
+
+        let first = queue.take().unwrap();
+        // The batch waits for "a", and "convert b" for the batch
+        assert_eq!(queue.lock().next_runnable(), None);
+        drop(first);
+        let (job, batch) = queue.take().unwrap();
+        assert_eq!(job, "batch a b");
+        drop(batch);
+        assert_eq!(queue.take().unwrap().0, "convert b");
+    }
+
+    #[test]
+    fn test_closed_queue_drains_then_ends() {
+        let queue = WorkQueue::new();
+        queue.push(1, sessions(&["a"]));
+        queue.close();
+        assert_eq!(queue.take().map(|(job, _)| job), Some(1));
+        assert!(queue.take().is_none());
+    }
+
+    #[test]
+    fn test_waiting_worker_wakes_when_session_frees() {
+        let queue = WorkQueue::new();
+        queue.push(1, sessions(&["a"]));
+        queue.push(2, sessions(&["a"]));
+        let first = queue.take().unwrap();
+        std::thread::scope(|scope| {
+            let second = scope.spawn(|| queue.take().map(|(job, _)| job));
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            drop(first);
+            assert_eq!(second.join().unwrap(), Some(2));
+        });
+    }
+}
@@ -5,7 +5,7 @@
 //!
 //! Requires the `zenzai` feature to be enabled.
 //!
-//! ## zenz-v3 Prompt Format
+//! ## Prompt Formats
 //!
 //! The model uses special Unicode characters as delimiters:
 //! - `\u{EE02}`: Context prefix (optional)
@@ -13,22 +13,106 @@
 //! - `\u{EE01}`: Output start
 //! - `</s>`: End of sequence
 //!
-//! Format: `\u{EE02}<context>\u{EE00}<hiragana>\u{EE01}<output></s>`
+//! zenz-v3: `\u{EE02}<context>\u{EE00}<hiragana>\u{EE01}<output></s>`
+//!
+//! zenz-v2: `\u{EE00}<hiragana>\u{EE02}<context>\u{EE01}<output></s>`
+//!
+//! The format is detected from the model's name in its GGUF metadata.
 
-use serde::Deserialize;
+#[cfg(feature = "zenzai")]
+use crate::cancel::CancelToken;
+#[cfg(feature = "zenzai")]
+use crate::error::{Result, ZenzaiError};
+use crate::metrics::CacheStats;
+#[cfg(feature = "zenzai")]
+use llama_cpp_2::context::params::LlamaContextParams;
+#[cfg(feature = "zenzai")]
+use llama_cpp_2::context::LlamaContext;
+#[cfg(feature = "zenzai")]
+use llama_cpp_2::llama_backend::LlamaBackend;
+#[cfg(feature = "zenzai")]
+use llama_cpp_2::llama_batch::LlamaBatch;
+#[cfg(feature = "zenzai")]
+use llama_cpp_2::model::{LlamaLoraAdapter, LlamaModel};
+#[cfg(feature = "zenzai")]
+use llama_cpp_2::token::LlamaToken;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(feature = "zenzai")]
+use std::collections::HashMap;
+#[cfg(feature = "zenzai")]
+use std::num::NonZeroU32;
 #[cfg(feature = "zenzai")]
 use std::path::PathBuf;
-
-// zenz-v3 special tokens (Unicode Private Use Area)
 #[cfg(feature = "zenzai")]
-const ZENZ_INPUT_START: char = '\u{EE00}';
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, Weak};
 #[cfg(feature = "zenzai")]
-const ZENZ_OUTPUT_START: char = '\u{EE01}';
+use std::thread;
+use std::time::Duration;
 #[cfg(feature = "zenzai")]
+use std::time::Instant;
+
+// zenz special tokens (Unicode Private Use Area)
+const ZENZ_INPUT_START: char = '\u{EE00}';
+const ZENZ_OUTPUT_START: char = '\u{EE01}';
 const ZENZ_CONTEXT: char = '\u{EE02}';
 
+/// Characters of context passed to the model at most
+///
+/// Only the end of the context is kept; it is what the reading continues.
+const MAX_PROMPT_CONTEXT: usize = 40;
+
+/// Prompt format a zenz model was trained with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptFormat {
+    /// zenz-v2: the context follows the reading
+    V2,
+    /// zenz-v3: the context precedes the reading
+    V3,
+}
+
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+impl PromptFormat {
+    /// Format of a model with this name, e.g. `general.name` from its GGUF
+    /// metadata or its file name; None if the name doesn't tell
+    fn detect(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase().replace(['_', ' '], "-");
+        if name.contains("zenz-v2") {
+            Some(PromptFormat::V2)
+        } else if name.contains("zenz-v3") {
+            Some(PromptFormat::V3)
+        } else {
+            None
+        }
+    }
+
+    /// Prompt asking for the conversion of `reading`, which follows
+    /// `context`
+    fn build(self, reading: &str, context: Option<&str>) -> String {
+        let context = context.filter(|ctx| !ctx.is_empty()).map(|ctx| {
+            let skip = ctx.chars().count().saturating_sub(MAX_PROMPT_CONTEXT);
+            ctx.chars().skip(skip).collect::<String>()
+        });
+        let mut prompt = String::new();
+        if let (PromptFormat::V3, Some(context)) = (self, &context) {
+            prompt.push(ZENZ_CONTEXT);
+            prompt.push_str(context);
+        }
+        prompt.push(ZENZ_INPUT_START);
+        prompt.push_str(reading);
+        if let (PromptFormat::V2, Some(context)) = (self, &context) {
+            prompt.push(ZENZ_CONTEXT);
+            prompt.push_str(context);
+        }
+        // The model generates after the output marker
+        prompt.push(ZENZ_OUTPUT_START);
+        prompt
+    }
+}
+
 /// Zenzai configuration
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)] // Fields are used when zenzai feature is enabled
 pub struct ZenzaiConfig {
     /// Enable Zenzai neural conversion
@@ -46,12 +130,135 @@ pub struct ZenzaiConfig {
     /// Enable contextual conversion (uses previous text for better results)
     #[serde(default)]
     pub contextual: bool,
+
+    /// Hypotheses kept by the beam search (1 decodes greedily)
+    #[serde(default = "default_beam_width")]
+    pub beam_width: u32,
+
+    /// Unload the model after this many seconds without a conversion
+    /// (0 keeps it loaded); it is loaded again when next needed
+    #[serde(default)]
+    pub idle_unload_secs: u64,
+
+    /// LoRA adapter (GGUF) applied on top of the model, e.g. one tuned to
+    /// the user's vocabulary and writing style
+    #[serde(default)]
+    pub lora_path: Option<String>,
+
+    /// How strongly the LoRA adapter applies (1.0 as trained)
+    #[serde(default = "default_lora_scale")]
+    pub lora_scale: f32,
+
+    /// Other models by name, e.g. a small one for live conversion, chosen
+    /// per request or per session; the settings above are the `default`
+    /// profile
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ZenzaiProfileConfig>,
+
+    /// Threads used for inference; half the available cores if unset, so
+    /// the editor keeps the rest
+    #[serde(default)]
+    pub n_threads: Option<u32>,
+
+    /// Prompt tokens decoded in one llama.cpp batch
+    #[serde(default = "default_n_batch")]
+    pub n_batch: u32,
+
+    /// Context size in tokens, shared by the beams; sized to each prompt
+    /// if unset
+    #[serde(default)]
+    pub n_ctx: Option<u32>,
+
+    /// Prompt format of the model; detected from the model if unset
+    #[serde(default)]
+    pub prompt_format: Option<PromptFormat>,
+
+    /// Map model files into memory instead of reading them, so pages the
+    /// system needs back can be dropped and read again
+    #[serde(default = "default_use_mmap")]
+    pub use_mmap: bool,
+
+    /// Lock models in RAM, so they are never paged out
+    #[serde(default)]
+    pub use_mlock: bool,
+
+    /// Sample the tokens beams continue with at this temperature instead
+    /// of taking the most likely ones (0 decodes deterministically)
+    #[serde(default)]
+    pub temperature: f32,
+
+    /// Seed of the sampling, so conversions are reproducible across runs;
+    /// taken from the clock if unset
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// A model other than the default one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZenzaiProfileConfig {
+    /// Path to the GGUF model file
+    pub model_path: String,
+    /// Maximum inference iterations, if not the default profile's
+    #[serde(default)]
+    pub inference_limit: Option<u32>,
+    /// Beam width, if not the default profile's
+    #[serde(default)]
+    pub beam_width: Option<u32>,
+    /// LoRA adapter for this model; adapters are specific to their base
+    /// model, so the default profile's isn't used
+    #[serde(default)]
+    pub lora_path: Option<String>,
+    /// LoRA scale, if not the default profile's
+    #[serde(default)]
+    pub lora_scale: Option<f32>,
+    /// Prompt format of this model; detected from the model if unset
+    #[serde(default)]
+    pub prompt_format: Option<PromptFormat>,
+}
+
+/// Name of the profile made of the top-level model settings
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Model settings a conversion uses
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+struct Profile {
+    /// Configured path; the default profile searches default paths without one
+    model_path: Option<String>,
+    inference_limit: u32,
+    beam_width: u32,
+    lora_path: Option<String>,
+    lora_scale: f32,
+    prompt_format: Option<PromptFormat>,
 }
 
 fn default_inference_limit() -> u32 {
     10
 }
 
+fn default_beam_width() -> u32 {
+    3
+}
+
+fn default_lora_scale() -> f32 {
+    1.0
+}
+
+fn default_n_batch() -> u32 {
+    512
+}
+
+fn default_use_mmap() -> bool {
+    true
+}
+
+/// Inference threads when `n_threads` is unset: half of `cores`
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+fn default_threads(cores: usize) -> u32 {
+    (cores / 2).max(1) as u32
+}
+
 impl Default for ZenzaiConfig {
     fn default() -> Self {
         Self {
@@ -59,11 +266,56 @@ impl Default for ZenzaiConfig {
             model_path: None,
             inference_limit: default_inference_limit(),
             contextual: false,
+            beam_width: default_beam_width(),
+            idle_unload_secs: 0,
+            lora_path: None,
+            lora_scale: default_lora_scale(),
+            profiles: BTreeMap::new(),
+            n_threads: None,
+            n_batch: default_n_batch(),
+            n_ctx: None,
+            prompt_format: None,
+            use_mmap: default_use_mmap(),
+            use_mlock: false,
+            temperature: 0.0,
+            seed: None,
         }
     }
 }
 
 impl ZenzaiConfig {
+    /// Settings of a profile, None if there is no such profile
+    #[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+    fn profile(&self, name: &str) -> Option<Profile> {
+        if name == DEFAULT_PROFILE {
+            return Some(Profile {
+                model_path: self.model_path.clone(),
+                inference_limit: self.inference_limit,
+                beam_width: self.beam_width,
+                lora_path: self.lora_path.clone(),
+                lora_scale: self.lora_scale,
+                prompt_format: self.prompt_format,
+            });
+        }
+        let profile = self.profiles.get(name)?;
+        Some(Profile {
+            model_path: Some(profile.model_path.clone()),
+            inference_limit: profile.inference_limit.unwrap_or(self.inference_limit),
+            beam_width: profile.beam_width.unwrap_or(self.beam_width),
+            lora_path: profile.lora_path.clone(),
+            lora_scale: profile.lora_scale.unwrap_or(self.lora_scale),
+            prompt_format: profile.prompt_format,
+        })
+    }
+
+    /// Threads used for inference
+    #[cfg(feature = "zenzai")]
+    pub fn threads(&self) -> u32 {
+        self.n_threads.filter(|&n| n > 0).unwrap_or_else(|| {
+            default_threads(thread::available_parallelism().map_or(1, |n| n.get()))
+        })
+    }
+
     /// Check if Zenzai is properly configured and can be used
     #[cfg(feature = "zenzai")]
     pub fn is_usable(&self) -> bool {
@@ -125,18 +377,434 @@ pub fn default_model_paths() -> Vec<PathBuf> {
     paths
 }
 
+/// One conversion Zenzai produced
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+pub struct ZenzaiCandidate {
+    pub text: String,
+    /// Sum of the log-probabilities of the generated tokens
+    pub log_prob: f64,
+    /// Lowest probability among the generated tokens (0.0 - 1.0)
+    pub confidence: f64,
+}
+
+/// Loaded models and their caches, for `zenzai_status`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+pub struct ModelStatus {
+    /// Loaded models by profile
+    pub models: BTreeMap<String, LoadedModel>,
+    /// Size in tokens of the context created last
+    pub n_ctx: Option<u32>,
+    /// Prompt tokens reused from kept contexts (hits) or decoded (misses)
+    pub prompt_cache: CacheStats,
+}
+
+/// A loaded model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+pub struct LoadedModel {
+    pub path: String,
+    /// Layers offloaded to the GPU
+    pub gpu_layers: i32,
+    pub prompt_format: PromptFormat,
+    /// Sessions whose context is kept
+    pub contexts: usize,
+}
+
+/// Output of the beam search, finished or still being extended
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+struct Hypothesis<T> {
+    tokens: Vec<T>,
+    /// Sum of the log-probabilities of `tokens`
+    log_prob: f32,
+    /// Lowest probability among `tokens`
+    min_prob: f32,
+}
+
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+impl<T: Copy> Hypothesis<T> {
+    fn new() -> Self {
+        Self {
+            tokens: Vec::new(),
+            log_prob: 0.0,
+            min_prob: 1.0,
+        }
+    }
+
+    /// This hypothesis followed by `token`
+    fn extend(&self, token: T, log_prob: f32) -> Self {
+        let mut tokens = self.tokens.clone();
+        tokens.push(token);
+        Self {
+            tokens,
+            log_prob: self.log_prob + log_prob,
+            min_prob: self.min_prob.min(log_prob.exp()),
+        }
+    }
+}
+
+/// SplitMix64, a small deterministic random number generator
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+struct Rng(u64);
+
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in (0, 1)
+    fn next_f32(&mut self) -> f32 {
+        ((self.next_u64() >> 40) as f32 + 0.5) / (1u64 << 24) as f32
+    }
+}
+
+/// Chooses the tokens beams continue with at random
+///
+/// Every conversion starts from the configured seed, so its result only
+/// depends on the reading and context.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+struct Sampler {
+    temperature: f32,
+    rng: Rng,
+}
+
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+impl Sampler {
+    /// Sampler of a conversion, None if it decodes deterministically
+    fn new(config: &ZenzaiConfig) -> Option<Self> {
+        if config.temperature <= 0.0 {
+            return None;
+        }
+        let seed = config.seed.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+        });
+        Some(Self {
+            temperature: config.temperature,
+            rng: Rng(seed),
+        })
+    }
+
+    /// `count` distinct tokens drawn from `logits` at the temperature,
+    /// with their log-probabilities under the model
+    ///
+    /// Draws without replacement by perturbing the scaled logits with
+    /// Gumbel noise and keeping the largest (the Gumbel-top-k trick).
+    fn pick(&mut self, logits: &[f32], count: usize) -> Vec<(usize, f32)> {
+        let perturbed: Vec<f32> = logits
+            .iter()
+            .map(|&logit| logit / self.temperature - (-self.rng.next_f32().ln()).ln())
+            .collect();
+        let shift = log_softmax_shift(logits);
+        top_tokens(&perturbed, count)
+            .into_iter()
+            .map(|(token, _)| (token, logits[token] - shift))
+            .collect()
+    }
+}
+
+/// The `count` most likely tokens with their log-probabilities, best first
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+fn top_tokens(logits: &[f32], count: usize) -> Vec<(usize, f32)> {
+    let shift = log_softmax_shift(logits);
+    let mut top: Vec<(usize, f32)> = Vec::with_capacity(count + 1);
+    for (token, &logit) in logits.iter().enumerate() {
+        if top.len() == count && top.last().is_some_and(|&(_, l)| logit <= l) {
+            continue;
+        }
+        let at = top.partition_point(|&(_, l)| l >= logit);
+        top.insert(at, (token, logit));
+        top.truncate(count);
+    }
+    top.into_iter()
+        .map(|(token, logit)| (token, logit - shift))
+        .collect()
+}
+
+/// What log softmax subtracts from each logit: max + ln(sum(exp(l - max)))
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+fn log_softmax_shift(logits: &[f32]) -> f32 {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = logits.iter().map(|&l| (l - max).exp()).sum::<f32>().ln();
+    max + log_sum
+}
+
+/// The `width` most likely extensions of the beams
+///
+/// `expansions` holds `(beam, token, log_prob)` for the tokens each beam
+/// may continue with. Each result names the beam it extends.
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+fn next_beams<T: Copy>(
+    beams: &[Hypothesis<T>],
+    expansions: Vec<(usize, T, f32)>,
+    width: usize,
+) -> Vec<(usize, Hypothesis<T>)> {
+    let mut next: Vec<(usize, Hypothesis<T>)> = expansions
+        .into_iter()
+        .map(|(beam, token, log_prob)| (beam, beams[beam].extend(token, log_prob)))
+        .collect();
+    next.sort_by(|(_, a), (_, b)| b.log_prob.total_cmp(&a.log_prob));
+    next.truncate(width);
+    next
+}
+
+/// Sessions whose llama context is kept for reuse at most
+#[cfg(feature = "zenzai")]
+const MAX_CACHED_CONTEXTS: usize = 2;
+
+/// Sequences a llama context holds at most (llama.cpp's `LLAMA_MAX_SEQ`)
+const MAX_SEQUENCES: usize = 64;
+
+/// Prompts of a batch conversion decoded in one context, each using `n_seq`
+/// sequences
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+fn prompts_per_context(n_seq: usize) -> usize {
+    (MAX_SEQUENCES / n_seq.max(1)).max(1)
+}
+
+/// Prompt tokens a kept context has room for at least
+///
+/// A longer prompt gets a context of its own size.
+const CONTEXT_PROMPT_TOKENS: usize = 128;
+
+/// Context size for `n_seq` sequences and the prompt tokens it has room for
+///
+/// Each sequence needs room for the prompt and `max_tokens` generated
+/// tokens. A configured `n_ctx` is used as is; otherwise the context fits
+/// `prompt_tokens`, and at least `CONTEXT_PROMPT_TOKENS`.
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+fn context_size(
+    n_ctx: Option<u32>,
+    prompt_tokens: usize,
+    n_seq: usize,
+    max_tokens: usize,
+) -> (u32, usize) {
+    match n_ctx {
+        Some(n_ctx) => {
+            let capacity = (n_ctx as usize / n_seq).saturating_sub(max_tokens);
+            (n_ctx, capacity)
+        }
+        None => {
+            let capacity = prompt_tokens.max(CONTEXT_PROMPT_TOKENS);
+            ((n_seq * (capacity + max_tokens)) as u32, capacity)
+        }
+    }
+}
+
+/// Values kept per session, the least recently used dropped first
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+struct SessionCache<V> {
+    /// Least recently used first
+    entries: Vec<(String, V)>,
+    capacity: usize,
+}
+
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+impl<V> SessionCache<V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            capacity,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Take a session's value out, to be put back with `insert` after use
+    fn take(&mut self, session_id: &str) -> Option<V> {
+        let index = self.entries.iter().position(|(id, _)| id == session_id)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    /// Keep a session's value as the most recently used
+    fn insert(&mut self, session_id: String, value: V) {
+        self.entries.retain(|(id, _)| *id != session_id);
+        self.entries.push((session_id, value));
+        if self.entries.len() > self.capacity {
+            self.entries.remove(0);
+        }
+    }
+}
+
+/// Prompt tokens whose cache can be kept when `tokens` follow `cached`
+///
+/// The last token is always decoded again, since its logits start the
+/// generation.
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+fn reusable_prefix<T: PartialEq>(cached: &[T], tokens: &[T]) -> usize {
+    let shared = cached
+        .iter()
+        .zip(tokens)
+        .take_while(|(a, b)| a == b)
+        .count();
+    shared.min(tokens.len().saturating_sub(1))
+}
+
+/// The llama.cpp backend, initialized once per process
+#[cfg(feature = "zenzai")]
+fn llama_backend() -> Result<&'static LlamaBackend> {
+    static BACKEND: OnceLock<Option<LlamaBackend>> = OnceLock::new();
+    BACKEND
+        .get_or_init(|| match LlamaBackend::init() {
+            Ok(backend) => Some(backend),
+            Err(e) => {
+                log_error!("[zenzai] Failed to init backend: {}", e);
+                None
+            }
+        })
+        .as_ref()
+        .ok_or_else(|| ZenzaiError::LoadError("Failed to init backend".to_string()).into())
+}
+
+/// How often to check whether a model has been idle for `idle`
+#[cfg_attr(not(feature = "zenzai"), allow(dead_code))]
+fn idle_check_interval(idle: Duration) -> Duration {
+    (idle / 2).clamp(Duration::from_secs(1), Duration::from_secs(60))
+}
+
+/// Unload the model of `backend` once it has been idle for the configured
+/// time, on a background thread that ends with the backend
+#[cfg(feature = "zenzai")]
+pub fn spawn_idle_unloader(backend: &Arc<Mutex<ZenzaiBackend>>) {
+    let idle = {
+        let backend = backend.lock().unwrap_or_else(PoisonError::into_inner);
+        Duration::from_secs(backend.config.idle_unload_secs)
+    };
+    if idle.is_zero() {
+        return;
+    }
+    let backend: Weak<Mutex<ZenzaiBackend>> = Arc::downgrade(backend);
+    thread::spawn(move || loop {
+        thread::sleep(idle_check_interval(idle));
+        let Some(backend) = backend.upgrade() else {
+            return;
+        };
+        let mut backend = backend.lock().unwrap_or_else(PoisonError::into_inner);
+        if backend.last_used.elapsed() >= idle && backend.unload() {
+            log_info!("[zenzai] Unloaded the model after {}s idle", idle.as_secs());
+        }
+    });
+}
+
 /// Zenzai conversion backend
 #[cfg(feature = "zenzai")]
 pub struct ZenzaiBackend {
     config: ZenzaiConfig,
-    // Model will be loaded lazily
-    model: Option<ZenzaiModel>,
+    // Models are loaded lazily, by profile name
+    models: HashMap<String, ZenzaiModel>,
+    /// Confidence of the most recent conversion
+    last_confidence: Option<f64>,
+    /// Tokens of the most likely output of the most recent conversion
+    last_output_tokens: usize,
+    /// When the model was last loaded or used
+    last_used: Instant,
+    /// Size of the context created last
+    last_n_ctx: Option<u32>,
+    /// Prompt tokens reused from kept contexts, and decoded
+    reused_tokens: u64,
+    decoded_tokens: u64,
 }
 
 #[cfg(feature = "zenzai")]
 struct ZenzaiModel {
-    model: llama_cpp_2::model::LlamaModel,
-    _model_path: PathBuf,
+    /// Contexts kept per session; they borrow `model`, so they are
+    /// declared first to be dropped before it
+    contexts: SessionCache<CachedContext>,
+    /// Adapter applied to every context; it belongs to `model`
+    lora: Option<LoraAdapter>,
+    /// Boxed so contexts can borrow it while this struct moves
+    model: Box<LlamaModel>,
+    format: PromptFormat,
+    model_path: PathBuf,
+    /// Layers offloaded to the GPU
+    gpu_layers: i32,
+}
+
+/// A LoRA adapter and the scale it is applied with
+#[cfg(feature = "zenzai")]
+struct LoraAdapter {
+    adapter: LlamaLoraAdapter,
+    scale: f32,
+}
+
+// SAFETY: the adapter is only used by the thread holding the backend's
+// mutex, like the contexts it is applied to.
+#[cfg(feature = "zenzai")]
+unsafe impl Send for LoraAdapter {}
+
+/// A llama context kept between the conversions of a session
+///
+/// Sequence 0 of its cache holds `prompt`; the beam search uses the others.
+#[cfg(feature = "zenzai")]
+struct CachedContext {
+    ctx: LlamaContext<'static>,
+    prompt: Vec<LlamaToken>,
+    /// Prompt tokens the context has room for
+    capacity: usize,
+    n_ctx: u32,
+}
+
+// SAFETY: a context is only used by the thread holding the backend's
+// mutex, never by two threads at once.
+#[cfg(feature = "zenzai")]
+unsafe impl Send for CachedContext {}
+
+#[cfg(feature = "zenzai")]
+impl ZenzaiModel {
+    /// Create a context for prompts of up to `prompt_tokens` tokens
+    fn new_context(
+        &mut self,
+        config: &ZenzaiConfig,
+        prompt_tokens: usize,
+        n_seq: usize,
+        max_tokens: usize,
+    ) -> Result<CachedContext> {
+        let (n_ctx, capacity) = context_size(config.n_ctx, prompt_tokens, n_seq, max_tokens);
+        if capacity < prompt_tokens {
+            return Err(ZenzaiError::InferenceError(format!(
+                "Prompt of {} tokens does not fit in n_ctx {}",
+                prompt_tokens, n_ctx
+            ))
+            .into());
+        }
+        let threads = config.threads() as i32;
+        // The beam search decodes a token per sequence at once
+        let n_batch = config.n_batch.max(n_seq as u32);
+        let ctx_params = LlamaContextParams::default()
+            .with_n_ctx(NonZeroU32::new(n_ctx))
+            .with_n_batch(n_batch)
+            .with_n_threads(threads)
+            .with_n_threads_batch(threads)
+            .with_n_seq_max(n_seq as u32);
+        // SAFETY: the model is boxed, so it stays in place, and contexts are
+        // dropped before it (see the field order of `ZenzaiModel`)
+        let model: &'static LlamaModel = unsafe { &*(self.model.as_ref() as *const LlamaModel) };
+        let mut ctx = model
+            .new_context(llama_backend()?, ctx_params)
+            .map_err(|e| ZenzaiError::InferenceError(format!("Context creation failed: {}", e)))?;
+        if let Some(lora) = &mut self.lora {
+            ctx.lora_adapter_set(&mut lora.adapter, lora.scale)
+                .map_err(|e| ZenzaiError::InferenceError(format!("LoRA apply failed: {}", e)))?;
+        }
+        Ok(CachedContext {
+            ctx,
+            prompt: Vec::new(),
+            capacity,
+            n_ctx,
+        })
+    }
 }
 
 #[cfg(feature = "zenzai")]
@@ -145,249 +813,697 @@ impl ZenzaiBackend {
     pub fn new(config: ZenzaiConfig) -> Self {
         Self {
             config,
-            model: None,
+            models: HashMap::new(),
+            last_confidence: None,
+            last_output_tokens: 0,
+            last_used: Instant::now(),
+            last_n_ctx: None,
+            reused_tokens: 0,
+            decoded_tokens: 0,
         }
     }
 
-    /// Initialize the model (lazy loading)
-    pub fn initialize(&mut self) -> Result<(), ZenzaiError> {
+    /// Initialize the model of the default profile (lazy loading)
+    pub fn initialize(&mut self) -> Result<()> {
+        self.load(DEFAULT_PROFILE)
+    }
+
+    /// Load the model of a profile unless it is loaded
+    pub fn load(&mut self, profile: &str) -> Result<()> {
         use llama_cpp_2::model::params::LlamaModelParams;
-        use llama_cpp_2::model::LlamaModel;
 
-        if self.model.is_some() {
+        if self.models.contains_key(profile) {
             return Ok(());
         }
 
-        let model_path = self
+        let settings = self
             .config
-            .get_model_path()
-            .ok_or(ZenzaiError::ModelNotFound)?;
-
-        eprintln!("[zenzai] Loading model from: {}", model_path.display());
+            .profile(profile)
+            .ok_or_else(|| ZenzaiError::UnknownProfile(profile.to_string()))?;
+        let model_path = match settings.model_path.as_deref() {
+            Some(path) if profile != DEFAULT_PROFILE => {
+                let path = PathBuf::from(path);
+                if !path.exists() {
+                    return Err(ZenzaiError::ModelNotFound.into());
+                }
+                path
+            }
+            _ => self
+                .config
+                .get_model_path()
+                .ok_or(ZenzaiError::ModelNotFound)?,
+        };
 
-        // Initialize llama.cpp backend
-        let backend = llama_cpp_2::llama_backend::LlamaBackend::init()
-            .map_err(|e| ZenzaiError::LoadError(format!("Failed to init backend: {}", e)))?;
+        log_info!(
+            "[zenzai] Loading model {} from: {}",
+            profile,
+            model_path.display()
+        );
 
         // Configure model parameters
-        let model_params = LlamaModelParams::default();
+        let model_params = LlamaModelParams::default()
+            .with_use_mmap(self.config.use_mmap)
+            .with_use_mlock(self.config.use_mlock);
 
         // Load the model
-        let model = LlamaModel::load_from_file(&backend, &model_path, &model_params)
+        let model = LlamaModel::load_from_file(llama_backend()?, &model_path, &model_params)
             .map_err(|e| ZenzaiError::LoadError(format!("Failed to load model: {}", e)))?;
 
-        self.model = Some(ZenzaiModel {
-            model,
-            _model_path: model_path,
-        });
-
-        eprintln!("[zenzai] Model loaded successfully");
-        Ok(())
-    }
-
-    /// Build prompt for zenz-v3 model
-    fn build_prompt(&self, reading: &str, context: Option<&str>) -> String {
-        let mut prompt = String::new();
-
-        // Add context if provided (zenz-v3 format: context comes first)
-        if let Some(ctx) = context {
-            if !ctx.is_empty() {
-                prompt.push(ZENZ_CONTEXT);
-                prompt.push_str(ctx);
+        let format = match settings.prompt_format {
+            Some(format) => format,
+            None => detect_format(&model, &model_path),
+        };
+        log_info!("[zenzai] Prompt format: {:?}", format);
+
+        let lora = match settings.lora_path {
+            Some(path) => {
+                log_info!("[zenzai] Loading LoRA adapter from: {}", path);
+                let adapter = model.lora_adapter_init(&path).map_err(|e| {
+                    ZenzaiError::LoadError(format!("Failed to load LoRA adapter: {}", e))
+                })?;
+                Some(LoraAdapter {
+                    adapter,
+                    scale: settings.lora_scale,
+                })
             }
-        }
-
-        // Add input reading
-        prompt.push(ZENZ_INPUT_START);
-        prompt.push_str(reading);
+            None => None,
+        };
 
-        // Add output marker (model will generate after this)
-        prompt.push(ZENZ_OUTPUT_START);
+        self.models.insert(
+            profile.to_string(),
+            ZenzaiModel {
+                contexts: SessionCache::new(MAX_CACHED_CONTEXTS),
+                lora,
+                model: Box::new(model),
+                format,
+                model_path,
+                gpu_layers: model_params.n_gpu_layers(),
+            },
+        );
 
-        prompt
+        self.last_used = Instant::now();
+        log_info!("[zenzai] Model loaded successfully");
+        Ok(())
     }
 
     /// Convert hiragana to kanji using neural network
+    ///
+    /// Returns up to `beam_width` distinct conversions with their
+    /// probabilities, most likely first; the list is empty when the model
+    /// produced nothing.
+    /// `context` is text preceding the reading; it is ignored unless the
+    /// configuration enables contextual conversion.
+    /// The llama context of `session_id` is kept, so the next conversion of
+    /// the session only decodes the part of its prompt that changed.
+    /// While decoding, `progress` gets the text of the most likely
+    /// hypothesis whenever it changes.
+    /// Generation stops with `Cancelled` once `cancel` is cancelled.
+    /// `profile` names the model to use, the default one if None.
     pub fn convert(
         &mut self,
+        session_id: &str,
+        profile: Option<&str>,
         reading: &str,
         context: Option<&str>,
-    ) -> Result<Vec<String>, ZenzaiError> {
-        use llama_cpp_2::context::params::LlamaContextParams;
-        use llama_cpp_2::llama_batch::LlamaBatch;
-        use llama_cpp_2::token::LlamaToken;
-
+        cancel: &CancelToken,
+        progress: &mut dyn FnMut(&str),
+    ) -> Result<Vec<ZenzaiCandidate>> {
+        let profile = profile.unwrap_or(DEFAULT_PROFILE);
+        let settings = self
+            .config
+            .profile(profile)
+            .ok_or_else(|| ZenzaiError::UnknownProfile(profile.to_string()))?;
         // Ensure model is loaded
-        if self.model.is_none() {
-            self.initialize()?;
-        }
+        self.load(profile)?;
+        self.last_used = Instant::now();
 
-        let zenzai_model = self.model.as_ref().ok_or(ZenzaiError::NotInitialized)?;
+        let context = context.filter(|_| self.config.contextual);
 
-        eprintln!(
-            "[zenzai] Converting: {} (context: {:?}, limit: {})",
-            reading, context, self.config.inference_limit
+        log_debug!(
+            "[zenzai] Converting: {} (profile: {}, context: {:?}, limit: {})",
+            reading,
+            profile,
+            context,
+            settings.inference_limit
         );
 
-        // Build the prompt
-        let prompt = self.build_prompt(reading, context);
-        eprintln!("[zenzai] Prompt: {:?}", prompt);
-
-        // Create context for inference
-        let ctx_params = LlamaContextParams::default().with_n_ctx(std::num::NonZeroU32::new(512));
-
-        let mut ctx = zenzai_model
-            .model
-            .new_context(
-                &llama_cpp_2::llama_backend::LlamaBackend::init().map_err(|e| {
-                    ZenzaiError::InferenceError(format!("Backend init failed: {}", e))
-                })?,
-                ctx_params,
-            )
-            .map_err(|e| ZenzaiError::InferenceError(format!("Context creation failed: {}", e)))?;
-
-        // Tokenize the prompt
-        let tokens = zenzai_model
-            .model
-            .str_to_token(&prompt, llama_cpp_2::model::AddBos::Always)
-            .map_err(|e| ZenzaiError::InferenceError(format!("Tokenization failed: {}", e)))?;
+        let width = settings.beam_width.max(1) as usize;
+        // Allow reasonable output length
+        let max_tokens = settings.inference_limit as usize * 10;
+        // Sequence 0 holds the prompt. Each beam decodes in a sequence of
+        // its own; beams are rebuilt every step, so two banks of `width`
+        // sequences alternate
+        let n_seq = 2 * width + 1;
+
+        let zenzai_model = self
+            .models
+            .get_mut(profile)
+            .ok_or(ZenzaiError::NotInitialized)?;
+
+        let prompt = zenzai_model.format.build(reading, context);
+        log_debug!("[zenzai] Prompt: {:?}", prompt);
+        let tokens = tokenize(&zenzai_model.model, &prompt)?;
+
+        let mut cached = match zenzai_model.contexts.take(session_id) {
+            Some(cached) if cached.capacity >= tokens.len() => cached,
+            _ => zenzai_model.new_context(&self.config, tokens.len(), n_seq, max_tokens)?,
+        };
 
-        eprintln!("[zenzai] Input tokens: {}", tokens.len());
+        // Keep the cache of the prompt up to where it differs from the
+        // previous one, and drop the previous beams
+        let keep = reusable_prefix(&cached.prompt, &tokens);
+        log_debug!("[zenzai] Input tokens: {} ({} cached)", tokens.len(), keep);
+        self.last_n_ctx = Some(cached.n_ctx);
+        self.reused_tokens += keep as u64;
+        self.decoded_tokens += (tokens.len() - keep) as u64;
+        let stale =
+            std::iter::once((0, Some(keep as u32))).chain((1..n_seq).map(|seq| (seq, None)));
+        for (seq, from) in stale {
+            cached
+                .ctx
+                .clear_kv_cache_seq(Some(seq as u32), from, None)
+                .map_err(|e| ZenzaiError::InferenceError(format!("Cache clear failed: {}", e)))?;
+        }
 
-        // Create batch and add tokens
-        let mut batch = LlamaBatch::new(512, 1);
-        for (i, &token) in tokens.iter().enumerate() {
-            let is_last = i == tokens.len() - 1;
-            batch
-                .add(token, i as i32, &[0], is_last)
-                .map_err(|e| ZenzaiError::InferenceError(format!("Batch add failed: {}", e)))?;
+        // Decode the rest of the prompt, `n_batch` tokens at a time
+        let n_batch = self.config.n_batch.max(1) as usize;
+        let mut batch = LlamaBatch::new(n_batch, 1);
+        let mut first_logits = 0;
+        for (chunk, start) in tokens[keep..]
+            .chunks(n_batch)
+            .zip((keep..).step_by(n_batch))
+        {
+            batch.clear();
+            for (offset, &token) in chunk.iter().enumerate() {
+                let i = start + offset;
+                let is_last = i == tokens.len() - 1;
+                batch
+                    .add(token, i as i32, &[0], is_last)
+                    .map_err(|e| ZenzaiError::InferenceError(format!("Batch add failed: {}", e)))?;
+            }
+            cached.ctx.decode(&mut batch).map_err(|e| {
+                ZenzaiError::InferenceError(format!("Initial decode failed: {}", e))
+            })?;
+            first_logits = (chunk.len() - 1) as i32;
         }
+        cached.prompt = tokens;
+        let model = &zenzai_model.model;
+        let mut reported = String::new();
+        let mut step = |best: &[LlamaToken]| -> Result<()> {
+            cancel.check()?;
+            let text = detokenize(model, best);
+            if !text.is_empty() && text != reported {
+                progress(&text);
+                reported = text;
+            }
+            Ok(())
+        };
+        let search = beam_search(
+            model,
+            &mut cached.ctx,
+            cached.prompt.len(),
+            first_logits,
+            width,
+            max_tokens,
+            Sampler::new(&self.config),
+            &mut step,
+        );
+        // Sequence 0 is intact even if the search failed or was cancelled
+        zenzai_model.contexts.insert(session_id.to_string(), cached);
+        let mut finished = search?;
+        finished.sort_by(|a, b| b.log_prob.total_cmp(&a.log_prob));
+        let outputs = candidates(&zenzai_model.model, &finished);
+
+        let confidence = finished.first().map_or(0.0, |h| h.min_prob);
+        log_debug!(
+            "[zenzai] Output: {:?} (confidence {:.3})",
+            outputs,
+            confidence
+        );
+        self.last_confidence = Some(f64::from(confidence));
+        self.last_output_tokens = finished.first().map_or(0, |h| h.tokens.len());
 
-        // Decode the initial prompt
-        ctx.decode(&mut batch)
-            .map_err(|e| ZenzaiError::InferenceError(format!("Initial decode failed: {}", e)))?;
+        Ok(outputs)
+    }
 
-        // Generate tokens (greedy decoding)
-        let mut output_tokens: Vec<LlamaToken> = Vec::new();
-        let max_tokens = self.config.inference_limit as usize * 10; // Allow reasonable output length
-        let mut n_cur = tokens.len();
+    /// Convert several readings at once, each with the text preceding it
+    ///
+    /// The beams of all readings are decoded together, a token each per
+    /// llama.cpp batch, which is faster than converting them one by one.
+    /// Returns the conversions of each reading as [`Self::convert`] does,
+    /// in the order of `items`. Contexts of sessions are left alone and
+    /// no progress is reported.
+    pub fn convert_batch(
+        &mut self,
+        profile: Option<&str>,
+        items: &[(&str, Option<&str>)],
+        cancel: &CancelToken,
+    ) -> Result<Vec<Vec<ZenzaiCandidate>>> {
+        let profile = profile.unwrap_or(DEFAULT_PROFILE);
+        let settings = self
+            .config
+            .profile(profile)
+            .ok_or_else(|| ZenzaiError::UnknownProfile(profile.to_string()))?;
+        self.load(profile)?;
+        self.last_used = Instant::now();
+
+        log_debug!(
+            "[zenzai] Converting {} readings (profile: {})",
+            items.len(),
+            profile
+        );
+        let width = settings.beam_width.max(1) as usize;
+        let max_tokens = settings.inference_limit as usize * 10;
+        let n_seq = 2 * width + 1;
+        let n_batch = self.config.n_batch.max(1) as usize;
+
+        let zenzai_model = self
+            .models
+            .get_mut(profile)
+            .ok_or(ZenzaiError::NotInitialized)?;
+        let contextual = self.config.contextual;
+        let prompts: Vec<String> = items
+            .iter()
+            .map(|&(reading, context)| {
+                let context = context.filter(|_| contextual);
+                zenzai_model.format.build(reading, context)
+            })
+            .collect();
+        let mut outputs = Vec::with_capacity(items.len());
+        for prompts in prompts.chunks(prompts_per_context(n_seq)) {
+            cancel.check()?;
+            let tokens = prompts
+                .iter()
+                .map(|prompt| tokenize(&zenzai_model.model, prompt))
+                .collect::<Result<Vec<_>>>()?;
+            let longest = tokens.iter().map(Vec::len).max().unwrap_or(0);
+            let mut scratch = zenzai_model.new_context(
+                &self.config,
+                longest,
+                n_seq * tokens.len(),
+                max_tokens,
+            )?;
+            self.last_n_ctx = Some(scratch.n_ctx);
+            self.decoded_tokens += tokens.iter().map(|t| t.len() as u64).sum::<u64>();
+            let searches = batch_beam_search(
+                &zenzai_model.model,
+                &mut scratch.ctx,
+                &tokens,
+                n_seq,
+                width,
+                max_tokens,
+                n_batch,
+                Sampler::new(&self.config),
+                cancel,
+            )?;
+            for mut finished in searches {
+                finished.sort_by(|a, b| b.log_prob.total_cmp(&a.log_prob));
+                outputs.push(candidates(&zenzai_model.model, &finished));
+            }
+        }
+        log_debug!("[zenzai] Batch output: {:?}", outputs);
 
-        // Get special token IDs for stopping
-        let eos_token = zenzai_model.model.token_eos();
+        Ok(outputs)
+    }
 
-        for _ in 0..max_tokens {
-            // Get logits for the last token
-            let logits = ctx.get_logits_ith((n_cur - 1) as i32);
+    /// Confidence of the most recent conversion (0.0 - 1.0)
+    ///
+    /// The lowest softmax probability among the tokens of the most likely
+    /// conversion, so a single uncertain token makes it uncertain.
+    pub fn last_confidence(&self) -> Option<f64> {
+        self.last_confidence
+    }
 
-            // Simple greedy sampling: pick the token with highest logit
-            let mut best_token = LlamaToken::new(0);
-            let mut best_logit = f32::NEG_INFINITY;
+    /// Tokens generated for the most likely output of the most recent
+    /// conversion, for throughput measurements
+    pub fn last_output_tokens(&self) -> usize {
+        self.last_output_tokens
+    }
 
-            for (token_id, &logit) in logits.iter().enumerate() {
-                if logit > best_logit {
-                    best_logit = logit;
-                    best_token = LlamaToken::new(token_id as i32);
-                }
-            }
+    /// Check if the backend is ready
+    #[allow(dead_code)]
+    pub fn is_ready(&self) -> bool {
+        self.models.contains_key(DEFAULT_PROFILE)
+    }
 
-            // Check for end of sequence
-            if best_token == eos_token {
-                break;
-            }
+    /// Drop the models and their kept contexts to free memory
+    ///
+    /// The next conversion loads its model again. Returns false if no
+    /// model was loaded.
+    pub fn unload(&mut self) -> bool {
+        let loaded = !self.models.is_empty();
+        self.models.clear();
+        loaded
+    }
 
-            // Decode the token to check for special markers
-            let token_str = zenzai_model
-                .model
-                .token_to_str(best_token, llama_cpp_2::model::Special::Tokenize)
-                .unwrap_or_default();
+    /// Loaded models and cache use
+    pub fn status(&self) -> ModelStatus {
+        let models = self
+            .models
+            .iter()
+            .map(|(profile, model)| {
+                let loaded = LoadedModel {
+                    path: model.model_path.display().to_string(),
+                    gpu_layers: model.gpu_layers,
+                    prompt_format: model.format,
+                    contexts: model.contexts.len(),
+                };
+                (profile.clone(), loaded)
+            })
+            .collect();
+        ModelStatus {
+            models,
+            n_ctx: self.last_n_ctx,
+            prompt_cache: CacheStats::new(self.reused_tokens, self.decoded_tokens),
+        }
+    }
 
-            // Stop if we hit the input start marker (shouldn't happen, but safety check)
-            if token_str.contains(ZENZ_INPUT_START) {
-                break;
-            }
+    /// Get configuration
+    #[allow(dead_code)]
+    pub fn config(&self) -> &ZenzaiConfig {
+        &self.config
+    }
+}
 
-            output_tokens.push(best_token);
+/// Prompt format of a model from its name in the GGUF metadata, or else
+/// its file name; zenz-v3 if neither tells
+#[cfg(feature = "zenzai")]
+fn detect_format(model: &LlamaModel, path: &std::path::Path) -> PromptFormat {
+    let name = model.meta_val_str("general.name").unwrap_or_default();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    PromptFormat::detect(&name)
+        .or_else(|| PromptFormat::detect(&file_name))
+        .unwrap_or_else(|| {
+            log_warn!(
+                "[zenzai] Can't tell the prompt format of model {:?}, assuming zenz-v3; \
+                 set prompt_format if it is wrong",
+                name
+            );
+            PromptFormat::V3
+        })
+}
 
-            // Prepare next batch
-            batch.clear();
-            batch
-                .add(best_token, n_cur as i32, &[0], true)
-                .map_err(|e| ZenzaiError::InferenceError(format!("Batch add failed: {}", e)))?;
+/// Tokens of a prompt, starting with BOS
+#[cfg(feature = "zenzai")]
+fn tokenize(model: &LlamaModel, prompt: &str) -> Result<Vec<LlamaToken>> {
+    model
+        .str_to_token(prompt, llama_cpp_2::model::AddBos::Always)
+        .map_err(|e| ZenzaiError::InferenceError(format!("Tokenization failed: {}", e)).into())
+}
 
-            // Decode
-            ctx.decode(&mut batch)
-                .map_err(|e| ZenzaiError::InferenceError(format!("Decode failed: {}", e)))?;
+/// Distinct non-empty texts of hypotheses sorted most likely first
+#[cfg(feature = "zenzai")]
+fn candidates(model: &LlamaModel, finished: &[Hypothesis<LlamaToken>]) -> Vec<ZenzaiCandidate> {
+    let mut outputs: Vec<ZenzaiCandidate> = Vec::new();
+    for hypothesis in finished {
+        let text = detokenize(model, &hypothesis.tokens);
+        if !text.is_empty() && outputs.iter().all(|o| o.text != text) {
+            outputs.push(ZenzaiCandidate {
+                text,
+                log_prob: f64::from(hypothesis.log_prob),
+                confidence: f64::from(hypothesis.min_prob),
+            });
+        }
+    }
+    outputs
+}
 
-            n_cur += 1;
+/// Text of generated tokens
+#[cfg(feature = "zenzai")]
+fn detokenize(model: &LlamaModel, tokens: &[LlamaToken]) -> String {
+    let mut output = String::new();
+    for token in tokens {
+        if let Ok(s) = model.token_to_str(*token, llama_cpp_2::model::Special::Tokenize) {
+            output.push_str(&s);
         }
+    }
+    // Clean up the output (remove </s> if present)
+    output.trim_end_matches("</s>").to_string()
+}
 
-        // Decode output tokens to string
-        let mut output = String::new();
-        for token in &output_tokens {
-            if let Ok(s) = zenzai_model
-                .model
-                .token_to_str(*token, llama_cpp_2::model::Special::Tokenize)
-            {
-                output.push_str(&s);
-            }
+/// A beam search from a prompt decoded into sequence `base` of a context
+///
+/// Beams decode in the `2 * width` sequences after `base`; they are rebuilt
+/// every step, so two banks of `width` sequences alternate.
+#[cfg(feature = "zenzai")]
+struct BeamSearch {
+    base: i32,
+    /// Live beams, the sequence each is decoded in and the batch index of
+    /// its logits
+    beams: Vec<Hypothesis<LlamaToken>>,
+    seqs: Vec<i32>,
+    logit_indices: Vec<i32>,
+    bank: usize,
+    finished: Vec<Hypothesis<LlamaToken>>,
+    /// Position of the next token
+    n_cur: usize,
+    steps: usize,
+    done: bool,
+    /// Draws the tokens beams continue with; the most likely ones are
+    /// taken without it
+    sampler: Option<Sampler>,
+}
+
+#[cfg(feature = "zenzai")]
+impl BeamSearch {
+    /// `first_logits` is the batch index of the prompt's last token
+    fn new(base: i32, prompt_len: usize, first_logits: i32, sampler: Option<Sampler>) -> Self {
+        Self {
+            base,
+            beams: vec![Hypothesis::new()],
+            seqs: vec![base],
+            logit_indices: vec![first_logits],
+            bank: 0,
+            finished: Vec::new(),
+            n_cur: prompt_len,
+            steps: 0,
+            done: false,
+            sampler,
         }
+    }
 
-        // Clean up the output (remove </s> if present)
-        let output = output.trim_end_matches("</s>").to_string();
+    /// Tokens of the most likely live beam
+    fn best(&self) -> &[LlamaToken] {
+        self.beams.first().map_or(&[][..], |h| h.tokens.as_slice())
+    }
 
-        eprintln!("[zenzai] Output: {}", output);
+    /// Extend the beams from the logits of the last decode, adding their
+    /// next tokens to `batch`
+    ///
+    /// Once no live beam can overtake a finished hypothesis, or after
+    /// `max_tokens` steps, the search is done and adds nothing.
+    fn extend(
+        &mut self,
+        model: &LlamaModel,
+        ctx: &mut LlamaContext,
+        batch: &mut LlamaBatch,
+        width: usize,
+        max_tokens: usize,
+    ) -> Result<()> {
+        let eos_token = model.token_eos();
+        let mut expansions = Vec::new();
+        for (beam, &index) in self.logit_indices.iter().enumerate() {
+            let logits = ctx.get_logits_ith(index);
+            let picks = match &mut self.sampler {
+                Some(sampler) => sampler.pick(logits, width),
+                None => top_tokens(logits, width),
+            };
+            for (token, log_prob) in picks {
+                expansions.push((beam, LlamaToken::new(token as i32), log_prob));
+            }
+        }
 
-        // Return the result (single candidate for now)
-        if output.is_empty() {
-            // Fallback to reading if no output
-            Ok(vec![reading.to_string()])
-        } else {
-            Ok(vec![output, reading.to_string()])
+        let mut live = Vec::new();
+        for (parent, hypothesis) in next_beams(&self.beams, expansions, width) {
+            let token = *hypothesis.tokens.last().unwrap_or(&eos_token);
+            // Stop at the end of sequence, or at the input start marker
+            // (shouldn't happen, but safety check)
+            let token_str = model
+                .token_to_str(token, llama_cpp_2::model::Special::Tokenize)
+                .unwrap_or_default();
+            if token == eos_token || token_str.contains(ZENZ_INPUT_START) {
+                let mut done = hypothesis;
+                done.tokens.pop();
+                self.finished.push(done);
+            } else {
+                live.push((parent, hypothesis));
+            }
+        }
+        self.steps += 1;
+
+        // Log-probabilities only fall, so no live beam can overtake a
+        // finished hypothesis that is already ahead of all of them
+        let best_finished = self
+            .finished
+            .iter()
+            .map(|h| h.log_prob)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let best_live = live
+            .iter()
+            .map(|(_, h)| h.log_prob)
+            .fold(f32::NEG_INFINITY, f32::max);
+        if live.is_empty()
+            || self.finished.len() >= width
+            || best_finished >= best_live
+            || self.steps >= max_tokens
+        {
+            self.beams = live.into_iter().map(|(_, h)| h).collect();
+            self.done = true;
+            return Ok(());
         }
-    }
 
-    /// Check if the backend is ready
-    pub fn is_ready(&self) -> bool {
-        self.model.is_some()
+        self.bank = 1 - self.bank;
+        let mut seqs = Vec::with_capacity(live.len());
+        self.logit_indices.clear();
+        for (offset, (parent, hypothesis)) in live.iter().enumerate() {
+            let seq = self.base + (1 + self.bank * width + offset) as i32;
+            ctx.clear_kv_cache_seq(Some(seq as u32), None, None)
+                .map_err(|e| ZenzaiError::InferenceError(format!("Cache clear failed: {}", e)))?;
+            ctx.copy_kv_cache_seq(self.seqs[*parent], seq, None, None)
+                .map_err(|e| ZenzaiError::InferenceError(format!("Cache copy failed: {}", e)))?;
+            let token = *hypothesis.tokens.last().unwrap_or(&eos_token);
+            self.logit_indices.push(batch.n_tokens());
+            batch
+                .add(token, self.n_cur as i32, &[seq], true)
+                .map_err(|e| ZenzaiError::InferenceError(format!("Batch add failed: {}", e)))?;
+            seqs.push(seq);
+        }
+        self.beams = live.into_iter().map(|(_, h)| h).collect();
+        self.seqs = seqs;
+        self.n_cur += 1;
+        Ok(())
     }
 
-    /// Get configuration
-    #[allow(dead_code)]
-    pub fn config(&self) -> &ZenzaiConfig {
-        &self.config
+    /// The finished hypotheses, or the live ones if none finished
+    fn into_finished(self) -> Vec<Hypothesis<LlamaToken>> {
+        // Beams cut off by the limit still count when nothing finished
+        if self.finished.is_empty() {
+            self.beams
+        } else {
+            self.finished
+        }
     }
 }
 
-/// Zenzai-specific errors
+/// Beam search from a prompt decoded into sequence 0 of `ctx`
+///
+/// `first_logits` is the batch index of the prompt's last token. `step`
+/// gets the tokens of the most likely live beam before each decoding step;
+/// an error from it, such as a cancellation, ends the search. Returns the
+/// finished hypotheses, or the live ones if none finished within
+/// `max_tokens`.
 #[cfg(feature = "zenzai")]
-#[derive(Debug)]
-pub enum ZenzaiError {
-    /// Model file not found
-    ModelNotFound,
-    /// Backend not initialized
-    NotInitialized,
-    /// Model loading failed
-    #[allow(dead_code)]
-    LoadError(String),
-    /// Inference failed
-    #[allow(dead_code)]
-    InferenceError(String),
+#[allow(clippy::too_many_arguments)]
+fn beam_search(
+    model: &LlamaModel,
+    ctx: &mut LlamaContext,
+    prompt_len: usize,
+    first_logits: i32,
+    width: usize,
+    max_tokens: usize,
+    sampler: Option<Sampler>,
+    step: &mut dyn FnMut(&[LlamaToken]) -> Result<()>,
+) -> Result<Vec<Hypothesis<LlamaToken>>> {
+    let mut batch = LlamaBatch::new(width, 1);
+    let mut search = BeamSearch::new(0, prompt_len, first_logits, sampler);
+    loop {
+        step(search.best())?;
+        batch.clear();
+        search.extend(model, ctx, &mut batch, width, max_tokens)?;
+        if search.done {
+            break;
+        }
+        ctx.decode(&mut batch)
+            .map_err(|e| ZenzaiError::InferenceError(format!("Decode failed: {}", e)))?;
+    }
+    Ok(search.into_finished())
 }
 
+/// Beam searches from several prompts, decoded together in `ctx`
+///
+/// Prompt `i` is decoded into sequence `i * n_seq` and its beams use the
+/// sequences after it. Each decoding step extends the live beams of every
+/// search at once. Each search samples with its own copy of `sampler`.
+/// Returns the hypotheses of each prompt as [`beam_search`] does.
 #[cfg(feature = "zenzai")]
-impl std::fmt::Display for ZenzaiError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ZenzaiError::ModelNotFound => write!(f, "Zenzai model file not found"),
-            ZenzaiError::NotInitialized => write!(f, "Zenzai backend not initialized"),
-            ZenzaiError::LoadError(msg) => write!(f, "Failed to load Zenzai model: {}", msg),
-            ZenzaiError::InferenceError(msg) => write!(f, "Zenzai inference failed: {}", msg),
+#[allow(clippy::too_many_arguments)]
+fn batch_beam_search(
+    model: &LlamaModel,
+    ctx: &mut LlamaContext,
+    prompts: &[Vec<LlamaToken>],
+    n_seq: usize,
+    width: usize,
+    max_tokens: usize,
+    n_batch: usize,
+    sampler: Option<Sampler>,
+    cancel: &CancelToken,
+) -> Result<Vec<Vec<Hypothesis<LlamaToken>>>> {
+    let mut batch = LlamaBatch::new(n_batch.max(prompts.len() * width), 1);
+
+    // Everything but the last token of each prompt, `n_batch` at a time
+    let pending: Vec<(LlamaToken, i32, i32)> = prompts
+        .iter()
+        .enumerate()
+        .flat_map(|(i, tokens)| {
+            let seq = (i * n_seq) as i32;
+            let body = &tokens[..tokens.len().saturating_sub(1)];
+            body.iter()
+                .enumerate()
+                .map(move |(pos, &token)| (token, pos as i32, seq))
+        })
+        .collect();
+    for chunk in pending.chunks(n_batch) {
+        cancel.check()?;
+        batch.clear();
+        for &(token, pos, seq) in chunk {
+            batch
+                .add(token, pos, &[seq], false)
+                .map_err(|e| ZenzaiError::InferenceError(format!("Batch add failed: {}", e)))?;
         }
+        ctx.decode(&mut batch)
+            .map_err(|e| ZenzaiError::InferenceError(format!("Initial decode failed: {}", e)))?;
     }
-}
 
-#[cfg(feature = "zenzai")]
-impl std::error::Error for ZenzaiError {}
+    // The last tokens go in one batch, so the logits the searches start
+    // from are all available
+    batch.clear();
+    let mut searches = Vec::with_capacity(prompts.len());
+    for (i, tokens) in prompts.iter().enumerate() {
+        let seq = (i * n_seq) as i32;
+        let Some(&last) = tokens.last() else {
+            return Err(ZenzaiError::InferenceError("Empty prompt".to_string()).into());
+        };
+        let first_logits = batch.n_tokens();
+        searches.push(BeamSearch::new(
+            seq,
+            tokens.len(),
+            first_logits,
+            sampler.clone(),
+        ));
+        batch
+            .add(last, (tokens.len() - 1) as i32, &[seq], true)
+            .map_err(|e| ZenzaiError::InferenceError(format!("Batch add failed: {}", e)))?;
+    }
+    ctx.decode(&mut batch)
+        .map_err(|e| ZenzaiError::InferenceError(format!("Initial decode failed: {}", e)))?;
+
+    loop {
+        cancel.check()?;
+        batch.clear();
+        for search in searches.iter_mut().filter(|s| !s.done) {
+            search.extend(model, ctx, &mut batch, width, max_tokens)?;
+        }
+        if batch.n_tokens() == 0 {
+            break;
+        }
+        ctx.decode(&mut batch)
+            .map_err(|e| ZenzaiError::InferenceError(format!("Decode failed: {}", e)))?;
+    }
+    Ok(searches
+        .into_iter()
+        .map(BeamSearch::into_finished)
+        .collect())
+}
 
 #[cfg(test)]
 mod tests {
@@ -400,6 +1516,211 @@ mod tests {
         assert!(config.model_path.is_none());
         assert_eq!(config.inference_limit, 10);
         assert!(!config.contextual);
+        assert_eq!(config.beam_width, 3);
+        assert_eq!(config.idle_unload_secs, 0);
+        assert!(config.profiles.is_empty());
+        assert!(config.lora_path.is_none());
+        assert_eq!(config.lora_scale, 1.0);
+        assert!(config.n_threads.is_none());
+        assert_eq!(config.n_batch, 512);
+        assert!(config.n_ctx.is_none());
+        assert!(config.use_mmap);
+        assert!(!config.use_mlock);
+        assert_eq!(config.temperature, 0.0);
+        assert!(config.seed.is_none());
+    }
+
+    #[test]
+    fn test_default_threads() {
+        assert_eq!(default_threads(8), 4);
+        assert_eq!(default_threads(3), 1);
+        assert_eq!(default_threads(1), 1);
+    }
+
+    #[test]
+    fn test_context_size() {
+        // Sized to the prompt, at least CONTEXT_PROMPT_TOKENS
+        assert_eq!(context_size(None, 10, 7, 100), (7 * 228, 128));
+        assert_eq!(context_size(None, 200, 3, 100), (900, 200));
+        // A configured size is split between the sequences
+        assert_eq!(context_size(Some(2048), 10, 7, 100), (2048, 192));
+        assert_eq!(context_size(Some(512), 10, 7, 100), (512, 0));
+    }
+
+    #[test]
+    fn test_profiles_inherit_default_settings() {
+        let config: ZenzaiConfig = serde_json::from_value(serde_json::json!({
+            "inference_limit": 20,
+            "lora_path": "/models/style.gguf",
+            "lora_scale": 0.5,
+            "profiles": {
+                "small": { "model_path": "/models/small.gguf", "beam_width": 1 }
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            config.profile("small"),
+            Some(Profile {
+                model_path: Some("/models/small.gguf".to_string()),
+                inference_limit: 20,
+                beam_width: 1,
+                lora_path: None,
+                lora_scale: 0.5,
+                prompt_format: None,
+            })
+        );
+        assert_eq!(
+            config.profile(DEFAULT_PROFILE),
+            Some(Profile {
+                model_path: None,
+                inference_limit: 20,
+                beam_width: 3,
+                lora_path: Some("/models/style.gguf".to_string()),
+                lora_scale: 0.5,
+                prompt_format: None,
+            })
+        );
+        assert!(config.profile("large").is_none());
+    }
+
+    #[test]
+    fn test_prompt_formats() {
+        assert_eq!(
+            PromptFormat::V3.build("かんじ", Some("前の文")),
+            "\u{EE02}前の文\u{EE00}かんじ\u{EE01}"
+        );
+        assert_eq!(
+            PromptFormat::V2.build("かんじ", Some("前の文")),
+            "\u{EE00}かんじ\u{EE02}前の文\u{EE01}"
+        );
+        assert_eq!(
+            PromptFormat::V2.build("かんじ", Some("")),
+            PromptFormat::V3.build("かんじ", None)
+        );
+        // Only the end of a long context is kept
+        let long = "あ".repeat(MAX_PROMPT_CONTEXT) + "い";
+        let prompt = PromptFormat::V3.build("う", Some(&long));
+        assert!(prompt.starts_with("\u{EE02}あ"));
+        assert_eq!(prompt.chars().count(), MAX_PROMPT_CONTEXT + 4);
+    }
+
+    #[test]
+    fn test_detect_prompt_format() {
+        assert_eq!(PromptFormat::detect("zenz-v2"), Some(PromptFormat::V2));
+        assert_eq!(
+            PromptFormat::detect("Zenz_v2.5-medium.gguf"),
+            Some(PromptFormat::V2)
+        );
+        assert_eq!(
+            PromptFormat::detect("zenz-v3.1-small-gguf"),
+            Some(PromptFormat::V3)
+        );
+        assert_eq!(PromptFormat::detect("gpt2"), None);
+        assert_eq!(PromptFormat::detect(""), None);
+    }
+
+    #[test]
+    fn test_prompts_per_context() {
+        // Beam width 3 uses 7 sequences per prompt
+        assert_eq!(prompts_per_context(7), 9);
+        assert_eq!(prompts_per_context(1), MAX_SEQUENCES);
+        // A prompt is converted even if it needs more than the limit
+        assert_eq!(prompts_per_context(MAX_SEQUENCES + 1), 1);
+    }
+
+    #[test]
+    fn test_idle_check_interval() {
+        assert_eq!(
+            idle_check_interval(Duration::from_secs(600)),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            idle_check_interval(Duration::from_secs(30)),
+            Duration::from_secs(15)
+        );
+        assert_eq!(
+            idle_check_interval(Duration::from_secs(1)),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_top_tokens() {
+        let top = top_tokens(&[1.0, 3.0, 2.0, 0.0], 2);
+        assert_eq!(top.iter().map(|&(t, _)| t).collect::<Vec<_>>(), [1, 2]);
+        // Log-probabilities of all tokens sum to 1 as probabilities
+        let all: f32 = top_tokens(&[1.0, 3.0, 2.0, 0.0], 4)
+            .iter()
+            .map(|&(_, l)| l.exp())
+            .sum();
+        assert!((all - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_sampling_is_reproducible_with_a_seed() {
+        let config = ZenzaiConfig {
+            temperature: 1.0,
+            seed: Some(42),
+            ..Default::default()
+        };
+        let logits = [1.0, 3.0, 2.0, 0.0, 2.5];
+        let mut first = Sampler::new(&config).unwrap();
+        let mut second = Sampler::new(&config).unwrap();
+        for _ in 0..10 {
+            assert_eq!(first.pick(&logits, 3), second.pick(&logits, 3));
+        }
+        // Picks are distinct and scored by the model's probabilities
+        let picks = first.pick(&logits, 5);
+        let mut tokens: Vec<usize> = picks.iter().map(|&(t, _)| t).collect();
+        tokens.sort_unstable();
+        assert_eq!(tokens, [0, 1, 2, 3, 4]);
+        let expected = top_tokens(&logits, 5);
+        for (token, log_prob) in picks {
+            let &(_, model) = expected.iter().find(|&&(t, _)| t == token).unwrap();
+            assert!((log_prob - model).abs() < 1e-5);
+        }
+        // Without a temperature, the most likely tokens are taken
+        assert!(Sampler::new(&ZenzaiConfig::default()).is_none());
+    }
+
+    #[test]
+    fn test_session_cache_drops_least_recently_used() {
+        let mut cache = SessionCache::new(2);
+        cache.insert("a".to_string(), 1);
+        cache.insert("b".to_string(), 2);
+        let a = cache.take("a").unwrap();
+        cache.insert("a".to_string(), a);
+        cache.insert("c".to_string(), 3);
+        assert_eq!(cache.take("b"), None);
+        assert_eq!(cache.take("a"), Some(1));
+        assert_eq!(cache.take("c"), Some(3));
+        assert_eq!(cache.take("c"), None);
+    }
+
+    #[test]
+    fn test_reusable_prefix() {
+        assert_eq!(reusable_prefix(&[1, 2, 3], &[1, 2, 3, 4, 5]), 3);
+        assert_eq!(reusable_prefix(&[1, 2, 9], &[1, 2, 3, 4]), 2);
+        // The last token is decoded again for its logits
+        assert_eq!(reusable_prefix(&[1, 2, 3], &[1, 2, 3]), 2);
+        assert_eq!(reusable_prefix(&[], &[1, 2]), 0);
+    }
+
+    #[test]
+    fn test_next_beams_keeps_most_likely() {
+        let beams = vec![
+            Hypothesis::new().extend('a', -0.1),
+            Hypothesis::new().extend('b', -2.0),
+        ];
+        let expansions = vec![(0, 'x', -3.0), (0, 'y', -0.2), (1, 'z', -0.1)];
+        let next = next_beams(&beams, expansions, 2);
+        assert_eq!(next.len(), 2);
+        assert_eq!(next[0].0, 0);
+        assert_eq!(next[0].1.tokens, ['a', 'y']);
+        assert_eq!(next[1].0, 1);
+        assert_eq!(next[1].1.tokens, ['b', 'z']);
+        assert!((next[0].1.min_prob - (-0.2f32).exp()).abs() < 1e-6);
     }
 
     #[test]
@@ -0,0 +1,1609 @@
+//! Protocol-level tests against the spawned azuki-server binary
+//!
+//! These run for both `cargo test` and `cargo test --features zenzai`, so the
+//! cfg-gated handler paths (zenzai merge, fallback, errors) are exercised on
+//! both sides through real length-prefixed frames.
+
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+struct TestServer {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+    home: PathBuf,
+    /// Frames read ahead while waiting for `dictionary_ready`
+    pending: VecDeque<Value>,
+}
+
+impl TestServer {
+    /// Spawn the server with the test dictionary and an isolated home directory
+    fn spawn() -> Self {
+        Self::spawn_with_env(&[])
+    }
+
+    /// Spawn the server with extra environment variables
+    fn spawn_with_env(vars: &[(&str, &str)]) -> Self {
+        Self::spawn_in(test_home(), vars, &[])
+    }
+
+    /// Spawn the server in a prepared home directory with arguments
+    ///
+    /// Returns once the dictionaries have loaded in the background.
+    fn spawn_in(home: PathBuf, vars: &[(&str, &str)], args: &[&str]) -> Self {
+        let mut server = Self::spawn_loading(home, vars, args);
+        server.wait_for("dictionary_ready");
+        server
+    }
+
+    /// Spawn the server without waiting for its dictionaries
+    fn spawn_loading(home: PathBuf, vars: &[(&str, &str)], args: &[&str]) -> Self {
+        let mut child = command(&home)
+            .envs(vars.iter().copied())
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to spawn azuki-server");
+
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().unwrap();
+        Self {
+            child,
+            stdin,
+            stdout,
+            home,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Read frames until one of the given type, keeping the others for `recv`
+    fn wait_for(&mut self, kind: &str) -> Value {
+        if let Some(at) = self.pending.iter().position(|frame| frame["type"] == kind) {
+            return self.pending.remove(at).unwrap();
+        }
+        let mut skipped = Vec::new();
+        let found = loop {
+            let frame = self.read_frame();
+            if frame["type"] == kind {
+                break frame;
+            }
+            skipped.push(frame);
+        };
+        self.pending.extend(skipped);
+        found
+    }
+
+    fn send_raw(&mut self, payload: &str) {
+        let bytes = payload.as_bytes();
+        let stdin = self.stdin.as_mut().expect("stdin already closed");
+        stdin
+            .write_all(&(bytes.len() as u32).to_be_bytes())
+            .unwrap();
+        stdin.write_all(bytes).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    fn close_stdin(&mut self) {
+        self.stdin.take();
+    }
+
+    fn recv(&mut self) -> Value {
+        match self.pending.pop_front() {
+            Some(frame) => frame,
+            None => self.read_frame(),
+        }
+    }
+
+    fn read_frame(&mut self) -> Value {
+        let mut len = [0u8; 4];
+        self.stdout.read_exact(&mut len).unwrap();
+        let mut buf = vec![0u8; u32::from_be_bytes(len) as usize];
+        self.stdout.read_exact(&mut buf).unwrap();
+        serde_json::from_slice(&buf).unwrap()
+    }
+
+    fn request(&mut self, request: Value) -> Value {
+        self.send_raw(&request.to_string());
+        self.recv()
+    }
+}
+
+/// Texts of a response's whole-phrase candidates
+fn candidate_texts(response: &Value) -> Vec<&str> {
+    response["candidates"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["text"].as_str().unwrap())
+        .collect()
+}
+
+/// Create an isolated home directory for one server
+fn test_home() -> PathBuf {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let home = std::env::temp_dir().join(format!("azuki-test-{}-{}", std::process::id(), id));
+    std::fs::create_dir_all(&home).unwrap();
+    home
+}
+
+/// Command running azuki-server with the test dictionary inside `home`
+fn command(home: &Path) -> Command {
+    let dict = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/test-dict.utf8");
+    let mut command = Command::new(env!("CARGO_BIN_EXE_azuki-server"));
+    command
+        .env("AZUKI_DICTIONARY", dict)
+        .env("HOME", home)
+        .env("XDG_DATA_HOME", home.join("data"))
+        .env("XDG_STATE_HOME", home.join("state"))
+        .env("XDG_RUNTIME_DIR", home.join("run"));
+    command
+}
+
+/// Run a one-shot azuki-server command inside `home`
+fn run(home: &Path, args: &[&str]) -> Output {
+    command(home).args(args).output().unwrap()
+}
+
+/// Connect to a socket-mode server, waiting for it to start listening
+#[cfg(unix)]
+fn connect(path: &Path) -> UnixStream {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        match UnixStream::connect(path) {
+            Ok(stream) => return stream,
+            Err(e) if Instant::now() >= deadline => panic!("Failed to connect: {}", e),
+            Err(_) => std::thread::sleep(Duration::from_millis(20)),
+        }
+    }
+}
+
+fn socket_request<S: Read + Write>(stream: &mut S, request: Value) -> Value {
+    let bytes = request.to_string().into_bytes();
+    stream
+        .write_all(&(bytes.len() as u32).to_be_bytes())
+        .unwrap();
+    stream.write_all(&bytes).unwrap();
+    socket_recv(stream)
+}
+
+fn socket_recv<S: Read>(stream: &mut S) -> Value {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut buf = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut buf).unwrap();
+    serde_json::from_slice(&buf).unwrap()
+}
+
+/// Kills a server spawned outside [`TestServer`] when the test ends
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.home);
+    }
+}
+
+#[test]
+fn test_init_reports_dictionary() {
+    let mut server = TestServer::spawn();
+    let response = server.request(json!({"type": "init", "seq": 1, "session_id": "s1"}));
+    assert_eq!(response["type"], "init_result");
+    assert_eq!(response["seq"], 1);
+    assert_eq!(response["session_id"], "s1");
+    assert_eq!(response["has_dictionary"], true);
+    assert!(response.get("zenzai_enabled").is_none());
+}
+
+#[test]
+fn test_dictionary_ready_after_background_load() {
+    let mut server = TestServer::spawn_loading(test_home(), &[], &[]);
+    // The notification may overtake the response
+    server.send_raw(&json!({"type": "init", "seq": 1}).to_string());
+    let init = server.wait_for("init_result");
+    if init["dictionary_loading"] == true {
+        assert_eq!(init["has_dictionary"], false);
+    }
+    let ready = server.wait_for("dictionary_ready");
+    assert_eq!(ready["has_dictionary"], true);
+    assert_eq!(ready["entries"], 12);
+    assert!(ready.get("seq").is_none());
+
+    let init = server.request(json!({"type": "init", "seq": 2}));
+    assert_eq!(init["dictionary_loading"], false);
+    assert_eq!(init["has_dictionary"], true);
+}
+
+#[test]
+fn test_hello_frame_precedes_init() {
+    let mut server = TestServer::spawn_with_env(&[("AZUKI_HELLO", "1")]);
+    let hello = server.recv();
+    assert_eq!(hello["type"], "hello");
+    assert_eq!(hello["version"], env!("CARGO_PKG_VERSION"));
+    assert!(hello["pid"].as_u64().is_some());
+    assert!(hello["protocol_versions"]
+        .as_array()
+        .unwrap()
+        .contains(&json!(1)));
+    assert!(hello["data_dir"].as_str().unwrap().ends_with("data/azuki"));
+
+    let response = server.request(json!({"type": "init", "seq": 1}));
+    assert_eq!(response["type"], "init_result");
+}
+
+#[test]
+fn test_ndjson_mode() {
+    let home = test_home();
+    let mut child = command(&home)
+        .arg("--ndjson")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let _child = KillOnDrop(child);
+    let mut recv = |kind: &str| loop {
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        let frame: Value = serde_json::from_str(&line).unwrap();
+        assert_ne!(frame["type"], "error", "{}", frame);
+        if frame["type"] == kind {
+            break frame;
+        }
+    };
+
+    recv("dictionary_ready");
+    writeln!(stdin, r#"{{"type":"init","seq":1}}"#).unwrap();
+    let session_id = recv("init_result")["session_id"].clone();
+    // Blank lines are ignored
+    write!(
+        stdin,
+        "\n{}\r\n",
+        json!({"type": "convert", "seq": 2, "session_id": session_id, "reading": "にほんご"})
+    )
+    .unwrap();
+    let response = recv("convert_result");
+    assert_eq!(candidate_texts(&response)[0], "日本語");
+    let _ = std::fs::remove_dir_all(&home);
+}
+
+#[test]
+fn test_jsonrpc_mode() {
+    let home = test_home();
+    let mut child = command(&home)
+        .arg("--jsonrpc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let _child = KillOnDrop(child);
+    let mut recv = || {
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        let message: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(message["jsonrpc"], "2.0");
+        message
+    };
+
+    let ready = recv();
+    assert_eq!(ready["method"], "dictionary_ready");
+    assert_eq!(ready["params"]["has_dictionary"], true);
+
+    writeln!(stdin, r#"{{"jsonrpc":"2.0","id":"init","method":"init"}}"#).unwrap();
+    let init = recv();
+    assert_eq!(init["id"], "init");
+    let session_id = init["result"]["session_id"].clone();
+    // Requests without an id aren't answered
+    writeln!(stdin, r#"{{"jsonrpc":"2.0","method":"ping"}}"#).unwrap();
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "convert",
+        "params": {"session_id": session_id, "reading": "にほんご"}
+    });
+    writeln!(stdin, "{}", request).unwrap();
+    let response = recv();
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["result"]["candidates"][0]["text"], "日本語");
+
+    writeln!(stdin, r#"{{"jsonrpc":"2.0","id":3,"method":"fly"}}"#).unwrap();
+    assert_eq!(recv()["error"]["code"], -32601);
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 4,
+        "method": "set_providers",
+        "params": {"session_id": session_id, "enabled": {"weather": true}}
+    });
+    writeln!(stdin, "{}", request).unwrap();
+    let response = recv();
+    assert_eq!(response["id"], 4);
+    assert_eq!(response["error"]["code"], -32000);
+    assert_eq!(response["error"]["data"]["code"], "protocol_error");
+    let _ = std::fs::remove_dir_all(&home);
+}
+
+#[test]
+fn test_large_responses_are_chunked() {
+    let mut server = TestServer::spawn_in(test_home(), &[], &["--max-message-size", "1024"]);
+    let init = server.request(json!({"type": "init", "seq": 1}));
+    let requests: Vec<Value> = (0..6)
+        .map(|i| {
+            json!({
+                "type": "convert",
+                "seq": 100 + i,
+                "session_id": init["session_id"],
+                "reading": "にほんご"
+            })
+        })
+        .collect();
+    server.send_raw(&json!({"type": "batch", "seq": 2, "requests": requests}).to_string());
+
+    let first = server.recv();
+    assert_eq!(first["type"], "chunk");
+    assert_eq!(first["seq"], 2);
+    let count = first["count"].as_u64().unwrap();
+    let mut text = first["data"].as_str().unwrap().to_string();
+    for index in 1..count {
+        let chunk = server.recv();
+        assert_eq!(chunk["index"], index);
+        text.push_str(chunk["data"].as_str().unwrap());
+    }
+    let response: Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(response["type"], "batch_result");
+    assert_eq!(response["responses"].as_array().unwrap().len(), 6);
+
+    // The connection goes on after the chunks
+    let ping = server.request(json!({"type": "ping", "seq": 3}));
+    assert_eq!(ping["type"], "ping_result");
+}
+
+#[test]
+fn test_compression_is_negotiated_in_init() {
+    let home = test_home();
+    let config = home.join("azuki.json");
+    std::fs::write(&config, r#"{"compression_threshold": 200}"#).unwrap();
+    let mut server = TestServer::spawn_in(home, &[], &["--config", config.to_str().unwrap()]);
+    let init =
+        server.request(json!({"type": "init", "seq": 1, "compression": ["zstd", "deflate"]}));
+    assert_eq!(init["compression"], "zstd");
+
+    let response = server.request(json!({
+        "type": "convert",
+        "seq": 2,
+        "session_id": init["session_id"],
+        "reading": "にほんご"
+    }));
+    assert_eq!(response["type"], "compressed");
+    assert_eq!(response["seq"], 2);
+    assert_eq!(response["encoding"], "zstd");
+    assert!(response["size"].as_u64().unwrap() >= 200);
+    // Small responses stay as they are
+    let ping = server.request(json!({"type": "ping", "seq": 3}));
+    assert_eq!(ping["type"], "ping_result");
+
+    // Without an offer nothing is compressed; `init_result` itself, which
+    // lists the dictionaries, may still go out with the previous encoding
+    let reinit = server.request(json!({"type": "init", "seq": 4, "compression": ["br"]}));
+    if reinit["type"] == "init_result" {
+        assert!(reinit.get("compression").is_none());
+    }
+    let response = server.request(json!({
+        "type": "convert",
+        "seq": 5,
+        "session_id": init["session_id"],
+        "reading": "にほんご"
+    }));
+    assert_eq!(response["type"], "convert_result");
+}
+
+#[test]
+fn test_cancel_answers_without_waiting() {
+    let mut server = TestServer::spawn();
+    let init = server.request(json!({"type": "init", "seq": 1}));
+    let session_id = init["session_id"].clone();
+
+    // Requests already answered can't be cancelled
+    let response = server.request(json!({"type": "cancel", "seq": 2, "target_seq": 1}));
+    assert_eq!(response["type"], "cancel_result");
+    assert_eq!(response["target_seq"], 1);
+    assert_eq!(response["cancelled"], false);
+
+    // A cancelled conversion is answered either way
+    server.send_raw(
+        &json!({"type": "convert", "seq": 3, "session_id": session_id, "reading": "にほんご"})
+            .to_string(),
+    );
+    server.send_raw(&json!({"type": "cancel", "seq": 4, "target_seq": 3}).to_string());
+    let mut types: Vec<Value> = (0..2).map(|_| server.recv()["type"].clone()).collect();
+    types.sort_by_key(|t| t.to_string());
+    assert_eq!(types[0], "cancel_result");
+    assert!(types[1] == "convert_result" || types[1] == "error");
+}
+
+#[test]
+fn test_pipelined_requests_of_a_session_run_in_order() {
+    let mut server = TestServer::spawn();
+    let init = server.request(json!({"type": "init", "seq": 1}));
+    let session_id = init["session_id"].clone();
+
+    // Each conversion must see the mode set just before it, even though
+    // the workers could pick them up out of order
+    let modes = ["katakana", "hiragana"].repeat(32);
+    for (i, mode) in modes.iter().enumerate() {
+        let seq = 10 + 2 * i;
+        server.send_raw(
+            &json!({"type": "set_mode", "seq": seq, "session_id": session_id, "mode": mode})
+                .to_string(),
+        );
+        server.send_raw(
+            &json!({"type": "convert", "seq": seq + 1, "session_id": session_id, "reading": "にほんご"})
+                .to_string(),
+        );
+    }
+    let mut conversions = std::collections::HashMap::new();
+    while conversions.len() < modes.len() {
+        let response = server.recv();
+        if response["type"] == "convert_result" {
+            let seq = response["seq"].as_u64().unwrap();
+            conversions.insert(seq, candidate_texts(&response)[0].to_string());
+        }
+    }
+    for (i, mode) in modes.iter().enumerate() {
+        let expected = match *mode {
+            "katakana" => "ニホンゴ",
+            _ => "日本語",
+        };
+        assert_eq!(conversions[&(11 + 2 * i as u64)], expected, "{}", i);
+    }
+}
+
+#[test]
+fn test_ping_reports_status() {
+    let mut server = TestServer::spawn();
+    server.request(json!({"type": "init", "seq": 1}));
+
+    let response = server.request(json!({"type": "ping", "seq": 2}));
+    assert_eq!(response["type"], "ping_result");
+    assert!(response["uptime_ms"].is_u64());
+    assert!(response["has_dictionary"].is_boolean());
+    assert!(response["dictionary_loading"].is_boolean());
+    assert_eq!(response["zenzai_ready"], false);
+    assert_eq!(response["queue_depth"], 0);
+}
+
+#[test]
+fn test_init_zenzai_without_model() {
+    let mut server = TestServer::spawn();
+    let response = server.request(json!({
+        "type": "init",
+        "seq": 1,
+        "zenzai": {"enabled": true, "model_path": "/nonexistent/zenz.gguf"}
+    }));
+    assert_eq!(response["type"], "init_result");
+    // Without the feature the config is ignored; with it the model is missing.
+    // Either way neural conversion must report as disabled.
+    assert_eq!(response["zenzai_enabled"], false);
+
+    let response = server.request(json!({
+        "type": "zenzai_status",
+        "seq": 2,
+        "session_id": response["session_id"]
+    }));
+    assert_eq!(response["type"], "zenzai_status_result");
+    assert_eq!(response["enabled"], false);
+    assert!(response["last_error"].is_string());
+    assert!(response.get("worker_pid").is_none());
+}
+
+#[test]
+fn test_convert_falls_back_to_dictionary() {
+    let mut server = TestServer::spawn();
+    server.request(json!({
+        "type": "init",
+        "seq": 1,
+        "session_id": "s1",
+        "zenzai": {"enabled": true, "model_path": "/nonexistent/zenz.gguf"}
+    }));
+
+    let response = server.request(json!({
+        "type": "convert",
+        "seq": 2,
+        "session_id": "s1",
+        "reading": "きょうは"
+    }));
+    assert_eq!(response["type"], "convert_result");
+    assert_eq!(response["seq"], 2);
+    assert_eq!(response["candidates"][0]["text"], "今日は");
+    assert_eq!(response["segments"][0]["reading"], "きょう");
+    assert_eq!(response["segments"][0]["candidates"][0], "今日");
+}
+
+#[test]
+fn test_adjust_segment_round_trip() {
+    let mut server = TestServer::spawn();
+    let converted = server.request(json!({
+        "type": "convert",
+        "seq": 1,
+        "session_id": "s1",
+        "reading": "にほんご"
+    }));
+
+    let response = server.request(json!({
+        "type": "adjust_segment",
+        "seq": 2,
+        "session_id": "s1",
+        "reading": "にほんごを",
+        "segments": [
+            converted["segments"][0].clone(),
+            {"reading": "を", "start": 4, "length": 1, "candidates": ["を"]}
+        ],
+        "segment_index": 0,
+        "direction": "shrink"
+    }));
+    assert_eq!(response["type"], "adjust_segment_result");
+    assert_eq!(response["segments"][0]["reading"], "にほん");
+    assert_eq!(response["segments"][1]["reading"], "ごを");
+    assert_eq!(response["candidates"][0]["text"], "にほんごを");
+}
+
+#[test]
+fn test_convert_segment_refreshes_one_segment() {
+    let mut server = TestServer::spawn();
+    let converted = server.request(json!({
+        "type": "convert",
+        "seq": 1,
+        "session_id": "s1",
+        "reading": "きょうはいしゃ"
+    }));
+
+    let response = server.request(json!({
+        "type": "convert_segment",
+        "seq": 2,
+        "session_id": "s1",
+        "reading": "きょうはいしゃ",
+        "segments": converted["segments"],
+        "segment_index": 2
+    }));
+    assert_eq!(response["type"], "convert_segment_result");
+    assert_eq!(response["segment_index"], 2);
+    assert_eq!(response["segment"]["reading"], "いしゃ");
+    assert_eq!(response["segment"]["candidates"], json!(["医者", "いしゃ"]));
+
+    let response = server.request(json!({
+        "type": "convert_segment",
+        "seq": 3,
+        "session_id": "s1",
+        "reading": "きょうはいしゃ",
+        "segments": converted["segments"],
+        "segment_index": 3
+    }));
+    assert_eq!(response["type"], "error");
+}
+
+#[test]
+fn test_convert_batch_converts_each_reading() {
+    let mut server = TestServer::spawn();
+    let response = server.request(json!({
+        "type": "convert_batch",
+        "seq": 1,
+        "session_id": "s1",
+        "readings": ["きょう", "いしゃ"]
+    }));
+    assert_eq!(response["type"], "convert_batch_result");
+    let results = response["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["reading"], "きょう");
+    assert_eq!(candidate_texts(&results[1])[0], "医者");
+
+    let response = server.request(json!({
+        "type": "convert_batch",
+        "seq": 2,
+        "session_id": "s1",
+        "readings": ["きょう", "いしゃ"],
+        "contexts": ["今日は"]
+    }));
+    assert_eq!(response["type"], "error");
+}
+
+#[test]
+fn test_adjusted_segments_are_kept_for_the_session() {
+    let mut server = TestServer::spawn();
+    let convert = |server: &mut TestServer, seq: u64, session_id: &str| {
+        server.request(json!({
+            "type": "convert",
+            "seq": seq,
+            "session_id": session_id,
+            "reading": "きょうはいしゃ"
+        }))
+    };
+    let readings = |response: &Value| -> Vec<String> {
+        response["segments"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|seg| seg["reading"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let converted = convert(&mut server, 1, "s1");
+    assert_eq!(readings(&converted), vec!["きょう", "は", "いしゃ"]);
+    let adjusted = server.request(json!({
+        "type": "adjust_segment",
+        "seq": 2,
+        "session_id": "s1",
+        "reading": "きょうはいしゃ",
+        "segments": converted["segments"],
+        "segment_index": 1,
+        "direction": "extend"
+    }));
+    assert_eq!(readings(&adjusted), vec!["きょう", "はい", "しゃ"]);
+
+    let reconverted = convert(&mut server, 3, "s1");
+    assert_eq!(readings(&reconverted), vec!["きょう", "はい", "しゃ"]);
+    assert_eq!(reconverted["candidates"][0], adjusted["candidates"][0]);
+
+    // Other sessions keep the greedy segmentation
+    assert_eq!(
+        readings(&convert(&mut server, 4, "s2")),
+        vec!["きょう", "は", "いしゃ"]
+    );
+}
+
+#[test]
+fn test_adjust_segment_without_resending_segments() {
+    let mut server = TestServer::spawn();
+    server.request(json!({
+        "type": "convert",
+        "seq": 1,
+        "session_id": "s1",
+        "reading": "きょうはいしゃ"
+    }));
+    let adjust = |seq: u64| {
+        json!({
+            "type": "adjust_segment",
+            "seq": seq,
+            "session_id": "s1",
+            "segment_index": 1,
+            "direction": "extend"
+        })
+    };
+    let adjusted = server.request(adjust(2));
+    assert_eq!(adjusted["type"], "adjust_segment_result");
+    assert_eq!(adjusted["segments"][1]["reading"], "はい");
+    // Adjustments build on each other
+    let adjusted = server.request(adjust(3));
+    assert_eq!(adjusted["segments"][1]["reading"], "はいし");
+
+    let state = server.request(json!({
+        "type": "session_state",
+        "seq": 4,
+        "session_id": "s1",
+        "select": {"segment": 0, "candidate": 0}
+    }));
+    assert_eq!(state["type"], "session_state_result");
+    assert_eq!(state["input_mode"], "hiragana");
+    assert_eq!(state["composition"]["reading"], "きょうはいしゃ");
+    assert_eq!(state["composition"]["segments"][1]["reading"], "はいし");
+
+    // Another session has nothing to adjust
+    let response = server.request(json!({
+        "type": "adjust_segment",
+        "seq": 5,
+        "session_id": "s2",
+        "segment_index": 0,
+        "direction": "extend"
+    }));
+    assert_eq!(response["code"], "session_error");
+}
+
+#[test]
+fn test_empty_and_control_readings_report_errors() {
+    let mut server = TestServer::spawn();
+    for (seq, reading) in [(1, ""), (2, "きょう\u{7}")] {
+        let response = server.request(json!({
+            "type": "convert",
+            "seq": seq,
+            "session_id": "s1",
+            "reading": reading
+        }));
+        assert_eq!(response["type"], "error");
+        assert_eq!(response["seq"], seq);
+        assert_eq!(response["code"], "protocol_error");
+    }
+
+    let response = server.request(json!({
+        "type": "adjust_segment",
+        "seq": 3,
+        "session_id": "s1",
+        "reading": "",
+        "segments": [],
+        "segment_index": 0,
+        "direction": "shrink"
+    }));
+    assert_eq!(response["type"], "error");
+    assert_eq!(response["code"], "protocol_error");
+}
+
+#[test]
+fn test_invalid_completion_prefixes_report_errors() {
+    let mut server = TestServer::spawn();
+    for (seq, prefix) in [(1, ""), (2, "き\u{7}")] {
+        let response = server.request(json!({
+            "type": "complete",
+            "seq": seq,
+            "session_id": "s1",
+            "prefix": prefix
+        }));
+        assert_eq!(response["type"], "error");
+        assert_eq!(response["seq"], seq);
+        assert_eq!(response["code"], "protocol_error");
+    }
+}
+
+#[test]
+fn test_whitespace_reading_passes_through() {
+    let mut server = TestServer::spawn();
+    let response = server.request(json!({
+        "type": "convert",
+        "seq": 1,
+        "session_id": "s1",
+        "reading": " \u{3000}"
+    }));
+    assert_eq!(response["type"], "convert_result");
+    assert_eq!(candidate_texts(&response), [" \u{3000}"]);
+    assert_eq!(response["segments"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn test_adjust_segment_rejects_inconsistent_segments() {
+    let mut server = TestServer::spawn();
+    let segment = json!({"reading": "きょう", "start": 0, "length": 3, "candidates": ["今日"]});
+    for (seq, segments, index) in [
+        (1, json!([segment.clone()]), 1),
+        (
+            2,
+            json!([segment.clone(), {"reading": "は", "start": 4, "length": 1, "candidates": ["は"]}]),
+            0,
+        ),
+        (3, json!([segment]), 0),
+    ] {
+        let response = server.request(json!({
+            "type": "adjust_segment",
+            "seq": seq,
+            "session_id": "s1",
+            "reading": "きょうは",
+            "segments": segments,
+            "segment_index": index,
+            "direction": "extend"
+        }));
+        assert_eq!(response["type"], "error", "seq {}", seq);
+        assert_eq!(response["code"], "protocol_error");
+    }
+}
+
+#[test]
+fn test_invalid_json_reports_error_with_seq() {
+    let mut server = TestServer::spawn();
+    server.send_raw(r#"{"type":"convert","seq":7,"session_id":1}"#);
+    let response = server.recv();
+    assert_eq!(response["type"], "error");
+    assert_eq!(response["seq"], 7);
+    assert_eq!(response["code"], "protocol_error");
+
+    server.send_raw("not json");
+    let response = server.recv();
+    assert_eq!(response["type"], "error");
+    assert_eq!(response["seq"], 0);
+}
+
+#[test]
+fn test_invalid_direction_reports_error() {
+    let mut server = TestServer::spawn();
+    let response = server.request(json!({
+        "type": "adjust_segment",
+        "seq": 3,
+        "session_id": "s1",
+        "reading": "きょう",
+        "segments": [],
+        "segment_index": 0,
+        "direction": "sideways"
+    }));
+    assert_eq!(response["type"], "error");
+    assert_eq!(response["seq"], 3);
+    assert_eq!(response["session_id"], "s1");
+}
+
+#[test]
+fn test_shutdown_exits_cleanly() {
+    let mut server = TestServer::spawn();
+    let response = server.request(json!({"type": "shutdown", "seq": 9}));
+    assert_eq!(response["type"], "shutdown_result");
+    assert_eq!(response["seq"], 9);
+    assert!(server.child.wait().unwrap().success());
+}
+
+#[test]
+fn test_queued_requests_are_answered_before_shutdown() {
+    let mut server = TestServer::spawn();
+    let init = server.request(json!({"type": "init", "seq": 1}));
+    let session_id = init["session_id"].clone();
+
+    for seq in 2..12 {
+        server.send_raw(
+            &json!({"type": "convert", "seq": seq, "session_id": session_id, "reading": "きょう"})
+                .to_string(),
+        );
+    }
+    server.send_raw(&json!({"type": "ping", "seq": 12}).to_string());
+    server.send_raw(&json!({"type": "shutdown", "seq": 13}).to_string());
+
+    // Responses may come in any order but each request is answered once
+    let mut seqs = Vec::new();
+    while seqs.len() < 11 {
+        let frame = server.recv();
+        if let Some(seq) = frame["seq"].as_u64() {
+            assert_ne!(frame["type"], "shutdown_result");
+            seqs.push(seq);
+        }
+    }
+    seqs.sort_unstable();
+    assert_eq!(seqs, (2..13).collect::<Vec<u64>>());
+    assert_eq!(server.wait_for("shutdown_result")["seq"], 13);
+    assert!(server.child.wait().unwrap().success());
+}
+
+#[test]
+fn test_eof_exits_cleanly() {
+    let mut server = TestServer::spawn();
+    server.request(json!({"type": "init", "seq": 1}));
+    server.close_stdin();
+    assert!(server.child.wait().unwrap().success());
+}
+
+#[test]
+fn test_learning_is_saved_on_shutdown() {
+    let mut server = TestServer::spawn();
+    let init = server.request(json!({"type": "init", "seq": 1}));
+    let response = server.request(json!({
+        "type": "commit",
+        "seq": 2,
+        "session_id": init["session_id"],
+        "reading": "きょう",
+        "candidate": "京"
+    }));
+    assert_eq!(response["type"], "commit_result");
+    let response = server.request(json!({"type": "shutdown", "seq": 3}));
+    assert_eq!(response["flushed"], true);
+    assert!(server.child.wait().unwrap().success());
+
+    let path = server.home.join("state/azuki/learning.json");
+    let saved: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(saved["counts"]["きょう"]["京"], 1);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_learning_is_saved_on_sigterm() {
+    let mut server = TestServer::spawn();
+    let init = server.request(json!({"type": "init", "seq": 1}));
+    server.request(json!({
+        "type": "commit",
+        "seq": 2,
+        "session_id": init["session_id"],
+        "reading": "きょう",
+        "candidate": "京"
+    }));
+    let status = Command::new("kill")
+        .arg(server.child.id().to_string())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(server.child.wait().unwrap().success());
+
+    let path = server.home.join("state/azuki/learning.json");
+    let saved: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(saved["counts"]["きょう"]["京"], 1);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_socket_mode_serves_several_clients() {
+    let home = test_home();
+    let socket = home.join("azuki.sock");
+    let child = command(&home)
+        .arg("--socket")
+        .arg(&socket)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let _child = KillOnDrop(child);
+
+    let mut first = connect(&socket);
+    let mut second = connect(&socket);
+    let a = socket_request(&mut first, json!({"type": "init", "seq": 1}));
+    let b = socket_request(&mut second, json!({"type": "init", "seq": 1}));
+    assert_ne!(a["session_id"], b["session_id"]);
+
+    // Shutdown only closes the requesting connection
+    let response = socket_request(&mut first, json!({"type": "shutdown", "seq": 2}));
+    assert_eq!(response["type"], "shutdown_result");
+    let response = socket_request(
+        &mut second,
+        json!({"type": "convert", "seq": 2, "session_id": b["session_id"], "reading": "にほんご"}),
+    );
+    assert_eq!(response["candidates"][0]["text"], "日本語");
+    let _ = std::fs::remove_dir_all(&home);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_session_eviction_reaches_the_owning_connection() {
+    let home = test_home();
+    let socket = home.join("azuki.sock");
+    let child = command(&home)
+        .arg("--socket")
+        .arg(&socket)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let _child = KillOnDrop(child);
+
+    let mut first = connect(&socket);
+    first
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut second = connect(&socket);
+    socket_request(
+        &mut first,
+        json!({"type": "init", "seq": 1, "session_id": "a", "sessions": {"max_sessions": 1}}),
+    );
+    socket_request(
+        &mut second,
+        json!({"type": "init", "seq": 1, "session_id": "b"}),
+    );
+    let evicted = socket_recv(&mut first);
+    assert_eq!(evicted["type"], "session_evicted");
+    assert_eq!(evicted["session_id"], "a");
+
+    // The evicting connection gets only its responses
+    let response = socket_request(&mut second, json!({"type": "ping", "seq": 2}));
+    assert_eq!(response["type"], "ping_result");
+    let _ = std::fs::remove_dir_all(&home);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_daemon_start_status_stop() {
+    let home = test_home();
+
+    let status = run(&home, &["daemon", "status"]);
+    assert!(!status.status.success());
+
+    let start = run(&home, &["daemon", "start"]);
+    assert!(start.status.success());
+    let socket = PathBuf::from(String::from_utf8(start.stdout).unwrap().trim());
+    assert_eq!(socket, home.join("run/azuki/azuki.sock"));
+
+    let mut stream = connect(&socket);
+    let response = socket_request(&mut stream, json!({"type": "init", "seq": 1}));
+    assert_eq!(response["type"], "init_result");
+
+    // Only one daemon runs at a time
+    let again = run(&home, &["--daemon"]);
+    assert!(!again.status.success());
+    assert!(String::from_utf8(again.stderr)
+        .unwrap()
+        .contains("already running"));
+
+    let status = run(&home, &["daemon", "status"]);
+    assert!(status.status.success());
+    assert!(String::from_utf8(status.stdout)
+        .unwrap()
+        .starts_with("running"));
+
+    assert!(run(&home, &["daemon", "stop"]).status.success());
+    assert!(!run(&home, &["daemon", "status"]).status.success());
+    assert!(!socket.exists());
+    let _ = std::fs::remove_dir_all(&home);
+}
+
+#[test]
+fn test_unknown_arguments_print_usage() {
+    let home = test_home();
+    let output = run(&home, &["--bogus"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Usage: azuki-server"));
+    let _ = std::fs::remove_dir_all(&home);
+}
+
+#[test]
+fn test_version_flag() {
+    let home = test_home();
+    let output = run(&home, &["--version"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim(),
+        format!("azuki-server {}", env!("CARGO_PKG_VERSION"))
+    );
+    let _ = std::fs::remove_dir_all(&home);
+}
+
+#[test]
+fn test_config_file_and_dictionary_flags() {
+    let home = test_home();
+    let config = home.join("azuki.json");
+    std::fs::write(&config, r#"{"conversion": {"n_best": 1}}"#).unwrap();
+    let extra = home.join("extra.dict");
+    std::fs::write(&extra, ";; okuri-nasi entries.\nさくら /桜/\nきょう /強/\n").unwrap();
+    let fixture = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/test-dict.utf8");
+
+    let args = [
+        "--config",
+        config.to_str().unwrap(),
+        "--dictionary",
+        fixture.to_str().unwrap(),
+        "--dictionary",
+        extra.to_str().unwrap(),
+    ];
+    let mut server = TestServer::spawn_in(home, &[], &args);
+    let init = server.request(json!({"type": "init", "seq": 1}));
+    assert_eq!(init["has_dictionary"], true);
+
+    let convert = |server: &mut TestServer, seq: u64, reading: &str| {
+        server.request(json!({
+            "type": "convert",
+            "seq": seq,
+            "session_id": init["session_id"],
+            "reading": reading
+        }))
+    };
+    // Both dictionaries are loaded, the first keeping priority
+    let response = convert(&mut server, 2, "さくら");
+    assert_eq!(response["candidates"][0]["text"], "桜");
+    let response = convert(&mut server, 3, "きょう");
+    assert_eq!(response["segments"][0]["candidates"][3], "強");
+    // n_best from the config file: one phrase plus the reading
+    assert_eq!(candidate_texts(&response), ["今日", "きょう"]);
+}
+
+#[test]
+fn test_config_pins_dictionary_encoding() {
+    let home = test_home();
+    let dict = home.join("sjis.dict");
+    // "かい /貝/" in Shift_JIS
+    let mut bytes = b";; okuri-nasi entries.\n".to_vec();
+    bytes.extend([0x82, 0xA9, 0x82, 0xA2, b' ', b'/', 0x8A, 0x4C, b'/', b'\n']);
+    std::fs::write(&dict, bytes).unwrap();
+    let config = home.join("azuki.json");
+    std::fs::write(
+        &config,
+        json!({"dictionaries": [{"path": dict, "encoding": "shift_jis"}]}).to_string(),
+    )
+    .unwrap();
+
+    let mut server = TestServer::spawn_in(home, &[], &["--config", config.to_str().unwrap()]);
+    let init = server.request(json!({"type": "init", "seq": 1}));
+    assert_eq!(init["dictionaries"][0]["encoding"], "Shift_JIS");
+    let response = server.request(json!({
+        "type": "convert",
+        "seq": 2,
+        "session_id": init["session_id"],
+        "reading": "かい"
+    }));
+    assert_eq!(response["candidates"][0]["text"], "貝");
+}
+
+#[test]
+fn test_dictionary_directory_loads_files_in_name_order() {
+    let home = test_home();
+    let dir = home.join("dict");
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("b.dict"), ";; okuri-nasi entries.\nきょう /京/\n").unwrap();
+    std::fs::write(
+        dir.join("a.dict"),
+        ";; okuri-nasi entries.\nきょう /今日/\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join(".hidden"), ";; okuri-nasi entries.\nきょう /教/\n").unwrap();
+
+    let mut server = TestServer::spawn_in(home, &[], &["--dictionary", dir.to_str().unwrap()]);
+    let init = server.request(json!({"type": "init", "seq": 1}));
+    let paths: Vec<_> = init["dictionaries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|dictionary| dictionary["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            dir.join("a.dict").to_str().unwrap(),
+            dir.join("b.dict").to_str().unwrap()
+        ]
+    );
+
+    // Files dropped in are picked up on reload
+    std::fs::write(dir.join("c.dict"), ";; okuri-nasi entries.\nさくら /桜/\n").unwrap();
+    server.request(json!({
+        "type": "reload_dictionaries",
+        "seq": 2,
+        "session_id": init["session_id"]
+    }));
+    let response = server.request(json!({
+        "type": "convert",
+        "seq": 3,
+        "session_id": init["session_id"],
+        "reading": "さくら"
+    }));
+    assert_eq!(response["candidates"][0]["text"], "桜");
+    let response = server.request(json!({
+        "type": "convert",
+        "seq": 4,
+        "session_id": init["session_id"],
+        "reading": "きょう"
+    }));
+    assert_eq!(response["segments"][0]["candidates"][0], "今日");
+    assert_eq!(response["segments"][0]["candidates"][1], "京");
+}
+
+#[test]
+fn test_emoji_and_symbol_candidates_have_a_kind() {
+    let home = test_home();
+    let dictionary = home.join("SKK-JISYO.emoji");
+    std::fs::write(
+        &dictionary,
+        ";; okuri-nasi entries.\nすし /寿司/🍣/\nほし /星/☆/\n",
+    )
+    .unwrap();
+    let mut server =
+        TestServer::spawn_in(home, &[], &["--dictionary", dictionary.to_str().unwrap()]);
+    let init = server.request(json!({"type": "init", "seq": 1}));
+
+    let response = server.request(json!({
+        "type": "convert",
+        "seq": 2,
+        "session_id": init["session_id"],
+        "reading": "すし"
+    }));
+    assert_eq!(candidate_texts(&response)[..2], ["寿司", "🍣"]);
+    assert!(response["candidates"][0].get("kind").is_none());
+    assert_eq!(response["candidates"][1]["kind"], "emoji");
+    assert_eq!(response["segments"][0]["kinds"], json!({"🍣": "emoji"}));
+
+    let response = server.request(json!({
+        "type": "convert",
+        "seq": 3,
+        "session_id": init["session_id"],
+        "reading": "ほし"
+    }));
+    assert_eq!(response["candidates"][1]["text"], "☆");
+    assert_eq!(response["candidates"][1]["kind"], "symbol");
+}
+
+#[test]
+fn test_dictionary_is_reloaded_on_change() {
+    let home = test_home();
+    let config = home.join("azuki.json");
+    std::fs::write(&config, r#"{"watch": {"interval_ms": 50}}"#).unwrap();
+    let dict = home.join("reload.dict");
+    std::fs::write(&dict, ";; okuri-nasi entries.\nきょう /今日/\n").unwrap();
+
+    let args = [
+        "--config",
+        config.to_str().unwrap(),
+        "--dictionary",
+        dict.to_str().unwrap(),
+    ];
+    let mut server = TestServer::spawn_in(home, &[], &args);
+    let init = server.request(json!({"type": "init", "seq": 1}));
+    let mut convert = |seq: u64| {
+        server.request(json!({
+            "type": "convert",
+            "seq": seq,
+            "session_id": init["session_id"],
+            "reading": "さくら"
+        }))
+    };
+    assert_eq!(convert(2)["candidates"][0]["text"], "さくら");
+
+    std::fs::write(
+        &dict,
+        ";; okuri-nasi entries.\nきょう /今日/\nさくら /桜/\n",
+    )
+    .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut seq = 3;
+    while convert(seq)["candidates"][0]["text"] != "桜" {
+        assert!(Instant::now() < deadline, "dictionary was not reloaded");
+        std::thread::sleep(Duration::from_millis(50));
+        seq += 1;
+    }
+}
+
+#[test]
+fn test_init_and_dict_info_report_loaded_dictionaries() {
+    let home = test_home();
+    let dict = home.join("main.dict");
+    std::fs::write(&dict, ";; okuri-nasi entries.\nきょう /今日/\n").unwrap();
+    let missing = home.join("missing.dict");
+    let args = [
+        "--dictionary",
+        dict.to_str().unwrap(),
+        "--dictionary",
+        missing.to_str().unwrap(),
+    ];
+    let mut server = TestServer::spawn_in(home, &[], &args);
+    let init = server.request(json!({"type": "init", "seq": 1}));
+    let dictionaries = init["dictionaries"].as_array().unwrap();
+    assert_eq!(dictionaries.len(), 2);
+    assert_eq!(dictionaries[0]["path"], dict.to_str().unwrap());
+    assert_eq!(dictionaries[0]["entries"], 1);
+    assert_eq!(dictionaries[0]["encoding"], "UTF-8");
+    assert!(dictionaries[0]["elapsed_ms"].is_u64());
+    assert!(dictionaries[1].get("encoding").is_none());
+    assert!(dictionaries[1]["error"].is_string());
+
+    let response = server.request(json!({
+        "type": "dict_info",
+        "seq": 2,
+        "session_id": init["session_id"]
+    }));
+    assert_eq!(response["type"], "dict_info_result");
+    assert_eq!(response["dictionaries"], init["dictionaries"]);
+    assert_eq!(response["loading"], false);
+    assert_eq!(response["user_entries"], 0);
+}
+
+#[test]
+fn test_sessions_switch_dictionary_sets() {
+    let home = test_home();
+    let main = home.join("main.dict");
+    std::fs::write(&main, ";; okuri-nasi entries.\nきょう /今日/\n").unwrap();
+    let names = home.join("names.dict");
+    std::fs::write(&names, ";; okuri-nasi entries.\nきょう /京子/\n").unwrap();
+    let config = home.join("azuki.json");
+    std::fs::write(
+        &config,
+        json!({"dictionaries": [main], "dictionary_sets": {"names": [names]}}).to_string(),
+    )
+    .unwrap();
+
+    let mut server = TestServer::spawn_in(home, &[], &["--config", config.to_str().unwrap()]);
+    server.request(json!({"type": "init", "seq": 1, "session_id": "prose"}));
+    server.request(json!({
+        "type": "init",
+        "seq": 2,
+        "session_id": "contacts",
+        "notifications": true
+    }));
+    let response = server.request(json!({
+        "type": "session_state",
+        "seq": 3,
+        "session_id": "contacts",
+        "dictionary_set": "names"
+    }));
+    assert_eq!(response["dictionary_set"], "names");
+    // The set loads in the background
+    assert_eq!(response["dictionary_set_loading"], true);
+    let ready = server.wait_for("dictionary_set_ready");
+    assert_eq!(ready["name"], "names");
+    assert_eq!(ready["entries"], 1);
+    server.request(json!({
+        "type": "add_entry",
+        "seq": 4,
+        "session_id": "prose",
+        "reading": "あずき",
+        "candidate": "小豆"
+    }));
+
+    let mut first = |seq: u64, session: &str, reading: &str| {
+        let response = server.request(json!({
+            "type": "convert",
+            "seq": seq,
+            "session_id": session,
+            "reading": reading
+        }));
+        response["candidates"][0]["text"].clone()
+    };
+    assert_eq!(first(5, "contacts", "きょう"), "京子");
+    assert_eq!(first(6, "prose", "きょう"), "今日");
+    // User words are in every set
+    assert_eq!(first(7, "contacts", "あずき"), "小豆");
+
+    let response = server.request(json!({"type": "dict_info", "seq": 8, "session_id": "contacts"}));
+    assert_eq!(response["dictionary_set"], "names");
+    assert_eq!(response["dictionary_sets"], json!(["names"]));
+    assert_eq!(response["dictionaries"][0]["path"], names.to_str().unwrap());
+    let response = server.request(json!({
+        "type": "session_state",
+        "seq": 9,
+        "session_id": "prose",
+        "dictionary_set": "technical"
+    }));
+    assert_eq!(response["code"], "config_error");
+}
+
+#[test]
+fn test_reload_dictionaries_reports_each_file() {
+    let home = test_home();
+    let dict = home.join("main.dict");
+    std::fs::write(&dict, ";; okuri-nasi entries.\nきょう /今日/\n").unwrap();
+    let missing = home.join("missing.dict");
+    let args = [
+        "--dictionary",
+        dict.to_str().unwrap(),
+        "--dictionary",
+        missing.to_str().unwrap(),
+    ];
+    let mut server = TestServer::spawn_in(home, &[], &args);
+    let init = server.request(json!({"type": "init", "seq": 1}));
+
+    std::fs::write(
+        &dict,
+        ";; okuri-nasi entries.\nきょう /今日/\nさくら /桜/\n",
+    )
+    .unwrap();
+    let response = server.request(json!({
+        "type": "reload_dictionaries",
+        "seq": 2,
+        "session_id": init["session_id"]
+    }));
+    assert_eq!(response["type"], "reload_dictionaries_result");
+    let dictionaries = response["dictionaries"].as_array().unwrap();
+    assert_eq!(dictionaries[0]["path"], dict.to_str().unwrap());
+    assert_eq!(dictionaries[0]["entries"], 2);
+    assert!(dictionaries[0].get("error").is_none());
+    assert_eq!(dictionaries[1]["entries"], 0);
+    assert!(dictionaries[1]["error"].is_string());
+    assert_eq!(response["user_entries"], 0);
+
+    let response = server.request(json!({
+        "type": "convert",
+        "seq": 3,
+        "session_id": init["session_id"],
+        "reading": "さくら"
+    }));
+    assert_eq!(response["candidates"][0]["text"], "桜");
+}
+
+#[test]
+fn test_server_events_after_opting_in() {
+    let home = test_home();
+    let dict = home.join("main.dict");
+    std::fs::write(&dict, ";; okuri-nasi entries.\nきょう /今日/\n").unwrap();
+    let config = home.join("azuki.json");
+    std::fs::write(&config, r#"{"watch": {"interval_ms": 50}}"#).unwrap();
+    let args = [
+        "--dictionary",
+        dict.to_str().unwrap(),
+        "--config",
+        config.to_str().unwrap(),
+    ];
+    let mut server = TestServer::spawn_in(home, &[], &args);
+    let init = server.request(json!({"type": "init", "seq": 1, "notifications": true}));
+    assert_eq!(init["type"], "init_result");
+
+    std::fs::write(
+        &dict,
+        ";; okuri-nasi entries.\nきょう /今日/\nさくら /桜/\n",
+    )
+    .unwrap();
+    let reloaded = server.wait_for("dictionary_reloaded");
+    assert_eq!(reloaded["system"], true);
+    assert_eq!(reloaded["user"], false);
+    assert_eq!(reloaded["entries"], 2);
+    assert!(reloaded.get("seq").is_none());
+
+    // Learning is saved every 20 commits
+    for seq in 2..22 {
+        server.send_raw(
+            &json!({
+                "type": "commit",
+                "seq": seq,
+                "session_id": init["session_id"],
+                "reading": "きょう",
+                "candidate": "今日"
+            })
+            .to_string(),
+        );
+    }
+    let saved = server.wait_for("learning_saved");
+    assert_eq!(saved["entries"], 1);
+}
+
+#[test]
+fn test_log_file_and_runtime_level() {
+    let home = test_home();
+    let config = home.join("azuki.json");
+    std::fs::write(&config, r#"{"log_level": "warn"}"#).unwrap();
+    let mut server = TestServer::spawn_in(home, &[], &["--config", config.to_str().unwrap()]);
+    let log = server.home.join("state/azuki/azuki.log");
+
+    let response = server.request(json!({"type": "set_log_level", "seq": 1}));
+    assert_eq!(response["type"], "log_level_result");
+    assert_eq!(response["level"], "warn");
+    assert_eq!(response["file"], log.to_str().unwrap());
+
+    let response = server.request(json!({"type": "set_log_level", "seq": 2, "level": "debug"}));
+    assert_eq!(response["level"], "debug");
+    let text = std::fs::read_to_string(&log).unwrap();
+    let line = text
+        .lines()
+        .find(|line| line.ends_with("Log level set to debug"))
+        .expect("level change logged");
+    assert!(line.contains(" INFO "));
+    assert!(line.contains(&format!(" {} ", server.child.id())));
+}
+
+#[test]
+fn test_invalid_config_file_is_reported() {
+    let home = test_home();
+    let config = home.join("azuki.json");
+    std::fs::write(&config, r#"{"conversoin": {}}"#).unwrap();
+    let output = run(&home, &["--config", config.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unknown field"));
+    let _ = std::fs::remove_dir_all(&home);
+}
+
+#[test]
+fn test_tcp_listener_serves_concurrent_clients() {
+    let home = test_home();
+    let mut child = command(&home)
+        .args(["--listen", "tcp:127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let _child = KillOnDrop(child);
+    let addr = line.trim().strip_prefix("tcp:").unwrap().to_string();
+
+    let clients: Vec<_> = ["きょう", "にほんご"]
+        .into_iter()
+        .map(|reading| {
+            let addr = addr.clone();
+            std::thread::spawn(move || {
+                let mut stream = TcpStream::connect(&addr).unwrap();
+                let init = socket_request(&mut stream, json!({"type": "init", "seq": 1}));
+                let mut first = Value::Null;
+                for seq in 2..20 {
+                    let response = socket_request(
+                        &mut stream,
+                        json!({
+                            "type": "convert",
+                            "seq": seq,
+                            "session_id": init["session_id"],
+                            "reading": reading
+                        }),
+                    );
+                    assert_eq!(response["session_id"], init["session_id"]);
+                    first = response["candidates"][0]["text"].clone();
+                }
+                socket_request(&mut stream, json!({"type": "shutdown", "seq": 20}));
+                (init["session_id"].clone(), first)
+            })
+        })
+        .collect();
+    let results: Vec<(Value, Value)> = clients.into_iter().map(|c| c.join().unwrap()).collect();
+    assert_ne!(results[0].0, results[1].0);
+    assert_eq!(results[0].1, "今日");
+    assert_eq!(results[1].1, "日本語");
+
+    // The listener outlives clients that sent shutdown
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    let response = socket_request(&mut stream, json!({"type": "init", "seq": 1}));
+    assert_eq!(response["type"], "init_result");
+    let _ = std::fs::remove_dir_all(&home);
+}
+
+/// Start an skkserv with the test dictionary, returning its address
+fn spawn_skkserv(home: &Path) -> (KillOnDrop, String) {
+    let mut child = command(home)
+        .args(["--skkserv", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let addr = line.trim().strip_prefix("skkserv:").unwrap().to_string();
+    (KillOnDrop(child), addr)
+}
+
+#[test]
+fn test_skkserv_lookup() {
+    let home = test_home();
+    let (_skkserv, addr) = spawn_skkserv(&home);
+
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let euc = |text: &str| encoding_rs::EUC_JP.encode(text).0.into_owned();
+
+    let mut request = b"1".to_vec();
+    request.extend(euc("きょう "));
+    stream.write_all(&request).unwrap();
+    let mut response = Vec::new();
+    reader.read_until(b'\n', &mut response).unwrap();
+    assert_eq!(response, euc("1/今日/京/教/\n"));
+
+    stream.write_all(&euc("1ないよ \n")).unwrap();
+    response.clear();
+    reader.read_until(b'\n', &mut response).unwrap();
+    assert_eq!(response, euc("4ないよ \n"));
+
+    stream.write_all(b"2").unwrap();
+    let mut version = vec![0u8; "azuki-server.".len()];
+    reader.read_exact(&mut version).unwrap();
+    assert_eq!(version, b"azuki-server.");
+
+    stream.write_all(b"0").unwrap();
+    let _ = std::fs::remove_dir_all(&home);
+}
+
+#[test]
+fn test_remote_dictionary() {
+    let remote_home = test_home();
+    let (_skkserv, addr) = spawn_skkserv(&remote_home);
+    let (host, port) = addr.rsplit_once(':').unwrap();
+
+    // No local dictionary: every candidate comes from the skkserv
+    let home = test_home();
+    let missing = home.join("missing.dict");
+    let mut server = TestServer::spawn_in(home, &[], &["--dictionary", missing.to_str().unwrap()]);
+    let init = server.request(json!({
+        "type": "init",
+        "seq": 1,
+        "remote_dictionary": {"host": host, "port": port.parse::<u16>().unwrap()}
+    }));
+    assert_eq!(init["has_dictionary"], true);
+
+    let response = server.request(json!({
+        "type": "convert",
+        "seq": 2,
+        "session_id": init["session_id"],
+        "reading": "きょうはいしゃに"
+    }));
+    assert_eq!(response["candidates"][0]["text"], "今日は医者に");
+    assert_eq!(
+        response["segments"][0]["candidates"],
+        json!(["今日", "京", "教", "きょう"])
+    );
+    let _ = std::fs::remove_dir_all(&remote_home);
+}
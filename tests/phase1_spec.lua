@@ -87,7 +87,12 @@ test("Convert request", function()
   assert(response.type == "convert_result", "unexpected response type: " .. tostring(response.type))
   assert(response.candidates ~= nil, "candidates is nil")
   assert(#response.candidates > 0, "no candidates returned")
-  print("  -> Candidates: " .. table.concat(response.candidates, ", "))
+  assert(type(response.candidates[1].text) == "string", "candidate has no text")
+  local texts = {}
+  for _, candidate in ipairs(response.candidates) do
+    table.insert(texts, candidate.text)
+  end
+  print("  -> Candidates: " .. table.concat(texts, ", "))
 end)
 
 -- Test 6: Commit request